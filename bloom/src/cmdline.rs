@@ -0,0 +1,55 @@
+use std::fs;
+
+/// Kernel command line as passed by the bootloader.
+pub const CMDLINE_PATH: &str = "/proc/cmdline";
+
+/// Read and split the running kernel's command line.
+pub fn read_cmdline() -> Vec<(String, Option<String>)> {
+    fs::read_to_string(CMDLINE_PATH)
+        .map(|s| parse_cmdline(&s))
+        .unwrap_or_default()
+}
+
+/// Split a command line into `key` / `key=value` parameters. Double quotes
+/// group words, so `foo="a b"` yields `("foo", Some("a b"))`.
+pub fn parse_cmdline(cmdline: &str) -> Vec<(String, Option<String>)> {
+    let mut params = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+
+    for c in cmdline.chars() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            c if c.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    params.push(split_param(&current));
+                    current.clear();
+                }
+            }
+            c => current.push(c),
+        }
+    }
+
+    if !current.is_empty() {
+        params.push(split_param(&current));
+    }
+
+    params
+}
+
+fn split_param(param: &str) -> (String, Option<String>) {
+    match param.split_once('=') {
+        Some((key, value)) => (key.to_string(), Some(value.to_string())),
+        None => (param.to_string(), None),
+    }
+}
+
+/// Value of the last occurrence of `key`, which is what the kernel honours.
+/// A bare flag yields `Some("")`.
+pub fn get_param(params: &[(String, Option<String>)], key: &str) -> Option<String> {
+    params
+        .iter()
+        .rev()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.clone().unwrap_or_default())
+}
@@ -0,0 +1,490 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use toml::{Table, Value};
+
+use crate::cmdline::{get_param, read_cmdline};
+use crate::errors::BloomError;
+
+//
+// ─── PATHS ───────────────────────────────────────────────────────────────
+
+/// Base configuration file shared by init and verdantd.
+pub const CONFIG_PATH: &str = "/etc/verdant/config.toml";
+
+/// Drop-in fragments, merged over the base file in lexical order.
+pub const CONFIG_DROPIN_DIR: &str = "/etc/verdant/config.d";
+
+/// Environment overrides look like `VERDANT_<SECTION>__<KEY>=<toml value>`,
+/// e.g. `VERDANT_INIT__TTY_SESSIONS='["tty1"]'`.
+const ENV_PREFIX: &str = "VERDANT_";
+const ENV_SEPARATOR: &str = "__";
+
+/// Kernel command line parameter that overrides `default_target`.
+pub const TARGET_CMDLINE_PARAM: &str = "verdant.target";
+
+/// Kernel command line flags booting the rescue target: only its packages
+/// and a root shell on the console.
+pub const RESCUE_CMDLINE_PARAMS: &[&str] = &["rescue", "single"];
+pub const RESCUE_TARGET: &str = "rescue";
+
+/// Kernel command line flag stopping boot at a root shell right after the
+/// virtual filesystems are mounted.
+pub const EMERGENCY_CMDLINE_PARAM: &str = "emergency";
+
+/// Kernel command line parameters that override `resume` and `resume_offset`,
+/// and one that skips resuming altogether.
+pub const RESUME_CMDLINE_PARAM: &str = "resume";
+pub const RESUME_OFFSET_CMDLINE_PARAM: &str = "resume_offset";
+pub const NORESUME_CMDLINE_PARAM: &str = "noresume";
+
+//
+// ─── SCHEMA ──────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerdantConfig {
+    pub init: InitConfig,
+    pub verdantd: VerdantdConfig,
+    /// The built-in SNTP client, verdant-timesyncd.
+    pub timesync: TimesyncConfig,
+    /// The journal kept by verdant-logd.
+    pub logd: LogdConfig,
+    /// `[[mount]]` tables, mounted by init alongside /etc/fstab.
+    #[serde(rename = "mount")]
+    pub mounts: Vec<MountConfig>,
+}
+
+/// A filesystem to mount at boot, declared in the config rather than fstab.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountConfig {
+    /// Device, `UUID=`, `LABEL=`, directory for a bind mount, or remote share.
+    pub source: String,
+    pub target: String,
+    /// Filesystem type; not needed for bind mounts.
+    #[serde(default)]
+    pub fstype: Option<String>,
+    /// Comma-separated, as in fstab.
+    #[serde(default = "default_mount_options")]
+    pub options: String,
+    /// Seconds to wait for the source device to appear.
+    #[serde(default)]
+    pub wait: Option<u64>,
+    /// Drop to a recovery shell if this fails, instead of only warning.
+    #[serde(default)]
+    pub required: bool,
+    /// Leave it to verdantd to mount on first access instead of at boot.
+    #[serde(default)]
+    pub automount: bool,
+    /// Seconds an automount may sit unused before it is unmounted; 0 keeps it.
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+}
+
+fn default_mount_options() -> String {
+    "defaults".into()
+}
+
+fn default_idle_timeout() -> u64 {
+    300
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InitConfig {
+    /// Consoles set up at boot, each getting a getty from verdantd.
+    pub tty_sessions: Vec<String>,
+    /// Static hostname used when /etc/hostname does not give one.
+    pub hostname: Option<String>,
+    /// Swap device hibernation images are written to and resumed from:
+    /// a path, `UUID=`, `LABEL=` or `major:minor`.
+    pub resume: Option<String>,
+    /// Page offset of the image within `resume`, for swap files.
+    pub resume_offset: Option<u64>,
+    /// What to do when a UPS daemon reports a power event with SIGPWR.
+    pub power_failure: PowerFailureConfig,
+    /// Compressed RAM devices set up at boot, one per entry.
+    pub zram: Vec<ZramConfig>,
+    /// Read-only root with writable overlays, for stateless systems.
+    pub stateless: StatelessConfig,
+    /// Grow the root partition and filesystem to fill their disk, as
+    /// flashed images are usually smaller than the disk they end up on.
+    pub grow_root: bool,
+    /// Shell started when boot cannot go on.
+    pub recovery_shell: RecoveryShellConfig,
+    /// Where firmware the kernel asks init for is looked up, in order.
+    pub firmware_dirs: Vec<String>,
+}
+
+impl Default for InitConfig {
+    fn default() -> Self {
+        Self {
+            tty_sessions: vec!["tty1".into()],
+            hostname: None,
+            resume: None,
+            resume_offset: None,
+            power_failure: PowerFailureConfig::default(),
+            zram: Vec::new(),
+            stateless: StatelessConfig::default(),
+            grow_root: false,
+            recovery_shell: RecoveryShellConfig::default(),
+            firmware_dirs: vec!["/lib/firmware/updates".into(), "/lib/firmware".into()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PowerFailureConfig {
+    /// Service started while running on battery and stopped once power returns.
+    pub service: Option<String>,
+    /// Seconds on battery before shutting down; none to wait for a low battery.
+    pub shutdown_delay: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecoveryShellConfig {
+    pub path: String,
+    /// Terminal device to run it on, such as `/dev/tty1`; the console when unset.
+    pub tty: Option<String>,
+    /// Start it again whenever it exits, rather than leaving init idle. Only
+    /// applies once init itself has failed; where boot can go on after the
+    /// shell, it is always run once.
+    pub respawn: bool,
+}
+
+impl Default for RecoveryShellConfig {
+    fn default() -> Self {
+        Self {
+            path: "/bin/sh".into(),
+            tty: None,
+            respawn: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StatelessConfig {
+    /// Leave the root read-only instead of remounting it read-write.
+    pub enabled: bool,
+    /// Partition holding persistent overlays: a path, `UUID=` or `LABEL=`.
+    pub data: Option<String>,
+    /// Filesystem on `data`.
+    pub data_fstype: String,
+    /// Size of the tmpfs behind the other overlays, as for tmpfs `size=`.
+    pub tmpfs_size: String,
+    /// Paths made writable, in order.
+    #[serde(rename = "overlay")]
+    pub overlays: Vec<OverlayConfig>,
+}
+
+impl Default for StatelessConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            data: None,
+            data_fstype: "ext4".into(),
+            tmpfs_size: "25%".into(),
+            overlays: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverlayConfig {
+    pub path: String,
+    /// Keep changes on the data partition; otherwise they are lost at shutdown.
+    #[serde(default)]
+    pub persistent: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZramConfig {
+    /// Uncompressed size: bytes with an optional K, M or G suffix, or a
+    /// percentage of RAM such as `50%`.
+    pub size: String,
+    /// Compression algorithm; the kernel default when unset.
+    pub algorithm: Option<String>,
+    /// Mount point for a filesystem on the device; swap when unset.
+    pub mount: Option<String>,
+    /// Filesystem created for `mount`.
+    pub fstype: String,
+    /// Swap priority, above disk swap so RAM is used first.
+    pub priority: i32,
+}
+
+impl Default for ZramConfig {
+    fn default() -> Self {
+        Self {
+            size: "50%".into(),
+            algorithm: None,
+            mount: None,
+            fstype: "ext4".into(),
+            priority: 100,
+        }
+    }
+}
+
+impl InitConfig {
+    /// Device to resume from, with its offset: the kernel command line wins
+    /// over config, and `noresume` disables resuming.
+    pub fn resume_device(&self) -> Option<(String, Option<u64>)> {
+        let params = read_cmdline();
+        if get_param(&params, NORESUME_CMDLINE_PARAM).is_some() {
+            return None;
+        }
+
+        let device = get_param(&params, RESUME_CMDLINE_PARAM)
+            .filter(|d| !d.is_empty())
+            .or_else(|| self.resume.clone())?;
+        let offset = get_param(&params, RESUME_OFFSET_CMDLINE_PARAM)
+            .and_then(|o| o.parse().ok())
+            .or(self.resume_offset);
+
+        Some((device, offset))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerdantdConfig {
+    /// Target booted unless `verdant.target=` is given on the kernel command line.
+    pub default_target: String,
+    /// Boot targets, each naming the startup packages it brings up.
+    pub targets: BTreeMap<String, Vec<String>>,
+    /// Reload automatically when service files are added, changed or removed.
+    pub watch_services: bool,
+    /// What pressing the power button does.
+    pub power_key: ButtonAction,
+    /// What closing the lid does.
+    pub lid_switch: ButtonAction,
+}
+
+/// Response to a power button press or the lid closing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ButtonAction {
+    Poweroff,
+    Suspend,
+    Ignore,
+}
+
+impl Default for VerdantdConfig {
+    fn default() -> Self {
+        let packages = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+
+        Self {
+            default_target: "default".into(),
+            targets: BTreeMap::from([
+                ("default".into(), packages(&["base", "network", "system"])),
+                (RESCUE_TARGET.into(), packages(&["base"])),
+            ]),
+            watch_services: true,
+            power_key: ButtonAction::Poweroff,
+            lid_switch: ButtonAction::Suspend,
+        }
+    }
+}
+
+impl VerdantdConfig {
+    /// Name of the target to boot: the kernel command line wins over config,
+    /// and `rescue` or `single` over both.
+    pub fn boot_target(&self) -> String {
+        let params = read_cmdline();
+        if RESCUE_CMDLINE_PARAMS.iter().any(|param| get_param(&params, param).is_some()) {
+            return RESCUE_TARGET.into();
+        }
+
+        get_param(&params, TARGET_CMDLINE_PARAM)
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| self.default_target.clone())
+    }
+
+    /// Startup packages of a target, if it is defined.
+    pub fn target(&self, name: &str) -> Option<&[String]> {
+        self.targets.get(name).map(Vec::as_slice)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogdConfig {
+    /// Size in MiB the journal grows to before it is rotated; 0 for no limit.
+    pub max_size_mb: u64,
+    /// Rotated journals kept besides the current one, all still queried;
+    /// the oldest is deleted once there are more.
+    pub max_files: u32,
+}
+
+impl Default for LogdConfig {
+    fn default() -> Self {
+        Self {
+            max_size_mb: 32,
+            max_files: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimesyncConfig {
+    /// NTP servers, as names or addresses with an optional `:port`; each
+    /// is asked every poll and the answer with the shortest delay is used.
+    pub servers: Vec<String>,
+    /// Seconds between polls once the clock is in sync.
+    pub poll_interval: u64,
+    /// How an offset is corrected.
+    pub adjust: ClockAdjust,
+    /// With `adjust = "auto"`, offsets beyond this many milliseconds are
+    /// stepped and smaller ones slewed.
+    pub step_threshold_ms: u64,
+}
+
+/// How the clock is brought in line with the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClockAdjust {
+    /// Step large offsets, slew small ones.
+    Auto,
+    /// Always slew, so time never jumps; large offsets take long to correct.
+    Slew,
+    /// Always set the clock outright.
+    Step,
+}
+
+impl Default for TimesyncConfig {
+    fn default() -> Self {
+        Self {
+            servers: vec!["pool.ntp.org".into()],
+            poll_interval: 1024,
+            adjust: ClockAdjust::Auto,
+            step_threshold_ms: 128,
+        }
+    }
+}
+
+/// Whether `emergency` is on the kernel command line.
+pub fn is_emergency_boot() -> bool {
+    get_param(&read_cmdline(), EMERGENCY_CMDLINE_PARAM).is_some()
+}
+
+//
+// ─── LOADING ─────────────────────────────────────────────────────────────
+
+/// Load the effective configuration: base file, then drop-ins, then
+/// environment overrides. A missing base file is not an error.
+pub fn load_config() -> Result<VerdantConfig, BloomError> {
+    let merged = load_merged_table(CONFIG_PATH, CONFIG_DROPIN_DIR, std::env::vars())?;
+
+    Value::Table(merged)
+        .try_into()
+        .map_err(|e| BloomError::Parse(format!("Invalid configuration: {}", e)))
+}
+
+/// Build the merged TOML table before it is mapped onto `VerdantConfig`.
+pub fn load_merged_table(
+    base_path: impl AsRef<Path>,
+    dropin_dir: impl AsRef<Path>,
+    env: impl Iterator<Item = (String, String)>,
+) -> Result<Table, BloomError> {
+    let mut merged = match fs::read_to_string(base_path.as_ref()) {
+        Ok(contents) => parse_table(base_path.as_ref(), &contents)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Table::new(),
+        Err(e) => return Err(BloomError::Io(e)),
+    };
+
+    for fragment in dropin_files(dropin_dir.as_ref()) {
+        let contents = fs::read_to_string(&fragment)?;
+        merge_tables(&mut merged, parse_table(&fragment, &contents)?);
+    }
+
+    apply_env_overrides(&mut merged, env);
+
+    Ok(merged)
+}
+
+fn parse_table(path: &Path, contents: &str) -> Result<Table, BloomError> {
+    contents
+        .parse::<Table>()
+        .map_err(|e| BloomError::Parse(format!("{}: {}", path.display(), e)))
+}
+
+/// `*.toml` files in the drop-in directory, sorted by file name.
+fn dropin_files(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|e| e.path())
+            .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("toml"))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    files.sort();
+    files
+}
+
+/// Recursively merge `overlay` into `base`. Tables merge key by key;
+/// every other value (including arrays) is replaced wholesale.
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(incoming)) => merge_tables(existing, incoming),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn apply_env_overrides(table: &mut Table, env: impl Iterator<Item = (String, String)>) {
+    for (name, raw) in env {
+        let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+
+        let keys: Vec<String> = path
+            .split(ENV_SEPARATOR)
+            .map(|k| k.to_lowercase())
+            .collect();
+
+        if keys.len() < 2 || keys.iter().any(|k| k.is_empty()) {
+            continue;
+        }
+
+        set_path(table, &keys, parse_env_value(&raw));
+    }
+}
+
+/// Interpret an override as a TOML value, falling back to a plain string.
+fn parse_env_value(raw: &str) -> Value {
+    format!("value = {}", raw)
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut t| t.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn set_path(table: &mut Table, keys: &[String], value: Value) {
+    let (last, parents) = keys.split_last().expect("override path is never empty");
+    let mut current = table;
+
+    for key in parents {
+        let entry = current
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+
+        current = entry.as_table_mut().expect("entry was just made a table");
+    }
+
+    current.insert(last.clone(), value);
+}
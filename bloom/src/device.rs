@@ -0,0 +1,69 @@
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errors::BloomError;
+use crate::uevent::UeventListener;
+
+/// How long to go without a uevent before looking again anyway: the
+/// /dev/disk links are made by the device manager a moment after the event.
+const RECHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Device spec prefixes and the /dev/disk directory each is looked up in.
+const SPEC_DIRS: &[(&str, &str)] = &[
+    ("UUID=", "/dev/disk/by-uuid"),
+    ("LABEL=", "/dev/disk/by-label"),
+    ("PARTUUID=", "/dev/disk/by-partuuid"),
+    ("PARTLABEL=", "/dev/disk/by-partlabel"),
+];
+
+/// Whether `spec` names a block device rather than a pseudo-filesystem or
+/// network share.
+pub fn is_device_spec(spec: &str) -> bool {
+    spec.starts_with("/dev/") || SPEC_DIRS.iter().any(|(prefix, _)| spec.starts_with(prefix))
+}
+
+/// The device node a `UUID=`, `LABEL=`, `PARTUUID=` or `PARTLABEL=` spec,
+/// or a path, refers to, if it exists yet.
+pub fn resolve_device(spec: &str) -> Option<String> {
+    let path = SPEC_DIRS
+        .iter()
+        .find_map(|(prefix, dir)| spec.strip_prefix(prefix).map(|name| Path::new(dir).join(name)))
+        .unwrap_or_else(|| Path::new(spec).to_path_buf());
+
+    // Links in /dev/disk are relative to their directory
+    fs::canonicalize(path).ok().map(|node| node.to_string_lossy().to_string())
+}
+
+/// Resolve `spec` to its device node, waiting up to `timeout` for a device
+/// still being enumerated. Each uevent prompts another look; without a
+/// uevent socket, as when not running as root, it is polled for instead.
+pub fn wait_for_device(spec: &str, timeout: Duration) -> Result<String, BloomError> {
+    let deadline = Instant::now() + timeout;
+    let listener = UeventListener::open().ok();
+
+    loop {
+        if let Some(node) = resolve_device(spec) {
+            return Ok(node);
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            let msg = if timeout.is_zero() {
+                format!("Device {} does not exist", spec)
+            } else {
+                format!("Device {} did not appear (waited {:?})", spec, timeout)
+            };
+            return Err(BloomError::Custom(msg));
+        }
+
+        let wait = remaining.min(RECHECK_INTERVAL);
+        match &listener {
+            Some(listener) => {
+                listener.next_event_timeout(wait)?;
+            }
+            None => thread::sleep(wait),
+        }
+    }
+}
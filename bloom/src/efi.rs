@@ -0,0 +1,41 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where efivarfs is mounted on EFI systems.
+pub const EFIVARS_DIR: &str = "/sys/firmware/efi/efivars";
+
+/// Vendor GUID of the variables defined by the UEFI specification.
+pub const GLOBAL_GUID: &str = "8be4df61-93ca-11d2-aa0d-00e098032b8c";
+
+/// `OsIndications` bit asking the firmware to stop in its setup screen.
+pub const BOOT_TO_FW_UI: u64 = 0x1;
+
+/// Non-volatile, boot service and runtime access.
+pub const DEFAULT_ATTRIBUTES: u32 = 0x7;
+
+/// Path of global variable `name` in efivarfs.
+pub fn variable_path(name: &str) -> PathBuf {
+    Path::new(EFIVARS_DIR).join(format!("{}-{}", name, GLOBAL_GUID))
+}
+
+/// Whether the machine booted through EFI.
+pub fn is_efi() -> bool {
+    Path::new("/sys/firmware/efi").is_dir()
+}
+
+/// A global variable holding a `u64`, without the attributes efivarfs puts
+/// in front of it.
+pub fn read_u64(name: &str) -> io::Result<u64> {
+    let data = fs::read(variable_path(name))?;
+    let value = data
+        .get(4..12)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} is too short", name)))?;
+
+    Ok(u64::from_le_bytes(value.try_into().unwrap()))
+}
+
+/// Whether the firmware can be asked to boot into its setup screen.
+pub fn firmware_setup_supported() -> bool {
+    is_efi() && read_u64("OsIndicationsSupported").is_ok_and(|bits| bits & BOOT_TO_FW_UI != 0)
+}
@@ -0,0 +1,32 @@
+use std::fs;
+
+pub const FSTAB_PATH: &str = "/etc/fstab";
+
+/// Filesystem types served over the network.
+pub const NETWORK_FILESYSTEMS: &[&str] = &["nfs", "nfs4", "cifs", "smb3", "9p", "ceph", "glusterfs", "fuse.sshfs"];
+
+/// Whether an fstab entry needs the network: marked `_netdev` or of a
+/// network filesystem type. These are mounted by verdantd once the network
+/// is online rather than during early boot.
+pub fn is_network_mount(fstype: &str, options: &str) -> bool {
+    NETWORK_FILESYSTEMS.contains(&fstype) || options.split(',').any(|opt| opt == "_netdev")
+}
+
+/// Whether any entry mounted at boot waits for the network.
+pub fn has_network_mounts() -> bool {
+    let Ok(fstab) = fs::read_to_string(FSTAB_PATH) else {
+        return false;
+    };
+
+    fstab
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            Some((*fields.get(2)?, *fields.get(3)?))
+        })
+        .any(|(fstype, options)| {
+            !options.split(',').any(|opt| opt == "noauto") && is_network_mount(fstype, options)
+        })
+}
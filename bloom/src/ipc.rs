@@ -1,6 +1,11 @@
-use std::io::{BufRead, BufReader, Write};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use serde::{Deserialize, Serialize};
@@ -17,7 +22,7 @@ pub const VERDANTD_SOCKET_PATH: &str = "/run/verdant/verdantd.sock";
 //
 // ─── MESSAGES ────────────────────────────────────────────────────
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub enum IpcTarget {
     Init,
     Verdantd,
@@ -29,22 +34,141 @@ pub enum IpcInternal {
     ReloadConfig,
 }
 
+/// Which processes of a service `KillService` signals.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KillWho {
+    /// Only the main process.
+    Main,
+    /// Every process in the service's cgroup.
+    #[default]
+    All,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum IpcCommand {
     // System-level
-    Shutdown,
-    Reboot,
+    /// Power off, now or after `delay_secs`. `message` is broadcast to
+    /// logged-in users while counting down.
+    Shutdown {
+        #[serde(default)]
+        delay_secs: u64,
+        #[serde(default)]
+        message: Option<String>,
+    },
+    /// Like `Shutdown`, but restart the machine; with `firmware_setup`, into
+    /// the EFI firmware's setup screen.
+    Reboot {
+        #[serde(default)]
+        delay_secs: u64,
+        #[serde(default)]
+        message: Option<String>,
+        #[serde(default)]
+        firmware_setup: bool,
+    },
+    /// Stop the machine without powering it off, like `Shutdown` otherwise.
+    Halt {
+        #[serde(default)]
+        delay_secs: u64,
+        #[serde(default)]
+        message: Option<String>,
+    },
+    /// Stop every service and boot straight into another kernel, skipping
+    /// the firmware. Without `kernel`, the one already loaded with
+    /// `kexec -l` is used; without `cmdline`, the running one is reused.
+    Kexec {
+        #[serde(default)]
+        kernel: Option<String>,
+        #[serde(default)]
+        initrd: Option<String>,
+        #[serde(default)]
+        cmdline: Option<String>,
+    },
+    /// Load the kernel for a `Kexec`, or without `kernel` check that one is
+    /// loaded, without going down yet (handled by init). Sent before any
+    /// service is stopped, so a bad image can still be reported.
+    KexecLoad {
+        #[serde(default)]
+        kernel: Option<String>,
+        #[serde(default)]
+        initrd: Option<String>,
+        #[serde(default)]
+        cmdline: Option<String>,
+    },
+    /// Call off a shutdown, reboot or halt scheduled with a delay.
+    CancelShutdown,
+    /// Suspend to RAM. Sent to verdantd, which runs the sleep hooks and
+    /// passes it on to init; answered once the machine has woken up.
+    Suspend,
+    /// Suspend to disk, like `Suspend`.
+    Hibernate,
+    /// Re-execute verdantd, or init when sent to it, in place, keeping
+    /// supervised services running.
+    Reexec,
 
     // Service control
     StartService(String),
     StopService(String),
     RestartService(String),
+    ReloadService(String),
     EnableService(String),
     DisableService(String),
+    MaskService(String),
+    UnmaskService(String),
+    Isolate(String),
+    /// Clear the Failed state and start limit of one service, or of all.
+    ResetFailed(Option<String>),
+    /// Send `signal` (a name such as `SIGHUP` or `HUP`, or a number) to a
+    /// running service.
+    KillService {
+        name: String,
+        signal: String,
+        #[serde(default)]
+        who: KillWho,
+    },
 
     // Status
     GetStatus,
     GetServiceStatus(String),
+    /// The status of every service, as `GetStatus` returns it, streamed
+    /// again whenever it changes until the client hangs up.
+    WatchServices,
+    /// Every effective setting of a service as `[key, value]` pairs.
+    ShowService(String),
+    /// The last `lines` lines of a service's captured output, or, given the
+    /// `cursors` returned by a previous call, everything written since.
+    /// With `follow`, new lines keep being streamed until the client hangs up.
+    ServiceLogs {
+        name: String,
+        lines: usize,
+        cursors: Option<Vec<u64>>,
+        #[serde(default)]
+        follow: bool,
+    },
+    /// The dependency tree of a service, or with `reverse` its dependents.
+    ListDependencies {
+        name: String,
+        reverse: bool,
+    },
+    GetConfig,
+    ListTimers,
+
+    // Kernel modules (handled by init)
+    ModuleLoad(String),
+    ModuleUnload(String),
+    ModuleList,
+
+    /// Set the hostname (handled by init). A static one is also written to
+    /// /etc/hostname; a `transient` one, such as a DHCP client's, only
+    /// applies while no static hostname is configured.
+    SetHostname {
+        name: String,
+        #[serde(default)]
+        transient: bool,
+    },
+
+    // fstab entries held back until the network is online (handled by init)
+    MountNetworkFilesystems,
 
     // Internal messages
     Internal(IpcInternal),
@@ -52,8 +176,69 @@ pub enum IpcCommand {
     BootComplete,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// How the system goes down, shared by vctl, verdantd and init.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PowerAction {
+    PowerOff,
+    /// With `firmware_setup`, into the EFI firmware's setup screen.
+    Reboot { firmware_setup: bool },
+    Halt,
+    /// Boot the kernel loaded beforehand with `KexecLoad`.
+    Kexec,
+}
+
+impl PowerAction {
+    /// The action, delay and message of a `Shutdown`, `Reboot` or `Halt`.
+    pub fn from_command(command: &IpcCommand) -> Option<(Self, u64, Option<String>)> {
+        match command {
+            IpcCommand::Shutdown { delay_secs, message } => Some((PowerAction::PowerOff, *delay_secs, message.clone())),
+            IpcCommand::Reboot {
+                delay_secs,
+                message,
+                firmware_setup,
+            } => Some((PowerAction::Reboot { firmware_setup: *firmware_setup }, *delay_secs, message.clone())),
+            IpcCommand::Halt { delay_secs, message } => Some((PowerAction::Halt, *delay_secs, message.clone())),
+            IpcCommand::Kexec { .. } => Some((PowerAction::Kexec, 0, None)),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerAction::PowerOff => "power off",
+            PowerAction::Reboot { firmware_setup: false } => "reboot",
+            PowerAction::Reboot { firmware_setup: true } => "reboot into firmware setup",
+            PowerAction::Halt => "halt",
+            PowerAction::Kexec => "kexec",
+        }
+    }
+
+    /// The command carrying this action out after `delay_secs`. A kexec is
+    /// never delayed and boots whatever kernel is loaded.
+    pub fn command(self, delay_secs: u64, message: Option<String>) -> IpcCommand {
+        match self {
+            PowerAction::PowerOff => IpcCommand::Shutdown { delay_secs, message },
+            PowerAction::Reboot { firmware_setup } => IpcCommand::Reboot {
+                delay_secs,
+                message,
+                firmware_setup,
+            },
+            PowerAction::Halt => IpcCommand::Halt { delay_secs, message },
+            PowerAction::Kexec => IpcCommand::Kexec {
+                kernel: None,
+                initrd: None,
+                cmdline: None,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct IpcRequest {
+    /// Echoed back on every reply so a client can keep several requests in
+    /// flight on one connection. `IpcClient` assigns it when sending.
+    #[serde(default)]
+    pub id: u64,
     pub target: IpcTarget,
     pub command: IpcCommand,
 }
@@ -65,76 +250,367 @@ pub struct IpcResponse {
     pub data: Option<serde_json::Value>,
 }
 
+/// One message of a reply: any number of `Chunk`s, then the final response
+/// in `End`. Line-based clients get a reply that is not streamed as a bare
+/// `IpcResponse`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum IpcStreamFrame {
+    Chunk(serde_json::Value),
+    End(IpcResponse),
+}
+
+/// A reply message on a framed connection, tagged with its request's id.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IpcReply {
+    pub id: u64,
+    pub frame: IpcStreamFrame,
+}
+
+//
+// ─── FRAMING ─────────────────────────────────────────────────────────────
+
+/// Start of every framed message.
+pub const FRAME_MAGIC: &[u8; 4] = b"VIPC";
+
+/// Bumped whenever the frame layout changes.
+pub const FRAME_VERSION: u8 = 1;
+
+/// Magic, version and the big-endian `u32` payload length.
+const FRAME_HEADER_LEN: usize = 4 + 1 + 4;
+
+/// Larger frames are refused rather than allocated.
+const MAX_FRAME_LEN: u32 = 64 * 1024 * 1024;
+
+/// How messages on a connection are delimited. Clients always send framed
+/// messages; servers answer in whichever framing the client used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// `FRAME_MAGIC`, `FRAME_VERSION`, the payload length and the JSON
+    /// payload, which may contain newlines.
+    Framed,
+    /// One JSON document per line, as sent by clients from before framing.
+    Lines,
+}
+
+impl Framing {
+    /// Tell the framing of an incoming connection from its first byte. A
+    /// JSON line can never start with the frame magic.
+    pub fn detect(reader: &mut impl BufRead) -> io::Result<Self> {
+        let buf = reader.fill_buf()?;
+        Ok(match buf.first() {
+            Some(byte) if *byte != FRAME_MAGIC[0] => Framing::Lines,
+            _ => Framing::Framed,
+        })
+    }
+
+    /// Wrap a JSON payload for sending.
+    pub fn encode(self, payload: &[u8]) -> Vec<u8> {
+        match self {
+            Framing::Framed => {
+                let mut vec = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+                vec.extend_from_slice(FRAME_MAGIC);
+                vec.push(FRAME_VERSION);
+                vec.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+                vec.extend_from_slice(payload);
+                vec
+            }
+            Framing::Lines => {
+                let mut vec = payload.to_vec();
+                vec.push(b'\n');
+                vec
+            }
+        }
+    }
+
+    /// Read the payload of the next message, or `None` once the peer has
+    /// closed the connection.
+    pub fn read(self, reader: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Framing::Framed => {
+                let mut header = [0; FRAME_HEADER_LEN];
+                match reader.read_exact(&mut header) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                    Err(e) => return Err(e),
+                }
+
+                if &header[..4] != FRAME_MAGIC {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "not an IPC frame"));
+                }
+                if header[4] != FRAME_VERSION {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unsupported IPC frame version {}", header[4]),
+                    ));
+                }
+
+                let len = u32::from_be_bytes(header[5..9].try_into().unwrap());
+                if len > MAX_FRAME_LEN {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("IPC frame of {} bytes is too large", len),
+                    ));
+                }
+
+                let mut payload = vec![0; len as usize];
+                reader.read_exact(&mut payload)?;
+                Ok(Some(payload))
+            }
+            Framing::Lines => {
+                let mut buf = Vec::new();
+                match reader.read_until(b'\n', &mut buf)? {
+                    0 => Ok(None),
+                    _ => Ok(Some(buf)),
+                }
+            }
+        }
+    }
+}
+
 //
 // ─── SERIALIZATION HELPERS ───────────────────────────────────────────────
 
 pub fn serialize_request(req: &IpcRequest) -> Vec<u8> {
-    let mut vec = serde_json::to_vec(req).expect("Failed to serialize IPC request");
-    vec.push(b'\n');
-    vec
+    let payload = serde_json::to_vec(req).expect("Failed to serialize IPC request");
+    Framing::Framed.encode(&payload)
 }
 
 pub fn deserialize_request(buf: &[u8]) -> IpcRequest {
     serde_json::from_slice(buf).expect("Failed to deserialize IPC request")
 }
 
-pub fn serialize_response(resp: &IpcResponse) -> Vec<u8> {
-    let mut vec = serde_json::to_vec(resp).expect("Failed to serialize IPC response");
-    vec.push(b'\n');
-    vec
+/// Serialize the complete response to request `id`, in the framing the
+/// client sent it in.
+pub fn serialize_reply(resp: &IpcResponse, framing: Framing, id: u64) -> Vec<u8> {
+    match framing {
+        Framing::Framed => serialize_frame(IpcStreamFrame::End(resp.clone()), framing, id),
+        Framing::Lines => {
+            let payload = serde_json::to_vec(resp).expect("Failed to serialize IPC response");
+            framing.encode(&payload)
+        }
+    }
 }
 
 pub fn deserialize_response(buf: &[u8]) -> IpcResponse {
     serde_json::from_slice(buf).expect("Failed to deserialize IPC response")
 }
 
+fn serialize_frame(frame: IpcStreamFrame, framing: Framing, id: u64) -> Vec<u8> {
+    let payload = match framing {
+        Framing::Framed => serde_json::to_vec(&IpcReply { id, frame }),
+        Framing::Lines => serde_json::to_vec(&frame),
+    };
+    framing.encode(&payload.expect("Failed to serialize IPC frame"))
+}
+
+fn closed_early() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed before the reply ended")
+}
+
 //
 // ─── IPC TRANSPORT CLIENT ────────────────────────────────────────────
 
-/// Sends an IPC request and waits for a response.
-/// Used by `vctl` to communicate with `init` or `verdantd`.
-pub fn send_ipc_request(socket_path: &str, request: &IpcRequest) -> Result<IpcResponse, std::io::Error> {
-    let mut stream = match UnixStream::connect(socket_path) {
-        Ok(s) => s,
-        Err(e) => return Err(e),
-    };
+/// Replies still expected, by request id. `None` once the connection is gone.
+type Pending = Arc<Mutex<Option<HashMap<u64, Sender<IpcStreamFrame>>>>>;
+
+/// A connection to `init` or `verdantd` that can carry several requests at
+/// once, for example a status poll while logs are being followed. Replies
+/// are matched to their request by id.
+pub struct IpcClient {
+    writer: Mutex<UnixStream>,
+    next_id: AtomicU64,
+    pending: Pending,
+}
+
+impl IpcClient {
+    pub fn connect(socket_path: &str) -> io::Result<Self> {
+        let stream = UnixStream::connect(socket_path)?;
+        let reader = BufReader::new(stream.try_clone()?);
+
+        let pending: Pending = Arc::new(Mutex::new(Some(HashMap::new())));
+        let dispatch_pending = Arc::clone(&pending);
+        thread::spawn(move || dispatch_replies(reader, &dispatch_pending));
+
+        Ok(Self {
+            writer: Mutex::new(stream),
+            next_id: AtomicU64::new(1),
+            pending,
+        })
+    }
+
+    /// Send a request and wait for its response.
+    pub fn request(&self, request: &IpcRequest) -> io::Result<IpcResponse> {
+        self.request_stream(request, |_| {})
+    }
+
+    /// Send a request whose reply may be streamed, handing each chunk to
+    /// `on_chunk` as it arrives, and return the final response.
+    pub fn request_stream(
+        &self,
+        request: &IpcRequest,
+        mut on_chunk: impl FnMut(serde_json::Value),
+    ) -> io::Result<IpcResponse> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel();
 
-    let data = serialize_request(request);
-    stream.write_all(&data)?;
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(id, tx),
+            None => return Err(closed_early()),
+        };
 
-    let mut reader = BufReader::new(stream);
-    let mut buf = Vec::new();
-    reader.read_until(b'\n', &mut buf)?;
+        let request = IpcRequest { id, ..request.clone() };
+        if let Err(e) = self.writer.lock().unwrap().write_all(&serialize_request(&request)) {
+            if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+                pending.remove(&id);
+            }
+            return Err(e);
+        }
 
-    Ok(deserialize_response(&buf))
+        loop {
+            match rx.recv() {
+                Ok(IpcStreamFrame::Chunk(data)) => on_chunk(data),
+                Ok(IpcStreamFrame::End(response)) => return Ok(response),
+                Err(_) => return Err(closed_early()),
+            }
+        }
+    }
 }
 
+impl Drop for IpcClient {
+    fn drop(&mut self) {
+        // Ends the dispatch thread
+        let _ = self.writer.lock().unwrap().shutdown(Shutdown::Both);
+    }
+}
+
+/// Route every reply to the request waiting for it. Once the connection
+/// closes, every waiting request is woken with an error.
+fn dispatch_replies(mut reader: BufReader<UnixStream>, pending: &Pending) {
+    while let Ok(Some(buf)) = Framing::Framed.read(&mut reader) {
+        let Ok(reply) = serde_json::from_slice::<IpcReply>(&buf) else {
+            continue;
+        };
+
+        let mut pending = pending.lock().unwrap();
+        let Some(pending) = pending.as_mut() else {
+            break;
+        };
+
+        let tx = match reply.frame {
+            IpcStreamFrame::End(_) => pending.remove(&reply.id),
+            IpcStreamFrame::Chunk(_) => pending.get(&reply.id).cloned(),
+        };
+        if let Some(tx) = tx {
+            let _ = tx.send(reply.frame);
+        }
+    }
+
+    *pending.lock().unwrap() = None;
+}
+
+/// Sends an IPC request and waits for a response.
+/// Used by `vctl` to communicate with `init` or `verdantd`.
+pub fn send_ipc_request(socket_path: &str, request: &IpcRequest) -> Result<IpcResponse, io::Error> {
+    IpcClient::connect(socket_path)?.request(request)
+}
+
+/// Sends an IPC request whose reply may be streamed, handing each chunk to
+/// `on_chunk` as it arrives. Returns the final response, which is all there
+/// is when the server answered with a single message.
+pub fn send_ipc_stream_request(
+    socket_path: &str,
+    request: &IpcRequest,
+    on_chunk: impl FnMut(serde_json::Value),
+) -> Result<IpcResponse, io::Error> {
+    IpcClient::connect(socket_path)?.request_stream(request, on_chunk)
+}
 
 //
 // ─── IPC SERVER HELPER ────────────────────────────────────────────
 
+/// The connection a request came in on, for handlers that stream their reply.
+/// Once a chunk has been sent, the handler's response is sent as the end marker.
+pub struct IpcStream {
+    writer: Arc<Mutex<UnixStream>>,
+    framing: Framing,
+    id: u64,
+    streaming: bool,
+}
+
+impl IpcStream {
+    /// Send one chunk of the reply. Fails once the client has gone away,
+    /// which is the handler's cue to stop.
+    pub fn send(&mut self, data: serde_json::Value) -> io::Result<()> {
+        self.streaming = true;
+        self.write(&serialize_frame(IpcStreamFrame::Chunk(data), self.framing, self.id))
+    }
+
+    fn finish(self, response: &IpcResponse) {
+        let data = if self.streaming {
+            serialize_frame(IpcStreamFrame::End(response.clone()), self.framing, self.id)
+        } else {
+            serialize_reply(response, self.framing, self.id)
+        };
+        let _ = self.write(&data);
+    }
+
+    /// Whole messages only, so replies to different requests never interleave.
+    fn write(&self, data: &[u8]) -> io::Result<()> {
+        self.writer.lock().unwrap().write_all(data)
+    }
+}
+
+/// Serve every request on one connection. Framed clients may send several
+/// requests without waiting, and each is handled on its own thread; a
+/// line-based client gets one request per connection, as before.
+fn serve_connection<H>(stream: UnixStream, handler: H)
+where
+    H: Fn(IpcRequest, &mut IpcStream) -> IpcResponse + Send + Sync + 'static + Clone,
+{
+    let Ok(read_half) = stream.try_clone() else {
+        return;
+    };
+    let mut reader = BufReader::new(read_half);
+    let Ok(framing) = Framing::detect(&mut reader) else {
+        return;
+    };
+    let writer = Arc::new(Mutex::new(stream));
+
+    while let Ok(Some(buf)) = framing.read(&mut reader) {
+        let Ok(request) = serde_json::from_slice::<IpcRequest>(&buf) else {
+            continue;
+        };
+
+        let mut ipc_stream = IpcStream {
+            writer: Arc::clone(&writer),
+            framing,
+            id: request.id,
+            streaming: false,
+        };
+
+        if framing == Framing::Lines {
+            let response = handler(request, &mut ipc_stream);
+            ipc_stream.finish(&response);
+            return;
+        }
+
+        let handler = handler.clone();
+        thread::spawn(move || {
+            let response = handler(request, &mut ipc_stream);
+            ipc_stream.finish(&response);
+        });
+    }
+}
+
 pub fn serve_ipc_socket<P: AsRef<Path>>(
     socket_path: P,
-    handler: impl Fn(IpcRequest) -> IpcResponse + Send + Sync + 'static + Clone,
+    handler: impl Fn(IpcRequest, &mut IpcStream) -> IpcResponse + Send + Sync + 'static + Clone,
 ) {
     let _ = std::fs::remove_file(&socket_path);
     let listener = UnixListener::bind(&socket_path).expect("Failed to bind to IPC socket");
 
-    for stream in listener.incoming() {
-        if let Ok(mut stream) = stream {
-            let handler = handler.clone();
-            thread::spawn(move || {
-                let mut reader = BufReader::new(&stream);
-                let mut buf = Vec::new();
-                if reader.read_until(b'\n', &mut buf).is_ok() {
-                    if let Ok(request) = serde_json::from_slice::<IpcRequest>(&buf) {
-                        let response = handler(request);
-                        let data = serialize_response(&response);
-                        let _ = stream.write_all(&data);
-                    }
-                }
-            });
-        }
+    for stream in listener.incoming().flatten() {
+        let handler = handler.clone();
+        thread::spawn(move || serve_connection(stream, handler));
     }
 }
-
@@ -0,0 +1,103 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::status::LogLevel;
+
+/// Datagram socket verdant-logd receives log frames on.
+pub const LOGD_SOCKET_PATH: &str = "/run/verdant/logd.sock";
+
+/// Stream socket verdant-logd answers journal queries on.
+pub const LOGD_QUERY_SOCKET_PATH: &str = "/run/verdant/logd-query.sock";
+
+const BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// One log message on its way to verdant-logd.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogFrame {
+    /// `init`, `verdantd` or the name of a service.
+    pub source: String,
+    pub level: LogLevel,
+    /// Microseconds since the Unix epoch.
+    pub timestamp_us: u64,
+    pub message: String,
+}
+
+/// Which journal entries to return. Unset fields match everything.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JournalQuery {
+    pub source: Option<String>,
+    /// Boot id as printed by `boot_id()`.
+    pub boot_id: Option<String>,
+    /// Only entries at this level or above.
+    pub min_level: Option<LogLevel>,
+    /// Newest entries to return; 0 means all.
+    pub limit: usize,
+}
+
+/// A stored log message, as returned by a query.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub boot_id: String,
+    pub source: String,
+    pub level: LogLevel,
+    pub timestamp_us: u64,
+    pub message: String,
+}
+
+/// The kernel's id for the current boot, without dashes.
+pub fn boot_id() -> String {
+    fs::read_to_string(BOOT_ID_PATH)
+        .map(|id| id.trim().replace('-', ""))
+        .unwrap_or_default()
+}
+
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Hand a message to verdant-logd. Best effort: nothing is queued, and if
+/// logd is not running the message only ends up wherever else it was logged.
+pub fn send(source: &str, level: LogLevel, message: &str) {
+    static SOCKET: OnceLock<Option<UnixDatagram>> = OnceLock::new();
+
+    let Some(socket) = SOCKET.get_or_init(|| {
+        let socket = UnixDatagram::unbound().ok()?;
+        socket.set_nonblocking(true).ok()?;
+        Some(socket)
+    }) else {
+        return;
+    };
+
+    let frame = LogFrame {
+        source: source.to_string(),
+        level,
+        timestamp_us: now_us(),
+        message: message.to_string(),
+    };
+
+    if let Ok(data) = serde_json::to_vec(&frame) {
+        let _ = socket.send_to(&data, LOGD_SOCKET_PATH);
+    }
+}
+
+/// Ask verdant-logd for journal entries, oldest first.
+pub fn query(query: &JournalQuery) -> io::Result<Vec<JournalEntry>> {
+    let mut stream = UnixStream::connect(LOGD_QUERY_SOCKET_PATH)?;
+
+    let mut request = serde_json::to_vec(query).map_err(io::Error::other)?;
+    request.push(b'\n');
+    stream.write_all(&request)?;
+
+    let mut response = Vec::new();
+    BufReader::new(stream).read_until(b'\n', &mut response)?;
+
+    serde_json::from_slice(&response).map_err(io::Error::other)
+}
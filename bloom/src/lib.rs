@@ -1,7 +1,16 @@
+pub mod cmdline;
 pub mod colour;
+pub mod config;
+pub mod device;
+pub mod efi;
+pub mod fstab;
+pub mod shell;
 pub mod status;
 pub mod log;
 pub mod ipc;
+pub mod journal;
+pub mod locale;
 pub mod errors;
 pub mod time;
+pub mod uevent;
 pub mod util;
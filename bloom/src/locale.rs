@@ -0,0 +1,32 @@
+use std::io;
+
+use crate::util::read_env_file;
+
+/// System locale, as `LANG=` and `LC_*=` lines.
+pub const LOCALE_CONF_PATH: &str = "/etc/locale.conf";
+
+/// Variables locale.conf may set; anything else in it is ignored.
+pub const LOCALE_VARS: &[&str] = &[
+    "LANG",
+    "LANGUAGE",
+    "LC_CTYPE",
+    "LC_NUMERIC",
+    "LC_TIME",
+    "LC_COLLATE",
+    "LC_MONETARY",
+    "LC_MESSAGES",
+    "LC_PAPER",
+    "LC_NAME",
+    "LC_ADDRESS",
+    "LC_TELEPHONE",
+    "LC_MEASUREMENT",
+    "LC_IDENTIFICATION",
+];
+
+/// The locale variables set in /etc/locale.conf, empty values left out.
+pub fn read_locale_conf() -> io::Result<Vec<(String, String)>> {
+    Ok(read_env_file(LOCALE_CONF_PATH)?
+        .into_iter()
+        .filter(|(key, value)| LOCALE_VARS.contains(&key.as_str()) && !value.is_empty())
+        .collect())
+}
@@ -1,4 +1,4 @@
-use std::fs::{metadata, OpenOptions};
+use std::fs::{metadata, File, OpenOptions};
 use std::io::Write;
 use std::time::{Duration, Instant};
 use std::path::Path;
@@ -10,6 +10,7 @@ use crate::status::LogLevel;
 use crate::time::format_duration;
 use crate::colour::color::{color_time, color_level, GREEN, RESET, BOLD};
 use crate::errors::BloomError;
+use crate::journal;
 
 impl LogLevel {
     pub fn as_str(&self) -> &'static str {
@@ -32,11 +33,45 @@ pub trait Logger {
 pub trait ConsoleLogger {
     fn message(&mut self, level: LogLevel, message: &str, duration: Duration);
     fn banner(&mut self, message: &str);
+
+    /// Start writing the boot log, flushing what was buffered so far.
+    /// Call once the filesystem is writable.
+    fn open_boot_log(&mut self) -> Result<(), BloomError> {
+        Ok(())
+    }
+}
+
+/// Copy of everything printed to the console during boot.
+pub const BOOT_LOG_PATH: &str = "/var/log/boot.log";
+
+/// Lines kept in memory while the boot log cannot be written yet.
+const BOOT_LOG_BUFFER_LINES: usize = 10_000;
+
+/// Console output teed to a file, buffered until the file can be opened.
+struct BootLog {
+    path: String,
+    /// Start the file over instead of appending to it.
+    fresh: bool,
+    file: Option<File>,
+    buffer: Vec<String>,
+}
+
+impl BootLog {
+    fn write(&mut self, line: &str) {
+        match &mut self.file {
+            Some(file) => {
+                let _ = writeln!(file, "{}", line);
+            }
+            None if self.buffer.len() < BOOT_LOG_BUFFER_LINES => self.buffer.push(line.to_string()),
+            None => {}
+        }
+    }
 }
 
 pub struct ConsoleLoggerImpl {
     pub min_level: LogLevel,
     pub start_time: Instant,
+    boot_log: Option<BootLog>,
 }
 
 impl ConsoleLoggerImpl {
@@ -44,9 +79,22 @@ impl ConsoleLoggerImpl {
         Self {
             min_level,
             start_time: Instant::now(),
+            boot_log: None,
         }
     }
 
+    /// Tee console output to `path`. A `fresh` log replaces the previous
+    /// boot's; otherwise lines are appended, as for a daemon joining the boot.
+    pub fn with_boot_log(mut self, path: impl Into<String>, fresh: bool) -> Self {
+        self.boot_log = Some(BootLog {
+            path: path.into(),
+            fresh,
+            file: None,
+            buffer: Vec::new(),
+        });
+        self
+    }
+
     fn format_console(&self, level: LogLevel, message: &str, duration: Duration) -> String {
         let raw_time_str = format_duration(duration);
         let time_str = color_time(&raw_time_str);
@@ -80,11 +128,48 @@ impl ConsoleLogger for ConsoleLoggerImpl {
         if level >= self.min_level {
             let line = self.format_console(level, message, duration);
             println!("{}", line);
+
+            if let Some(boot_log) = &mut self.boot_log {
+                boot_log.write(&format!("{} {} {}", format_duration(duration), message, padded_level(level)));
+            }
         }
     }
 
     fn banner(&mut self, message: &str) {
         println!("{BOLD}{GREEN}{message}{RESET}\n");
+
+        if let Some(boot_log) = &mut self.boot_log {
+            boot_log.write(message);
+        }
+    }
+
+    fn open_boot_log(&mut self) -> Result<(), BloomError> {
+        let Some(boot_log) = &mut self.boot_log else {
+            return Ok(());
+        };
+
+        if boot_log.file.is_some() {
+            return Ok(());
+        }
+
+        if let Some(parent) = Path::new(&boot_log.path).parent() {
+            std::fs::create_dir_all(parent).map_err(BloomError::Io)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(!boot_log.fresh)
+            .truncate(boot_log.fresh)
+            .open(&boot_log.path)
+            .map_err(BloomError::Io)?;
+
+        for line in boot_log.buffer.drain(..) {
+            writeln!(file, "{}", line).map_err(BloomError::Io)?;
+        }
+        boot_log.file = Some(file);
+
+        Ok(())
     }
 }
 
@@ -100,6 +185,8 @@ pub trait FileLogger {
 pub struct FileLoggerImpl {
     pub min_level: LogLevel,
     pub file_path: String,
+    /// Source name for copies sent to verdant-logd, if enabled.
+    journal_source: Option<String>,
     has_initialized: bool,
     buffer: Vec<String>,
 }
@@ -109,11 +196,18 @@ impl FileLoggerImpl {
         Self {
             min_level,
             file_path: file_path.into(),
+            journal_source: None,
             has_initialized: false,
             buffer: Vec::new(),
         }
     }
 
+    /// Also send every logged line to verdant-logd under `source`.
+    pub fn with_journal(mut self, source: impl Into<String>) -> Self {
+        self.journal_source = Some(source.into());
+        self
+    }
+
     fn format_file(&self, level: LogLevel, message: &str) -> String {
         let now = chrono::Local::now();
         let timestamp = now.format("[%d-%m-%Y %H:%M:%S]").to_string();
@@ -146,6 +240,10 @@ impl FileLoggerImpl {
 impl FileLogger for FileLoggerImpl {
     fn log(&mut self, level: LogLevel, message: &str) {
         if level >= self.min_level {
+            if let Some(source) = &self.journal_source {
+                journal::send(source, level, message);
+            }
+
             let line = self.format_file(level, message);
 
             if self.has_initialized {
@@ -0,0 +1,22 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+/// Where sulogin may be installed, in the order it is tried.
+pub const SULOGIN_PATHS: &[&str] = &["/sbin/sulogin", "/usr/sbin/sulogin", "/bin/sulogin", "/usr/bin/sulogin"];
+
+pub fn find_sulogin() -> Option<&'static str> {
+    SULOGIN_PATHS.iter().copied().find(|path| Path::new(path).is_file())
+}
+
+/// Run sulogin on the console and wait for the shell it starts once the
+/// root password is given. Fails with `NotFound` if it is not installed.
+pub fn run_sulogin() -> io::Result<ExitStatus> {
+    let sulogin = find_sulogin().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "sulogin is not installed"))?;
+
+    Command::new(sulogin)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+}
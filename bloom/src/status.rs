@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 /// Represents general status results for operations.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
@@ -8,13 +10,20 @@ pub enum Status {
 }
 
 /// Represents the current lifecycle state of a service.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ServiceState {
     Stopped,
     Starting,
     Running,
+    /// Running, but its health check is failing.
+    Degraded,
+    /// Re-reading its configuration in place.
+    Reloading,
     Stopping,
     Failed,
+    /// Not started because a `condition_*` check did not hold.
+    Skipped,
 }
 
 /// Commands used to control services or the system.
@@ -29,7 +38,8 @@ pub enum Command {
 }
 
 /// Log levels to control verbosity of logging output.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LogLevel {
     Info,
     Warn,
@@ -1,5 +1,13 @@
+use std::fs;
+use std::io;
+use std::path::Path;
 use std::time::{Duration, Instant};
 
+use serde::{Deserialize, Serialize};
+
+/// Where init and verdantd record how long each part of the boot took.
+pub const BOOT_TIMES_PATH: &str = "/run/verdant/boot-times.json";
+
 /// Tracks overall elapsed time since system start.
 pub struct SystemTimer {
     start: Instant,
@@ -58,3 +66,52 @@ pub fn format_duration(duration: Duration) -> String {
 
     format!("[ {:02}:{:02}:{:03} ]", mins, secs, millis)
 }
+
+/// How long one boot step or service activation took.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BootTime {
+    pub name: String,
+    pub duration_ms: u64,
+}
+
+impl BootTime {
+    pub fn new(name: &str, duration: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            duration_ms: duration.as_millis() as u64,
+        }
+    }
+}
+
+/// Timings of the current boot, as shown by `vctl blame`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BootTimes {
+    /// Steps init ran before handing over to verdantd, in order.
+    #[serde(default)]
+    pub init: Vec<BootTime>,
+    /// Time from each boot service being started until it was ready.
+    #[serde(default)]
+    pub services: Vec<BootTime>,
+}
+
+impl BootTimes {
+    /// What has been recorded so far; nothing if the file is missing or unreadable.
+    pub fn load() -> Self {
+        fs::read(BOOT_TIMES_PATH)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default()
+    }
+
+    /// Replace the recorded timings in one step.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(parent) = Path::new(BOOT_TIMES_PATH).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let data = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        let partial = format!("{}.tmp", BOOT_TIMES_PATH);
+        fs::write(&partial, data)?;
+        fs::rename(partial, BOOT_TIMES_PATH)
+    }
+}
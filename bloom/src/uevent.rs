@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::socket::{
+    bind, recvfrom, setsockopt, socket, sockopt, AddressFamily, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+
+use crate::errors::BloomError;
+
+/// Multicast group the kernel sends uevents to; udevd re-sends them on 2.
+const KERNEL_GROUP: u32 = 1;
+
+/// Receive buffer requested for the socket, so a burst of events at
+/// coldplug is not dropped while the previous one is handled.
+const RECEIVE_BUFFER: usize = 8 * 1024 * 1024;
+
+/// Longest uevent the kernel sends (`UEVENT_BUFFER_SIZE`).
+const MAX_UEVENT_SIZE: usize = 2048;
+
+/// A kernel uevent: what happened to which device, with its environment.
+#[derive(Debug, Clone)]
+pub struct Uevent {
+    /// `add`, `remove`, `change`, `move`, `bind`, `unbind`, ...
+    pub action: String,
+    /// Path below /sys, e.g. `/devices/pci0000:00/.../block/sda`.
+    pub devpath: String,
+    pub vars: HashMap<String, String>,
+}
+
+impl Uevent {
+    /// Parse `action@devpath` followed by NUL-separated `KEY=value` pairs.
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        let mut parts = buf.split(|&b| b == 0).filter(|part| !part.is_empty());
+
+        let header = std::str::from_utf8(parts.next()?).ok()?;
+        let (action, devpath) = header.split_once('@')?;
+
+        let vars = parts
+            .filter_map(|part| std::str::from_utf8(part).ok()?.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        Some(Self {
+            action: action.to_string(),
+            devpath: devpath.to_string(),
+            vars,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.vars.get(key).map(String::as_str)
+    }
+
+    pub fn subsystem(&self) -> Option<&str> {
+        self.get("SUBSYSTEM")
+    }
+
+    /// Last component of the devpath, e.g. `sda1`.
+    pub fn sysname(&self) -> &str {
+        self.devpath.rsplit('/').next().unwrap_or_default()
+    }
+
+    /// The device node under /dev, if the device has one.
+    pub fn devnode(&self) -> Option<String> {
+        self.get("DEVNAME").map(|name| format!("/dev/{}", name))
+    }
+}
+
+/// NETLINK_KOBJECT_UEVENT socket receiving uevents straight from the kernel,
+/// so no udevd or libudev is needed to follow hotplug.
+pub struct UeventListener {
+    socket: OwnedFd,
+}
+
+impl UeventListener {
+    pub fn open() -> Result<Self, BloomError> {
+        let socket = socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkKObjectUEvent,
+        )
+        .map_err(BloomError::Nix)?;
+
+        // Forcing the size needs CAP_NET_ADMIN, which init has; the plain
+        // option is capped by net.core.rmem_max
+        if setsockopt(&socket, sockopt::RcvBufForce, &RECEIVE_BUFFER).is_err() {
+            let _ = setsockopt(&socket, sockopt::RcvBuf, &RECEIVE_BUFFER);
+        }
+
+        bind(socket.as_raw_fd(), &NetlinkAddr::new(0, KERNEL_GROUP)).map_err(BloomError::Nix)?;
+
+        Ok(Self { socket })
+    }
+
+    /// Block until the next uevent from the kernel. Messages from other
+    /// processes, which anyone may send to the group, are skipped.
+    pub fn next_event(&self) -> Result<Uevent, BloomError> {
+        loop {
+            if let Some(event) = self.receive()? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Like `next_event`, but give up once `timeout` has passed.
+    pub fn next_event_timeout(&self, timeout: Duration) -> Result<Option<Uevent>, BloomError> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut fds = [PollFd::new(self.socket.as_fd(), PollFlags::POLLIN)];
+
+            match poll(&mut fds, PollTimeout::try_from(remaining).unwrap_or(PollTimeout::MAX)) {
+                Ok(0) => return Ok(None),
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(BloomError::Nix(e)),
+            }
+
+            if let Some(event) = self.receive()? {
+                return Ok(Some(event));
+            }
+        }
+    }
+
+    /// Read one message; `None` if it was not a uevent from the kernel.
+    fn receive(&self) -> Result<Option<Uevent>, BloomError> {
+        let mut buf = [0u8; MAX_UEVENT_SIZE];
+
+        let (len, sender) = match recvfrom::<NetlinkAddr>(self.socket.as_raw_fd(), &mut buf) {
+            Ok(received) => received,
+            Err(Errno::EINTR) => return Ok(None),
+            // The buffer overflowed; the events lost cannot be had back
+            Err(Errno::ENOBUFS) => return Ok(None),
+            Err(e) => return Err(BloomError::Nix(e)),
+        };
+
+        if sender.is_none_or(|addr| addr.pid() != 0) {
+            return Ok(None);
+        }
+
+        Ok(Uevent::parse(&buf[..len]))
+    }
+}
@@ -0,0 +1,133 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Match `name` against a shell-style pattern: `*` stands for any run of
+/// characters, `?` for any one and `[...]` for one of a set or range, negated
+/// with a leading `!` or `^`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of the name it has taken
+    let mut star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some(b'?') => Some(1),
+            Some(b'[') => match_class(&pattern[p..], name[n]),
+            Some(&c) if c == name[n] => Some(1),
+            _ => None,
+        };
+
+        match (step, star) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            (None, Some((star_p, star_n))) => {
+                p = star_p + 1;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&b| b == b'*')
+}
+
+/// Match one character against the `[...]` class `class` starts with,
+/// returning the length of the class if it matches. An unclosed `[` is a
+/// literal.
+fn match_class(class: &[u8], c: u8) -> Option<usize> {
+    let Some(end) = class.iter().skip(2).position(|&b| b == b']').map(|i| i + 2) else {
+        return (c == b'[').then_some(1);
+    };
+
+    let (negate, set) = match class[1] {
+        b'!' | b'^' => (true, &class[2..end]),
+        _ => (false, &class[1..end]),
+    };
+
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == b'-' {
+            found |= (set[i]..=set[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+
+    (found != negate).then_some(end + 1)
+}
+
+/// Read a file of `KEY=value` lines, as /etc/vconsole.conf and
+/// /etc/locale.conf are: blank lines and `#` comments are skipped, and
+/// values may be wrapped in single or double quotes.
+pub fn read_env_file(path: impl AsRef<Path>) -> io::Result<Vec<(String, String)>> {
+    let contents = fs::read_to_string(path)?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.strip_prefix("export ").unwrap_or(line).split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let unquoted = ['"', '\'']
+                .iter()
+                .find_map(|&q| value.strip_prefix(q).and_then(|v| v.strip_suffix(q)))
+                .unwrap_or(value);
+            (key.trim().to_string(), unquoted.to_string())
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("*.conf", "sysctl.conf"));
+        assert!(glob_match("tty?", "tty1"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("tty?", "tty10"));
+        assert!(!glob_match("*.conf", "sysctl.conf.bak"));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(glob_match("sd[a-c]", "sdb"));
+        assert!(!glob_match("sd[a-c]", "sdd"));
+        assert!(glob_match("sd[!a-c]", "sdd"));
+        assert!(glob_match("sd[^ab]1", "sdc1"));
+        // An unclosed class is a literal `[`
+        assert!(glob_match("a[b", "a[b"));
+    }
+
+    #[test]
+    fn env_file_quotes_and_comments() {
+        let path = std::env::temp_dir().join(format!("bloom-env-test-{}", std::process::id()));
+        fs::write(&path, "# comment\n\nKEYMAP=us\nFONT=\"ter-v16n\"\nexport LANG='en_US.UTF-8'\n NO_EQUALS \n")
+            .unwrap();
+
+        let vars = read_env_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            vars,
+            [("KEYMAP", "us"), ("FONT", "ter-v16n"), ("LANG", "en_US.UTF-8")]
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+    }
+}
@@ -7,4 +7,6 @@ startup: base
 
 restart: on-failure
 
+dependencies: syslogd
+
 tags: sys, cron
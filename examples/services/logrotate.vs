@@ -0,0 +1,14 @@
+name: logrotate
+desc: Rotate system logs
+
+cmd: /usr/sbin/logrotate
+args: /etc/logrotate.conf
+
+startup: system
+
+restart: never
+
+on_boot_sec: 15min
+on_calendar: daily
+
+tags: sys, log
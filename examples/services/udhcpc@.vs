@@ -1,8 +1,9 @@
 name: udhcpc@{}
 desc: Lightweight DHCP client
 
+# The script also passes the hostname from the lease on to init
 cmd: /sbin/udhcpc
-args: -i {} -q
+args: -i {} -q -s /etc/verdant/udhcpc.script
 
 startup: network
 
@@ -0,0 +1,10 @@
+name: verdant-logd
+desc: Verdant journal daemon
+
+cmd: /usr/sbin/verdant-logd
+
+startup: base
+
+restart: always
+
+tags: sys, log
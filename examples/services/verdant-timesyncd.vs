@@ -0,0 +1,10 @@
+name: verdant-timesyncd
+desc: Verdant SNTP time synchronization
+
+cmd: /usr/sbin/verdant-timesyncd
+
+startup: network
+
+restart: always
+
+tags: sys, time
@@ -1,7 +1,54 @@
 use libc;
 
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::fd::AsRawFd;
+use std::sync::Mutex;
 use std::{fs, io};
 
+use bloom::cmdline::CMDLINE_PATH;
+use bloom::efi;
+use bloom::ipc::PowerAction;
+
+/// Set by the kernel once a kexec image is loaded.
+const KEXEC_LOADED_PATH: &str = "/sys/kernel/kexec_loaded";
+
+/// `kexec_file_load` flag for loading a kernel without an initramfs.
+const KEXEC_FILE_NO_INITRAMFS: libc::c_ulong = 0x4;
+
+/// The kernel a kexec boots; with no `kernel`, the one already loaded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KexecImage {
+    pub kernel: Option<String>,
+    pub initrd: Option<String>,
+    pub cmdline: Option<String>,
+}
+
+/// Take the system down the way `action` says.
+pub fn perform(action: PowerAction) -> io::Result<()> {
+    match action {
+        PowerAction::PowerOff => shutdown(),
+        PowerAction::Reboot { firmware_setup } => {
+            // Still reboot if the firmware cannot be told; that is what was asked for
+            if firmware_setup && let Err(e) = request_firmware_setup() {
+                eprintln!("Cannot request firmware setup, rebooting normally: {}", e);
+            }
+            reboot()
+        }
+        PowerAction::Halt => halt(),
+        PowerAction::Kexec => kexec(),
+    }
+}
+
+/// Record `action` for the main loop to carry out. The first request wins;
+/// later ones cannot change course halfway through going down.
+pub fn request(pending: &Mutex<Option<PowerAction>>, action: PowerAction) {
+    if let Ok(mut pending) = pending.lock() {
+        pending.get_or_insert(action);
+    }
+}
+
 /// Shutdown the system gracefully:
 /// 1. Sync disks
 /// 2. Reboot syscall with POWER_OFF
@@ -36,6 +83,147 @@ pub fn reboot() -> io::Result<()> {
     fs::write("/proc/sysrq-trigger", "b\n")
 }
 
+/// Halt the system without powering it off:
+/// 1. Sync disks
+/// 2. Reboot syscall with HALT
+pub fn halt() -> io::Result<()> {
+    unsafe { libc::sync() };
+
+    reboot_syscall(libc::LINUX_REBOOT_CMD_HALT)
+}
+
+/// Load `image` with kexec_file_load for a later kexec, or without a
+/// `kernel` check that one is loaded already. Done when the kexec is asked
+/// for, while the kernel's filesystem, usually /boot, is still mounted.
+pub fn load_kexec(image: &KexecImage) -> io::Result<()> {
+    match &image.kernel {
+        Some(kernel) => load_kexec_image(kernel, image.initrd.as_deref(), image.cmdline.as_deref()),
+        None => kexec_loaded(),
+    }
+}
+
+/// Boot into the kernel loaded with `load_kexec` without going through the
+/// firmware:
+/// 1. Sync disks
+/// 2. Reboot syscall with KEXEC
+/// 3. Fallback: a normal reboot, so the machine never stays half down
+pub fn kexec() -> io::Result<()> {
+    unsafe { libc::sync() };
+
+    if let Err(e) = reboot_syscall(libc::LINUX_REBOOT_CMD_KEXEC) {
+        eprintln!("kexec failed, rebooting normally: {}", e);
+    }
+    reboot()
+}
+
+/// Whether a kernel has been loaded for kexec, e.g. by `kexec -l`.
+fn kexec_loaded() -> io::Result<()> {
+    match fs::read_to_string(KEXEC_LOADED_PATH)?.trim() {
+        "1" => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::NotFound, "no kexec kernel is loaded")),
+    }
+}
+
+/// Load `kernel` (and `initrd`) to be booted by the next kexec. The command
+/// line defaults to that of the running kernel.
+fn load_kexec_image(kernel: &str, initrd: Option<&str>, cmdline: Option<&str>) -> io::Result<()> {
+    let kernel = File::open(kernel)?;
+    let initrd = initrd.map(File::open).transpose()?;
+
+    let cmdline = match cmdline {
+        Some(cmdline) => cmdline.to_string(),
+        None => fs::read_to_string(CMDLINE_PATH)?.trim().to_string(),
+    };
+    let cmdline = CString::new(cmdline).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let flags = if initrd.is_some() { 0 } else { KEXEC_FILE_NO_INITRAMFS };
+    let initrd_fd = initrd.as_ref().map_or(-1, |f| f.as_raw_fd());
+
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_kexec_file_load,
+            kernel.as_raw_fd(),
+            initrd_fd,
+            cmdline.as_bytes_with_nul().len() as libc::c_ulong,
+            cmdline.as_ptr(),
+            flags,
+        )
+    };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Ask the firmware to stop in its setup screen on the next boot, by
+/// setting the boot-to-firmware-UI bit of the `OsIndications` variable.
+fn request_firmware_setup() -> io::Result<()> {
+    if !efi::firmware_setup_supported() {
+        return Err(io::Error::new(io::ErrorKind::Unsupported, "not supported by the firmware"));
+    }
+
+    let path = efi::variable_path("OsIndications");
+    let current = efi::read_u64("OsIndications").unwrap_or(0);
+
+    // efivarfs marks existing variables immutable
+    if let Ok(file) = File::open(&path) {
+        clear_immutable(&file)?;
+    }
+
+    let mut data = efi::DEFAULT_ATTRIBUTES.to_le_bytes().to_vec();
+    data.extend_from_slice(&(current | efi::BOOT_TO_FW_UI).to_le_bytes());
+
+    // The attributes and value must reach efivarfs in a single write
+    let mut file = OpenOptions::new().write(true).create(true).truncate(false).open(&path)?;
+    file.write_all(&data)
+}
+
+fn clear_immutable(file: &File) -> io::Result<()> {
+    const FS_IMMUTABLE_FL: libc::c_long = 0x10;
+
+    let mut flags: libc::c_long = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_GETFLAGS, &mut flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if flags & FS_IMMUTABLE_FL == 0 {
+        return Ok(());
+    }
+
+    flags &= !FS_IMMUTABLE_FL;
+    if unsafe { libc::ioctl(file.as_raw_fd(), libc::FS_IOC_SETFLAGS, &flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Suspend to RAM. Returns once the system has woken up again.
+pub fn suspend() -> io::Result<()> {
+    enter_sleep_state("mem")
+}
+
+/// Suspend to disk. Returns once the system has been resumed from the image.
+/// The image goes to the swap device set up by the resume step at boot.
+pub fn hibernate() -> io::Result<()> {
+    let resume = fs::read_to_string("/sys/power/resume").unwrap_or_default();
+    if matches!(resume.trim(), "" | "0:0") {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no resume device; set resume= on the kernel command line or init.resume in the config",
+        ));
+    }
+
+    enter_sleep_state("disk")
+}
+
+fn enter_sleep_state(state: &str) -> io::Result<()> {
+    // Whatever is not on disk yet is lost if the battery dies while asleep
+    unsafe { libc::sync() };
+
+    fs::write("/sys/power/state", state)
+}
+
 /// Perform the Linux reboot syscall with the given command.
 ///
 /// Uses the standard magic constants. Returns Ok(()) on success.
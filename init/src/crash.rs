@@ -0,0 +1,72 @@
+use std::backtrace::Backtrace;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::panic::{self, PanicHookInfo};
+use std::path::Path;
+use std::thread;
+
+/// Where crash reports are appended; /run survives until the next boot.
+pub const CRASH_LOG_PATH: &str = "/run/verdant/init-crash.log";
+
+const KMSG_PATH: &str = "/dev/kmsg";
+
+/// Backtrace lines sent to the kernel log, which is not meant for pages of text.
+const KMSG_MAX_LINES: usize = 64;
+
+/// Replace the default panic output with a crash report: the message,
+/// where it happened and a backtrace, appended to `CRASH_LOG_PATH`, copied
+/// to the kernel log, and summed up on the console. A panic in init may be
+/// the last thing printed before the recovery shell, and without this it
+/// is gone once the screen scrolls.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let message = panic_message(info);
+        let location = info.location().map(|l| l.to_string()).unwrap_or_else(|| "unknown location".into());
+        let thread = thread::current().name().unwrap_or("unnamed").to_string();
+
+        let report = format!(
+            "init panicked in thread '{}' at {}: {}\n\nBacktrace:\n{}\n",
+            thread,
+            location,
+            message,
+            Backtrace::force_capture()
+        );
+
+        eprintln!("\n*** init panicked at {}: {}", location, message);
+        match write_report(&report) {
+            Ok(()) => eprintln!("*** Crash report with backtrace written to {}", CRASH_LOG_PATH),
+            Err(e) => eprintln!("*** Cannot write crash report to {}: {}", CRASH_LOG_PATH, e),
+        }
+
+        log_to_kmsg(&report);
+    }));
+}
+
+fn panic_message(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "panic payload is not a string".into())
+}
+
+fn write_report(report: &str) -> io::Result<()> {
+    if let Some(dir) = Path::new(CRASH_LOG_PATH).parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(CRASH_LOG_PATH)?;
+    file.write_all(report.as_bytes())
+}
+
+/// One record per line at critical priority, as the kernel log expects.
+fn log_to_kmsg(report: &str) {
+    let Ok(mut kmsg) = OpenOptions::new().write(true).open(KMSG_PATH) else {
+        return;
+    };
+
+    for line in report.lines().filter(|line| !line.trim().is_empty()).take(KMSG_MAX_LINES) {
+        let _ = kmsg.write_all(format!("<2>verdant-init: {}\n", line).as_bytes());
+    }
+}
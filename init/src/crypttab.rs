@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use bloom::device::wait_for_device;
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+use crate::mount::device_timeout;
+use crate::storage::find_program;
+
+const CRYPTTAB_PATH: &str = "/etc/crypttab";
+
+/// Where opened devices show up, under the name given in crypttab.
+const MAPPER_DIR: &str = "/dev/mapper";
+
+/// Open the encrypted devices in /etc/crypttab with cryptsetup, waiting for
+/// each as mounts do, so `/dev/mapper/<name>` exists before anything is
+/// mounted. Without a key file cryptsetup asks for the passphrase on the
+/// console. One that fails is logged and skipped.
+pub fn open_crypttab(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let Ok(crypttab) = fs::read_to_string(CRYPTTAB_PATH) else {
+        return Ok(());
+    };
+
+    let entries: Vec<&str> = crypttab.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')).collect();
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let Some(cryptsetup) = find_program("cryptsetup") else {
+        let msg = format!("{} has entries, but cryptsetup is not installed", CRYPTTAB_PATH);
+        console_logger.message(LogLevel::Warn, &msg, timer.elapsed());
+        file_logger.log(LogLevel::Warn, &msg);
+        return Ok(());
+    };
+
+    // name device [keyfile [options]]
+    for line in entries {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (Some(&name), Some(&source)) = (fields.first(), fields.get(1)) else {
+            continue;
+        };
+        let key_file = fields.get(2).copied().filter(|key| !matches!(*key, "none" | "-"));
+        let options = fields.get(3).copied().unwrap_or("luks");
+        if options.split(',').any(|opt| opt == "noauto") {
+            continue;
+        }
+
+        // Already open, say after a re-run of this step
+        if Path::new(MAPPER_DIR).join(name).exists() {
+            continue;
+        }
+
+        let result = wait_for_device(source, device_timeout(options))
+            .and_then(|device| open_device(&cryptsetup, name, &device, key_file, options));
+
+        let (level, msg) = match result {
+            Ok(()) => (LogLevel::Ok, format!("Opened encrypted device {} as {}/{}", source, MAPPER_DIR, name)),
+            Err(e) if options.split(',').any(|opt| opt == "nofail") => {
+                (LogLevel::Warn, format!("Failed to open encrypted device {}: {}", source, e))
+            }
+            Err(e) => (LogLevel::Fail, format!("Failed to open encrypted device {}: {}", source, e)),
+        };
+        console_logger.message(level, &msg, timer.elapsed());
+        file_logger.log(level, &msg);
+    }
+
+    Ok(())
+}
+
+/// `cryptsetup open`, on init's console so a passphrase can be typed in.
+fn open_device(
+    cryptsetup: &str,
+    name: &str,
+    device: &str,
+    key_file: Option<&str>,
+    options: &str,
+) -> Result<(), BloomError> {
+    let mut cmd = Command::new(cryptsetup);
+    cmd.arg("open");
+
+    for opt in options.split(',') {
+        match opt {
+            "plain" => cmd.args(["--type", "plain"]),
+            "discard" => cmd.arg("--allow-discards"),
+            "readonly" | "read-only" => cmd.arg("--readonly"),
+            _ => match opt.strip_prefix("tries=") {
+                Some(tries) => cmd.args(["--tries", tries]),
+                None => continue,
+            },
+        };
+    }
+    if let Some(key_file) = key_file {
+        cmd.args(["--key-file", key_file]);
+    }
+
+    let status = cmd.arg(device).arg(name).status().map_err(BloomError::Io)?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(BloomError::Custom(format!("cryptsetup open {} {} failed: {}", device, name, status)))
+    }
+}
@@ -7,8 +7,12 @@ use bloom::errors::BloomError;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::time::ProcessTimer;
+use bloom::uevent::UeventListener;
 
-use udev::{MonitorBuilder, EventType};
+use crate::devices::start_builtin_manager;
+use crate::firmware::load_firmware;
+use crate::hardware_drivers::HotplugDrivers;
+use crate::storage;
 
 fn detect_device_manager() -> Option<&'static str> {
     let candidates = [
@@ -102,49 +106,69 @@ pub fn start_device_manager(
             }
         }
     } else {
-        let msg = "No device manager daemon found on system";
-        if let Ok(mut con_log) = console_logger.lock() {
-            con_log.message(LogLevel::Warn, msg, timer.elapsed());
-        }
+        let msg = "No device manager daemon found on system, using the built-in one";
         if let Ok(mut file_log) = file_logger.lock() {
-            file_log.log(LogLevel::Warn, msg);
+            file_log.log(LogLevel::Info, msg);
         }
-        Err(BloomError::Custom(msg.to_string()))
+        start_builtin_manager(console_logger, file_logger)
     }
 }
 
-pub fn monitor_udev_events(
+/// Follow kernel uevents for as long as init runs, logging each one,
+/// loading drivers and firmware for hardware and handing block devices that
+/// carry md RAID members to mdadm.
+pub fn monitor_uevents(
+    firmware_dirs: &[String],
     file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
 ) -> Result<(), BloomError> {
-
-    let monitor = MonitorBuilder::new()
-        .map_err(BloomError::from)?
-        .listen()
-        .map_err(BloomError::from)?;
+    let listener = UeventListener::open()?;
+    let drivers = HotplugDrivers::new(file_logger);
 
     if let Ok(mut file_log) = file_logger.lock() {
-        file_log.log(LogLevel::Info, "Started udev event monitor");
+        file_log.log(LogLevel::Info, "Started uevent monitor");
     }
 
-    for event in monitor.iter() {
-        let evtype = match event.event_type() {
-            EventType::Add => "add",
-            EventType::Remove => "remove",
-            EventType::Change => "change",
-            _ => "unknown",
-        };
-
-        let devnode = event.devnode()
-            .map(|p| p.to_string_lossy().into_owned())
-            .unwrap_or_else(|| "<no devnode>".to_string());
+    loop {
+        let event = listener.next_event()?;
+        let devnode = event.devnode();
 
-        let msg = format!("udev event: {} on device {}", evtype, devnode);
+        let msg = format!(
+            "uevent: {} on device {}",
+            event.action,
+            devnode.as_deref().unwrap_or("<no devnode>")
+        );
         if let Ok(mut file_log) = file_logger.lock() {
             file_log.log(LogLevel::Info, &msg);
         }
-    }
 
-    Ok(())
+        drivers.handle(&event);
+
+        if event.action == "add" && event.subsystem() == Some("firmware") {
+            let name = event.get("FIRMWARE").unwrap_or_default();
+            let (level, msg) = match load_firmware(&event, firmware_dirs) {
+                Ok(blob) => (LogLevel::Info, format!("Loaded firmware {} from {}", name, blob.display())),
+                Err(e) => (LogLevel::Warn, format!("Cannot load firmware {}: {}", name, e)),
+            };
+            if let Ok(mut file_log) = file_logger.lock() {
+                file_log.log(level, &msg);
+            }
+        }
+
+        // Incremental assembly: arrays start once their last member appears
+        if event.action == "add"
+            && event.subsystem() == Some("block")
+            && let Some(devnode) = &devnode
+            && storage::is_raid_member(event.sysname())
+        {
+            let msg = match storage::add_raid_member(devnode) {
+                Ok(()) => format!("Added {} to its md RAID array", devnode),
+                Err(e) => format!("Cannot add {} to its md RAID array: {}", devnode, e),
+            };
+            if let Ok(mut file_log) = file_logger.lock() {
+                file_log.log(LogLevel::Info, &msg);
+            }
+        }
+    }
 }
 
 fn is_process_running(name: &str) -> io::Result<bool> {
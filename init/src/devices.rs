@@ -0,0 +1,299 @@
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{symlink, FileTypeExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use nix::unistd::chown;
+use walkdir::WalkDir;
+
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+use bloom::uevent::{Uevent, UeventListener};
+use bloom::util::glob_match;
+
+use crate::storage::find_program;
+use crate::tmpfiles::{lookup_group, lookup_user};
+
+/// One rule per line: `pattern mode user group`, with `-` for defaults.
+/// The pattern is a shell glob matched against the node's name below /dev;
+/// the first rule that matches applies.
+pub const DEVICE_RULES_PATH: &str = "/etc/verdant/devices.conf";
+
+const BY_UUID_DIR: &str = "/dev/disk/by-uuid";
+
+/// Mode of a node the kernel gives none for and no rule covers.
+const DEFAULT_MODE: u32 = 0o600;
+
+/// Set once the built-in manager runs, so a re-exec knows to restart it.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug)]
+struct Rule {
+    pattern: String,
+    mode: Option<u32>,
+    user: Option<String>,
+    group: Option<String>,
+}
+
+/// Stand in for udevd or mdev when neither is installed: follow uevents to
+/// create and remove device nodes, set their modes and owners from
+/// `DEVICE_RULES_PATH` and keep /dev/disk/by-uuid up to date, then replay
+/// an `add` for every device already present so they are handled alike.
+pub fn start_builtin_manager(
+    console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
+    file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+    let log = |level: LogLevel, msg: &str| {
+        if let Ok(mut con_log) = console_logger.lock() {
+            con_log.message(level, msg, timer.elapsed());
+        }
+        if let Ok(mut file_log) = file_logger.lock() {
+            file_log.log(level, msg);
+        }
+    };
+
+    // Listening before coldplug so none of the replayed events are missed
+    if let Err(e) = spawn_event_handler(file_logger) {
+        log(LogLevel::Fail, &format!("Cannot listen for uevents: {}", e));
+        return Err(e);
+    }
+
+    let triggered = coldplug();
+    log(LogLevel::Ok, &format!("Built-in device manager started, {} devices coldplugged", triggered));
+    Ok(())
+}
+
+/// Take up handling uevents again after a re-exec, which the old init's
+/// thread did not survive. Devices already present were handled by it.
+pub fn resume_builtin_manager(file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>) -> Result<(), BloomError> {
+    spawn_event_handler(file_logger)?;
+    if let Ok(mut file_log) = file_logger.lock() {
+        file_log.log(LogLevel::Info, "Built-in device manager resumed");
+    }
+    Ok(())
+}
+
+/// Whether the built-in manager was started by this init.
+pub fn is_builtin_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+fn spawn_event_handler(file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>) -> Result<(), BloomError> {
+    let rules = match load_rules() {
+        Ok(rules) => rules,
+        Err(e) => {
+            if let Ok(mut file_log) = file_logger.lock() {
+                file_log.log(LogLevel::Warn, &format!("Ignoring {}: {}", DEVICE_RULES_PATH, e));
+            }
+            Vec::new()
+        }
+    };
+
+    let listener = UeventListener::open()?;
+    RUNNING.store(true, Ordering::Relaxed);
+
+    let events_logger = Arc::clone(file_logger);
+    thread::spawn(move || {
+        loop {
+            let event = match listener.next_event() {
+                Ok(event) => event,
+                Err(e) => {
+                    if let Ok(mut file_log) = events_logger.lock() {
+                        file_log.log(LogLevel::Fail, &format!("Device manager stopped: {}", e));
+                    }
+                    break;
+                }
+            };
+
+            if let Err(e) = handle_event(&event, &rules)
+                && let Ok(mut file_log) = events_logger.lock()
+            {
+                let name = event.get("DEVNAME").unwrap_or(event.sysname());
+                file_log.log(LogLevel::Warn, &format!("Cannot {} device {}: {}", event.action, name, e));
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Have the kernel send `add` again for every device in /sys. Returns how
+/// many were triggered.
+fn coldplug() -> usize {
+    WalkDir::new("/sys/devices")
+        .follow_links(false)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name() == "uevent" && entry.file_type().is_file())
+        .filter(|entry| {
+            OpenOptions::new()
+                .write(true)
+                .open(entry.path())
+                .and_then(|mut file| file.write_all(b"add"))
+                .is_ok()
+        })
+        .count()
+}
+
+fn handle_event(event: &Uevent, rules: &[Rule]) -> Result<(), BloomError> {
+    let Some(name) = event.get("DEVNAME") else {
+        return Ok(());
+    };
+    let node = format!("/dev/{}", name);
+    let is_block = event.subsystem() == Some("block");
+
+    match event.action.as_str() {
+        "add" | "change" => {
+            create_node(event, name, &node, is_block, rules)?;
+            if is_block {
+                // A new filesystem on the device changes its UUID
+                remove_links(name);
+                link_by_uuid(name, &node)?;
+            }
+        }
+        "remove" => {
+            if is_block {
+                remove_links(name);
+            }
+            // devtmpfs usually beat us to it
+            let is_node = fs::symlink_metadata(&node)
+                .is_ok_and(|meta| meta.file_type().is_block_device() || meta.file_type().is_char_device());
+            if is_node {
+                fs::remove_file(&node).map_err(BloomError::Io)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Make the node unless devtmpfs already has, then apply the first matching
+/// rule to it.
+fn create_node(event: &Uevent, name: &str, node: &str, is_block: bool, rules: &[Rule]) -> Result<(), BloomError> {
+    let rule = rules.iter().find(|rule| glob_match(&rule.pattern, name));
+    let mode = rule.and_then(|rule| rule.mode);
+
+    if fs::symlink_metadata(node).is_err() {
+        let (Some(major), Some(minor)) = (
+            event.get("MAJOR").and_then(|m| m.parse().ok()),
+            event.get("MINOR").and_then(|m| m.parse().ok()),
+        ) else {
+            return Ok(());
+        };
+
+        if let Some(parent) = Path::new(node).parent() {
+            fs::create_dir_all(parent).map_err(BloomError::Io)?;
+        }
+
+        let kind = if is_block { SFlag::S_IFBLK } else { SFlag::S_IFCHR };
+        let default = event.get("DEVMODE").and_then(|m| u32::from_str_radix(m, 8).ok()).unwrap_or(DEFAULT_MODE);
+        let perm = Mode::from_bits_truncate(mode.unwrap_or(default));
+        mknod(node, kind, perm, makedev(major, minor)).map_err(BloomError::Nix)?;
+    }
+
+    let Some(rule) = rule else {
+        return Ok(());
+    };
+
+    if let Some(mode) = mode {
+        fs::set_permissions(node, Permissions::from_mode(mode)).map_err(BloomError::Io)?;
+    }
+
+    let uid = rule.user.as_deref().map(lookup_user).transpose()?;
+    let gid = rule.group.as_deref().map(lookup_group).transpose()?;
+    if uid.is_some() || gid.is_some() {
+        chown(node, uid, gid).map_err(BloomError::Nix)?;
+    }
+    Ok(())
+}
+
+/// Point /dev/disk/by-uuid/<uuid> at the device, if it holds a filesystem.
+fn link_by_uuid(name: &str, node: &str) -> Result<(), BloomError> {
+    let Some(uuid) = probe_uuid(node) else {
+        return Ok(());
+    };
+
+    fs::create_dir_all(BY_UUID_DIR).map_err(BloomError::Io)?;
+
+    // Relative, as udev makes them, so they hold up inside a chroot
+    let link = Path::new(BY_UUID_DIR).join(&uuid);
+    let _ = fs::remove_file(&link);
+    symlink(format!("../../{}", name), &link).map_err(BloomError::Io)
+}
+
+/// Remove the by-uuid links pointing at `name`.
+fn remove_links(name: &str) {
+    let target = format!("../../{}", name);
+
+    for entry in fs::read_dir(BY_UUID_DIR).into_iter().flatten().flatten() {
+        if fs::read_link(entry.path()).is_ok_and(|link| link == Path::new(&target)) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// The filesystem UUID blkid finds on the device. Its plain output, unlike
+/// `-o export`, is the same for util-linux and busybox:
+/// `/dev/sda1: UUID="..." TYPE="ext4"`.
+fn probe_uuid(node: &str) -> Option<String> {
+    let blkid = find_program("blkid")?;
+    let output = Command::new(blkid).arg(node).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (_, fields) = stdout.split_once(": ")?;
+    fields
+        .split_whitespace()
+        .filter_map(|field| field.split_once('='))
+        .find(|(key, _)| *key == "UUID")
+        .map(|(_, value)| value.trim_matches('"').to_string())
+        .filter(|uuid| !uuid.is_empty() && !uuid.contains('/'))
+}
+
+/// A missing file means no rules, not an error.
+fn load_rules() -> Result<Vec<Rule>, BloomError> {
+    let contents = match fs::read_to_string(DEVICE_RULES_PATH) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(BloomError::Io(e)),
+    };
+
+    let field = |f: Option<&str>| f.filter(|f| *f != "-").map(str::to_string);
+    let mut rules = Vec::new();
+
+    for (line_no, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next().unwrap_or_default().to_string();
+        let mode = field(fields.next())
+            .map(|m| {
+                u32::from_str_radix(&m, 8)
+                    .map_err(|_| BloomError::Parse(format!("line {}: invalid mode '{}'", line_no + 1, m)))
+            })
+            .transpose()?;
+
+        rules.push(Rule {
+            pattern,
+            mode,
+            user: field(fields.next()),
+            group: field(fields.next()),
+        });
+    }
+
+    Ok(rules)
+}
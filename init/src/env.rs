@@ -1,6 +1,7 @@
 use std::env;
 
 use bloom::errors::BloomError;
+use bloom::locale::{read_locale_conf, LOCALE_CONF_PATH, LOCALE_VARS};
 use bloom::status::LogLevel;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::time::ProcessTimer;
@@ -28,6 +29,40 @@ pub fn set_basic_env_vars(
     Ok(())
 }
 
+/// Export the locale from /etc/locale.conf into init's environment, which
+/// verdantd and so every service inherit. Without the file the C locale is
+/// left in place.
+pub fn set_locale_env_vars(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let vars = match read_locale_conf() {
+        Ok(vars) => vars,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            let msg = format!("Cannot read {}: {}", LOCALE_CONF_PATH, e);
+            file_logger.log(LogLevel::Warn, &msg);
+            return Err(BloomError::Io(e));
+        }
+    };
+
+    unsafe {
+        for name in LOCALE_VARS {
+            env::remove_var(name);
+        }
+        for (key, value) in &vars {
+            env::set_var(key, value);
+        }
+    }
+
+    let lang = vars.iter().find(|(key, _)| key == "LANG").map_or("C", |(_, value)| value.as_str());
+    log_message(&format!("Locale set to {}", lang), console_logger, file_logger, &timer);
+
+    Ok(())
+}
+
 /// Helper function to log to console and file with Info level.
 fn log_message(
     message: &str,
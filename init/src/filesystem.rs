@@ -6,12 +6,13 @@ use std::sync::{Arc, Mutex};
 use nix::errno::Errno;
 use nix::mount::{mount, MsFlags};
 
+use bloom::efi;
 use bloom::errors::BloomError;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::time::ProcessTimer;
 
-/// Mounts standard Linux virtual filesystems: /proc, /sys, /dev, /run
+/// Mounts standard Linux virtual filesystems: /proc, /sys, /sys/fs/cgroup, efivarfs, /dev, /run
 pub fn mount_virtual_filesystems(
     console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
@@ -24,6 +25,12 @@ pub fn mount_virtual_filesystems(
 
     mount_fs(Some("proc"), "/proc", Some("proc"), MsFlags::empty(), None, "proc", &mut *con_log, &mut *file_log, &timer)?;
     mount_fs(Some("sysfs"), "/sys", Some("sysfs"), MsFlags::empty(), None, "sysfs", &mut *con_log, &mut *file_log, &timer)?;
+    // verdantd places each service in its own cgroup; without cgroup2 it falls back to PID tracking
+    let _ = mount_fs(Some("cgroup2"), "/sys/fs/cgroup", Some("cgroup2"), MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC, None, "cgroup2", &mut *con_log, &mut *file_log, &timer);
+    // Needed to reboot into firmware setup; only there on EFI systems
+    if efi::is_efi() {
+        let _ = mount_fs(Some("efivarfs"), efi::EFIVARS_DIR, Some("efivarfs"), MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC, None, "efivarfs", &mut *con_log, &mut *file_log, &timer);
+    }
     mount_fs(Some("devtmpfs"), "/dev", Some("devtmpfs"), MsFlags::empty(), None, "devtmpfs", &mut *con_log, &mut *file_log, &timer)?;
     mount_fs(Some("tmpfs"), "/run", Some("tmpfs"), MsFlags::empty(), Some("mode=755"), "tmpfs", &mut *con_log, &mut *file_log, &timer)?;
 
@@ -84,8 +91,8 @@ pub fn mount_fs(
         return Ok(());
     }
 
-    // Pass mount data only for certain filesystem types (tmpfs, nfs, cifs, fuse)
-    let supported_data_fs = ["tmpfs", "nfs", "cifs", "fuse"];
+    // Pass mount data only for certain filesystem types (tmpfs, nfs, cifs, fuse, btrfs for subvol=)
+    let supported_data_fs = ["tmpfs", "nfs", "cifs", "fuse", "btrfs"];
     let mount_data = match fstype {
         Some(fs) if supported_data_fs.contains(&fs) => data,
         _ => None,
@@ -106,7 +113,7 @@ pub fn mount_fs(
 }
 
 /// Check if the target is mounted by parsing `/proc/self/mountinfo`
-fn is_mounted(target: &str) -> Result<bool, BloomError> {
+pub(crate) fn is_mounted(target: &str) -> Result<bool, BloomError> {
     let target_canonical = fs::canonicalize(target).unwrap_or_else(|_| std::path::PathBuf::from(target));
 
     let file = std::fs::File::open("/proc/self/mountinfo")?;
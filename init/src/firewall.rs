@@ -0,0 +1,156 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+const FIREWALL_DIR: &str = "/etc/verdant/firewall";
+
+/// nftables ruleset, loaded atomically with `nft -f`
+const NFT_RULESET: &str = "ruleset.nft";
+/// iptables-save style dumps, loaded with `iptables-restore` / `ip6tables-restore`
+const IPTABLES_V4_RULES: &str = "rules.v4";
+const IPTABLES_V6_RULES: &str = "rules.v6";
+
+const NFT_CANDIDATES: &[&str] = &["/usr/sbin/nft", "/sbin/nft", "/usr/bin/nft", "/bin/nft"];
+const IPTABLES_RESTORE_CANDIDATES: &[&str] = &[
+    "/usr/sbin/iptables-restore",
+    "/sbin/iptables-restore",
+    "/usr/bin/iptables-restore",
+];
+const IP6TABLES_RESTORE_CANDIDATES: &[&str] = &[
+    "/usr/sbin/ip6tables-restore",
+    "/sbin/ip6tables-restore",
+    "/usr/bin/ip6tables-restore",
+];
+
+/// Loads the firewall ruleset from /etc/verdant/firewall.
+///
+/// Runs before any interface is brought up or any service is started, so the
+/// machine is never reachable without its packet filter in place.
+/// An nftables ruleset takes precedence over iptables dumps when both exist.
+pub fn load_firewall_ruleset(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+    let dir = Path::new(FIREWALL_DIR);
+
+    if !dir.is_dir() {
+        file_logger.log(LogLevel::Info, "No firewall ruleset configured, skipping");
+        return Ok(());
+    }
+
+    let nft_rules = dir.join(NFT_RULESET);
+    if nft_rules.is_file() {
+        return restore_ruleset(
+            "nftables",
+            NFT_CANDIDATES,
+            &["-f"],
+            &nft_rules,
+            console_logger,
+            file_logger,
+            &timer,
+        );
+    }
+
+    let v4_rules = dir.join(IPTABLES_V4_RULES);
+    let v6_rules = dir.join(IPTABLES_V6_RULES);
+    let mut loaded_any = false;
+    let mut result = Ok(());
+
+    if v4_rules.is_file() {
+        loaded_any = true;
+        result = restore_ruleset(
+            "iptables",
+            IPTABLES_RESTORE_CANDIDATES,
+            &[],
+            &v4_rules,
+            console_logger,
+            file_logger,
+            &timer,
+        );
+    }
+
+    if v6_rules.is_file() {
+        loaded_any = true;
+        let v6_result = restore_ruleset(
+            "ip6tables",
+            IP6TABLES_RESTORE_CANDIDATES,
+            &[],
+            &v6_rules,
+            console_logger,
+            file_logger,
+            &timer,
+        );
+        if result.is_ok() {
+            result = v6_result;
+        }
+    }
+
+    if !loaded_any {
+        log_message(
+            console_logger,
+            file_logger,
+            &timer,
+            LogLevel::Warn,
+            &format!("{} exists but contains no known ruleset", FIREWALL_DIR),
+        );
+    }
+
+    result
+}
+
+/// Feed a ruleset file to its restore tool and report the outcome.
+fn restore_ruleset(
+    kind: &str,
+    candidates: &[&'static str],
+    args: &[&str],
+    rules: &Path,
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+    timer: &ProcessTimer,
+) -> Result<(), BloomError> {
+    let Some(binary) = find_binary(candidates) else {
+        let msg = format!("Cannot load {} ruleset: no restore tool found", kind);
+        log_message(console_logger, file_logger, timer, LogLevel::Fail, &msg);
+        return Err(BloomError::NotFound);
+    };
+
+    let output = Command::new(binary)
+        .args(args)
+        .arg(rules)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(BloomError::Io)?;
+
+    if output.status.success() {
+        let msg = format!("Loaded {} ruleset from {}", kind, rules.display());
+        log_message(console_logger, file_logger, timer, LogLevel::Ok, &msg);
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let msg = format!("Failed to load {} ruleset ({})", kind, output.status);
+        log_message(console_logger, file_logger, timer, LogLevel::Fail, &msg);
+        file_logger.log(LogLevel::Fail, stderr.trim());
+        Err(BloomError::Custom(msg))
+    }
+}
+
+fn find_binary(candidates: &[&'static str]) -> Option<&'static str> {
+    candidates.iter().copied().find(|path| Path::new(path).exists())
+}
+
+fn log_message(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+    timer: &ProcessTimer,
+    level: LogLevel,
+    msg: &str,
+) {
+    let elapsed = timer.elapsed();
+    console_logger.message(level, msg, elapsed);
+    file_logger.log(level, msg);
+}
@@ -0,0 +1,69 @@
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use bloom::errors::BloomError;
+use bloom::uevent::Uevent;
+
+/// Search path the kernel was given with `firmware_class.path=`.
+const KERNEL_PATH_PARAM: &str = "/sys/module/firmware_class/parameters/path";
+
+/// Answer a firmware request the kernel could not serve by loading directly,
+/// through its sysfs fallback: the blob is looked up in the kernel's own
+/// custom path and then `dirs`, each with a subdirectory for the running
+/// kernel tried first, and written to the request's `data` file. A blob that
+/// cannot be found aborts the request, so the driver does not wait out its
+/// timeout.
+pub fn load_firmware(event: &Uevent, dirs: &[String]) -> Result<PathBuf, BloomError> {
+    let name = event
+        .get("FIRMWARE")
+        .ok_or_else(|| BloomError::Parse(format!("No FIRMWARE in request {}", event.devpath)))?;
+    let request = Path::new("/sys").join(event.devpath.trim_start_matches('/'));
+
+    let Some(blob) = find_firmware(name, dirs) else {
+        let _ = write_loading(&request, "-1");
+        return Err(BloomError::NotFound);
+    };
+
+    let result = (|| {
+        let data = fs::read(&blob)?;
+        write_loading(&request, "1")?;
+        OpenOptions::new().write(true).open(request.join("data"))?.write_all(&data)?;
+        write_loading(&request, "0")
+    })();
+
+    match result {
+        Ok(()) => Ok(blob),
+        Err(e) => {
+            let _ = write_loading(&request, "-1");
+            Err(BloomError::Io(e))
+        }
+    }
+}
+
+fn find_firmware(name: &str, dirs: &[String]) -> Option<PathBuf> {
+    // A request naming a path outside the firmware directories is refused
+    if name.split('/').any(|part| part == "..") {
+        return None;
+    }
+
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    let release = release.trim();
+    let custom = fs::read_to_string(KERNEL_PATH_PARAM).unwrap_or_default();
+
+    let custom = custom.trim();
+    let dirs = (!custom.is_empty()).then_some(custom).into_iter().chain(dirs.iter().map(String::as_str));
+
+    dirs.flat_map(|dir| {
+        let dir = Path::new(dir);
+        [(!release.is_empty()).then(|| dir.join(release)), Some(dir.to_path_buf())]
+    })
+    .flatten()
+    .map(|dir| dir.join(name))
+    .find(|path| path.is_file())
+}
+
+/// `1` starts a load, `0` finishes it and `-1` aborts it.
+fn write_loading(request: &Path, value: &str) -> io::Result<()> {
+    OpenOptions::new().write(true).open(request.join("loading"))?.write_all(value.as_bytes())
+}
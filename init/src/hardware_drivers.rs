@@ -1,19 +1,21 @@
 use std::collections::BTreeSet;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
+use std::path::Path;
 use std::process::{Command, Stdio};
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
 
 use threadpool::ThreadPool;
-use wait_timeout::ChildExt;
 use walkdir::WalkDir;
 
 use bloom::errors::BloomError;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::time::ProcessTimer;
+use bloom::uevent::Uevent;
+
+use crate::modules::{ModuleLoader, LOAD_THREADS};
 
 pub fn load_hardware_drivers(
     console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
@@ -66,49 +68,27 @@ pub fn load_hardware_drivers(
         return Ok(());
     }
 
-    // Parallel modprobe execution
-    let pool = ThreadPool::new(12);
-    let timeout = Duration::from_secs(2);
+    // Parallel module loading
+    let pool = ThreadPool::new(LOAD_THREADS);
     let loaded = Arc::new(AtomicUsize::new(0));
     let failed = Arc::new(AtomicUsize::new(0));
     let file_logger = Arc::clone(file_logger);
+    let loader = Arc::new(ModuleLoader::load());
 
     for alias in aliases {
         let loaded = Arc::clone(&loaded);
         let failed = Arc::clone(&failed);
         let file_logger = Arc::clone(&file_logger);
-        let alias = alias.clone();
-
-        pool.execute(move || {
-            let mut cmd = Command::new("/sbin/modprobe");
-            cmd.arg("-b").arg(&alias);
-            cmd.stdout(Stdio::null()).stderr(Stdio::null());
-
-            match cmd.spawn() {
-                Ok(mut child) => match child.wait_timeout(timeout).unwrap_or(None) {
-                    Some(status) if status.success() => {
-                        loaded.fetch_add(1, Ordering::Relaxed);
-                    }
-                    _ => {
-                        let _ = child.kill();
-                        let _ = child.wait();
-                        failed.fetch_add(1, Ordering::Relaxed);
-                        if let Ok(mut log) = file_logger.lock() {
-                            let _ = log.log(
-                                LogLevel::Info,
-                                &format!("modprobe timed out or failed for alias: {}", alias),
-                            );
-                        }
-                    }
-                },
-                Err(e) => {
-                    failed.fetch_add(1, Ordering::Relaxed);
-                    if let Ok(mut log) = file_logger.lock() {
-                        let _ = log.log(
-                            LogLevel::Info,
-                            &format!("Failed to spawn modprobe for {}: {}", alias, e),
-                        );
-                    }
+        let loader = Arc::clone(&loader);
+
+        pool.execute(move || match loader.load_alias(&alias) {
+            Ok(()) => {
+                loaded.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                failed.fetch_add(1, Ordering::Relaxed);
+                if let Ok(mut log) = file_logger.lock() {
+                    log.log(LogLevel::Info, &e.to_string());
                 }
             }
         });
@@ -135,3 +115,48 @@ pub fn load_hardware_drivers(
     Ok(())
 }
 
+
+/// Loads drivers for devices plugged in after boot, as their uevents come
+/// in, on the same kind of pool as `load_hardware_drivers`.
+pub struct HotplugDrivers {
+    pool: ThreadPool,
+    loader: Arc<ModuleLoader>,
+    file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>>,
+}
+
+impl HotplugDrivers {
+    pub fn new(file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>) -> Self {
+        Self {
+            pool: ThreadPool::new(LOAD_THREADS),
+            loader: Arc::new(ModuleLoader::load()),
+            file_logger: Arc::clone(file_logger),
+        }
+    }
+
+    /// Load the driver for an `add` event's MODALIAS, unless the device
+    /// already has one bound.
+    pub fn handle(&self, event: &Uevent) {
+        if event.action != "add" {
+            return;
+        }
+        let Some(alias) = event.get("MODALIAS").filter(|alias| !alias.is_empty()) else {
+            return;
+        };
+        if Path::new("/sys").join(event.devpath.trim_start_matches('/')).join("driver").exists() {
+            return;
+        }
+
+        let alias = alias.to_string();
+        let loader = Arc::clone(&self.loader);
+        let file_logger = Arc::clone(&self.file_logger);
+        self.pool.execute(move || {
+            let msg = match loader.load_alias(&alias) {
+                Ok(()) => format!("Loaded driver for hotplugged device: {}", alias),
+                Err(e) => e.to_string(),
+            };
+            if let Ok(mut log) = file_logger.lock() {
+                log.log(LogLevel::Info, &msg);
+            }
+        });
+    }
+}
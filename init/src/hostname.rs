@@ -0,0 +1,223 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use bloom::cmdline::read_cmdline;
+use bloom::config::InitConfig;
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+/// The static hostname, which the admin sets and nothing else overrides.
+const HOSTNAME_PATH: &str = "/etc/hostname";
+
+/// Used when nothing names the machine, until DHCP may offer a name.
+const FALLBACK_HOSTNAME: &str = "localhost";
+
+/// Longest hostname the kernel accepts (HOST_NAME_MAX).
+const HOSTNAME_MAX: usize = 64;
+
+/// Where the hostname set at boot came from. The first two are static; the
+/// others are transient and give way to one a DHCP client hands out later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HostnameSource {
+    File,
+    Config,
+    Cmdline,
+    Fallback,
+}
+
+impl HostnameSource {
+    fn describe(self) -> &'static str {
+        match self {
+            HostnameSource::File => HOSTNAME_PATH,
+            HostnameSource::Config => "config",
+            HostnameSource::Cmdline => "kernel command line",
+            HostnameSource::Fallback => "fallback",
+        }
+    }
+}
+
+/// Set the hostname from the first of /etc/hostname, `hostname` in the
+/// `[init]` config and `hostname=` on the kernel command line that gives a
+/// valid one, or `localhost` when none does.
+pub fn set_hostname(
+    config: &InitConfig,
+    console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
+    file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+    let log = |level: LogLevel, msg: &str| {
+        if let Ok(mut con_log) = console_logger.lock() {
+            con_log.message(level, msg, timer.elapsed());
+        }
+        if let Ok(mut file_log) = file_logger.lock() {
+            file_log.log(level, msg);
+        }
+    };
+
+    let (hostname, source) =
+        resolve_hostname(config, read_static_hostname(), &read_cmdline(), |msg| log(LogLevel::Warn, msg));
+
+    match apply_hostname(&hostname) {
+        Ok(()) => {
+            log(LogLevel::Ok, &format!("Hostname set to '{}' ({})", hostname, source.describe()));
+            Ok(())
+        }
+        Err(e) => {
+            log(LogLevel::Fail, &format!("Failed to set hostname: {}", e));
+            Err(e)
+        }
+    }
+}
+
+/// Pick the hostname from what /etc/hostname held and the parsed kernel
+/// command line, which needs /proc mounted.
+fn resolve_hostname(
+    config: &InitConfig,
+    static_hostname: io::Result<Option<String>>,
+    cmdline: &[(String, Option<String>)],
+    mut warn: impl FnMut(&str),
+) -> (String, HostnameSource) {
+    let mut candidates: Vec<(String, HostnameSource)> = Vec::new();
+
+    match static_hostname {
+        Ok(Some(name)) => candidates.push((name, HostnameSource::File)),
+        Ok(None) => {}
+        Err(e) => warn(&format!("Cannot read {}: {}", HOSTNAME_PATH, e)),
+    }
+    if let Some(name) = &config.hostname {
+        candidates.push((name.trim().to_string(), HostnameSource::Config));
+    }
+    if let Some((_, Some(name))) = cmdline.iter().find(|(key, _)| key == "hostname") {
+        candidates.push((name.clone(), HostnameSource::Cmdline));
+    }
+
+    for (name, source) in candidates {
+        if is_valid_hostname(&name) {
+            return (name, source);
+        }
+        warn(&format!("Ignoring invalid hostname '{}' from {}", name, source.describe()));
+    }
+
+    (FALLBACK_HOSTNAME.to_string(), HostnameSource::Fallback)
+}
+
+/// The hostname in /etc/hostname, ignoring comments and blank lines.
+fn read_static_hostname() -> io::Result<Option<String>> {
+    match fs::read_to_string(HOSTNAME_PATH) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether the admin has fixed the hostname, in /etc/hostname or config.
+pub fn has_static_hostname(config: &InitConfig) -> bool {
+    matches!(read_static_hostname(), Ok(Some(_))) || config.hostname.as_deref().is_some_and(|name| !name.trim().is_empty())
+}
+
+/// Make `name` the static hostname: write it to /etc/hostname, so it holds
+/// across reboots, and apply it now.
+pub fn set_static_hostname(name: &str) -> Result<(), BloomError> {
+    check_hostname(name)?;
+    fs::write(HOSTNAME_PATH, format!("{}\n", name))?;
+    apply_hostname(name)
+}
+
+/// Apply a hostname handed out at runtime, such as by a DHCP client. A
+/// static hostname always wins, so `Ok(false)` is returned when there is one
+/// and the name is left alone.
+pub fn set_transient_hostname(name: &str, config: &InitConfig) -> Result<bool, BloomError> {
+    check_hostname(name)?;
+    if has_static_hostname(config) {
+        return Ok(false);
+    }
+    apply_hostname(name).map(|()| true)
+}
+
+/// Labels of letters, digits and hyphens separated by dots, as hostname(7)
+/// describes, no longer than the kernel allows.
+fn is_valid_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= HOSTNAME_MAX
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+fn check_hostname(name: &str) -> Result<(), BloomError> {
+    if is_valid_hostname(name) {
+        Ok(())
+    } else {
+        Err(BloomError::Parse(format!("Invalid hostname '{}'", name)))
+    }
+}
+
+fn apply_hostname(name: &str) -> Result<(), BloomError> {
+    let c_name = CString::new(name).map_err(|_| BloomError::Parse("Hostname contains invalid null byte".into()))?;
+
+    if unsafe { libc::sethostname(c_name.as_ptr(), name.len()) } != 0 {
+        return Err(BloomError::Io(io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bloom::cmdline::parse_cmdline;
+
+    #[test]
+    fn valid_hostnames() {
+        assert!(is_valid_hostname("verdant"));
+        assert!(is_valid_hostname("web-01.example.org"));
+        assert!(is_valid_hostname(&"a".repeat(HOSTNAME_MAX)));
+    }
+
+    #[test]
+    fn invalid_hostnames() {
+        assert!(!is_valid_hostname(""));
+        assert!(!is_valid_hostname(&"a".repeat(HOSTNAME_MAX + 1)));
+        assert!(!is_valid_hostname("-leading"));
+        assert!(!is_valid_hostname("trailing-"));
+        assert!(!is_valid_hostname("double..dot"));
+        assert!(!is_valid_hostname("under_score"));
+        assert!(!is_valid_hostname("sp ace"));
+    }
+
+    #[test]
+    fn falls_back_to_cmdline_hostname() {
+        let cmdline = parse_cmdline("root=/dev/sda1 quiet hostname=kiosk-3");
+        let config = InitConfig::default();
+        let mut warnings = Vec::new();
+
+        let resolved = resolve_hostname(&config, Ok(None), &cmdline, |msg| warnings.push(msg.to_string()));
+        assert_eq!(resolved, ("kiosk-3".to_string(), HostnameSource::Cmdline));
+        assert!(warnings.is_empty());
+
+        let config = InitConfig { hostname: Some("bad_name".into()), ..InitConfig::default() };
+        let resolved = resolve_hostname(&config, Ok(None), &cmdline, |msg| warnings.push(msg.to_string()));
+        assert_eq!(resolved.1, HostnameSource::Cmdline);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn static_hostname_wins_over_cmdline() {
+        let cmdline = parse_cmdline("hostname=kiosk-3");
+        let resolved = resolve_hostname(&InitConfig::default(), Ok(Some("verdant".into())), &cmdline, |_| {});
+        assert_eq!(resolved, ("verdant".to_string(), HostnameSource::File));
+
+        let resolved = resolve_hostname(&InitConfig::default(), Ok(None), &[], |_| {});
+        assert_eq!(resolved, (FALLBACK_HOSTNAME.to_string(), HostnameSource::Fallback));
+    }
+}
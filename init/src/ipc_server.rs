@@ -1,30 +1,47 @@
 use std::fs;
 use std::path::Path;
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
-use std::io::{BufRead, Write};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
-};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-use bloom::ipc::{IpcRequest, IpcResponse, IpcCommand, serialize_response, INIT_SOCKET_PATH};
+use bloom::errors::BloomError;
+use bloom::ipc::{Framing, IpcRequest, IpcResponse, IpcCommand, PowerAction, serialize_reply, INIT_SOCKET_PATH};
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use serde_json;
 
+use crate::actions::{self, KexecImage};
+use crate::hostname;
+use crate::kernel;
+use crate::mount;
+use crate::reexec;
+use crate::state::{BootPhase, InitState};
+
+/// How long a client may take to send its request or read the response.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub fn run_ipc_server(
-    shutdown_flag: Arc<AtomicBool>,
-    reboot_flag: Arc<AtomicBool>,
+    pending: Arc<Mutex<Option<PowerAction>>>,
     console_logger: Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>>,
+    init_state: Arc<Mutex<InitState>>,
     main_thread: std::thread::Thread,
+    inherited: Option<RawFd>,
 ) -> std::io::Result<()> {
-    if Path::new(INIT_SOCKET_PATH).exists() {
-        fs::remove_file(INIT_SOCKET_PATH)?;
-    }
-
-    let listener = UnixListener::bind(INIT_SOCKET_PATH)?;
+    // After a re-exec the old init's listener is still open and bound
+    let listener = match inherited {
+        Some(fd) => unsafe { UnixListener::from_raw_fd(fd) },
+        None => {
+            if Path::new(INIT_SOCKET_PATH).exists() {
+                fs::remove_file(INIT_SOCKET_PATH)?;
+            }
+            UnixListener::bind(INIT_SOCKET_PATH)?
+        }
+    };
+    reexec::register_listener(listener.as_raw_fd());
 
     log_message(&console_logger, &file_logger, LogLevel::Info, &format!(
         "Init IPC server listening on {}",
@@ -34,22 +51,33 @@ pub fn run_ipc_server(
     for stream_result in listener.incoming() {
         match stream_result {
             Ok(mut stream) => {
-                let shutdown_flag = Arc::clone(&shutdown_flag);
-                let reboot_flag = Arc::clone(&reboot_flag);
+                let pending = Arc::clone(&pending);
                 let console_logger = Arc::clone(&console_logger);
                 let file_logger = Arc::clone(&file_logger);
+                let init_state = Arc::clone(&init_state);
                 let main_thread = main_thread.clone();
 
-                if let Err(e) = handle_client(
-                    &mut stream,
-                    shutdown_flag,
-                    reboot_flag,
-                    console_logger,
-                    file_logger,
-                    main_thread,
-                ) {
-                    eprintln!("Error handling IPC client: {}", e);
-                }
+                // Each client gets its own thread so a stuck one cannot hold
+                // up shutdown requests from everyone else
+                thread::spawn(move || {
+                    let result = stream
+                        .set_read_timeout(Some(CLIENT_TIMEOUT))
+                        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+                        .and_then(|_| {
+                            handle_client(
+                                &mut stream,
+                                pending,
+                                console_logger,
+                                file_logger,
+                                init_state,
+                                main_thread,
+                            )
+                        });
+
+                    if let Err(e) = result {
+                        eprintln!("Error handling IPC client: {}", e);
+                    }
+                });
             }
             Err(e) => eprintln!("Failed to accept IPC connection: {}", e),
         }
@@ -60,15 +88,16 @@ pub fn run_ipc_server(
 
 fn handle_client(
     stream: &mut UnixStream,
-    shutdown_flag: Arc<AtomicBool>,
-    reboot_flag: Arc<AtomicBool>,
+    pending: Arc<Mutex<Option<PowerAction>>>,
     console_logger: Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>>,
+    init_state: Arc<Mutex<InitState>>,
     main_thread: std::thread::Thread,
 ) -> std::io::Result<()> {
-    let mut buf = Vec::new();
+    // Old clients send newline-delimited JSON and get answered the same way
     let mut reader = std::io::BufReader::new(stream.try_clone()?);
-    reader.read_until(b'\n', &mut buf)?;
+    let framing = Framing::detect(&mut reader)?;
+    let buf = framing.read(&mut reader)?.unwrap_or_default();
 
     let request = match serde_json::from_slice::<IpcRequest>(&buf) {
         Ok(req) => req,
@@ -78,43 +107,98 @@ fn handle_client(
                 message: "Invalid IPC request".into(),
                 data: None,
             };
-            let _ = stream.write_all(&serialize_response(&resp));
+            let _ = stream.write_all(&serialize_reply(&resp, framing, 0));
             return Ok(());
         }
     };
 
     match request.command {
-        IpcCommand::Shutdown => {
-            // Respond immediately
+        // Counting down is verdantd's job; it notifies init when time is up
+        IpcCommand::Shutdown { delay_secs, .. }
+        | IpcCommand::Reboot { delay_secs, .. }
+        | IpcCommand::Halt { delay_secs, .. }
+            if delay_secs > 0 =>
+        {
             let resp = IpcResponse {
-                success: true,
-                message: "Shutdown scheduled".into(),
+                success: false,
+                message: "Scheduled shutdowns are handled by verdantd".into(),
                 data: None,
             };
-            stream.write_all(&serialize_response(&resp))?;
-
-            // Delay flag set/unpark to avoid blocking client
-            let shutdown_flag_clone = Arc::clone(&shutdown_flag);
-            thread::spawn(move || {
-                shutdown_flag_clone.store(true, Ordering::SeqCst);
-                main_thread.unpark();
-            });
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
         }
-        IpcCommand::Reboot => {
-            // Respond immediately
+        IpcCommand::Shutdown { .. } | IpcCommand::Reboot { .. } | IpcCommand::Halt { .. } => {
+            let action = PowerAction::from_command(&request.command).map_or(PowerAction::PowerOff, |(a, ..)| a);
+
+            // Respond before the main loop starts taking things down
             let resp = IpcResponse {
                 success: true,
-                message: "Reboot scheduled".into(),
+                message: format!("Proceeding with {}", action.as_str()),
                 data: None,
             };
-            stream.write_all(&serialize_response(&resp))?;
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
 
-            // Delay flag set/unpark to avoid blocking client
-            let reboot_flag_clone = Arc::clone(&reboot_flag);
+            actions::request(&pending, action);
             main_thread.unpark();
-            thread::spawn(move || {
-                reboot_flag_clone.store(true, Ordering::SeqCst);
-            });
+        }
+        IpcCommand::KexecLoad { ref kernel, ref initrd, ref cmdline }
+        | IpcCommand::Kexec { ref kernel, ref initrd, ref cmdline } => {
+            let go = matches!(request.command, IpcCommand::Kexec { .. });
+            let image = KexecImage {
+                kernel: kernel.clone(),
+                initrd: initrd.clone(),
+                cmdline: cmdline.clone(),
+            };
+
+            let resp = match actions::load_kexec(&image) {
+                Ok(()) => IpcResponse {
+                    success: true,
+                    message: if go { "Kexec scheduled" } else { "Kexec kernel loaded" }.into(),
+                    data: None,
+                },
+                Err(e) => {
+                    log_message(&console_logger, &file_logger, LogLevel::Fail, &format!("Cannot load kexec kernel: {}", e));
+                    IpcResponse {
+                        success: false,
+                        message: format!("Cannot load kexec kernel: {}", e),
+                        data: None,
+                    }
+                }
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+
+            if go && resp.success {
+                actions::request(&pending, PowerAction::Kexec);
+                main_thread.unpark();
+            }
+        }
+        IpcCommand::Suspend | IpcCommand::Hibernate => {
+            let hibernate = matches!(request.command, IpcCommand::Hibernate);
+            let what = if hibernate { "hibernate" } else { "suspend" };
+            log_message(&console_logger, &file_logger, LogLevel::Info, &format!("Entering {}", what));
+
+            // Blocks until the machine wakes up
+            let result = if hibernate { actions::hibernate() } else { actions::suspend() };
+
+            let resp = match result {
+                Ok(()) => {
+                    log_message(&console_logger, &file_logger, LogLevel::Ok, &format!("Resumed from {}", what));
+                    IpcResponse {
+                        success: true,
+                        message: format!("Resumed from {}", what),
+                        data: None,
+                    }
+                }
+                Err(e) => {
+                    let msg = format!("Cannot {}: {}", what, e);
+                    log_message(&console_logger, &file_logger, LogLevel::Fail, &msg);
+                    IpcResponse {
+                        success: false,
+                        message: msg,
+                        data: None,
+                    }
+                }
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
         }
         IpcCommand::BootComplete => {
             let resp = IpcResponse {
@@ -122,17 +206,187 @@ fn handle_client(
                 message: "Boot complete acknowledged".into(),
                 data: None,
             };
-            stream.write_all(&serialize_response(&resp))?;
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+
+            if let Ok(mut state) = init_state.lock() {
+                state.verdantd_boot_complete = true;
+            }
 
             log_message(&console_logger, &file_logger, LogLevel::Info, "Verdantd reported boot complete.");
         }
+        IpcCommand::MountNetworkFilesystems => {
+            let mounts = init_state.lock().map(|state| state.config.mounts.clone()).unwrap_or_default();
+            // mount(8) may wait on the network for long; log once it is done
+            let mut con = DeferredConsole::default();
+            let mut file = DeferredFile::default();
+            let result = mount::mount_network_filesystems(&mounts, &mut con, &mut file);
+            con.replay(&console_logger);
+            file.replay(&file_logger);
+
+            // Failures of single entries are logged; this only fails for required ones
+            let resp = match result {
+                Ok(()) => IpcResponse {
+                    success: true,
+                    message: "Network filesystems mounted".into(),
+                    data: None,
+                },
+                Err(e) => IpcResponse {
+                    success: false,
+                    message: format!("Cannot mount network filesystems: {}", e),
+                    data: None,
+                },
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+        }
+        IpcCommand::SetHostname { name, transient } => {
+            let result = if transient {
+                let config = init_state.lock().map(|state| state.config.init.clone()).unwrap_or_default();
+                hostname::set_transient_hostname(&name, &config)
+            } else {
+                hostname::set_static_hostname(&name).map(|()| true)
+            };
+
+            let resp = match result {
+                Ok(true) => {
+                    log_message(&console_logger, &file_logger, LogLevel::Ok, &format!("Hostname set to '{}'", name));
+                    IpcResponse {
+                        success: true,
+                        message: format!("Hostname set to '{}'", name),
+                        data: None,
+                    }
+                }
+                Ok(false) => IpcResponse {
+                    success: true,
+                    message: format!("Static hostname configured, '{}' not applied", name),
+                    data: None,
+                },
+                Err(e) => IpcResponse {
+                    success: false,
+                    message: format!("Cannot set hostname: {}", e),
+                    data: None,
+                },
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+        }
+        IpcCommand::Reexec => {
+            let running = init_state.lock().is_ok_and(|state| state.phase == BootPhase::Running)
+                && pending.lock().is_ok_and(|p| p.is_none());
+
+            let resp = IpcResponse {
+                success: running,
+                message: if running { "Re-executing init".into() } else { "Init is not running, cannot re-exec now".into() },
+                data: None,
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+            if !running {
+                return Ok(());
+            }
+
+            log_message(&console_logger, &file_logger, LogLevel::Info, "Re-executing init...");
+
+            // The state lock is held until the exec, so nothing changes after it is saved
+            let error = match init_state.lock() {
+                Ok(state) => reexec::reexec(&state),
+                Err(_) => std::io::Error::other("init state unavailable"),
+            };
+            log_message(&console_logger, &file_logger, LogLevel::Fail, &format!("Re-exec failed, continuing as before: {}", error));
+        }
+        IpcCommand::GetConfig => {
+            let resp = match init_state.lock() {
+                Ok(state) => IpcResponse {
+                    success: true,
+                    message: "Effective init configuration".into(),
+                    data: serde_json::to_value(&state.config).ok(),
+                },
+                Err(_) => IpcResponse {
+                    success: false,
+                    message: "Init state unavailable".into(),
+                    data: None,
+                },
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+        }
+        IpcCommand::GetStatus => {
+            let pending_action = pending.lock().ok().and_then(|p| p.as_ref().map(|a| a.as_str()));
+
+            let resp = match init_state.lock() {
+                Ok(state) => IpcResponse {
+                    success: true,
+                    message: "Init status".into(),
+                    data: Some(state.snapshot(pending_action)),
+                },
+                Err(_) => IpcResponse {
+                    success: false,
+                    message: "Init state unavailable".into(),
+                    data: None,
+                },
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+        }
+        IpcCommand::ModuleLoad(name) => {
+            let resp = match kernel::load_module(&name) {
+                Ok(()) => {
+                    log_message(&console_logger, &file_logger, LogLevel::Ok, &format!("Loaded kernel module '{}'", name));
+                    IpcResponse {
+                        success: true,
+                        message: format!("Module '{}' loaded", name),
+                        data: None,
+                    }
+                }
+                Err(e) => {
+                    log_message(&console_logger, &file_logger, LogLevel::Fail, &format!("Failed to load kernel module '{}': {}", name, e));
+                    IpcResponse {
+                        success: false,
+                        message: e.to_string(),
+                        data: None,
+                    }
+                }
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+        }
+        IpcCommand::ModuleUnload(name) => {
+            let resp = match kernel::unload_module(&name) {
+                Ok(()) => {
+                    log_message(&console_logger, &file_logger, LogLevel::Ok, &format!("Unloaded kernel module '{}'", name));
+                    IpcResponse {
+                        success: true,
+                        message: format!("Module '{}' unloaded", name),
+                        data: None,
+                    }
+                }
+                Err(e) => {
+                    log_message(&console_logger, &file_logger, LogLevel::Fail, &format!("Failed to unload kernel module '{}': {}", name, e));
+                    IpcResponse {
+                        success: false,
+                        message: e.to_string(),
+                        data: None,
+                    }
+                }
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+        }
+        IpcCommand::ModuleList => {
+            let resp = match kernel::list_modules() {
+                Ok(modules) => IpcResponse {
+                    success: true,
+                    message: format!("{} modules loaded", modules.len()),
+                    data: serde_json::to_value(&modules).ok(),
+                },
+                Err(e) => IpcResponse {
+                    success: false,
+                    message: e.to_string(),
+                    data: None,
+                },
+            };
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
+        }
         _ => {
             let resp = IpcResponse {
                 success: false,
                 message: "Unsupported command for init".into(),
                 data: None,
             };
-            stream.write_all(&serialize_response(&resp))?;
+            stream.write_all(&serialize_reply(&resp, framing, request.id))?;
             log_message(&console_logger, &file_logger, LogLevel::Fail, "Unsupported command for init");
         }
     }
@@ -154,3 +408,49 @@ fn log_message(
     }
 }
 
+
+/// Console messages held back so a slow step can run without the logger locked.
+#[derive(Default)]
+struct DeferredConsole(Vec<(LogLevel, String, Duration)>);
+
+impl DeferredConsole {
+    fn replay(self, console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>) {
+        if let Ok(mut con) = console_logger.lock() {
+            for (level, msg, duration) in self.0 {
+                con.message(level, &msg, duration);
+            }
+        }
+    }
+}
+
+impl ConsoleLogger for DeferredConsole {
+    fn message(&mut self, level: LogLevel, message: &str, duration: Duration) {
+        self.0.push((level, message.to_string(), duration));
+    }
+
+    fn banner(&mut self, _message: &str) {}
+}
+
+/// File log lines held back like `DeferredConsole`.
+#[derive(Default)]
+struct DeferredFile(Vec<(LogLevel, String)>);
+
+impl DeferredFile {
+    fn replay(self, file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>) {
+        if let Ok(mut file) = file_logger.lock() {
+            for (level, msg) in self.0 {
+                file.log(level, &msg);
+            }
+        }
+    }
+}
+
+impl FileLogger for DeferredFile {
+    fn log(&mut self, level: LogLevel, message: &str) {
+        self.0.push((level, message.to_string()));
+    }
+
+    fn initialize(&mut self, _console_logger: &mut dyn ConsoleLogger) -> Result<(), BloomError> {
+        Ok(())
+    }
+}
@@ -1,18 +1,31 @@
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
-use std::path::Path;
-use std::ffi::CString;
-use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 
-use nix::unistd::{fork, ForkResult, execvp};
-use nix::sys::wait::{waitpid, WaitStatus};
+use serde::Serialize;
+use threadpool::ThreadPool;
 
+use bloom::cmdline::read_cmdline;
 use bloom::errors::BloomError;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::time::ProcessTimer;
 
+use crate::modules::{ModuleLoader, LOAD_THREADS};
+
+/// sysctl.d directories, most important first.
+const SYSCTL_DIRS: &[&str] = &["/etc/sysctl.d", "/run/sysctl.d", "/usr/lib/sysctl.d"];
+
+const SYSCTL_CONF_PATH: &str = "/etc/sysctl.conf";
+
+/// Kernel command line prefixes of sysctl settings, e.g. `sysctl.vm.swappiness=10`.
+const SYSCTL_PREFIX: &str = "sysctl.";
+const VERDANT_SYSCTL_PREFIX: &str = "verdant.sysctl.";
+
 /// Collects module names from a given file path.
 /// Returns Vec<String> of module names.
 fn collect_modules_from_file(path: &Path) -> Result<Vec<String>, BloomError> {
@@ -77,42 +90,48 @@ pub fn load_kernel_modules(
     all_modules.sort();
     all_modules.dedup();
 
+    let loader = Arc::new(ModuleLoader::load());
+    all_modules.retain(|name| {
+        let blacklisted = loader.config().is_blacklisted(name);
+        if blacklisted && let Ok(mut file_log) = file_logger.lock() {
+            file_log.log(LogLevel::Info, &format!("Not loading blacklisted module {}", name));
+        }
+        !blacklisted
+    });
+
     if all_modules.is_empty() {
         log_success(console_logger, file_logger, &timer, LogLevel::Info, "No kernel modules to load");
         return Ok(());
     }
 
-    // Now load each module by forking modprobe
-    let mut success_count = 0;
-    let mut fail_count = 0;
-    let mut children = Vec::new();
+    // Load in parallel; each module waits for its own softdeps
+    let pool = ThreadPool::new(LOAD_THREADS);
+    let loaded = Arc::new(AtomicUsize::new(0));
+    let failed = Arc::new(AtomicUsize::new(0));
 
     for module_name in all_modules {
-        match unsafe { fork() } {
-            Ok(ForkResult::Child) => {
-                let cmd = CString::new("modprobe").expect("CString::new failed");
-                let arg = CString::new(module_name).expect("CString::new failed");
-                let args = &[cmd.as_c_str(), arg.as_c_str()];
-                let _ = execvp(&cmd, args);
-
-                std::process::exit(1);
+        let loader = Arc::clone(&loader);
+        let loaded = Arc::clone(&loaded);
+        let failed = Arc::clone(&failed);
+        let file_logger = Arc::clone(file_logger);
+
+        pool.execute(move || match loader.load_module(&module_name) {
+            Ok(()) => {
+                loaded.fetch_add(1, Ordering::Relaxed);
             }
-            Ok(ForkResult::Parent { child }) => {
-                children.push(child);
-            }
-            Err(_) => {
-                fail_count += 1;
+            Err(e) => {
+                failed.fetch_add(1, Ordering::Relaxed);
+                if let Ok(mut file_log) = file_logger.lock() {
+                    file_log.log(LogLevel::Warn, &e.to_string());
+                }
             }
-        }
+        });
     }
 
-    for child in children {
-        match waitpid(child, None) {
-            Ok(WaitStatus::Exited(_pid, 0)) => success_count += 1,
-            Ok(WaitStatus::Exited(_pid, _)) => fail_count += 1,
-            Ok(_) | Err(_) => fail_count += 1,
-        }
-    }
+    pool.join();
+
+    let success_count = loaded.load(Ordering::Relaxed);
+    let fail_count = failed.load(Ordering::Relaxed);
 
     let msg = format!("Kernel modules loaded: {} successful, {} failed", success_count, fail_count);
     let simple_console_msg = if success_count > 0 {
@@ -138,35 +157,40 @@ pub fn load_kernel_modules(
 
 /// Applies kernel sysctl settings from common sysctl configuration files.
 /// Only applies keys where the current value differs from the desired value.
+///
+/// `*.conf` files in `SYSCTL_DIRS` are applied in file name order, later
+/// files overriding earlier ones; a file hides any of the same name in the
+/// directories after it. /etc/sysctl.conf comes next, and `sysctl.` or
+/// `verdant.sysctl.` parameters on the kernel command line override them all.
 pub fn apply_sysctl_settings(
     console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
 ) -> Result<(), BloomError> {
     let timer = ProcessTimer::start();
-    let mut settings: HashMap<String, String> = HashMap::new();
-
-    // Load settings from all sysctl sources
-    let paths = [
-        "/etc/sysctl.conf",
-        "/etc/sysctl.d",
-        "/usr/lib/sysctl.d",
-    ];
-
-    for path in paths.iter() {
-        let p = Path::new(path);
-        if p.is_file() {
-            load_sysctl_file(p, &mut settings)?;
-        } else if p.is_dir() {
-            for entry in fs::read_dir(p).map_err(BloomError::Io)? {
-                let entry = entry.map_err(BloomError::Io)?;
-                let path = entry.path();
-                if path.extension().and_then(|s| s.to_str()) == Some("conf") {
-                    load_sysctl_file(&path, &mut settings)?;
-                }
+    let mut settings: BTreeMap<String, String> = BTreeMap::new();
+
+    let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+    for dir in SYSCTL_DIRS.iter().rev() {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("conf") {
+                files.insert(entry.file_name().to_string_lossy().to_string(), path);
             }
         }
     }
 
+    let sysctl_conf = Path::new(SYSCTL_CONF_PATH);
+    for path in files.values().map(PathBuf::as_path).chain(sysctl_conf.is_file().then_some(sysctl_conf)) {
+        load_sysctl_file(path, &mut settings)?;
+    }
+
+    for (key, value) in read_cmdline() {
+        let key = key.strip_prefix(VERDANT_SYSCTL_PREFIX).or_else(|| key.strip_prefix(SYSCTL_PREFIX));
+        if let (Some(key), Some(value)) = (key, value) {
+            settings.insert(key.to_string(), value);
+        }
+    }
+
     let mut applied = 0;
     let mut skipped = 0;
     let mut failed = 0;
@@ -221,7 +245,7 @@ pub fn apply_sysctl_settings(
 
 
 /// Helper to parse key=value lines from sysctl files
-fn load_sysctl_file(path: &Path, map: &mut std::collections::HashMap<String, String>) -> Result<(), BloomError> {
+fn load_sysctl_file(path: &Path, map: &mut BTreeMap<String, String>) -> Result<(), BloomError> {
     let file = File::open(path).map_err(BloomError::Io)?;
     let reader = BufReader::new(file);
 
@@ -238,6 +262,74 @@ fn load_sysctl_file(path: &Path, map: &mut std::collections::HashMap<String, Str
     Ok(())
 }
 
+/// A module currently loaded into the kernel, as reported by /proc/modules.
+#[derive(Debug, Serialize)]
+pub struct LoadedModule {
+    pub name: String,
+    pub size: u64,
+    pub refcount: u32,
+    pub used_by: Vec<String>,
+    pub state: String,
+}
+
+/// Load a single module at runtime, refusing blacklisted modules.
+pub fn load_module(name: &str) -> Result<(), BloomError> {
+    ModuleLoader::load().load_module(name)
+}
+
+/// Unload a single module at runtime.
+pub fn unload_module(name: &str) -> Result<(), BloomError> {
+    run_modprobe(&["-r", name])
+}
+
+/// Parse /proc/modules into a list of loaded modules.
+pub fn list_modules() -> Result<Vec<LoadedModule>, BloomError> {
+    let contents = fs::read_to_string("/proc/modules").map_err(BloomError::Io)?;
+    let mut modules = Vec::new();
+
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 5 {
+            continue;
+        }
+
+        let used_by = fields[3]
+            .split(',')
+            .filter(|s| !s.is_empty() && *s != "-")
+            .map(|s| s.to_string())
+            .collect();
+
+        modules.push(LoadedModule {
+            name: fields[0].to_string(),
+            size: fields[1].parse().unwrap_or(0),
+            refcount: fields[2].parse().unwrap_or(0),
+            used_by,
+            state: fields[4].to_string(),
+        });
+    }
+
+    modules.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(modules)
+}
+
+fn run_modprobe(args: &[&str]) -> Result<(), BloomError> {
+    let output = Command::new("/sbin/modprobe")
+        .args(args)
+        .output()
+        .map_err(BloomError::Io)?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(BloomError::Custom(format!(
+            "modprobe {} failed: {}",
+            args.join(" "),
+            stderr.trim()
+        )))
+    }
+}
+
 fn log_success(
     console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
@@ -270,3 +362,29 @@ fn log_error(
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sysctl_file_later_keys_win() {
+        let path = std::env::temp_dir().join(format!("verdant-sysctl-test-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\n\nnet.ipv4.ip_forward = 1\nkernel.panic=10\n  vm.swappiness =  20  \nno-equals\nkernel.panic = 5\n",
+        )
+        .unwrap();
+
+        let mut settings = BTreeMap::new();
+        settings.insert("vm.swappiness".to_string(), "60".to_string());
+        let result = load_sysctl_file(&path, &mut settings);
+        fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        let expected = [("kernel.panic", "5"), ("net.ipv4.ip_forward", "1"), ("vm.swappiness", "20")];
+        assert_eq!(
+            settings,
+            expected.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<BTreeMap<_, _>>()
+        );
+    }
+}
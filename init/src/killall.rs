@@ -0,0 +1,98 @@
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+/// How long processes get to exit after SIGTERM before they are killed.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// How often to check whether everything has exited during the grace period.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Parent of every kernel thread, which signals cannot touch.
+const KTHREADD_PID: i32 = 2;
+
+/// Send SIGTERM to every process still running after verdantd stopped its
+/// services, give them `KILL_GRACE` to exit, then SIGKILL whatever is left,
+/// so nothing keeps a filesystem busy while it is unmounted.
+pub fn kill_remaining_processes(console_logger: &mut dyn ConsoleLogger, file_logger: &mut dyn FileLogger) {
+    let timer = ProcessTimer::start();
+
+    let remaining = user_processes().len();
+    if remaining == 0 {
+        return;
+    }
+
+    let msg = format!("Sending SIGTERM to {} remaining process(es)", remaining);
+    log(console_logger, file_logger, &timer, LogLevel::Info, &msg);
+
+    // -1 reaches every process except init itself
+    let _ = kill(Pid::from_raw(-1), Signal::SIGTERM);
+
+    let deadline = Instant::now() + KILL_GRACE;
+    while Instant::now() < deadline {
+        if user_processes().is_empty() {
+            log(console_logger, file_logger, &timer, LogLevel::Ok, "All remaining processes exited");
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let survivors = user_processes();
+    if survivors.is_empty() {
+        return;
+    }
+
+    let msg = format!(
+        "Sending SIGKILL to {} process(es) that ignored SIGTERM: {}",
+        survivors.len(),
+        survivors.join(", ")
+    );
+    log(console_logger, file_logger, &timer, LogLevel::Warn, &msg);
+
+    let _ = kill(Pid::from_raw(-1), Signal::SIGKILL);
+    thread::sleep(POLL_INTERVAL);
+}
+
+/// Processes other than init, kernel threads and zombies, as `name[pid]`.
+fn user_processes() -> Vec<String> {
+    let own = std::process::id() as i32;
+
+    fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let pid: i32 = entry.file_name().to_str()?.parse().ok()?;
+            if pid == own || pid == KTHREADD_PID {
+                return None;
+            }
+
+            // The name is in parentheses and may itself contain spaces
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            let (name, rest) = stat.split_once(" (")?.1.rsplit_once(") ")?;
+            let mut fields = rest.split_whitespace();
+            let state = fields.next()?;
+            let ppid: i32 = fields.next()?.parse().ok()?;
+
+            (state != "Z" && ppid != KTHREADD_PID).then(|| format!("{}[{}]", name, pid))
+        })
+        .collect()
+}
+
+fn log(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+    timer: &ProcessTimer,
+    level: LogLevel,
+    msg: &str,
+) {
+    console_logger.message(level, msg, timer.elapsed());
+    file_logger.log(level, msg);
+}
@@ -0,0 +1,105 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+use nix::mount::{mount, MsFlags};
+
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+const MACHINE_ID_PATH: &str = "/etc/machine-id";
+
+/// Where the ID lives for this boot when /etc cannot be written.
+const RUNTIME_MACHINE_ID_PATH: &str = "/run/machine-id";
+
+/// Make sure /etc/machine-id holds an ID before any service starts. On
+/// first boot (no file, or an empty one) a random one is generated and
+/// saved; on a read-only root it is kept in /run and bind-mounted over the
+/// existing file, so it is at least stable until the next boot.
+pub fn ensure_machine_id(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+    let mut log = |level: LogLevel, msg: &str| {
+        console_logger.message(level, msg, timer.elapsed());
+        file_logger.log(level, msg);
+    };
+
+    if let Ok(id) = fs::read_to_string(MACHINE_ID_PATH)
+        && is_valid(id.trim())
+    {
+        file_logger.log(LogLevel::Info, &format!("Machine ID is {}", id.trim()));
+        return Ok(());
+    }
+
+    let id = generate().map_err(BloomError::Io)?;
+
+    match write_id(MACHINE_ID_PATH, &id) {
+        Ok(()) => {
+            log(LogLevel::Ok, &format!("Generated machine ID {}", id));
+            return Ok(());
+        }
+        Err(e) if e.raw_os_error() != Some(libc::EROFS) => {
+            log(LogLevel::Fail, &format!("Cannot write {}: {}", MACHINE_ID_PATH, e));
+            return Err(BloomError::Io(e));
+        }
+        Err(_) => {}
+    }
+
+    // Read-only root: a bind mount needs a file to cover, which cannot be created now
+    if !Path::new(MACHINE_ID_PATH).exists() {
+        let msg = format!(
+            "Root is read-only and has no {} to bind a machine ID over; create an empty one in the image",
+            MACHINE_ID_PATH
+        );
+        log(LogLevel::Fail, &msg);
+        return Err(BloomError::Custom(msg));
+    }
+
+    let result = write_id(RUNTIME_MACHINE_ID_PATH, &id).map_err(BloomError::Io).and_then(|()| {
+        mount(
+            Some(RUNTIME_MACHINE_ID_PATH),
+            MACHINE_ID_PATH,
+            None::<&str>,
+            MsFlags::MS_BIND,
+            None::<&str>,
+        )
+        .map_err(BloomError::Nix)
+    });
+
+    match result {
+        Ok(()) => {
+            log(LogLevel::Warn, &format!("Root is read-only, using machine ID {} for this boot only", id));
+            Ok(())
+        }
+        Err(e) => {
+            log(LogLevel::Fail, &format!("Cannot set up a machine ID on a read-only root: {}", e));
+            Err(e)
+        }
+    }
+}
+
+/// 32 lowercase hex digits, not all zero.
+fn is_valid(id: &str) -> bool {
+    id.len() == 32 && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) && id.bytes().any(|b| b != b'0')
+}
+
+/// A random version 4 UUID, written without dashes.
+fn generate() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn write_id(path: &str, id: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o444).open(path)?;
+    file.write_all(format!("{}\n", id).as_bytes())
+}
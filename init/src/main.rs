@@ -1,37 +1,60 @@
 mod actions;
+mod crash;
+mod crypttab;
 mod device_manager;
+mod devices;
 mod env;
 mod filesystem;
+mod firewall;
+mod firmware;
 mod hardware_drivers;
+mod hostname;
 mod ipc_server;
 mod kernel;
+mod killall;
+mod machine_id;
+mod modules;
 mod mount;
 mod network;
+mod powerfail;
+mod reexec;
+mod resume;
 mod run;
 mod seed;
 mod service_manager;
 mod signal;
+mod state;
+mod stateless;
+mod storage;
+mod swap;
+mod tmpfiles;
 mod unmount;
 mod utils;
+mod vconsole;
+mod zram;
 
 use std::{
     env::args, 
-    fs, 
+    fs::{self, OpenOptions}, 
+    io, 
+    os::unix::process::CommandExt, 
     path::Path, 
-    process::{Command, Stdio}, 
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex,
-    }, 
+    process::{Command, ExitStatus, Stdio}, 
+    sync::{Arc, Mutex}, 
     thread, 
     time::Duration
 };
 
+use bloom::config::{load_config, RecoveryShellConfig};
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::ipc::INIT_SOCKET_PATH;
 
 use crate::{service_manager::launch_verdant_service_manager};
+use crate::state::{BootPhase, InitState};
+
+/// Shell started when the configured recovery shell cannot be.
+const FALLBACK_SHELL: &str = "/bin/sh";
 
 fn main() {
     let is_test = args().any(|arg| arg == "test");
@@ -41,11 +64,14 @@ fn main() {
         std::process::exit(1);
     }
 
+    crash::install_panic_hook();
+
     let result = std::panic::catch_unwind(inner_main);
 
     if result.is_err() {
-        eprintln!("Fatal error in init process. Dropping to emergency shell.");
-        spawn_recovery_shell();
+        eprintln!("Fatal error in init process, see {}. Dropping to emergency shell.", crash::CRASH_LOG_PATH);
+        let config = load_config().map(|config| config.init.recovery_shell).unwrap_or_default();
+        spawn_recovery_shell(&config);
     }
 
     // Minimal fallback loop to keep PID 1 alive without spamming output
@@ -56,29 +82,46 @@ fn main() {
 
 
 fn inner_main() {
-    let (console_logger_impl, file_logger, start_time) = run::boot();
+    // Started by `vctl daemon-reexec --init`: the system is already up
+    let restored = reexec::take_state();
+
+    let (console_logger_impl, file_logger, start_time, config) = match &restored {
+        Some(saved) => run::resume(saved),
+        None => run::boot(),
+    };
 
     let console_logger: Arc<Mutex<dyn ConsoleLogger + Send + Sync>> = console_logger_impl;
     let file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>> = file_logger;
 
-    let shutdown_flag = Arc::new(AtomicBool::new(false));
-    let reboot_flag = Arc::new(AtomicBool::new(false));
+    let pending = Arc::new(Mutex::new(None));
+    let power_failure = config.init.power_failure.clone();
+    let recovery_shell = config.init.recovery_shell.clone();
+
+    let init_state = Arc::new(Mutex::new(InitState::new(config)));
+    if let Ok(mut state) = init_state.lock() {
+        match &restored {
+            Some(saved) => saved.restore(&mut state),
+            None => state.boot_duration = Some(start_time.elapsed()),
+        }
+    }
 
     // Start IPC server thread (comment out if suspected to cause issues)
     {
-        let ipc_shutdown_flag = Arc::clone(&shutdown_flag);
-        let ipc_reboot_flag = Arc::clone(&reboot_flag);
+        let ipc_pending = Arc::clone(&pending);
         let ipc_console_logger = Arc::clone(&console_logger);
         let ipc_file_logger = Arc::clone(&file_logger);
+        let ipc_init_state = Arc::clone(&init_state);
         let ipc_main_thread = thread::current();
+        let ipc_listener = restored.as_ref().and_then(|saved| saved.listener_fd);
 
         thread::spawn(move || {
             if let Err(e) = ipc_server::run_ipc_server(
-                ipc_shutdown_flag,
-                ipc_reboot_flag,
+                ipc_pending,
                 ipc_console_logger,
                 ipc_file_logger,
+                ipc_init_state,
                 ipc_main_thread,
+                ipc_listener,
             ) {
                 eprintln!("Init IPC server failed: {e}");
             }
@@ -88,7 +131,7 @@ fn inner_main() {
     thread::sleep(Duration::from_millis(500));
 
     // Show boot timing
-    {
+    if restored.is_none() {
         use bloom::colour::color::{RESET, YELLOW};
         use bloom::time::format_duration;
 
@@ -96,79 +139,155 @@ fn inner_main() {
         println!("\nTook: {} {} {}", YELLOW, format_duration(elapsed), RESET);
     }
 
-    // Launch VerdantD service manager
-    if let Ok(mut guard) = console_logger.lock() {
+    // Launch VerdantD service manager; after a re-exec it is still running
+    if restored.is_none()
+        && let Ok(mut guard) = console_logger.lock()
+    {
         let logger: &mut dyn ConsoleLogger = &mut *guard;
-        if launch_verdant_service_manager(logger).is_none() {
-            logger.message(
-                LogLevel::Fail,
-                "Critical: Could not launch Verdant Service Manager. Dropping to recovery shell.",
-                Duration::ZERO,
-            );
-            drop(guard);
-            spawn_recovery_shell();
+        match launch_verdant_service_manager(logger) {
+            Some(child) => {
+                if let Ok(mut state) = init_state.lock() {
+                    state.verdantd_pid = Some(child.id());
+                }
+            }
+            None => {
+                logger.message(
+                    LogLevel::Fail,
+                    "Critical: Could not launch Verdant Service Manager. Dropping to recovery shell.",
+                    Duration::ZERO,
+                );
+                drop(guard);
+                // Init carries on afterwards to reap and handle shutdown
+                spawn_recovery_shell(&RecoveryShellConfig { respawn: false, ..recovery_shell.clone() });
+            }
         }
     }
 
+    if let Ok(mut state) = init_state.lock() {
+        state.phase = BootPhase::Running;
+    }
+
+    if restored.is_some()
+        && let Ok(mut file) = file_logger.lock()
+    {
+        file.log(LogLevel::Ok, "Re-executed init, carrying on");
+    }
+
     // Install signal handlers (simplified, no global blocking)
     signal::install_signal_handlers(
-        Arc::clone(&shutdown_flag),
-        Arc::clone(&reboot_flag),
+        Arc::clone(&pending),
         Arc::clone(&file_logger),
         Arc::clone(&console_logger),
         thread::current(),
+        power_failure,
     )
     .expect("Failed to install signal handlers");
 
     // Main control loop
     loop {
-        if reboot_flag.load(Ordering::SeqCst) {
-            log_shutdown(&console_logger, &file_logger, "Reboot");
-            
+        let action = pending.lock().ok().and_then(|p| *p);
+        if let Some(action) = action {
+            set_phase(&init_state, BootPhase::ShuttingDown);
+            log_shutdown(&console_logger, &file_logger, action.as_str());
+
             if let (Ok(mut con), Ok(mut file)) = (console_logger.lock(), file_logger.lock()) {
-                let _ = unmount::unmount_fstab_filesystems(&mut *con, &mut *file);
+                killall::kill_remaining_processes(&mut *con, &mut *file);
+                swap::deactivate_swap(&mut *con, &mut *file);
+                let _ = unmount::unmount_filesystems(&mut *con, &mut *file);
             }
 
             remove_init_socket(&file_logger);
-            let _ = actions::reboot();
+            let _ = actions::perform(action);
             loop {
                 thread::park();
             }
         }
 
-        if shutdown_flag.load(Ordering::SeqCst) {
-            log_shutdown(&console_logger, &file_logger, "Shutdown");
+        thread::park_timeout(Duration::from_millis(500));
+    }
+}
 
-            if let (Ok(mut con), Ok(mut file)) = (console_logger.lock(), file_logger.lock()) {
-                let _ = unmount::unmount_fstab_filesystems(&mut *con, &mut *file);
+/// Start the configured recovery shell and wait for it, starting it again
+/// each time it exits when `respawn` is set. A shell that cannot be started
+/// is replaced by `FALLBACK_SHELL`.
+fn spawn_recovery_shell(config: &RecoveryShellConfig) {
+    loop {
+        let status = run_shell(&config.path, config.tty.as_deref()).or_else(|e| {
+            if config.path == FALLBACK_SHELL {
+                return Err(e);
             }
-  
-            remove_init_socket(&file_logger);
-            let _ = actions::shutdown();
-            loop {
-                thread::park();
+            eprintln!("Failed to launch recovery shell {}: {e}, trying {FALLBACK_SHELL}", config.path);
+            run_shell(FALLBACK_SHELL, config.tty.as_deref())
+        });
+
+        match status {
+            Ok(status) if config.respawn => eprintln!("Recovery shell exited with status: {status}, restarting it"),
+            Ok(status) => {
+                eprintln!("Recovery shell exited with status: {status}");
+                return;
+            }
+            Err(e) => {
+                eprintln!("Failed to launch recovery shell: {e}");
+                return;
             }
         }
 
-        thread::park_timeout(Duration::from_millis(500));
+        // Keeps a shell that exits straight away from spinning
+        thread::sleep(Duration::from_secs(1));
     }
 }
 
-fn spawn_recovery_shell() {
-    match Command::new("/bin/sh")
-        .stdin(Stdio::inherit())
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()
-        .and_then(|mut child| child.wait())
-    {
-        Ok(status) => {
-            eprintln!("Recovery shell exited with status: {status}");
+/// Run `path` on `tty` as a new session with the terminal as its
+/// controlling one, or on init's own console when no tty is given.
+fn run_shell(path: &str, tty: Option<&str>) -> io::Result<ExitStatus> {
+    let mut cmd = Command::new(path);
+
+    match tty {
+        Some(tty) => {
+            let terminal = OpenOptions::new().read(true).write(true).open(tty)?;
+            cmd.stdin(terminal.try_clone()?).stdout(terminal.try_clone()?).stderr(terminal);
+
+            // Without a controlling terminal there is no job control or ^C
+            unsafe {
+                cmd.pre_exec(|| {
+                    if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
         }
-        Err(e) => {
-            eprintln!("Failed to launch recovery shell: {e}");
+        None => {
+            cmd.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
         }
     }
+
+    cmd.status()
+}
+
+/// Stop for an authenticated root shell; boot carries on once it exits.
+/// Without sulogin installed no shell is started, as it would hand root to
+/// anyone at the console, and boot carries on straight away.
+fn spawn_emergency_shell(file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>) {
+    let (level, msg) = match bloom::shell::run_sulogin() {
+        Ok(status) => (LogLevel::Info, format!("Emergency shell exited with status: {status}, continuing boot")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (
+            LogLevel::Fail,
+            "No sulogin to ask for the root password, refusing to start an unauthenticated shell".to_string(),
+        ),
+        Err(e) => (LogLevel::Fail, format!("Failed to launch emergency shell: {e}")),
+    };
+
+    eprintln!("{msg}");
+    if let Ok(mut file) = file_logger.lock() {
+        file.log(level, &msg);
+    }
+}
+
+fn set_phase(init_state: &Arc<Mutex<InitState>>, phase: BootPhase) {
+    if let Ok(mut state) = init_state.lock() {
+        state.phase = phase;
+    }
 }
 
 fn log_shutdown(
@@ -0,0 +1,417 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use wait_timeout::ChildExt;
+
+use bloom::cmdline::read_cmdline;
+use bloom::errors::BloomError;
+use bloom::util::glob_match;
+
+const MODPROBE_PATH: &str = "/sbin/modprobe";
+
+/// A modprobe taking longer than this is killed.
+const MODPROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// finit_module(2) flag from <linux/module.h>: the kernel decompresses the
+/// file itself.
+const MODULE_INIT_COMPRESSED_FILE: libc::c_uint = 4;
+
+/// modprobe.d directories, most important first: a file in one hides any
+/// file of the same name in those after it.
+pub const MODPROBE_D_DIRS: &[&str] = &["/etc/modprobe.d", "/run/modprobe.d", "/usr/lib/modprobe.d", "/lib/modprobe.d"];
+
+/// Modules to load before and after a module, from `softdep` lines.
+#[derive(Debug, Default, Clone)]
+pub struct Softdep {
+    pub pre: Vec<String>,
+    pub post: Vec<String>,
+}
+
+/// How many modules are loaded at once.
+pub const LOAD_THREADS: usize = 12;
+
+/// The `blacklist`, `options`, `softdep` and `install` directives of
+/// modprobe.d, with `modprobe.blacklist=` and `module.param=value` from the
+/// kernel command line on top, as kmod reads them.
+#[derive(Debug, Default)]
+pub struct ModprobeConfig {
+    blacklist: HashSet<String>,
+    options: HashMap<String, Vec<String>>,
+    softdeps: HashMap<String, Softdep>,
+    /// Modules with an `install` command, which only modprobe can run.
+    install: HashSet<String>,
+}
+
+impl ModprobeConfig {
+    pub fn load() -> Self {
+        let mut config = Self::default();
+
+        // Applied in file name order across all directories
+        let mut files: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for dir in MODPROBE_D_DIRS.iter().rev() {
+            for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|e| e.to_str()) == Some("conf") {
+                    files.insert(entry.file_name().to_string_lossy().to_string(), path);
+                }
+            }
+        }
+
+        for path in files.values() {
+            if let Ok(contents) = fs::read_to_string(path) {
+                config.parse(&contents);
+            }
+        }
+
+        for (key, value) in read_cmdline() {
+            let Some(value) = value else {
+                continue;
+            };
+            match key.as_str() {
+                "modprobe.blacklist" | "module_blacklist" => {
+                    config.blacklist.extend(value.split(',').map(normalize_module_name));
+                }
+                _ => {
+                    if let Some((module, param)) = key.split_once('.') {
+                        let option = format!("{}={}", param, value);
+                        config.options.entry(normalize_module_name(module)).or_default().push(option);
+                    }
+                }
+            }
+        }
+
+        config
+    }
+
+    fn parse(&mut self, contents: &str) {
+        for line in contents.lines() {
+            let mut words = line.split_whitespace();
+            let (Some(directive), Some(module)) = (words.next(), words.next()) else {
+                continue;
+            };
+            let module = normalize_module_name(module);
+
+            match directive {
+                "blacklist" => {
+                    self.blacklist.insert(module);
+                }
+                "options" => self.options.entry(module).or_default().extend(words.map(str::to_string)),
+                "install" => {
+                    self.install.insert(module);
+                }
+                "softdep" => {
+                    let softdep = self.softdeps.entry(module).or_default();
+                    let mut list = None;
+                    for word in words {
+                        match word {
+                            "pre:" => list = Some(&mut softdep.pre),
+                            "post:" => list = Some(&mut softdep.post),
+                            name => {
+                                if let Some(list) = list.as_mut() {
+                                    list.push(normalize_module_name(name));
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn is_blacklisted(&self, name: &str) -> bool {
+        self.blacklist.contains(&normalize_module_name(name))
+    }
+
+    /// Parameters to load `name` with, as `param=value` words.
+    pub fn options(&self, name: &str) -> &[String] {
+        self.options.get(&normalize_module_name(name)).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    pub fn softdep(&self, name: &str) -> Option<&Softdep> {
+        self.softdeps.get(&normalize_module_name(name))
+    }
+
+    /// Whether loading `name` runs an `install` command instead, such as the
+    /// common `install cramfs /bin/false` that keeps it from loading at all.
+    pub fn has_install(&self, name: &str) -> bool {
+        self.install.contains(&normalize_module_name(name))
+    }
+}
+
+/// Kernel module names treat '-' and '_' as the same character.
+pub fn normalize_module_name(name: &str) -> String {
+    name.trim().replace('-', "_")
+}
+
+/// Module directory of the running kernel.
+pub fn modules_dir() -> PathBuf {
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    PathBuf::from("/lib/modules").join(release.trim())
+}
+
+/// A module in `modules.dep`: its file and every module it needs, with
+/// paths relative to the module directory.
+#[derive(Debug)]
+struct DepEntry {
+    path: String,
+    deps: Vec<String>,
+}
+
+/// What depmod wrote about the running kernel's modules.
+#[derive(Debug, Default)]
+struct ModuleIndex {
+    deps: HashMap<String, DepEntry>,
+    /// Modules built into the kernel, which need no loading.
+    builtin: HashSet<String>,
+}
+
+impl ModuleIndex {
+    fn load() -> Self {
+        let dir = modules_dir();
+
+        let deps = fs::read_to_string(dir.join("modules.dep"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(path, deps)| {
+                let entry = DepEntry {
+                    path: path.to_string(),
+                    deps: deps.split_whitespace().map(str::to_string).collect(),
+                };
+                (module_name(path), entry)
+            })
+            .collect();
+
+        let builtin = fs::read_to_string(dir.join("modules.builtin"))
+            .unwrap_or_default()
+            .lines()
+            .map(module_name)
+            .collect();
+
+        Self { deps, builtin }
+    }
+}
+
+/// Loads modules the way modprobe.d says to: blacklisted modules are
+/// refused, options are passed along and softdeps are loaded around them.
+/// Modules are loaded in-process with finit_module(2), in the order
+/// `modules.dep` gives; modprobe is only run for what that cannot load.
+pub struct ModuleLoader {
+    config: ModprobeConfig,
+    /// `modules.alias` as (pattern, module), read on first use.
+    aliases: OnceLock<Vec<(String, String)>>,
+    index: OnceLock<ModuleIndex>,
+}
+
+impl ModuleLoader {
+    pub fn load() -> Self {
+        Self {
+            config: ModprobeConfig::load(),
+            aliases: OnceLock::new(),
+            index: OnceLock::new(),
+        }
+    }
+
+    pub fn config(&self) -> &ModprobeConfig {
+        &self.config
+    }
+
+    /// Load `name` with its options, after its softdep `pre:` modules and
+    /// before its `post:` ones; those may fail without failing it.
+    pub fn load_module(&self, name: &str) -> Result<(), BloomError> {
+        if self.config.is_blacklisted(name) {
+            return Err(BloomError::Custom(format!("Module '{}' is blacklisted", name)));
+        }
+
+        let softdep = self.config.softdep(name).cloned().unwrap_or_default();
+        for pre in softdep.pre.iter().filter(|pre| !self.config.is_blacklisted(pre)) {
+            let _ = self.insert(pre);
+        }
+
+        self.insert(name)?;
+
+        for post in softdep.post.iter().filter(|post| !self.config.is_blacklisted(post)) {
+            let _ = self.insert(post);
+        }
+        Ok(())
+    }
+
+    /// Load the modules for a device's modalias. Blacklisted matches are left
+    /// out; with no `modules.alias` to go by, modprobe resolves it instead.
+    pub fn load_alias(&self, alias: &str) -> Result<(), BloomError> {
+        let modules = self.resolve_alias(alias);
+        if modules.is_empty() {
+            return run_modprobe(&["-b", alias]);
+        }
+
+        // Every match is loaded, as modprobe does; the first failure is kept
+        let mut result = Ok(());
+        for module in modules.iter().filter(|module| !self.config.is_blacklisted(module)) {
+            let loaded = self.load_module(module);
+            result = result.and(loaded);
+        }
+        result
+    }
+
+    /// Modules whose `modules.alias` patterns match a device's modalias.
+    pub fn resolve_alias(&self, alias: &str) -> Vec<String> {
+        let aliases = self.aliases.get_or_init(|| {
+            fs::read_to_string(modules_dir().join("modules.alias"))
+                .unwrap_or_default()
+                .lines()
+                .filter_map(|line| line.strip_prefix("alias "))
+                .filter_map(|line| line.split_once(' '))
+                .map(|(pattern, module)| (pattern.to_string(), normalize_module_name(module)))
+                .collect()
+        });
+
+        let mut modules: Vec<String> = aliases
+            .iter()
+            .filter(|(pattern, _)| glob_match(pattern, alias))
+            .map(|(_, module)| module.clone())
+            .collect();
+
+        modules.sort();
+        modules.dedup();
+        modules
+    }
+
+    /// Load one module after the modules it depends on. One that is not in
+    /// `modules.dep`, such as an alias, one with an `install` command, or one
+    /// the kernel refuses to load from its file, is handed to modprobe, which
+    /// has the final word. It gets the name as given, as aliases such as
+    /// `fs-xfs` are not module names.
+    fn insert(&self, name: &str) -> Result<(), BloomError> {
+        let module = normalize_module_name(name);
+        let index = self.index.get_or_init(ModuleIndex::load);
+
+        if index.builtin.contains(&module) || is_loaded(&module) {
+            return Ok(());
+        }
+        if self.config.has_install(&module) {
+            return self.modprobe(name);
+        }
+        let Some(entry) = index.deps.get(&module) else {
+            return self.modprobe(name);
+        };
+
+        let dir = modules_dir();
+
+        // depmod lists dependencies so that the last is loaded first
+        for dep in entry.deps.iter().rev() {
+            let dep_name = module_name(dep);
+            if is_loaded(&dep_name) {
+                continue;
+            }
+            if self.config.has_install(&dep_name)
+                || finit_module(&dir.join(dep), self.config.options(&dep_name)).is_err()
+            {
+                return self.modprobe(name);
+            }
+        }
+
+        match finit_module(&dir.join(&entry.path), self.config.options(&module)) {
+            Ok(()) => Ok(()),
+            Err(_) => self.modprobe(name),
+        }
+    }
+
+    fn modprobe(&self, name: &str) -> Result<(), BloomError> {
+        let args: Vec<&str> = std::iter::once(name).chain(self.config.options(name).iter().map(String::as_str)).collect();
+        run_modprobe(&args)
+    }
+}
+
+/// Name of the module in a `modules.dep` or `modules.builtin` path, e.g.
+/// `snd_hda_intel` for `kernel/sound/pci/hda/snd-hda-intel.ko.zst`.
+fn module_name(path: &str) -> String {
+    let file = path.rsplit('/').next().unwrap_or(path);
+    normalize_module_name(file.split_once(".ko").map_or(file, |(name, _)| name))
+}
+
+/// Whether the module is loaded or being loaded.
+fn is_loaded(name: &str) -> bool {
+    Path::new("/sys/module").join(name).join("initstate").exists()
+}
+
+/// Load a module file into the kernel. Compressed files are decompressed by
+/// the kernel, which needs 5.17 or later built with module decompression.
+fn finit_module(path: &Path, options: &[String]) -> io::Result<()> {
+    let file = File::open(path)?;
+    let params = CString::new(options.join(" ")).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let flags = if path.extension().is_some_and(|ext| ext != "ko") { MODULE_INIT_COMPRESSED_FILE } else { 0 };
+
+    if unsafe { libc::syscall(libc::SYS_finit_module, file.as_raw_fd(), params.as_ptr(), flags) } != 0 {
+        let e = io::Error::last_os_error();
+        // Loaded meanwhile, say as another module's dependency
+        if e.raw_os_error() != Some(libc::EEXIST) {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Run modprobe, killing it after `MODPROBE_TIMEOUT`.
+fn run_modprobe(args: &[&str]) -> Result<(), BloomError> {
+    let mut child = Command::new(MODPROBE_PATH)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| BloomError::Custom(format!("Failed to spawn modprobe for {}: {}", args.join(" "), e)))?;
+
+    match child.wait_timeout(MODPROBE_TIMEOUT).unwrap_or(None) {
+        Some(status) if status.success() => Ok(()),
+        _ => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(BloomError::Custom(format!("modprobe timed out or failed for {}", args.join(" "))))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modprobe_directives() {
+        let mut config = ModprobeConfig::default();
+        config.parse(
+            "# comment\n\
+             blacklist pcspkr\n\
+             options snd-hda-intel power_save=1 model=auto\n\
+             softdep btrfs pre: crc32c-intel post: zstd\n\
+             install cramfs /bin/false\n\
+             options\n",
+        );
+
+        assert!(config.is_blacklisted("pcspkr"));
+        assert!(!config.is_blacklisted("snd_hda_intel"));
+        assert_eq!(config.options("snd_hda_intel"), ["power_save=1", "model=auto"]);
+        assert!(config.options("pcspkr").is_empty());
+
+        let softdep = config.softdep("btrfs").unwrap();
+        assert_eq!(softdep.pre, ["crc32c_intel"]);
+        assert_eq!(softdep.post, ["zstd"]);
+
+        assert!(config.has_install("cramfs"));
+    }
+
+    #[test]
+    fn later_options_add_to_earlier_ones() {
+        let mut config = ModprobeConfig::default();
+        config.parse("options e1000e InterruptThrottleRate=3000\n");
+        config.parse("options e1000e SmartPowerDownEnable=1\n");
+
+        assert_eq!(config.options("e1000e"), ["InterruptThrottleRate=3000", "SmartPowerDownEnable=1"]);
+    }
+}
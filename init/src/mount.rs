@@ -1,15 +1,29 @@
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
 
 use nix::mount::{mount, MsFlags};
 use nix::sys::statvfs::statvfs;
 
+use bloom::config::MountConfig;
+use bloom::device::{is_device_spec, resolve_device, wait_for_device};
 use bloom::errors::BloomError;
+use bloom::fstab::{is_network_mount, FSTAB_PATH};
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::time::ProcessTimer;
 
+/// How long to wait for the device of an fstab entry to appear, unless it
+/// sets `x-verdant.device-timeout=`.
+const DEFAULT_DEVICE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// fstab option overriding `DEFAULT_DEVICE_TIMEOUT` for one entry.
+const DEVICE_TIMEOUT_OPTION: &str = "x-verdant.device-timeout=";
+
+const MOUNT_PATH: &str = "/bin/mount";
+
 /// Check if root `/` is read-only and remount as read-write if needed.
 pub fn remount_root(
     console_logger: &mut dyn ConsoleLogger,
@@ -48,88 +62,295 @@ fn is_root_readonly() -> Result<bool, BloomError> {
     Ok(false)
 }
 
-/// Mount entries in /etc/fstab except the root `/`.
+/// One filesystem to mount, from /etc/fstab or a `[[mount]]` table.
+struct MountEntry {
+    source: String,
+    target: String,
+    fstype: Option<String>,
+    options: String,
+    timeout: Duration,
+    required: bool,
+}
+
+/// Mount entries in /etc/fstab and `[[mount]]` tables except the root `/`
+/// and those needing the network, which verdantd asks for once it is
+/// online. Fails only if a mount marked `required` could not be made.
 pub fn mount_fstab_filesystems(
+    mounts: &[MountConfig],
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    mount_entries(mounts, console_logger, file_logger, false)
+}
+
+/// Mount the `_netdev` and network filesystem entries skipped at boot.
+pub fn mount_network_filesystems(
+    mounts: &[MountConfig],
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    mount_entries(mounts, console_logger, file_logger, true)
+}
+
+fn mount_entries(
+    mounts: &[MountConfig],
     console_logger: &mut dyn ConsoleLogger,
     file_logger: &mut dyn FileLogger,
+    network: bool,
 ) -> Result<(), BloomError> {
     let timer = ProcessTimer::start();
-    let fstab = File::open("/etc/fstab").map_err(BloomError::Io)?;
 
-    for line_result in BufReader::new(fstab).lines() {
-        let line = line_result.map_err(BloomError::Io)?.trim().to_string();
-        if line.is_empty() || line.starts_with('#') {
+    // Automounts are verdantd's, mounted on first access
+    let configured = mounts.iter().filter(|m| !m.automount).map(|m| MountEntry {
+        source: m.source.clone(),
+        target: m.target.clone(),
+        fstype: m.fstype.clone(),
+        options: m.options.clone(),
+        timeout: m.wait.map(Duration::from_secs).unwrap_or_else(|| device_timeout(&m.options)),
+        required: m.required,
+    });
+    let entries: Vec<MountEntry> = fstab_entries(console_logger, file_logger, &timer).into_iter().chain(configured).collect();
+
+    let mut failed_required = Vec::new();
+
+    for entry in &entries {
+        let target = entry.target.as_str();
+        let fstype = entry.fstype.as_deref().unwrap_or_default();
+
+        if target == "/" || target == "none" || !Path::new(target).is_absolute() {
             continue;
         }
 
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        if fields.len() < 4 {
-            log_success(console_logger, file_logger, &timer, LogLevel::Warn, &format!("Skipping invalid fstab line: {}", line));
+        if entry.options.split(',').any(|opt| opt == "noauto") || is_network_mount(fstype, &entry.options) != network {
             continue;
         }
 
-        let source = fields[0];
-        let target = fields[1];
-        let fstype = fields[2];
-        let options = fields[3];
+        // Already mounted by an earlier request
+        if network && crate::filesystem::is_mounted(target).unwrap_or(false) {
+            continue;
+        }
 
-        if target == "/" || target == "none" || !Path::new(target).is_absolute() {
+        if mount_entry(entry, network, console_logger, file_logger, &timer).is_err() && entry.required {
+            failed_required.push(target);
+        }
+    }
+
+    if failed_required.is_empty() {
+        Ok(())
+    } else {
+        let msg = format!("Required mounts failed: {}", failed_required.join(", "));
+        log_error(console_logger, file_logger, &timer, LogLevel::Fail, &msg);
+        Err(BloomError::Custom(msg))
+    }
+}
+
+/// Entries in /etc/fstab; a missing file has none.
+fn fstab_entries(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+    timer: &ProcessTimer,
+) -> Vec<MountEntry> {
+    let fstab = match fs::read_to_string(FSTAB_PATH) {
+        Ok(fstab) => fstab,
+        Err(e) => {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log_error(console_logger, file_logger, timer, LogLevel::Warn, &format!("Cannot read {}: {}", FSTAB_PATH, e));
+            }
+            return Vec::new();
+        }
+    };
+
+    let mut entries = Vec::new();
+
+    for line in fstab.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
             continue;
         }
 
-        if options.split(',').any(|opt| opt == "noauto") {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            log_success(console_logger, file_logger, timer, LogLevel::Warn, &format!("Skipping invalid fstab line: {}", line));
             continue;
         }
 
-        let target_path = Path::new(target);
-        if !target_path.exists() {
-            if let Err(e) = fs::create_dir_all(target_path) {
-                log_error(console_logger, file_logger, &timer, LogLevel::Warn, &format!("Failed to create mount point {}: {}", target, e));
-                continue;
-            }
+        entries.push(MountEntry {
+            source: fields[0].to_string(),
+            target: fields[1].to_string(),
+            fstype: Some(fields[2].to_string()),
+            options: fields[3].to_string(),
+            timeout: device_timeout(fields[3]),
+            required: false,
+        });
+    }
+
+    entries
+}
+
+/// Mount one entry, logging how it went.
+fn mount_entry(
+    entry: &MountEntry,
+    network: bool,
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+    timer: &ProcessTimer,
+) -> Result<(), BloomError> {
+    let target = entry.target.as_str();
+
+    let target_path = Path::new(target);
+    if !target_path.exists() {
+        if let Err(e) = fs::create_dir_all(target_path) {
+            log_error(console_logger, file_logger, timer, LogLevel::Warn, &format!("Failed to create mount point {}: {}", target, e));
+            return Err(BloomError::Io(e));
         }
+    }
 
-        let resolved_source = match resolve_source(source) {
-            Ok(s) => s,
+    // Hostnames and credentials are left to the mount.<type> helpers
+    if network {
+        return match mount_with_helper(entry) {
+            Ok(()) => {
+                log_success(console_logger, file_logger, timer, LogLevel::Ok, &format!("Mounted {}", target));
+                Ok(())
+            }
             Err(e) => {
-                log_error(console_logger, file_logger, &timer, LogLevel::Warn, &format!("Failed to resolve {}: {}", source, e));
-                continue;
+                log_error(console_logger, file_logger, timer, LogLevel::Fail, &format!("Mount failed for {}: {}", target, e));
+                Err(e)
             }
         };
+    }
 
-        let (flags, data) = split_mount_options(options);
-
-        if let Err(e) = crate::filesystem::mount_fs(
-            Some(&resolved_source),
-            target,
-            Some(fstype),
-            flags,
-            data.as_deref(),
-            &format!("fstab entry {}", target),
-            console_logger,
-            file_logger,
-            &timer,
-        ) {
-            let level = if e.to_string().contains("EINVAL") || e.to_string().contains("ENOENT") {
-                LogLevel::Warn
-            } else {
-                LogLevel::Fail
-            };
-            log_error(console_logger, file_logger, &timer, level, &format!("Mount failed for {}: {}", target, e));
+    let resolved_source = match wait_for_source(&entry.source, entry.timeout) {
+        Ok(s) => s,
+        Err(e) => {
+            log_error(console_logger, file_logger, timer, LogLevel::Warn, &format!("Failed to resolve {}: {}", entry.source, e));
+            return Err(e);
         }
+    };
+
+    let (flags, data) = split_mount_options(&entry.options);
+
+    // The type column of a bind mount is only a placeholder
+    let bind = flags.contains(MsFlags::MS_BIND);
+    let fstype = match entry.fstype.as_deref() {
+        _ if bind => None,
+        Some(fstype) => Some(fstype),
+        None => {
+            let msg = format!("Mount failed for {}: no filesystem type given", target);
+            log_error(console_logger, file_logger, timer, LogLevel::Fail, &msg);
+            return Err(BloomError::Custom(msg));
+        }
+    };
+
+    if let Err(e) = crate::filesystem::mount_fs(
+        Some(&resolved_source),
+        target,
+        fstype,
+        flags,
+        data.as_deref(),
+        &format!("fstab entry {}", target),
+        console_logger,
+        file_logger,
+        timer,
+    ) {
+        let level = if e.to_string().contains("EINVAL") || e.to_string().contains("ENOENT") {
+            LogLevel::Warn
+        } else {
+            LogLevel::Fail
+        };
+        log_error(console_logger, file_logger, timer, level, &format!("Mount failed for {}: {}", target, e));
+        return Err(e);
+    }
+
+    if bind
+        && flags.intersects(BIND_PER_MOUNT_FLAGS)
+        && let Err(e) = remount_bind(target, flags)
+    {
+        log_error(console_logger, file_logger, timer, LogLevel::Warn, &format!("Cannot apply ro, nosuid, nodev or noexec to bind mount {}: {}", target, e));
     }
 
     Ok(())
 }
 
+/// Flags a bind mount ignores when it is made.
+const BIND_PER_MOUNT_FLAGS: MsFlags = MsFlags::MS_RDONLY
+    .union(MsFlags::MS_NOSUID)
+    .union(MsFlags::MS_NODEV)
+    .union(MsFlags::MS_NOEXEC);
+
+/// A bind mount ignores `ro`, `nosuid`, `nodev` and `noexec` when it is
+/// made; they only take effect through a remount of the bind mount itself.
+fn remount_bind(target: &str, flags: MsFlags) -> nix::Result<()> {
+    mount(
+        None::<&str>,
+        target,
+        None::<&str>,
+        MsFlags::MS_REMOUNT | MsFlags::MS_BIND | (flags & BIND_PER_MOUNT_FLAGS),
+        None::<&str>,
+    )
+}
+
+/// Mount an entry through mount(8).
+fn mount_with_helper(entry: &MountEntry) -> Result<(), BloomError> {
+    let mut command = Command::new(MOUNT_PATH);
+    if let Some(fstype) = &entry.fstype {
+        command.args(["-t", fstype]);
+    }
+    let output = command
+        .args(["-o", &entry.options, &entry.source, &entry.target])
+        .output()
+        .map_err(BloomError::Io)?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(BloomError::Custom(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+    }
+}
+
+/// Timeout for an entry's device: `x-verdant.device-timeout=` if given,
+/// otherwise the default, or none at all for `nofail` entries, which are
+/// expected to be missing at times.
+pub(crate) fn device_timeout(options: &str) -> Duration {
+    let explicit = options
+        .split(',')
+        .find_map(|opt| opt.strip_prefix(DEVICE_TIMEOUT_OPTION))
+        .and_then(parse_timeout);
+
+    match explicit {
+        Some(timeout) => timeout,
+        None if options.split(',').any(|opt| opt == "nofail") => Duration::ZERO,
+        None => DEFAULT_DEVICE_TIMEOUT,
+    }
+}
+
+/// Plain seconds, or a number with an `ms`, `s` or `min` suffix.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: u64 = number.parse().ok()?;
+
+    match unit {
+        "ms" => Some(Duration::from_millis(number)),
+        "" | "s" => Some(Duration::from_secs(number)),
+        "min" => number.checked_mul(60).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Resolve `source`, waiting up to `timeout` for a device that is still
+/// being enumerated. Sources that are not devices are resolved only once.
+pub(crate) fn wait_for_source(source: &str, timeout: Duration) -> Result<String, BloomError> {
+    if is_device_spec(source) {
+        wait_for_device(source, timeout)
+    } else {
+        resolve_source(source)
+    }
+}
+
 /// Resolve UUID= or LABEL= sources to device paths
 /// For pseudo-filesystems like tmpfs, proc, etc., return as-is.
-fn resolve_source(source: &str) -> Result<String, BloomError> {
-    if source.starts_with("UUID=") {
-        return resolve_symlink_target("/dev/disk/by-uuid", &source[5..]);
-    }
-    if source.starts_with("LABEL=") {
-        return resolve_symlink_target("/dev/disk/by-label", &source[6..]);
+pub(crate) fn resolve_source(source: &str) -> Result<String, BloomError> {
+    if is_device_spec(source) {
+        return resolve_device(source).ok_or_else(|| BloomError::Custom(format!("Device {} does not exist", source)));
     }
 
     // Pseudo-filesystems or filesystems like tmpfs, proc, etc.
@@ -150,31 +371,6 @@ fn resolve_source(source: &str) -> Result<String, BloomError> {
     }
 }
 
-fn resolve_symlink_target(base_dir: &str, name: &str) -> Result<String, BloomError> {
-    let path = Path::new(base_dir).join(name);
-    if !path.exists() {
-        return Err(BloomError::Custom(format!("{} does not exist", path.display())));
-    }
-
-    let target = fs::read_link(&path)
-        .map_err(|e| BloomError::Custom(format!("Failed to read symlink {}: {}", path.display(), e)))?;
-
-    let full_path = if target.is_absolute() {
-        target
-    } else {
-        path.parent().unwrap_or(Path::new("/")).join(target)
-    };
-
-    let canonical = fs::canonicalize(&full_path)
-        .map_err(|e| BloomError::Custom(format!("Failed to canonicalize {}: {}", full_path.display(), e)))?;
-
-    if canonical.exists() {
-        Ok(canonical.to_string_lossy().to_string())
-    } else {
-        Err(BloomError::Custom(format!("Resolved device {} does not exist", canonical.display())))
-    }
-}
-
 /// Helper: split mount options into MsFlags and data string for mount syscall
 fn split_mount_options(options: &str) -> (MsFlags, Option<String>) {
     let mut flags = MsFlags::empty();
@@ -190,6 +386,13 @@ fn split_mount_options(options: &str) -> (MsFlags, Option<String>) {
             "relatime" => flags |= MsFlags::MS_RELATIME,
             "nodiratime" => flags |= MsFlags::MS_NODIRATIME,
             "sync" => flags |= MsFlags::MS_SYNCHRONOUS,
+            "noatime" => flags |= MsFlags::MS_NOATIME,
+            "bind" => flags |= MsFlags::MS_BIND,
+            "rbind" => flags |= MsFlags::MS_BIND | MsFlags::MS_REC,
+            // For userspace only, the kernel rejects them
+            "defaults" | "auto" | "noauto" | "nofail" | "_netdev" => {}
+            "user" | "nouser" | "users" | "owner" | "group" => {}
+            x if x.starts_with("x-") || x.starts_with("comment=") => {}
             other => data_opts.push(other),
         }
     }
@@ -303,3 +506,25 @@ pub fn check_filesystem_health(
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_units() {
+        assert_eq!(parse_timeout("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_timeout("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_timeout("2min"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_timeout("2h"), None);
+        assert_eq!(parse_timeout(""), None);
+    }
+
+    #[test]
+    fn overflowing_timeout_is_rejected() {
+        let huge = format!("{}min", u64::MAX / 60 + 1);
+        assert_eq!(parse_timeout(&huge), None);
+
+        let options = format!("defaults,{}{}", DEVICE_TIMEOUT_OPTION, huge);
+        assert_eq!(device_timeout(&options), DEFAULT_DEVICE_TIMEOUT);
+    }
+}
@@ -0,0 +1,158 @@
+use std::fs;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+
+use bloom::config::PowerFailureConfig;
+use bloom::ipc::{send_ipc_request, IpcCommand, IpcRequest, IpcTarget, PowerAction, VERDANTD_SOCKET_PATH};
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+use crate::actions;
+
+/// Where UPS daemons leave the power status before sending SIGPWR, in the
+/// order they are checked.
+const POWERSTATUS_PATHS: &[&str] = &["/run/powerstatus", "/etc/powerstatus"];
+
+/// Power state reported along with SIGPWR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerStatus {
+    /// `F`: running on battery.
+    Failing,
+    /// `L`: the battery is about to run out.
+    Low,
+    /// `O`: mains power is back.
+    Restored,
+}
+
+/// Read and remove the status file. Without one, SIGPWR means a power
+/// failure, as it does for sysvinit.
+pub fn read_power_status() -> PowerStatus {
+    for path in POWERSTATUS_PATHS {
+        if let Ok(status) = fs::read_to_string(path) {
+            let _ = fs::remove_file(path);
+            return match status.trim_start().chars().next() {
+                Some('O') => PowerStatus::Restored,
+                Some('L') => PowerStatus::Low,
+                _ => PowerStatus::Failing,
+            };
+        }
+    }
+
+    PowerStatus::Failing
+}
+
+/// Start the thread that handles power events, one at a time and in the
+/// order they came in. The signal thread only sends to the returned channel,
+/// so talking to verdantd never holds up reaping children.
+pub fn spawn_power_worker(
+    config: PowerFailureConfig,
+    pending: Arc<Mutex<Option<PowerAction>>>,
+    main_thread: Thread,
+    file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>>,
+    console_logger: Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
+) -> Sender<()> {
+    let (tx, rx) = channel();
+
+    thread::spawn(move || {
+        let timer = ProcessTimer::start();
+        for () in rx {
+            handle_power_event(&config, &pending, &main_thread, &file_logger, &console_logger, &timer);
+        }
+    });
+
+    tx
+}
+
+/// React to a SIGPWR: on failure start the configured service and schedule
+/// a shutdown through verdantd, on a low battery shut down at once, and
+/// once power is back call it all off again.
+fn handle_power_event(
+    config: &PowerFailureConfig,
+    pending: &Mutex<Option<PowerAction>>,
+    main_thread: &Thread,
+    file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
+    console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
+    timer: &ProcessTimer,
+) {
+    let log = |level: LogLevel, msg: &str| {
+        if let Ok(mut file) = file_logger.lock() {
+            file.log(level, msg);
+        }
+        if let Ok(mut con) = console_logger.lock() {
+            con.message(level, msg, timer.elapsed());
+        }
+    };
+
+    match read_power_status() {
+        PowerStatus::Failing => {
+            log(LogLevel::Warn, "Power failure: running on battery");
+
+            if let Some(service) = &config.service
+                && let Err(e) = verdantd(IpcCommand::StartService(service.clone()))
+            {
+                log(LogLevel::Fail, &format!("Cannot start '{}': {}", service, e));
+            }
+
+            if let Some(delay_secs) = config.shutdown_delay {
+                let command = IpcCommand::Shutdown {
+                    delay_secs,
+                    message: Some("Power failure: the system is running on battery".into()),
+                };
+                if let Err(e) = verdantd(command) {
+                    log(LogLevel::Fail, &format!("Cannot schedule shutdown: {}", e));
+                    if delay_secs == 0 {
+                        shut_down(pending, main_thread);
+                    }
+                }
+            }
+        }
+
+        PowerStatus::Low => {
+            log(LogLevel::Fail, "UPS battery low, shutting down");
+
+            let command = IpcCommand::Shutdown {
+                delay_secs: 0,
+                message: Some("UPS battery low".into()),
+            };
+            // Without verdantd, at least get the filesystems unmounted in time
+            if let Err(e) = verdantd(command) {
+                log(LogLevel::Fail, &format!("Cannot reach verdantd, shutting down directly: {}", e));
+                shut_down(pending, main_thread);
+            }
+        }
+
+        PowerStatus::Restored => {
+            log(LogLevel::Ok, "Power restored");
+
+            // Nothing may have been scheduled; that is fine
+            let _ = verdantd(IpcCommand::CancelShutdown);
+
+            if let Some(service) = &config.service
+                && let Err(e) = verdantd(IpcCommand::StopService(service.clone()))
+            {
+                log(LogLevel::Warn, &format!("Cannot stop '{}': {}", service, e));
+            }
+        }
+    }
+}
+
+fn shut_down(pending: &Mutex<Option<PowerAction>>, main_thread: &Thread) {
+    actions::request(pending, PowerAction::PowerOff);
+    main_thread.unpark();
+}
+
+fn verdantd(command: IpcCommand) -> Result<(), String> {
+    let request = IpcRequest {
+        id: 0,
+        target: IpcTarget::Verdantd,
+        command,
+    };
+
+    match send_ipc_request(VERDANTD_SOCKET_PATH, &request) {
+        Ok(response) if response.success => Ok(()),
+        Ok(response) => Err(response.message),
+        Err(e) => Err(e.to_string()),
+    }
+}
@@ -0,0 +1,118 @@
+use std::fs;
+use std::io;
+use std::os::fd::RawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::devices;
+use crate::state::InitState;
+
+/// State handed from the old init to the new one.
+pub const STATE_PATH: &str = "/run/verdant/init-state.json";
+
+/// Binary re-executed, if installed under this name; otherwise the one
+/// the kernel started.
+const INIT_PATH: &str = "/sbin/verdant-init";
+
+/// The IPC listener, kept open across the exec so no client is turned away.
+static LISTENER_FD: OnceLock<RawFd> = OnceLock::new();
+
+/// What a re-executed init needs to carry on where the old one was.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedState {
+    pub boot_duration_ms: Option<u64>,
+    /// verdantd stays a child of PID 1 through the exec.
+    pub verdantd_pid: Option<u32>,
+    pub verdantd_boot_complete: bool,
+    pub listener_fd: Option<RawFd>,
+    /// Its uevent thread has to be started again.
+    #[serde(default)]
+    pub builtin_device_manager: bool,
+}
+
+impl SavedState {
+    pub fn restore(&self, state: &mut InitState) {
+        state.boot_duration = self.boot_duration_ms.map(Duration::from_millis);
+        state.verdantd_pid = self.verdantd_pid;
+        state.verdantd_boot_complete = self.verdantd_boot_complete;
+    }
+}
+
+/// Note the IPC listener, so a re-exec can pass it on.
+pub fn register_listener(fd: RawFd) {
+    let _ = LISTENER_FD.set(fd);
+}
+
+/// Save the state, keep the IPC listener open and replace this process with
+/// the init binary on disk. PID 1 and its children stay. Only returns on
+/// failure.
+pub fn reexec(state: &InitState) -> io::Error {
+    let listener_fd = LISTENER_FD.get().copied();
+
+    let saved = SavedState {
+        boot_duration_ms: state.boot_duration.map(|d| d.as_millis() as u64),
+        verdantd_pid: state.verdantd_pid,
+        verdantd_boot_complete: state.verdantd_boot_complete,
+        listener_fd,
+        builtin_device_manager: devices::is_builtin_running(),
+    };
+
+    if let Some(fd) = listener_fd
+        && let Err(e) = set_cloexec(fd, false)
+    {
+        return e;
+    }
+
+    if let Err(e) = save_state(&saved) {
+        return e;
+    }
+
+    let mut args = std::env::args_os();
+    let argv0 = args.next().unwrap_or_else(|| INIT_PATH.into());
+    let program = if Path::new(INIT_PATH).is_file() { INIT_PATH.into() } else { argv0 };
+
+    let error = Command::new(program).args(args).exec();
+
+    // Still the old init: nothing to hand over after all
+    let _ = fs::remove_file(STATE_PATH);
+    if let Some(fd) = listener_fd {
+        let _ = set_cloexec(fd, true);
+    }
+    error
+}
+
+fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    let flags = if cloexec { libc::FD_CLOEXEC } else { 0 };
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn save_state(saved: &SavedState) -> io::Result<()> {
+    if let Some(parent) = Path::new(STATE_PATH).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_vec(saved).map_err(io::Error::other)?;
+    fs::write(STATE_PATH, json)
+}
+
+/// Read and remove the saved state, if this process was started by a re-exec.
+pub fn take_state() -> Option<SavedState> {
+    let contents = fs::read(STATE_PATH).ok()?;
+    let _ = fs::remove_file(STATE_PATH);
+
+    match serde_json::from_slice(&contents) {
+        Ok(saved) => Some(saved),
+        Err(e) => {
+            eprintln!("Ignoring unreadable {}: {}", STATE_PATH, e);
+            None
+        }
+    }
+}
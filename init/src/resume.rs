@@ -0,0 +1,76 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+
+use bloom::config::InitConfig;
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+use nix::sys::stat::{major, minor};
+
+use crate::mount::resolve_source;
+
+const RESUME_PATH: &str = "/sys/power/resume";
+const RESUME_OFFSET_PATH: &str = "/sys/power/resume_offset";
+
+/// Resume from a hibernation image if there is one.
+///
+/// Points the kernel at the configured swap device, which makes it restore
+/// the image there and never return. Must run before anything is mounted
+/// read-write, since the restored system expects its filesystems as it
+/// left them. With no image the boot simply continues, and the device stays
+/// set for a later `vctl hibernate`.
+pub fn resume_from_hibernation(
+    config: &InitConfig,
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let Some((device, offset)) = config.resume_device() else {
+        file_logger.log(LogLevel::Info, "No resume device configured, skipping resume");
+        return Ok(());
+    };
+
+    let number = match device_number(&device) {
+        Ok(number) => number,
+        Err(e) => {
+            let msg = format!("Cannot resume from {}: {}", device, e);
+            console_logger.message(LogLevel::Warn, &msg, timer.elapsed());
+            file_logger.log(LogLevel::Warn, &msg);
+            return Err(e);
+        }
+    };
+
+    // The offset has to be in place before the device is
+    if let Some(offset) = offset {
+        fs::write(RESUME_OFFSET_PATH, offset.to_string()).map_err(BloomError::Io)?;
+    }
+
+    file_logger.log(LogLevel::Info, &format!("Checking {} ({}) for a hibernation image", device, number));
+    fs::write(RESUME_PATH, &number).map_err(BloomError::Io)?;
+
+    // Still here: there was no image to restore
+    console_logger.message(LogLevel::Info, "No hibernation image found", timer.elapsed());
+    file_logger.log(LogLevel::Info, &format!("No hibernation image on {}", device));
+    Ok(())
+}
+
+/// `major:minor` of a device given as a path, `UUID=`, `LABEL=` or
+/// `major:minor` already.
+fn device_number(device: &str) -> Result<String, BloomError> {
+    if let Some((maj, min)) = device.split_once(':')
+        && maj.parse::<u64>().is_ok()
+        && min.parse::<u64>().is_ok()
+    {
+        return Ok(device.to_string());
+    }
+
+    let path = resolve_source(device)?;
+    let rdev = fs::metadata(&path).map_err(BloomError::Io)?.rdev();
+    if rdev == 0 {
+        return Err(BloomError::Custom(format!("{} is not a block device", path)));
+    }
+
+    Ok(format!("{}:{}", major(rdev), minor(rdev)))
+}
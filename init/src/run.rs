@@ -1,29 +1,45 @@
 use std::io::Write;
 use std::sync::{Arc, Mutex};
 
-use bloom::log::{ConsoleLogger, ConsoleLoggerImpl, FileLogger, FileLoggerImpl};
+use bloom::config::{is_emergency_boot, load_config, VerdantConfig};
+use bloom::log::{ConsoleLogger, ConsoleLoggerImpl, FileLogger, FileLoggerImpl, BOOT_LOG_PATH};
 use bloom::status::LogLevel;
-use bloom::time::SystemTimer;
+use bloom::time::{BootTime, BootTimes, ProcessTimer, SystemTimer};
 
-use crate::device_manager::{monitor_udev_events, start_device_manager};
-use crate::env::set_basic_env_vars;
+use crate::crypttab::open_crypttab;
+use crate::device_manager::{monitor_uevents, start_device_manager};
+use crate::devices::resume_builtin_manager;
+use crate::env::{set_basic_env_vars, set_locale_env_vars};
 use crate::filesystem::{mount_virtual_filesystems, mount_securityfs};
+use crate::firewall::load_firewall_ruleset;
 use crate::hardware_drivers::load_hardware_drivers;
+use crate::hostname::set_hostname;
 use crate::kernel::{apply_sysctl_settings, load_kernel_modules};
+use crate::machine_id::ensure_machine_id;
 use crate::mount::{check_filesystem_health, mount_fstab_filesystems, remount_root};
 use crate::network::setup_networks;
+use crate::reexec::SavedState;
+use crate::resume::resume_from_hibernation;
 use crate::seed::seed_entropy;
-use crate::utils::{detect_timezone, set_hostname, sync_clock_from_hardware};
+use crate::stateless::setup_overlays;
+use crate::storage::{activate_lvm, assemble_raid, grow_root, scan_btrfs};
+use crate::swap::activate_swap;
+use crate::tmpfiles::apply_tmpfiles;
+use crate::utils::{detect_timezone, sync_clock_from_hardware};
+use crate::vconsole::setup_vconsole;
+use crate::zram::setup_zram;
 
 pub fn boot() -> (
     Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     Arc<Mutex<dyn FileLogger + Send + Sync>>,
     SystemTimer,
+    VerdantConfig,
 ) {
     let console_logger: Arc<Mutex<dyn ConsoleLogger + Send + Sync>> =
-        Arc::new(Mutex::new(ConsoleLoggerImpl::new(LogLevel::Info)));
-    let file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>> =
-        Arc::new(Mutex::new(FileLoggerImpl::new(LogLevel::Info, "/var/log/verdant/init.log")));
+        Arc::new(Mutex::new(ConsoleLoggerImpl::new(LogLevel::Info).with_boot_log(BOOT_LOG_PATH, true)));
+    let file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>> = Arc::new(Mutex::new(
+        FileLoggerImpl::new(LogLevel::Info, "/var/log/verdant/init.log").with_journal("init"),
+    ));
 
     let start_time = SystemTimer::new();
 
@@ -36,47 +52,171 @@ pub fn boot() -> (
         con_log.banner(&format!("Verdant Init v{} - Rooted in Resilience", env!("CARGO_PKG_VERSION")));
     }
 
+    let config = load_config_or_default(&console_logger, &file_logger, &start_time);
+
+    // Each step's duration ends up in `vctl blame`
+    let mut times = Vec::new();
+
     // Setup phase: call funcs passing Arc<Mutex<_>> refs directly
-    let _ = set_hostname(&console_logger, &file_logger);
-    let _ = detect_timezone(&console_logger, &file_logger);
-    let _ = mount_virtual_filesystems(&console_logger, &file_logger);
-    let _ = start_device_manager(&console_logger, &file_logger);
-    let _ = load_kernel_modules(&console_logger, &file_logger);
-    let _ = apply_sysctl_settings(&console_logger, &file_logger);
-
-    // Spawn udev monitor thread — clone and move Arc
-    {
-        let file_logger_clone = Arc::clone(&file_logger);
-        std::thread::spawn(move || {
-            if let Err(e) = monitor_udev_events(&file_logger_clone) {
-                if let Ok(mut log) = file_logger_clone.lock() {
-                    log.log(LogLevel::Fail, &format!("udev event monitor failed: {}", e));
-                }
-            }
-        });
+    let _ = timed(&mut times, "timezone", || detect_timezone(&console_logger, &file_logger));
+    let _ = timed(&mut times, "virtual-filesystems", || mount_virtual_filesystems(&console_logger, &file_logger));
+    // After /proc is mounted, for hostname= on the kernel command line
+    let _ = timed(&mut times, "hostname", || set_hostname(&config.init, &console_logger, &file_logger));
+
+    if is_emergency_boot() {
+        if let Ok(mut con_log) = console_logger.lock() {
+            con_log.message(LogLevel::Warn, "Emergency mode: exit the shell to continue booting", start_time.elapsed());
+        }
+        crate::spawn_emergency_shell(&file_logger);
     }
 
+    let _ = timed(&mut times, "device-manager", || start_device_manager(&console_logger, &file_logger));
+    let _ = timed(&mut times, "kernel-modules", || load_kernel_modules(&console_logger, &file_logger));
+    let _ = timed(&mut times, "sysctl", || apply_sysctl_settings(&console_logger, &file_logger));
+
+    spawn_uevent_monitor(&config, &file_logger);
+
     // Continue boot, calling functions with Arc<Mutex<_>> refs
-    let _ = load_hardware_drivers(&console_logger, &file_logger);
+    let _ = timed(&mut times, "hardware-drivers", || load_hardware_drivers(&console_logger, &file_logger));
 
     // For operations needing multiple logs locked, lock explicitly once:
     {
         let mut con_log = console_logger.lock().unwrap();
         let mut file_log = file_logger.lock().unwrap();
 
-        let _ = check_filesystem_health(&mut *con_log, &mut *file_log);
-        let _ = remount_root(&mut *con_log, &mut *file_log);
-        let _ = mount_fstab_filesystems(&mut *con_log, &mut *file_log);
-        let _ = mount_securityfs(&mut *con_log, &mut *file_log);
+        // Block devices stacked on others, which fstab and resume may refer to
+        let _ = timed(&mut times, "raid", || assemble_raid(&mut *con_log, &mut *file_log));
+        // Before LVM, so volume groups on encrypted devices are found
+        let _ = timed(&mut times, "crypttab", || open_crypttab(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "lvm", || activate_lvm(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "btrfs-scan", || scan_btrfs(&mut *con_log, &mut *file_log));
+
+        // A hibernated system resumes here, before anything is written to disk
+        let _ = timed(&mut times, "resume", || resume_from_hibernation(&config.init, &mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "fsck", || check_filesystem_health(&mut *con_log, &mut *file_log));
+        if !config.init.stateless.enabled {
+            let _ = timed(&mut times, "remount-root", || remount_root(&mut *con_log, &mut *file_log));
+            let _ = timed(&mut times, "grow-root", || grow_root(&config.init, &mut *con_log, &mut *file_log));
+        }
+        let mounted =
+            timed(&mut times, "fstab", || mount_fstab_filesystems(&config.mounts, &mut *con_log, &mut *file_log));
+        if mounted.is_err() {
+            con_log.message(
+                LogLevel::Fail,
+                "A required mount failed. Dropping to emergency shell, exit it to continue booting.",
+                start_time.elapsed(),
+            );
+            // Other threads keep logging while the shell runs
+            drop(con_log);
+            drop(file_log);
+            crate::spawn_emergency_shell(&file_logger);
+            con_log = console_logger.lock().unwrap();
+            file_log = file_logger.lock().unwrap();
+        }
+        let _ = timed(&mut times, "swap", || activate_swap(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "overlays", || setup_overlays(&config.init.stateless, &mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "zram", || setup_zram(&config.init.zram, &mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "securityfs", || mount_securityfs(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "tmpfiles", || apply_tmpfiles(&mut *con_log, &mut *file_log));
 
         let _ = file_log.initialize(&mut *con_log);
+        if let Err(e) = con_log.open_boot_log() {
+            file_log.log(LogLevel::Warn, &format!("Cannot write {}: {}", BOOT_LOG_PATH, e));
+        }
 
-        let _ = seed_entropy(&mut *con_log, &mut *file_log);
-        let _ = sync_clock_from_hardware(&mut *con_log, &mut *file_log);
-        let _ = set_basic_env_vars(&mut *con_log, &mut *file_log);
-        let _ = setup_networks(&mut *con_log, &mut *file_log);
+        let _ = timed(&mut times, "entropy", || seed_entropy(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "machine-id", || ensure_machine_id(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "hardware-clock", || sync_clock_from_hardware(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "environment", || set_basic_env_vars(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "locale", || set_locale_env_vars(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "vconsole", || {
+            setup_vconsole(&config.init.tty_sessions, &mut *con_log, &mut *file_log)
+        });
+
+        // Packet filter must be in place before any interface comes up
+        let _ = timed(&mut times, "firewall", || load_firewall_ruleset(&mut *con_log, &mut *file_log));
+        let _ = timed(&mut times, "network", || setup_networks(&mut *con_log, &mut *file_log));
+
+        // verdantd adds its services to this once they are up
+        let boot_times = BootTimes { init: times, services: Vec::new() };
+        if let Err(e) = boot_times.save() {
+            file_log.log(LogLevel::Warn, &format!("Cannot record boot times: {}", e));
+        }
     }
 
-    (console_logger, file_logger, start_time)
+    (console_logger, file_logger, start_time, config)
 }
 
+/// Set logging and configuration up again after a re-exec, and restart the
+/// threads following uevents; everything else the boot did is still in place.
+pub fn resume(saved: &SavedState) -> (
+    Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
+    Arc<Mutex<dyn FileLogger + Send + Sync>>,
+    SystemTimer,
+    VerdantConfig,
+) {
+    let console_logger: Arc<Mutex<dyn ConsoleLogger + Send + Sync>> =
+        Arc::new(Mutex::new(ConsoleLoggerImpl::new(LogLevel::Info)));
+    let file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>> = Arc::new(Mutex::new(
+        FileLoggerImpl::new(LogLevel::Info, "/var/log/verdant/init.log").with_journal("init"),
+    ));
+
+    let start_time = SystemTimer::new();
+
+    if let (Ok(mut con_log), Ok(mut file_log)) = (console_logger.lock(), file_logger.lock()) {
+        let _ = file_log.initialize(&mut *con_log);
+    }
+
+    let config = load_config_or_default(&console_logger, &file_logger, &start_time);
+
+    spawn_uevent_monitor(&config, &file_logger);
+    if saved.builtin_device_manager
+        && let Err(e) = resume_builtin_manager(&file_logger)
+        && let Ok(mut file_log) = file_logger.lock()
+    {
+        file_log.log(LogLevel::Fail, &format!("Cannot resume the built-in device manager: {}", e));
+    }
+
+    (console_logger, file_logger, start_time, config)
+}
+
+/// Follow uevents on a thread of its own for as long as init runs.
+fn spawn_uevent_monitor(config: &VerdantConfig, file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>) {
+    let file_logger = Arc::clone(file_logger);
+    let firmware_dirs = config.init.firmware_dirs.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = monitor_uevents(&firmware_dirs, &file_logger)
+            && let Ok(mut log) = file_logger.lock()
+        {
+            log.log(LogLevel::Fail, &format!("uevent monitor failed: {}", e));
+        }
+    });
+}
+
+fn load_config_or_default(
+    console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
+    file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
+    start_time: &SystemTimer,
+) -> VerdantConfig {
+    match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            let msg = format!("Failed to load configuration, using defaults: {}", e);
+            if let Ok(mut con_log) = console_logger.lock() {
+                con_log.message(LogLevel::Warn, &msg, start_time.elapsed());
+            }
+            if let Ok(mut file_log) = file_logger.lock() {
+                file_log.log(LogLevel::Warn, &msg);
+            }
+            VerdantConfig::default()
+        }
+    }
+}
+
+/// Run one boot step and note how long it took.
+fn timed<T>(times: &mut Vec<BootTime>, name: &str, step: impl FnOnce() -> T) -> T {
+    let timer = ProcessTimer::start();
+    let result = step();
+    times.push(BootTime::new(name, timer.elapsed()));
+    result
+}
@@ -1,8 +1,9 @@
-use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+use bloom::config::PowerFailureConfig;
 use bloom::errors::BloomError;
+use bloom::ipc::PowerAction;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::time::ProcessTimer;
@@ -11,12 +12,15 @@ use libc::SIGPWR;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use signal_hook::{consts::signal::*, iterator::Signals};
 
+use crate::actions;
+use crate::powerfail::spawn_power_worker;
+
 pub fn install_signal_handlers(
-    shutdown_flag: Arc<AtomicBool>,
-    reboot_flag: Arc<AtomicBool>,
+    pending: Arc<Mutex<Option<PowerAction>>>,
     file_logger: Arc<Mutex<dyn FileLogger + Send + Sync>>,
     console_logger: Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     main_thread: std::thread::Thread,
+    power_failure: PowerFailureConfig,
 ) -> Result<(), BloomError> {
     let handled_signals = &[
         SIGCHLD,
@@ -30,6 +34,14 @@ pub fn install_signal_handlers(
     let mut signals = Signals::new(handled_signals)
         .map_err(|e| BloomError::Custom(format!("Failed to register signals: {e}")))?;
 
+    let power_events = spawn_power_worker(
+        power_failure,
+        Arc::clone(&pending),
+        main_thread.clone(),
+        Arc::clone(&file_logger),
+        Arc::clone(&console_logger),
+    );
+
     thread::spawn(move || {
         let timer = ProcessTimer::start();
 
@@ -57,10 +69,13 @@ pub fn install_signal_handlers(
                     }
                 }
 
-                SIGTERM | SIGINT | SIGPWR => {
+                SIGPWR => {
+                    let _ = power_events.send(());
+                }
+
+                SIGTERM | SIGINT => {
                     let msg = match signal {
                         SIGINT => "Ignored SIGINT (Ctrl+C)",
-                        SIGPWR => "Ignored SIGPWR (power event)",
                         _ => "Ignored signal",
                     };
 
@@ -83,7 +98,7 @@ pub fn install_signal_handlers(
                         con.message(LogLevel::Warn, msg, timer.elapsed());
                     }
 
-                    shutdown_flag.store(true, Ordering::SeqCst);
+                    actions::request(&pending, PowerAction::PowerOff);
                     main_thread.unpark();
                 }
 
@@ -96,7 +111,7 @@ pub fn install_signal_handlers(
                         con.message(LogLevel::Warn, msg, timer.elapsed());
                     }
 
-                    reboot_flag.store(true, Ordering::SeqCst);
+                    actions::request(&pending, PowerAction::Reboot { firmware_setup: false });
                     main_thread.unpark();
                 }
 
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use bloom::config::VerdantConfig;
+
+/// Coarse lifecycle phase of PID 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BootPhase {
+    Booting,
+    Running,
+    ShuttingDown,
+}
+
+/// Runtime state shared between the main loop and the IPC server,
+/// reported by `vctl --target init status`.
+#[derive(Debug)]
+pub struct InitState {
+    pub config: VerdantConfig,
+    pub phase: BootPhase,
+    pub boot_duration: Option<Duration>,
+    pub verdantd_pid: Option<u32>,
+    pub verdantd_boot_complete: bool,
+}
+
+impl InitState {
+    pub fn new(config: VerdantConfig) -> Self {
+        Self {
+            config,
+            phase: BootPhase::Booting,
+            boot_duration: None,
+            verdantd_pid: None,
+            verdantd_boot_complete: false,
+        }
+    }
+
+    /// Build the JSON status document returned for `IpcCommand::GetStatus`.
+    pub fn snapshot(&self, pending_action: Option<&str>) -> Value {
+        let verdantd_running = self.verdantd_pid.map(is_pid_alive).unwrap_or(false);
+
+        json!({
+            "phase": self.phase,
+            "uptime_secs": read_uptime().map(|d| d.as_secs()),
+            "boot_duration_ms": self.boot_duration.map(|d| d.as_millis() as u64),
+            "pending_action": pending_action,
+            "mounts": mount_summary(),
+            "verdantd": {
+                "pid": self.verdantd_pid,
+                "running": verdantd_running,
+                "boot_complete": self.verdantd_boot_complete,
+            },
+        })
+    }
+}
+
+/// Seconds since the kernel booted, from /proc/uptime.
+fn read_uptime() -> Option<Duration> {
+    let contents = fs::read_to_string("/proc/uptime").ok()?;
+    let secs: f64 = contents.split_whitespace().next()?.parse().ok()?;
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// Summarize /proc/self/mounts: totals per filesystem type plus the list of
+/// block-backed mounts, which are the ones operators usually care about.
+fn mount_summary() -> Value {
+    let Ok(contents) = fs::read_to_string("/proc/self/mounts") else {
+        return Value::Null;
+    };
+
+    let mut by_type: BTreeMap<String, usize> = BTreeMap::new();
+    let mut devices = Vec::new();
+    let mut total = 0;
+
+    for line in contents.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            continue;
+        }
+
+        total += 1;
+        *by_type.entry(fields[2].to_string()).or_default() += 1;
+
+        if fields[0].starts_with("/dev/") {
+            devices.push(json!({
+                "source": fields[0],
+                "target": fields[1],
+                "fstype": fields[2],
+                "read_only": fields[3].split(',').any(|opt| opt == "ro"),
+            }));
+        }
+    }
+
+    json!({
+        "total": total,
+        "by_type": by_type,
+        "devices": devices,
+    })
+}
+
+/// True if `pid` exists and is not a zombie.
+pub fn is_pid_alive(pid: u32) -> bool {
+    match fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // The state field follows the parenthesised command name
+        Ok(stat) => stat
+            .rsplit_once(')')
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .map(|state| state != "Z" && state != "X")
+            .unwrap_or(false),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use nix::mount::{mount, MsFlags};
+
+use bloom::config::StatelessConfig;
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+use crate::mount::wait_for_source;
+
+/// Where the data partition is mounted.
+const DATA_DIR: &str = "/run/verdant/data";
+
+/// Holds the upper layers of overlays that do not persist.
+const VOLATILE_DIR: &str = "/run/verdant/overlay";
+
+/// How long to wait for the data partition to show up.
+const DATA_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Make the configured paths of a read-only root writable, each with an
+/// overlay whose upper layer is on the data partition when it should
+/// persist, or in RAM otherwise. Runs after fstab so /var and the like are
+/// overlaid on whatever is mounted there; a failing overlay is logged and
+/// its path stays read-only.
+pub fn setup_overlays(
+    config: &StatelessConfig,
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    if !config.enabled {
+        return Ok(());
+    }
+
+    let timer = ProcessTimer::start();
+    let mut log = |level: LogLevel, msg: &str| {
+        console_logger.message(level, msg, timer.elapsed());
+        file_logger.log(level, msg);
+    };
+
+    log(LogLevel::Info, "Stateless mode: root stays read-only");
+
+    let data = match &config.data {
+        Some(source) => match mount_data(source, &config.data_fstype) {
+            Ok(()) => true,
+            Err(e) => {
+                let msg = format!("Cannot mount data partition {}, changes will not persist: {}", source, e);
+                log(LogLevel::Warn, &msg);
+                false
+            }
+        },
+        None => false,
+    };
+
+    if config.overlays.iter().any(|overlay| !overlay.persistent || !data) {
+        let options = format!("mode=755,size={}", config.tmpfs_size);
+        let result = fs::create_dir_all(VOLATILE_DIR).map_err(BloomError::Io).and_then(|()| {
+            let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+            mount(Some("tmpfs"), VOLATILE_DIR, Some("tmpfs"), flags, Some(options.as_str())).map_err(BloomError::Nix)
+        });
+
+        if let Err(e) = result {
+            log(LogLevel::Fail, &format!("Cannot mount tmpfs for overlays at {}: {}", VOLATILE_DIR, e));
+            return Err(e);
+        }
+    }
+
+    // Built as a module on most kernels
+    if !config.overlays.is_empty() {
+        let _ = crate::kernel::load_module("overlay");
+    }
+
+    let mut failed = 0;
+    for overlay in &config.overlays {
+        let persistent = overlay.persistent && data;
+        let base = if persistent { Path::new(DATA_DIR).join("overlay") } else { PathBuf::from(VOLATILE_DIR) };
+
+        match mount_overlay(&overlay.path, &base) {
+            Ok(()) => {
+                let kind = if persistent { "persistent" } else { "volatile" };
+                log(LogLevel::Ok, &format!("Overlaid {} ({})", overlay.path, kind));
+            }
+            Err(e) => {
+                failed += 1;
+                log(LogLevel::Fail, &format!("Cannot overlay {}: {}", overlay.path, e));
+            }
+        }
+    }
+
+    if failed == 0 {
+        Ok(())
+    } else {
+        Err(BloomError::Custom(format!("{} overlay(s) failed", failed)))
+    }
+}
+
+fn mount_data(source: &str, fstype: &str) -> Result<(), BloomError> {
+    let device = wait_for_source(source, DATA_TIMEOUT)?;
+    fs::create_dir_all(DATA_DIR).map_err(BloomError::Io)?;
+
+    mount(Some(device.as_str()), DATA_DIR, Some(fstype), MsFlags::MS_NOATIME, None::<&str>).map_err(BloomError::Nix)
+}
+
+/// Overlay `path` onto itself, keeping the upper and work directories in
+/// `base` under a name derived from the path.
+fn mount_overlay(path: &str, base: &Path) -> Result<(), BloomError> {
+    if !Path::new(path).is_absolute() || path == "/" {
+        return Err(BloomError::Parse(format!("{} is not an absolute path below /", path)));
+    }
+
+    // /etc/ssh becomes etc-ssh
+    let dir = base.join(path.trim_matches('/').replace('/', "-"));
+    let (upper, work) = (dir.join("upper"), dir.join("work"));
+    fs::create_dir_all(&upper).map_err(BloomError::Io)?;
+    fs::create_dir_all(&work).map_err(BloomError::Io)?;
+
+    let options = format!("lowerdir={},upperdir={},workdir={}", path, upper.display(), work.display());
+    mount(Some("overlay"), path, Some("overlay"), MsFlags::empty(), Some(options.as_str())).map_err(BloomError::Nix)
+}
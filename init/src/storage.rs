@@ -0,0 +1,429 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use nix::sys::stat::{major, minor};
+
+use bloom::config::InitConfig;
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+/// The label of a physical volume is in one of the first four sectors.
+const LVM_LABEL_SECTORS: usize = 4;
+const SECTOR_SIZE: usize = 512;
+
+/// mdadm configuration, Red Hat and Debian style.
+const MDADM_CONF_PATHS: &[&str] = &["/etc/mdadm.conf", "/etc/mdadm/mdadm.conf"];
+
+/// Magic number of every md superblock version, little-endian on disk.
+const MD_MAGIC: u32 = 0xa92b_4efc;
+
+/// Registers devices of multi-device btrfs filesystems with the kernel.
+const BTRFS_CONTROL_PATH: &str = "/dev/btrfs-control";
+
+/// `_IOW(0x94, 4, struct btrfs_ioctl_vol_args)`
+const BTRFS_IOC_SCAN_DEV: u32 = 0x5000_9404;
+
+/// Where the btrfs superblock magic sits: 64 bytes into the superblock at 64K.
+const BTRFS_MAGIC_OFFSET: u64 = 0x1_0040;
+const BTRFS_MAGIC: &[u8; 8] = b"_BHRfS_M";
+
+/// Where tools looked up by name may be installed, in the order tried.
+const TOOL_DIRS: &[&str] = &["/sbin", "/usr/sbin", "/bin", "/usr/bin"];
+
+/// Free space at the end of a disk, in sectors, left for alignment and the
+/// backup GPT header; a root partition this close to the end is not grown.
+const GROW_SLACK_SECTORS: u64 = 2048;
+
+/// `struct btrfs_ioctl_vol_args`
+#[repr(C)]
+struct BtrfsVolArgs {
+    fd: i64,
+    name: [u8; 4088],
+}
+
+/// Activate every LVM volume group, so logical volumes named in fstab or as
+/// the resume device exist before anything is mounted. Skipped unless a
+/// block device carries a physical volume label and the LVM tools are
+/// installed.
+pub fn activate_lvm(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let volumes: Vec<String> = block_devices().into_iter().filter(|name| is_lvm_member(name)).collect();
+    if volumes.is_empty() {
+        file_logger.log(LogLevel::Info, "No LVM physical volumes, skipping activation");
+        return Ok(());
+    }
+
+    let Some(lvm) = find_program("lvm") else {
+        let msg = format!("LVM physical volumes found on {}, but lvm is not installed", volumes.join(", "));
+        log(console_logger, file_logger, &timer, LogLevel::Warn, &msg);
+        return Ok(());
+    };
+
+    // --sysinit: no lvmetad or polling daemons to talk to this early
+    match run(&lvm, &["vgchange", "--activate", "y", "--sysinit"]) {
+        Ok(()) => {
+            let msg = format!("Activated LVM volume groups on {}", volumes.join(", "));
+            log(console_logger, file_logger, &timer, LogLevel::Ok, &msg);
+            Ok(())
+        }
+        Err(e) => {
+            log(console_logger, file_logger, &timer, LogLevel::Fail, &format!("LVM activation failed: {}", e));
+            Err(e)
+        }
+    }
+}
+
+/// Assemble md software RAID arrays, so `/dev/md*` devices named in fstab
+/// exist before anything is mounted: arrays listed in mdadm.conf all at
+/// once, otherwise incrementally from each member device found. Members
+/// that show up later are added by the uevent monitor.
+pub fn assemble_raid(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let members: Vec<String> = block_devices().into_iter().filter(|name| is_raid_member(name)).collect();
+    if members.is_empty() {
+        file_logger.log(LogLevel::Info, "No md RAID members, skipping assembly");
+        return Ok(());
+    }
+
+    let Some(mdadm) = find_program("mdadm") else {
+        let msg = format!("md RAID members found on {}, but mdadm is not installed", members.join(", "));
+        log(console_logger, file_logger, &timer, LogLevel::Warn, &msg);
+        return Ok(());
+    };
+
+    let configured = MDADM_CONF_PATHS.iter().any(|path| {
+        fs::read_to_string(path).is_ok_and(|conf| conf.lines().any(|line| line.trim_start().starts_with("ARRAY")))
+    });
+
+    let result = if configured {
+        run(&mdadm, &["--assemble", "--scan"])
+    } else {
+        let failed: Vec<String> = members
+            .iter()
+            .filter_map(|name| add_raid_member(&format!("/dev/{}", name)).err().map(|e| e.to_string()))
+            .collect();
+
+        if failed.is_empty() { Ok(()) } else { Err(BloomError::Custom(failed.join("; "))) }
+    };
+
+    match result {
+        Ok(()) => {
+            let msg = format!("Assembled md RAID arrays from {}", members.join(", "));
+            log(console_logger, file_logger, &timer, LogLevel::Ok, &msg);
+            Ok(())
+        }
+        Err(e) => {
+            log(console_logger, file_logger, &timer, LogLevel::Fail, &format!("RAID assembly failed: {}", e));
+            Err(e)
+        }
+    }
+}
+
+/// Hand one member device to mdadm, which starts its array once every
+/// member is there.
+pub fn add_raid_member(devnode: &str) -> Result<(), BloomError> {
+    let mdadm = find_program("mdadm").ok_or_else(|| BloomError::Custom("mdadm is not installed".into()))?;
+    run(&mdadm, &["--incremental", devnode])
+}
+
+/// Whether `/dev/<name>` carries an md superblock, of any version: 0.90 in
+/// the last 64K-aligned 64K, 1.0 8K from the end, 1.1 at the start and 1.2
+/// 4K in.
+pub fn is_raid_member(name: &str) -> bool {
+    if name.starts_with("md") {
+        return false;
+    }
+
+    let Some(size) = fs::read_to_string(Path::new("/sys/class/block").join(name).join("size"))
+        .ok()
+        .and_then(|sectors| sectors.trim().parse::<u64>().ok())
+        .map(|sectors| sectors * SECTOR_SIZE as u64)
+    else {
+        return false;
+    };
+
+    let Ok(mut dev) = File::open(Path::new("/dev").join(name)) else {
+        return false;
+    };
+
+    let mut offsets = vec![0, 4096];
+    if size >= 8192 {
+        offsets.push((size - 8192) & !4095);
+    }
+    if size >= 128 * 1024 {
+        offsets.push((size & !(64 * 1024 - 1)) - 64 * 1024);
+    }
+
+    offsets.into_iter().any(|offset| {
+        let mut magic = [0u8; 4];
+        dev.seek(SeekFrom::Start(offset)).and_then(|_| dev.read_exact(&mut magic)).is_ok()
+            && u32::from_le_bytes(magic) == MD_MAGIC
+    })
+}
+
+/// Register every btrfs device with the kernel, as `btrfs device scan`
+/// does, so a filesystem spanning several devices mounts through any one of
+/// them.
+pub fn scan_btrfs(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let devices: Vec<String> = block_devices().into_iter().filter(|name| is_btrfs(name)).collect();
+    if devices.is_empty() {
+        file_logger.log(LogLevel::Info, "No btrfs devices, skipping scan");
+        return Ok(());
+    }
+
+    // The control device only exists once the module is in
+    if !Path::new(BTRFS_CONTROL_PATH).exists() {
+        let _ = crate::kernel::load_module("btrfs");
+    }
+
+    let control = match File::open(BTRFS_CONTROL_PATH) {
+        Ok(control) => control,
+        Err(e) => {
+            let msg = format!("Cannot open {}: {}", BTRFS_CONTROL_PATH, e);
+            log(console_logger, file_logger, &timer, LogLevel::Fail, &msg);
+            return Err(BloomError::Io(e));
+        }
+    };
+
+    let mut failed = Vec::new();
+    for name in &devices {
+        let path = format!("/dev/{}", name);
+        let mut args = BtrfsVolArgs { fd: 0, name: [0; 4088] };
+        args.name[..path.len()].copy_from_slice(path.as_bytes());
+
+        if unsafe { libc::ioctl(control.as_raw_fd(), BTRFS_IOC_SCAN_DEV as _, &args) } != 0 {
+            failed.push(format!("{}: {}", path, std::io::Error::last_os_error()));
+        }
+    }
+
+    if failed.is_empty() {
+        let msg = format!("Registered btrfs devices {}", devices.join(", "));
+        log(console_logger, file_logger, &timer, LogLevel::Ok, &msg);
+        Ok(())
+    } else {
+        let msg = format!("btrfs device scan failed for {}", failed.join(", "));
+        log(console_logger, file_logger, &timer, LogLevel::Warn, &msg);
+        Err(BloomError::Custom(msg))
+    }
+}
+
+/// Grow the partition holding the root filesystem to the end of its disk,
+/// then the filesystem to fill it, while mounted. Meant for first boot of a
+/// flashed image; once grown, nothing is left to do on later boots.
+pub fn grow_root(
+    config: &InitConfig,
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    if !config.grow_root {
+        return Ok(());
+    }
+
+    let timer = ProcessTimer::start();
+
+    let Some((sysfs, fstype)) = root_device() else {
+        log(console_logger, file_logger, &timer, LogLevel::Warn, "Cannot find the root device, not growing it");
+        return Ok(());
+    };
+
+    // Only a partition can be grown, and only into space after it
+    let Some(number) = read_sysfs(&sysfs.join("partition")) else {
+        file_logger.log(LogLevel::Info, "Root is not on a partition, not growing it");
+        return Ok(());
+    };
+    let disk_sysfs = sysfs.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let (Some(start), Some(size), Some(disk_size)) = (
+        read_sysfs(&sysfs.join("start")),
+        read_sysfs(&sysfs.join("size")),
+        read_sysfs(&disk_sysfs.join("size")),
+    ) else {
+        log(console_logger, file_logger, &timer, LogLevel::Warn, "Cannot read the root partition layout");
+        return Ok(());
+    };
+
+    if start + size + GROW_SLACK_SECTORS >= disk_size {
+        file_logger.log(LogLevel::Info, "Root partition already fills its disk");
+        return Ok(());
+    }
+
+    let name = |path: &Path| path.file_name().map(|n| format!("/dev/{}", n.to_string_lossy())).unwrap_or_default();
+    let (partition, disk) = (name(&sysfs), name(&disk_sysfs));
+
+    let result = grow_partition(&disk, number).and_then(|()| resize_filesystem(&fstype, &partition));
+
+    match result {
+        Ok(()) => {
+            let grown = read_sysfs(&sysfs.join("size")).unwrap_or(size);
+            let mib = |sectors: u64| (sectors * SECTOR_SIZE as u64) >> 20;
+            let msg = format!("Grew root {} on {} from {} MiB to {} MiB", fstype, partition, mib(size), mib(grown));
+            log(console_logger, file_logger, &timer, LogLevel::Ok, &msg);
+            Ok(())
+        }
+        Err(e) => {
+            log(console_logger, file_logger, &timer, LogLevel::Fail, &format!("Growing root failed: {}", e));
+            Err(e)
+        }
+    }
+}
+
+/// The sysfs directory of the device the root filesystem is on, and its type.
+fn root_device() -> Option<(PathBuf, String)> {
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+
+    // The last mount on / is the one in use
+    let (dev, fstype, source) = mountinfo.lines().rev().find_map(|line| {
+        let (mount, fs) = line.split_once(" - ")?;
+        let mount: Vec<&str> = mount.split_whitespace().collect();
+        if mount.len() < 5 || mount[4] != "/" {
+            return None;
+        }
+
+        let mut fs = fs.split_whitespace();
+        Some((mount[2].to_string(), fs.next()?.to_string(), fs.next()?.to_string()))
+    })?;
+
+    // btrfs reports an anonymous device number; the source names the real one
+    let mut sysfs = Path::new("/sys/dev/block").join(&dev);
+    if !sysfs.exists() {
+        let rdev = fs::metadata(&source).ok()?.rdev();
+        sysfs = Path::new("/sys/dev/block").join(format!("{}:{}", major(rdev), minor(rdev)));
+    }
+
+    Some((sysfs.canonicalize().ok()?, fstype))
+}
+
+fn read_sysfs(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Move the end of partition `number` of `disk` to the end of the disk and
+/// tell the kernel, with growpart if installed or else sfdisk and partx.
+fn grow_partition(disk: &str, number: u64) -> Result<(), BloomError> {
+    let number = number.to_string();
+
+    if let Some(growpart) = find_program("growpart") {
+        return run(&growpart, &[disk, &number]);
+    }
+
+    let (Some(sfdisk), Some(partx)) = (find_program("sfdisk"), find_program("partx")) else {
+        return Err(BloomError::Custom("neither growpart nor sfdisk and partx are installed".into()));
+    };
+
+    // Keeps the start, takes all the space after it; the disk is in use so
+    // the kernel is told about the one partition separately
+    let mut child = Command::new(&sfdisk)
+        .args(["--no-reread", "-N", &number, disk])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(BloomError::Io)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(b", +\n").map_err(BloomError::Io)?;
+    }
+
+    let output = child.wait_with_output().map_err(BloomError::Io)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(BloomError::Custom(format!("{} failed: {}", sfdisk, stderr.trim())));
+    }
+
+    run(&partx, &["--update", "--nr", &number, disk])
+}
+
+/// Grow a mounted filesystem to the size of its partition.
+fn resize_filesystem(fstype: &str, partition: &str) -> Result<(), BloomError> {
+    let (tool, args): (&str, &[&str]) = match fstype {
+        "ext2" | "ext3" | "ext4" => ("resize2fs", &[partition]),
+        "xfs" => ("xfs_growfs", &["/"]),
+        "btrfs" => ("btrfs", &["filesystem", "resize", "max", "/"]),
+        other => return Err(BloomError::Custom(format!("{} cannot be grown while mounted", other))),
+    };
+
+    let program = find_program(tool).ok_or_else(|| BloomError::Custom(format!("{} is not installed", tool)))?;
+    run(&program, args)
+}
+
+/// Whether `/dev/<name>` holds a btrfs superblock.
+fn is_btrfs(name: &str) -> bool {
+    let mut magic = [0u8; 8];
+    File::open(Path::new("/dev").join(name))
+        .and_then(|mut dev| dev.seek(SeekFrom::Start(BTRFS_MAGIC_OFFSET)).and_then(|_| dev.read_exact(&mut magic)))
+        .is_ok_and(|_| &magic == BTRFS_MAGIC)
+}
+
+/// Names of the block devices the kernel knows about, partitions included,
+/// leaving out RAM disks and loop devices.
+fn block_devices() -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir("/sys/class/block")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !["ram", "loop", "zram"].iter().any(|prefix| name.starts_with(prefix)))
+        .collect();
+
+    names.sort();
+    names
+}
+
+/// Whether `/dev/<name>` carries an LVM2 physical volume label.
+fn is_lvm_member(name: &str) -> bool {
+    let mut buf = vec![0u8; LVM_LABEL_SECTORS * SECTOR_SIZE];
+    let read = File::open(Path::new("/dev").join(name)).and_then(|mut dev| dev.read_exact(&mut buf));
+    if read.is_err() {
+        return false;
+    }
+
+    buf.chunks(SECTOR_SIZE)
+        .any(|sector| sector.starts_with(b"LABELONE") && &sector[24..32] == b"LVM2 001")
+}
+
+pub(crate) fn find_program(name: &str) -> Option<String> {
+    TOOL_DIRS
+        .iter()
+        .map(|dir| format!("{}/{}", dir, name))
+        .find(|path| Path::new(path).is_file())
+}
+
+fn run(program: &str, args: &[&str]) -> Result<(), BloomError> {
+    let output = Command::new(program).args(args).output().map_err(BloomError::Io)?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(BloomError::Custom(format!("{} {} failed: {}", program, args.join(" "), stderr.trim())))
+    }
+}
+
+fn log(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+    timer: &ProcessTimer,
+    level: LogLevel,
+    msg: &str,
+) {
+    console_logger.message(level, msg, timer.elapsed());
+    file_logger.log(level, msg);
+}
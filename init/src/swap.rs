@@ -0,0 +1,122 @@
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bloom::device::{is_device_spec, wait_for_device};
+use bloom::errors::BloomError;
+use bloom::fstab::FSTAB_PATH;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+use crate::mount::device_timeout;
+use crate::unmount::unescape;
+
+const SWAPS_PATH: &str = "/proc/swaps";
+
+/// swapon(2) flags from <sys/swap.h>, which libc does not carry.
+const SWAP_FLAG_PREFER: i32 = 0x8000;
+const SWAP_FLAG_PRIO_MASK: i32 = 0x7fff;
+
+/// Turn on the swap entries in /etc/fstab, waiting for their devices as
+/// mounts do. One that fails is logged and skipped.
+pub fn activate_swap(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let Ok(fstab) = fs::read_to_string(FSTAB_PATH) else {
+        return Ok(());
+    };
+
+    // Already on, say after a re-run of this step
+    let active = fs::read_to_string(SWAPS_PATH).unwrap_or_default();
+    let active: Vec<String> = active.lines().skip(1).filter_map(|l| l.split_whitespace().next()).map(unescape).collect();
+
+    for line in fstab.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let (Some(&source), Some(&"swap")) = (fields.first(), fields.get(2)) else {
+            continue;
+        };
+        let options = fields.get(3).copied().unwrap_or("defaults");
+        if options.split(',').any(|opt| opt == "noauto") {
+            continue;
+        }
+
+        let priority = options.split(',').find_map(|opt| opt.strip_prefix("pri=")?.parse().ok());
+        let result = swap_path(source, options).and_then(|path| {
+            if !active.contains(&path) {
+                swapon(&path, priority).map_err(BloomError::Io)?;
+            }
+            Ok(path)
+        });
+
+        let (level, msg) = match result {
+            Ok(path) => (LogLevel::Ok, format!("Activated swap {}", path)),
+            Err(e) => (LogLevel::Warn, format!("Failed to activate swap {}: {}", source, e)),
+        };
+        console_logger.message(level, &msg, timer.elapsed());
+        file_logger.log(level, &msg);
+    }
+
+    Ok(())
+}
+
+/// The device or file behind an fstab swap entry, once it is there.
+fn swap_path(source: &str, options: &str) -> Result<String, BloomError> {
+    if is_device_spec(source) {
+        wait_for_device(source, device_timeout(options))
+    } else if Path::new(source).exists() {
+        Ok(source.to_string())
+    } else {
+        Err(BloomError::Custom(format!("{} does not exist", source)))
+    }
+}
+
+/// Turn off every active swap device and file, so swapped-out pages are read
+/// back and whatever holds the swap (a filesystem, an LV, a crypt device) is
+/// released before it is unmounted or torn down.
+pub fn deactivate_swap(console_logger: &mut dyn ConsoleLogger, file_logger: &mut dyn FileLogger) {
+    let timer = ProcessTimer::start();
+
+    let Ok(swaps) = fs::read_to_string(SWAPS_PATH) else {
+        return;
+    };
+
+    // Filename Type Size Used Priority, after a header line
+    for line in swaps.lines().skip(1) {
+        let Some(name) = line.split_whitespace().next() else {
+            continue;
+        };
+        let name = unescape(name);
+
+        let (level, msg) = match swapoff(&name) {
+            Ok(()) => (LogLevel::Ok, format!("Deactivated swap {}", name)),
+            Err(e) => (LogLevel::Warn, format!("Failed to deactivate swap {}: {}", name, e)),
+        };
+        console_logger.message(level, &msg, timer.elapsed());
+        file_logger.log(level, &msg);
+    }
+}
+
+fn swapoff(path: &str) -> io::Result<()> {
+    let path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    if unsafe { libc::swapoff(path.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Turn on swap at `path`, ahead of devices without a priority if given one.
+pub(crate) fn swapon(path: &str, priority: Option<i32>) -> io::Result<()> {
+    let path = CString::new(path).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let flags = priority.map_or(0, |priority| SWAP_FLAG_PREFER | priority.clamp(0, SWAP_FLAG_PRIO_MASK));
+
+    if unsafe { libc::swapon(path.as_ptr(), flags) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
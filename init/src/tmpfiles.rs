@@ -0,0 +1,297 @@
+use std::fs::{self, DirBuilder, OpenOptions, Permissions};
+use std::io::Write;
+use std::os::unix::fs::{symlink, DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use nix::unistd::{chown, Gid, Group, Uid, User};
+
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+/// `*.conf` files applied in name order, one entry per line:
+/// `type path mode user group age argument`, with `-` for defaults.
+pub const TMPFILES_DIR: &str = "/etc/verdant/tmpfiles.d";
+
+/// What an entry does to its path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// `d`: create a directory, cleaning out files older than its age.
+    Directory,
+    /// `D`: like `d`, but emptied at every boot.
+    EmptyDirectory,
+    /// `f`: create a file, writing the argument into it.
+    File,
+    /// `L`: create a symlink to the argument.
+    Symlink,
+    /// `z`: only set the mode and owner of what is there.
+    Adjust,
+    /// `r`: remove a file or empty directory.
+    Remove,
+}
+
+#[derive(Debug)]
+struct Entry {
+    kind: Kind,
+    path: PathBuf,
+    mode: Option<u32>,
+    user: Option<String>,
+    group: Option<String>,
+    age: Option<Duration>,
+    argument: Option<String>,
+}
+
+/// Create the directories, files and symlinks declared in `TMPFILES_DIR`,
+/// fix up their modes and owners, and clean out old files. Runs once /run
+/// and /tmp are mounted; a bad line or failing entry is logged and skipped.
+pub fn apply_tmpfiles(
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+
+    let mut files: Vec<PathBuf> = fs::read_dir(TMPFILES_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("conf"))
+        .collect();
+    files.sort();
+
+    let (mut applied, mut failed) = (0, 0);
+
+    for file in &files {
+        let contents = match fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) => {
+                file_logger.log(LogLevel::Warn, &format!("Cannot read {}: {}", file.display(), e));
+                continue;
+            }
+        };
+
+        for (line_no, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let result = parse_entry(line).and_then(|entry| apply(&entry));
+            if let Err(e) = result {
+                failed += 1;
+                let msg = format!("{} line {}: {}", file.display(), line_no + 1, e);
+                console_logger.message(LogLevel::Warn, &msg, timer.elapsed());
+                file_logger.log(LogLevel::Warn, &msg);
+            } else {
+                applied += 1;
+            }
+        }
+    }
+
+    if applied + failed > 0 {
+        let msg = format!("Applied {} tmpfiles entries, {} failed", applied, failed);
+        let level = if failed == 0 { LogLevel::Ok } else { LogLevel::Warn };
+        console_logger.message(level, &msg, timer.elapsed());
+        file_logger.log(level, &msg);
+    }
+
+    Ok(())
+}
+
+fn parse_entry(line: &str) -> Result<Entry, BloomError> {
+    // The argument is the rest of the line and may contain spaces
+    let mut rest = line;
+    let field = |f: Option<&str>| f.filter(|f| *f != "-").map(str::to_string);
+
+    let kind = match next_field(&mut rest) {
+        Some("d") => Kind::Directory,
+        Some("D") => Kind::EmptyDirectory,
+        Some("f") => Kind::File,
+        Some("L") => Kind::Symlink,
+        Some("z") => Kind::Adjust,
+        Some("r") => Kind::Remove,
+        Some(other) => return Err(BloomError::Parse(format!("Unknown entry type '{}'", other))),
+        None => return Err(BloomError::Parse("Empty entry".into())),
+    };
+
+    let path = PathBuf::from(next_field(&mut rest).ok_or_else(|| BloomError::Parse("Missing path".into()))?);
+    if !path.is_absolute() {
+        return Err(BloomError::Parse(format!("{} is not an absolute path", path.display())));
+    }
+
+    let mode = field(next_field(&mut rest))
+        .map(|m| u32::from_str_radix(&m, 8).map_err(|_| BloomError::Parse(format!("Invalid mode '{}'", m))))
+        .transpose()?;
+    let user = field(next_field(&mut rest));
+    let group = field(next_field(&mut rest));
+    let age = field(next_field(&mut rest)).map(|a| parse_age(&a)).transpose()?;
+    let argument = field(Some(rest.trim()).filter(|a| !a.is_empty()));
+
+    if kind == Kind::Symlink && argument.is_none() {
+        return Err(BloomError::Parse(format!("Symlink {} has no target", path.display())));
+    }
+
+    Ok(Entry {
+        kind,
+        path,
+        mode,
+        user,
+        group,
+        age,
+        argument,
+    })
+}
+
+/// Split off the next whitespace-separated field of `rest`.
+fn next_field<'a>(rest: &mut &'a str) -> Option<&'a str> {
+    let trimmed = rest.trim_start();
+    let end = trimmed.find(char::is_whitespace).unwrap_or(trimmed.len());
+    let (field, remainder) = trimmed.split_at(end);
+    *rest = remainder;
+    (!field.is_empty()).then_some(field)
+}
+
+/// A number with an `s`, `m`, `h`, `d` or `w` suffix; plain numbers are seconds.
+fn parse_age(age: &str) -> Result<Duration, BloomError> {
+    let split = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let (value, unit) = age.split_at(split);
+    let value: u64 = value.parse().map_err(|_| BloomError::Parse(format!("Invalid age '{}'", age)))?;
+
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" | "min" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        "w" => 7 * 86_400,
+        _ => return Err(BloomError::Parse(format!("Invalid age '{}'", age))),
+    };
+
+    Ok(Duration::from_secs(value * multiplier))
+}
+
+fn apply(entry: &Entry) -> Result<(), BloomError> {
+    let path = &entry.path;
+
+    match entry.kind {
+        Kind::Directory | Kind::EmptyDirectory => {
+            if !path.is_dir() {
+                DirBuilder::new()
+                    .recursive(true)
+                    .mode(entry.mode.unwrap_or(0o755))
+                    .create(path)
+                    .map_err(BloomError::Io)?;
+            }
+
+            let age = if entry.kind == Kind::EmptyDirectory { Some(Duration::ZERO) } else { entry.age };
+            if let Some(age) = age {
+                clean(path, age)?;
+            }
+        }
+        Kind::File => {
+            if !path.exists() {
+                let mut file = OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(entry.mode.unwrap_or(0o644))
+                    .open(path)
+                    .map_err(BloomError::Io)?;
+
+                if let Some(content) = &entry.argument {
+                    file.write_all(content.as_bytes()).map_err(BloomError::Io)?;
+                }
+            }
+        }
+        Kind::Symlink => {
+            if fs::symlink_metadata(path).is_err() {
+                symlink(entry.argument.as_deref().unwrap_or_default(), path).map_err(BloomError::Io)?;
+            }
+            // A symlink has no mode or owner of its own to set
+            return Ok(());
+        }
+        Kind::Adjust => {
+            if !path.exists() {
+                return Ok(());
+            }
+        }
+        Kind::Remove => {
+            return match fs::symlink_metadata(path) {
+                Ok(meta) if meta.is_dir() => fs::remove_dir(path).map_err(BloomError::Io),
+                Ok(_) => fs::remove_file(path).map_err(BloomError::Io),
+                Err(_) => Ok(()),
+            };
+        }
+    }
+
+    set_ownership(entry)
+}
+
+/// Apply the entry's mode, user and group to what is at its path now.
+fn set_ownership(entry: &Entry) -> Result<(), BloomError> {
+    if let Some(mode) = entry.mode {
+        fs::set_permissions(&entry.path, Permissions::from_mode(mode)).map_err(BloomError::Io)?;
+    }
+
+    let uid = match &entry.user {
+        Some(name) => Some(lookup_user(name)?),
+        None => None,
+    };
+    let gid = match &entry.group {
+        Some(name) => Some(lookup_group(name)?),
+        None => None,
+    };
+
+    if uid.is_some() || gid.is_some() {
+        chown(&entry.path, uid, gid).map_err(BloomError::Nix)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn lookup_user(name: &str) -> Result<Uid, BloomError> {
+    if let Ok(uid) = name.parse() {
+        return Ok(Uid::from_raw(uid));
+    }
+    User::from_name(name)
+        .map_err(BloomError::Nix)?
+        .map(|user| user.uid)
+        .ok_or_else(|| BloomError::Custom(format!("No such user '{}'", name)))
+}
+
+pub(crate) fn lookup_group(name: &str) -> Result<Gid, BloomError> {
+    if let Ok(gid) = name.parse() {
+        return Ok(Gid::from_raw(gid));
+    }
+    Group::from_name(name)
+        .map_err(BloomError::Nix)?
+        .map(|group| group.gid)
+        .ok_or_else(|| BloomError::Custom(format!("No such group '{}'", name)))
+}
+
+/// Remove what is inside `dir` and was neither modified nor accessed within
+/// `age`, emptied subdirectories included. The directory itself stays.
+fn clean(dir: &Path, age: Duration) -> Result<(), BloomError> {
+    let cutoff = SystemTime::now().checked_sub(age).unwrap_or(SystemTime::UNIX_EPOCH);
+
+    for entry in fs::read_dir(dir).map_err(BloomError::Io)?.flatten() {
+        let path = entry.path();
+        let Ok(meta) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+
+        let newest = [meta.modified().ok(), meta.accessed().ok()].into_iter().flatten().max();
+        let old = age.is_zero() || newest.is_some_and(|t| t < cutoff);
+
+        if meta.is_dir() {
+            clean(&path, age)?;
+            // Only once empty; cleaning may have just made it newer
+            if old {
+                let _ = fs::remove_dir(&path);
+            }
+        } else if old {
+            let _ = fs::remove_file(&path);
+        }
+    }
+
+    Ok(())
+}
@@ -1,66 +1,201 @@
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::thread;
+use std::time::Duration;
 
-use nix::mount::umount;
+use nix::mount::{mount, umount2, MntFlags, MsFlags};
+use nix::unistd::sync;
 
 use bloom::errors::BloomError;
+use bloom::fstab::NETWORK_FILESYSTEMS;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::status::LogLevel;
 use bloom::time::ProcessTimer;
 
-/// Unmount all filesystems listed in /etc/fstab, except the root `/`
-pub fn unmount_fstab_filesystems(
+/// Further attempts at a busy unmount before remounting read-only instead.
+const UNMOUNT_RETRIES: u32 = 3;
+
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+
+/// Kernel and memory-backed filesystems: nothing on them needs writing back,
+/// and init itself still relies on some of them.
+const VIRTUAL_FILESYSTEMS: &[&str] = &[
+    "proc", "sysfs", "devtmpfs", "devpts", "tmpfs", "ramfs", "cgroup", "cgroup2", "securityfs", "debugfs",
+    "tracefs", "pstore", "efivarfs", "bpf", "mqueue", "hugetlbfs", "configfs", "fusectl", "binfmt_misc",
+    "autofs", "rpc_pipefs", "selinuxfs",
+];
+
+/// Pause between unmount attempts, for processes to let go of the filesystem.
+const RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Unmount everything in `/proc/self/mountinfo` except the root and virtual
+/// filesystems, in reverse order of mounting, so mounts made at runtime are
+/// released as well as those from fstab. One that stays busy is remounted
+/// read-only and synced, so no dirty data is left behind, then detached
+/// lazily; which of these it took is logged. The root is remounted
+/// read-only last.
+pub fn unmount_filesystems(
     console_logger: &mut dyn ConsoleLogger,
     file_logger: &mut dyn FileLogger,
 ) -> Result<(), BloomError> {
     let timer = ProcessTimer::start();
 
-    let file = File::open("/etc/fstab").map_err(BloomError::Io)?;
+    let file = File::open(MOUNTINFO_PATH).map_err(BloomError::Io)?;
     let mut mount_points = Vec::new();
 
     for line_result in BufReader::new(file).lines() {
-        let line = line_result.map_err(BloomError::Io)?.trim().to_string();
-        if line.is_empty() || line.starts_with('#') {
-            continue;
-        }
+        let line = line_result.map_err(BloomError::Io)?;
 
-        let fields: Vec<&str> = line.split_whitespace().collect();
-        if fields.len() < 4 {
+        // id parent major:minor root mount_point options [optional...] - fstype source super_options
+        let Some((before, after)) = line.split_once(" - ") else {
             continue;
-        }
-
-        let target = fields[1];
-        let options = fields[3];
-
-        if target == "/" || target == "none" || !Path::new(target).is_absolute() {
+        };
+        let (Some(target), Some(fstype)) = (before.split(' ').nth(4), after.split(' ').next()) else {
             continue;
-        }
+        };
 
-        if options.split(',').any(|opt| opt == "noauto") {
+        let target = unescape(target);
+        if target == "/" || VIRTUAL_FILESYSTEMS.contains(&fstype) || is_api_mount(&target) {
             continue;
         }
 
-        mount_points.push(target.to_string());
+        mount_points.push((target, fstype.to_string()));
     }
 
-    // Sort by descending path length to unmount deeper mounts first
-    mount_points.sort_by(|a, b| b.len().cmp(&a.len()));
+    // mountinfo lists a mount after its parent and after whatever it was
+    // mounted from, such as an overlay's lower and upper directories, so
+    // reverse order releases each before what it depends on
+    mount_points.reverse();
 
-    for mount_point in mount_points {
+    for (mount_point, fstype) in mount_points {
         let path = Path::new(&mount_point);
-        match umount(path) {
-            Ok(()) => {
+
+        match unmount(path, NETWORK_FILESYSTEMS.contains(&fstype.as_str())) {
+            Ok(Strategy::Unmounted) => {
                 let msg = format!("Unmounted {}", mount_point);
                 log_success(console_logger, file_logger, &timer, LogLevel::Ok, &msg);
             }
-            Err(e) => {
-                let msg = format!("Failed to unmount {}: {}", mount_point, e);
+            Ok(strategy) => {
+                let msg = format!("Unmounted {} ({})", mount_point, strategy.as_str());
                 log_error(console_logger, file_logger, &timer, LogLevel::Warn, &msg);
             }
+            Err(e) => {
+                let msg = format!("Failed to unmount {} or remount it read-only: {}", mount_point, e);
+                log_error(console_logger, file_logger, &timer, LogLevel::Fail, &msg);
+            }
+        }
+    }
+
+    match remount_read_only(Path::new("/")) {
+        Ok(()) => log_success(console_logger, file_logger, &timer, LogLevel::Ok, "Remounted / read-only"),
+        Err(e) => {
+            let msg = format!("Failed to remount / read-only: {}", e);
+            log_error(console_logger, file_logger, &timer, LogLevel::Warn, &msg);
+        }
+    }
+
+    // Whatever could not be unmounted or remounted still gets flushed
+    sync();
+
+    Ok(())
+}
+
+/// Whether `target` is under /proc, /sys or /dev, which stay mounted.
+fn is_api_mount(target: &str) -> bool {
+    ["/proc", "/sys", "/dev"]
+        .iter()
+        .any(|api| Path::new(target).starts_with(api))
+}
+
+/// Undo the octal escapes mountinfo uses for spaces, tabs, newlines and
+/// backslashes in paths.
+pub(crate) fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(code) = field.get(i + 1..i + 4).and_then(|o| u8::from_str_radix(o, 8).ok())
+        {
+            out.push(code);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// How a filesystem ended up released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Strategy {
+    Unmounted,
+    /// MNT_FORCE, aborting requests to an unreachable server.
+    Forced,
+    /// Still mounted, but read-only with nothing left to write.
+    ReadOnly,
+    /// Remounted read-only, then lazily detached.
+    ReadOnlyDetached,
+    /// MNT_DETACH alone: out of the tree, released once no longer busy.
+    Detached,
+}
+
+impl Strategy {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Strategy::Unmounted => "unmounted",
+            Strategy::Forced => "forced",
+            Strategy::ReadOnly => "busy, remounted read-only",
+            Strategy::ReadOnlyDetached => "busy, remounted read-only and detached",
+            Strategy::Detached => "busy, detached lazily",
+        }
+    }
+}
+
+/// Unmount `path`, retrying while it is busy, then falling back to a
+/// read-only remount and a lazy detach. Network filesystems are forced from
+/// the start, as a plain unmount can hang forever on a dead server, and are
+/// never remounted for the same reason.
+fn unmount(path: &Path, network: bool) -> nix::Result<Strategy> {
+    let flags = if network { MntFlags::MNT_FORCE } else { MntFlags::empty() };
+
+    let mut result = umount2(path, flags);
+    for _ in 0..UNMOUNT_RETRIES {
+        if result.is_ok() {
+            break;
         }
+        thread::sleep(RETRY_INTERVAL);
+        result = umount2(path, flags);
     }
 
+    let Err(e) = result else {
+        return Ok(if network { Strategy::Forced } else { Strategy::Unmounted });
+    };
+
+    let read_only = !network && remount_read_only(path).is_ok();
+
+    match umount2(path, MntFlags::MNT_DETACH) {
+        Ok(()) if read_only => Ok(Strategy::ReadOnlyDetached),
+        Ok(()) => Ok(Strategy::Detached),
+        Err(_) if read_only => Ok(Strategy::ReadOnly),
+        Err(_) => Err(e),
+    }
+}
+
+/// Remount `path` read-only, which flushes its dirty data, then sync.
+fn remount_read_only(path: &Path) -> nix::Result<()> {
+    mount(
+        None::<&str>,
+        path,
+        None::<&str>,
+        MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None::<&str>,
+    )?;
+    sync();
     Ok(())
 }
 
@@ -1,7 +1,5 @@
 use std::fs;
-use std::io::Read;
 use std::path::Path;
-use std::ffi::CString;
 use std::process::{Command, Stdio};
 use std::sync::{Arc, Mutex};
 
@@ -10,47 +8,6 @@ use bloom::errors::BloomError;
 use bloom::log::{ConsoleLogger, FileLogger};
 use bloom::time::ProcessTimer;
 
-pub fn set_hostname(
-    console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
-    file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
-) -> Result<(), BloomError> {
-    let timer = ProcessTimer::start();
-    let hostname_path = "/etc/hostname";
-
-    match fs::File::open(hostname_path) {
-        Ok(mut file) => {
-            let mut hostname = String::new();
-            if let Err(e) = file.read_to_string(&mut hostname) {
-                log_error(console_logger, file_logger, &timer, LogLevel::Fail, &format!("Failed to read hostname file: {}", e));
-                return Err(BloomError::Io(e));
-            }
-            let hostname = hostname.trim();
-
-            match CString::new(hostname) {
-                Ok(c_hostname) => {
-                    let result = unsafe { libc::sethostname(c_hostname.as_ptr(), hostname.len()) };
-                    if result != 0 {
-                        let e = std::io::Error::last_os_error();
-                        log_error(console_logger, file_logger, &timer, LogLevel::Fail, &format!("Failed to set hostname: {}", e));
-                        return Err(BloomError::Io(e));
-                    }
-                    log_success(console_logger, file_logger, &timer, LogLevel::Ok, &format!("Hostname set to '{}'", hostname));
-                    Ok(())
-                }
-                Err(_) => {
-                    let msg = "Hostname contains invalid null byte";
-                    log_error(console_logger, file_logger, &timer, LogLevel::Fail, msg);
-                    Err(BloomError::Parse(msg.into()))
-                }
-            }
-        }
-        Err(e) => {
-            log_error(console_logger, file_logger, &timer, LogLevel::Fail, &format!("Failed to open hostname file: {}", e));
-            Err(BloomError::Io(e))
-        }
-    }
-}
-
 pub fn detect_timezone(
     console_logger: &Arc<Mutex<dyn ConsoleLogger + Send + Sync>>,
     file_logger: &Arc<Mutex<dyn FileLogger + Send + Sync>>,
@@ -0,0 +1,169 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::process::Command;
+
+use bloom::cmdline::read_cmdline;
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+use bloom::util::read_env_file;
+
+use crate::storage::find_program;
+
+/// `KEYMAP=`, `KEYMAP_TOGGLE=`, `FONT=`, `FONT_MAP=` and `FONT_UNIMAP=`, as
+/// systemd reads them.
+const VCONSOLE_CONF_PATH: &str = "/etc/vconsole.conf";
+
+/// Kernel command line parameters overriding the file.
+const CMDLINE_PARAMS: &[(&str, &str)] = &[
+    ("vconsole.keymap", "KEYMAP"),
+    ("vconsole.keymap_toggle", "KEYMAP_TOGGLE"),
+    ("vconsole.font", "FONT"),
+    ("vconsole.font_map", "FONT_MAP"),
+    ("vconsole.font_unimap", "FONT_UNIMAP"),
+];
+
+/// Console ioctl from <linux/kd.h> and the keyboard mode that sends UTF-8.
+const KDSKBMODE: libc::c_ulong = 0x4B45;
+const K_UNICODE: libc::c_ulong = 0x03;
+
+#[derive(Debug, Default)]
+struct VconsoleConfig {
+    keymap: Option<String>,
+    keymap_toggle: Option<String>,
+    font: Option<String>,
+    font_map: Option<String>,
+    font_unimap: Option<String>,
+}
+
+/// Load the keymap and font from /etc/vconsole.conf onto the login ttys
+/// before any getty starts on them, with the keyboard and display switched
+/// to UTF-8. The keymap is shared by every virtual terminal, so loadkeys runs
+/// once; setfont runs for each tty, as fonts are per terminal.
+pub fn setup_vconsole(
+    ttys: &[String],
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    let timer = ProcessTimer::start();
+    let mut log = |level: LogLevel, msg: &str| {
+        console_logger.message(level, msg, timer.elapsed());
+        file_logger.log(level, msg);
+    };
+
+    let config = load_config();
+    if config.keymap.is_none() && config.font.is_none() {
+        return Ok(());
+    }
+
+    let devices: Vec<String> = ttys
+        .iter()
+        .map(|tty| if tty.starts_with("/dev/") { tty.clone() } else { format!("/dev/{}", tty) })
+        .collect();
+
+    for device in &devices {
+        if let Err(e) = set_unicode(device) {
+            log(LogLevel::Warn, &format!("Cannot switch {} to UTF-8: {}", device, e));
+        }
+    }
+
+    let mut failed = false;
+
+    if let (Some(keymap), Some(device)) = (&config.keymap, devices.first()) {
+        let mut args = vec!["-q", "-u", "-C", device.as_str(), keymap.as_str()];
+        if let Some(toggle) = &config.keymap_toggle {
+            args.push(toggle);
+        }
+
+        match run_tool("loadkeys", &args) {
+            Ok(()) => log(LogLevel::Ok, &format!("Loaded keymap {}", keymap)),
+            Err(e) => {
+                failed = true;
+                log(LogLevel::Warn, &format!("Cannot load keymap {}: {}", keymap, e));
+            }
+        }
+    }
+
+    if let Some(font) = &config.font {
+        let mut loaded = 0;
+        for device in &devices {
+            let mut args = vec!["-C", device.as_str(), font.as_str()];
+            if let Some(map) = &config.font_map {
+                args.extend(["-m", map]);
+            }
+            if let Some(unimap) = &config.font_unimap {
+                args.extend(["-u", unimap]);
+            }
+
+            match run_tool("setfont", &args) {
+                Ok(()) => loaded += 1,
+                Err(e) => {
+                    failed = true;
+                    log(LogLevel::Warn, &format!("Cannot load font {} on {}: {}", font, device, e));
+                }
+            }
+        }
+
+        if loaded > 0 {
+            log(LogLevel::Ok, &format!("Loaded console font {} on {} tty(s)", font, loaded));
+        }
+    }
+
+    if failed {
+        Err(BloomError::Custom("Console setup incomplete".into()))
+    } else {
+        Ok(())
+    }
+}
+
+fn load_config() -> VconsoleConfig {
+    let mut vars = read_env_file(VCONSOLE_CONF_PATH).unwrap_or_default();
+
+    for (key, value) in read_cmdline() {
+        if let (Some((_, var)), Some(value)) = (CMDLINE_PARAMS.iter().find(|(param, _)| *param == key), value) {
+            vars.push((var.to_string(), value));
+        }
+    }
+
+    // Later settings win, so the command line overrides the file
+    let get = |key: &str| {
+        vars.iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.clone())
+            .filter(|v| !v.is_empty())
+    };
+
+    VconsoleConfig {
+        keymap: get("KEYMAP"),
+        keymap_toggle: get("KEYMAP_TOGGLE"),
+        font: get("FONT"),
+        font_map: get("FONT_MAP"),
+        font_unimap: get("FONT_UNIMAP"),
+    }
+}
+
+/// Put the keyboard of `device` in Unicode mode and have the terminal
+/// interpret output as UTF-8.
+fn set_unicode(device: &str) -> io::Result<()> {
+    let mut tty = OpenOptions::new().write(true).open(device)?;
+
+    if unsafe { libc::ioctl(tty.as_raw_fd(), KDSKBMODE as _, K_UNICODE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    tty.write_all(b"\x1b%G")
+}
+
+fn run_tool(name: &str, args: &[&str]) -> Result<(), BloomError> {
+    let program = find_program(name).ok_or_else(|| BloomError::Custom(format!("{} not found", name)))?;
+    let output = Command::new(&program).args(args).output().map_err(BloomError::Io)?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(BloomError::Custom(format!("{} failed: {}", name, stderr.trim())))
+    }
+}
@@ -0,0 +1,184 @@
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::Command;
+
+use nix::mount::{mount, MsFlags};
+
+use bloom::config::ZramConfig;
+use bloom::errors::BloomError;
+use bloom::log::{ConsoleLogger, FileLogger};
+use bloom::status::LogLevel;
+use bloom::time::ProcessTimer;
+
+use crate::kernel;
+use crate::storage::find_program;
+use crate::swap::swapon;
+
+/// Reading this adds a zram device and returns its number.
+const HOT_ADD_PATH: &str = "/sys/class/zram-control/hot_add";
+
+/// Marks the end of the first page of a version 1 swap area.
+const SWAP_SIGNATURE: &[u8; 10] = b"SWAPSPACE2";
+
+/// Set up one zram device per configured entry, as swap or with a mounted
+/// filesystem. A device that fails is logged and skipped.
+pub fn setup_zram(
+    devices: &[ZramConfig],
+    console_logger: &mut dyn ConsoleLogger,
+    file_logger: &mut dyn FileLogger,
+) -> Result<(), BloomError> {
+    if devices.is_empty() {
+        return Ok(());
+    }
+
+    let timer = ProcessTimer::start();
+    let mut log = |level: LogLevel, msg: &str| {
+        console_logger.message(level, msg, timer.elapsed());
+        file_logger.log(level, msg);
+    };
+
+    if let Err(e) = kernel::load_module("zram") {
+        log(LogLevel::Fail, &format!("Cannot load the zram module: {}", e));
+        return Err(e);
+    }
+
+    for (index, config) in devices.iter().enumerate() {
+        let result = device(index).and_then(|name| {
+            configure(&name, config)?;
+            match &config.mount {
+                Some(target) => format_and_mount(&name, target, &config.fstype),
+                None => make_swap(&name, config.priority),
+            }
+            .map(|()| name)
+        });
+
+        match (result, &config.mount) {
+            (Ok(name), Some(target)) => {
+                log(LogLevel::Ok, &format!("Mounted {} ({}) at {}", name, config.size, target))
+            }
+            (Ok(name), None) => log(LogLevel::Ok, &format!("Activated {} ({}) as swap", name, config.size)),
+            (Err(e), _) => log(LogLevel::Fail, &format!("Cannot set up zram device {}: {}", index, e)),
+        }
+    }
+
+    Ok(())
+}
+
+/// `zram<index>`, adding devices until it exists; the module creates one.
+fn device(index: usize) -> Result<String, BloomError> {
+    let name = format!("zram{}", index);
+
+    while !Path::new("/sys/block").join(&name).exists() {
+        let mut added = String::new();
+        fs::File::open(HOT_ADD_PATH)
+            .and_then(|mut hot_add| hot_add.read_to_string(&mut added))
+            .map_err(BloomError::Io)?;
+
+        // Numbers are handed out in order; one past ours means it is gone
+        let added: usize = added
+            .trim()
+            .parse()
+            .map_err(|_| BloomError::Custom(format!("Bad zram device {}", added)))?;
+        if added > index {
+            return Err(BloomError::Custom(format!("{} was not created", name)));
+        }
+    }
+
+    Ok(name)
+}
+
+/// The algorithm has to be chosen before the size, which allocates the device.
+fn configure(name: &str, config: &ZramConfig) -> Result<(), BloomError> {
+    let sysfs = Path::new("/sys/block").join(name);
+
+    if let Some(algorithm) = &config.algorithm {
+        fs::write(sysfs.join("comp_algorithm"), algorithm)
+            .map_err(|e| BloomError::Custom(format!("algorithm '{}': {}", algorithm, e)))?;
+    }
+
+    let size = parse_size(&config.size)?;
+    fs::write(sysfs.join("disksize"), size.to_string()).map_err(BloomError::Io)
+}
+
+/// Bytes with an optional K, M or G suffix, or a percentage of RAM.
+fn parse_size(size: &str) -> Result<u64, BloomError> {
+    let invalid = || BloomError::Parse(format!("Invalid zram size: {}", size));
+    let size = size.trim();
+
+    if let Some(percent) = size.strip_suffix('%') {
+        let percent: u64 = percent.trim().parse().map_err(|_| invalid())?;
+        return (total_memory()? / 100).checked_mul(percent).ok_or_else(invalid);
+    }
+
+    let split = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (value, unit) = size.split_at(split);
+    let value: u64 = value.parse().map_err(|_| invalid())?;
+
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return Err(invalid()),
+    };
+
+    value.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+/// MemTotal from /proc/meminfo, in bytes.
+fn total_memory() -> Result<u64, BloomError> {
+    fs::read_to_string("/proc/meminfo")
+        .map_err(BloomError::Io)?
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))
+        .and_then(|kb| kb.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .and_then(|kb| kb.checked_mul(1024))
+        .ok_or_else(|| BloomError::Parse("No MemTotal in /proc/meminfo".into()))
+}
+
+/// Write a swap header, as mkswap would, and turn the device on with
+/// `priority`.
+fn make_swap(name: &str, priority: i32) -> Result<(), BloomError> {
+    let path = format!("/dev/{}", name);
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let size: u64 = fs::read_to_string(Path::new("/sys/block").join(name).join("disksize"))
+        .map_err(BloomError::Io)?
+        .trim()
+        .parse()
+        .map_err(|_| BloomError::Custom(format!("Cannot read the size of {}", name)))?;
+
+    // Version, last page and bad page count follow the boot block
+    let mut header = vec![0u8; page_size];
+    let last_page = (size / page_size as u64).saturating_sub(1) as u32;
+    header[1024..1028].copy_from_slice(&1u32.to_ne_bytes());
+    header[1028..1032].copy_from_slice(&last_page.to_ne_bytes());
+    header[page_size - SWAP_SIGNATURE.len()..].copy_from_slice(SWAP_SIGNATURE);
+
+    OpenOptions::new()
+        .write(true)
+        .open(&path)
+        .and_then(|mut dev| dev.write_all(&header))
+        .map_err(BloomError::Io)?;
+
+    swapon(&path, Some(priority)).map_err(BloomError::Io)
+}
+
+fn format_and_mount(name: &str, target: &str, fstype: &str) -> Result<(), BloomError> {
+    let path = format!("/dev/{}", name);
+    let mkfs = format!("mkfs.{}", fstype);
+    let program = find_program(&mkfs).ok_or_else(|| BloomError::Custom(format!("{} not found", mkfs)))?;
+
+    let output = Command::new(program)
+        .arg(&path)
+        .output()
+        .map_err(BloomError::Io)?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(BloomError::Custom(format!("{} failed: {}", mkfs, stderr.trim())));
+    }
+
+    fs::create_dir_all(target).map_err(BloomError::Io)?;
+    mount(Some(path.as_str()), target, Some(fstype), MsFlags::MS_NOSUID | MsFlags::MS_NODEV, None::<&str>)
+        .map_err(BloomError::Nix)
+}
@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use bloom::journal::{JournalEntry, JournalQuery, LogFrame};
+use bloom::status::LogLevel;
+
+/// Start of every journal file; bump the digit when the layout changes.
+const MAGIC: &[u8; 8] = b"VJOURNL1";
+
+/// Fixed part of a record after its length prefix: timestamp (8),
+/// level (1), boot id (16) and source length (2). Source and message follow.
+const RECORD_HEADER: usize = 8 + 1 + 16 + 2;
+
+/// Longest record taken for real when reading a journal back; anything
+/// longer is corruption, as frames are far smaller.
+const MAX_RECORD: u32 = 16 * 1024 * 1024;
+
+pub type BootId = [u8; 16];
+
+/// Where a record lives and what it can be filtered on, so queries only
+/// read the messages they return.
+struct IndexEntry {
+    /// Offset of the record body, just past its length prefix.
+    offset: u64,
+    len: u32,
+    timestamp_us: u64,
+    level: LogLevel,
+    boot: u16,
+    source: u32,
+}
+
+/// Append-only binary log store with an in-memory index.
+///
+/// Each record is a little-endian `u32` length followed by the header
+/// described at `RECORD_HEADER`, the source name and the message.
+///
+/// Once the file reaches `max_size` it is renamed to `<path>.1`, older ones
+/// moving up a number, and a new one is started. The `max_files` newest
+/// rotated journals are kept open and searched along with the current one.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    end: u64,
+    index: Vec<IndexEntry>,
+    sources: Vec<String>,
+    source_ids: HashMap<String, u32>,
+    boots: Vec<BootId>,
+    max_size: u64,
+    max_files: u32,
+    /// Rotated journals, newest first.
+    archives: Vec<Journal>,
+}
+
+impl Journal {
+    /// Open or create the journal at `path` and index what is already in it,
+    /// along with the rotated journals next to it. A record cut short by a
+    /// crash is dropped.
+    pub fn open(path: &str, max_size: u64, max_files: u32) -> io::Result<Self> {
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut journal = Self::open_file(path, max_size, max_files)?;
+
+        for n in 1..=max_files {
+            let archive = numbered(path, n);
+            if !archive.exists() {
+                break;
+            }
+            match Self::open_file(&archive, max_size, 0) {
+                Ok(archive) => journal.archives.push(archive),
+                Err(e) => {
+                    eprintln!("[logd] Skipping {}: {}", archive.display(), e);
+                    break;
+                }
+            }
+        }
+
+        Ok(journal)
+    }
+
+    fn open_file(path: &Path, max_size: u64, max_files: u32) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        let len = file.metadata()?.len();
+
+        let mut journal = Self {
+            path: path.to_path_buf(),
+            file,
+            end: MAGIC.len() as u64,
+            index: Vec::new(),
+            sources: Vec::new(),
+            source_ids: HashMap::new(),
+            boots: Vec::new(),
+            max_size,
+            max_files,
+            archives: Vec::new(),
+        };
+
+        if len == 0 {
+            journal.file.write_all_at(MAGIC, 0)?;
+            return Ok(journal);
+        }
+
+        let mut magic = [0; 8];
+        journal.file.read_exact_at(&mut magic, 0)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a journal", path.display())));
+        }
+
+        journal.scan(len)?;
+        Ok(journal)
+    }
+
+    fn scan(&mut self, len: u64) -> io::Result<()> {
+        let mut pos = self.end;
+
+        while pos + 4 <= len {
+            let mut prefix = [0; 4];
+            self.file.read_exact_at(&mut prefix, pos)?;
+            let record_len = u32::from_le_bytes(prefix);
+
+            if record_len < RECORD_HEADER as u32 || record_len > MAX_RECORD || pos + 4 + record_len as u64 > len {
+                break;
+            }
+
+            let mut header = [0; RECORD_HEADER];
+            self.file.read_exact_at(&mut header, pos + 4)?;
+            let source_len = u16::from_le_bytes([header[25], header[26]]) as usize;
+            if source_len > record_len as usize - RECORD_HEADER {
+                break;
+            }
+
+            let mut source = vec![0; source_len];
+            self.file.read_exact_at(&mut source, pos + 4 + RECORD_HEADER as u64)?;
+
+            let boot: BootId = header[9..25].try_into().unwrap();
+            let entry = IndexEntry {
+                offset: pos + 4,
+                len: record_len,
+                timestamp_us: u64::from_le_bytes(header[0..8].try_into().unwrap()),
+                level: level_from_u8(header[8]),
+                boot: self.boot_index(boot),
+                source: self.source_index(&String::from_utf8_lossy(&source)),
+            };
+            self.index.push(entry);
+
+            pos += 4 + record_len as u64;
+        }
+
+        if pos < len {
+            eprintln!("[logd] Dropping {} bytes of incomplete or corrupt journal data", len - pos);
+            self.file.set_len(pos)?;
+        }
+
+        self.end = pos;
+        Ok(())
+    }
+
+    fn boot_index(&mut self, boot: BootId) -> u16 {
+        match self.boots.iter().position(|b| *b == boot) {
+            Some(i) => i as u16,
+            None => {
+                self.boots.push(boot);
+                (self.boots.len() - 1) as u16
+            }
+        }
+    }
+
+    fn source_index(&mut self, source: &str) -> u32 {
+        if let Some(id) = self.source_ids.get(source) {
+            return *id;
+        }
+
+        let id = self.sources.len() as u32;
+        self.sources.push(source.to_string());
+        self.source_ids.insert(source.to_string(), id);
+        id
+    }
+
+    pub fn append(&mut self, boot: BootId, frame: &LogFrame) -> io::Result<()> {
+        let source = truncate(&frame.source, u16::MAX as usize);
+        let len = RECORD_HEADER + source.len() + frame.message.len();
+
+        let mut record = Vec::with_capacity(4 + len);
+        record.extend_from_slice(&(len as u32).to_le_bytes());
+        record.extend_from_slice(&frame.timestamp_us.to_le_bytes());
+        record.push(level_to_u8(frame.level));
+        record.extend_from_slice(&boot);
+        record.extend_from_slice(&(source.len() as u16).to_le_bytes());
+        record.extend_from_slice(source.as_bytes());
+        record.extend_from_slice(frame.message.as_bytes());
+
+        // A failed rotation is retried with the next record; nothing is lost
+        if self.max_size > 0
+            && self.end > MAGIC.len() as u64
+            && self.end + record.len() as u64 > self.max_size
+            && let Err(e) = self.rotate()
+        {
+            eprintln!("[logd] Cannot rotate {}: {}", self.path.display(), e);
+        }
+
+        self.file.write_all_at(&record, self.end)?;
+
+        let entry = IndexEntry {
+            offset: self.end + 4,
+            len: len as u32,
+            timestamp_us: frame.timestamp_us,
+            level: frame.level,
+            boot: self.boot_index(boot),
+            source: self.source_index(source),
+        };
+        self.index.push(entry);
+        self.end += record.len() as u64;
+
+        Ok(())
+    }
+
+    /// Move this file to `<path>.1`, the rotated ones up a number, and carry
+    /// on in a new, empty one.
+    fn rotate(&mut self) -> io::Result<()> {
+        let keep = self.max_files as usize;
+
+        while self.archives.len() >= keep.max(1) {
+            self.archives.pop();
+            let _ = fs::remove_file(numbered(&self.path, self.archives.len() as u32 + 1));
+        }
+        for n in (1..=self.archives.len() as u32).rev() {
+            fs::rename(numbered(&self.path, n), numbered(&self.path, n + 1))?;
+        }
+
+        if keep > 0 {
+            fs::rename(&self.path, numbered(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        let archives = mem::take(&mut self.archives);
+        let fresh = Self::open_file(&self.path, self.max_size, self.max_files)?;
+        let old = mem::replace(self, fresh);
+
+        self.archives = archives;
+        if keep > 0 {
+            self.archives.insert(0, old);
+        }
+        Ok(())
+    }
+
+    /// Matching entries across the current and rotated journals, oldest
+    /// first; with a limit, the newest `limit` of them.
+    pub fn query(&self, query: &JournalQuery) -> io::Result<Vec<JournalEntry>> {
+        let limit = if query.limit == 0 { usize::MAX } else { query.limit };
+        let mut entries = Vec::new();
+
+        for journal in std::iter::once(self).chain(&self.archives) {
+            if entries.len() >= limit {
+                break;
+            }
+            let mut older = journal.query_file(query, limit - entries.len())?;
+            older.append(&mut entries);
+            entries = older;
+        }
+
+        Ok(entries)
+    }
+
+    fn query_file(&self, query: &JournalQuery, limit: usize) -> io::Result<Vec<JournalEntry>> {
+        let source = match &query.source {
+            Some(name) => match self.source_ids.get(name) {
+                Some(id) => Some(*id),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+
+        let boot = match &query.boot_id {
+            Some(id) => match parse_boot_id(id).and_then(|b| self.boots.iter().position(|known| *known == b)) {
+                Some(i) => Some(i as u16),
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+
+        let matches = self.index.iter().rev().filter(|e| {
+            source.is_none_or(|s| e.source == s)
+                && boot.is_none_or(|b| e.boot == b)
+                && query.min_level.is_none_or(|level| e.level >= level)
+        });
+
+        let mut selected: Vec<&IndexEntry> = matches.take(limit).collect();
+        selected.reverse();
+
+        selected.into_iter().map(|e| self.read_entry(e)).collect()
+    }
+
+    fn read_entry(&self, entry: &IndexEntry) -> io::Result<JournalEntry> {
+        let mut record = vec![0; entry.len as usize];
+        self.file.read_exact_at(&mut record, entry.offset)?;
+
+        let source_len = match record.get(25..27) {
+            Some(len) => u16::from_le_bytes([len[0], len[1]]) as usize,
+            None => 0,
+        };
+        let message = record
+            .get(RECORD_HEADER + source_len..)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "journal record shorter than its source name"))?;
+
+        Ok(JournalEntry {
+            boot_id: format_boot_id(&self.boots[entry.boot as usize]),
+            source: self.sources[entry.source as usize].clone(),
+            level: entry.level,
+            timestamp_us: entry.timestamp_us,
+            message: String::from_utf8_lossy(message).into_owned(),
+        })
+    }
+}
+
+fn level_to_u8(level: LogLevel) -> u8 {
+    match level {
+        LogLevel::Info => 0,
+        LogLevel::Warn => 1,
+        LogLevel::Fail => 2,
+        LogLevel::Ok => 3,
+    }
+}
+
+fn level_from_u8(level: u8) -> LogLevel {
+    match level {
+        1 => LogLevel::Warn,
+        2 => LogLevel::Fail,
+        3 => LogLevel::Ok,
+        _ => LogLevel::Info,
+    }
+}
+
+/// `<path>.<n>`, where the `n`th newest rotated journal is kept.
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Parse the 32 hex digits of a boot id, as given by `bloom::journal::boot_id`.
+pub fn parse_boot_id(id: &str) -> Option<BootId> {
+    if id.len() != 32 {
+        return None;
+    }
+
+    let mut boot = [0; 16];
+    for (i, byte) in boot.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(id.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(boot)
+}
+
+fn format_boot_id(boot: &BootId) -> String {
+    boot.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Longest prefix of `s` that fits in `max` bytes without splitting a character.
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
@@ -0,0 +1,89 @@
+mod journal;
+mod server;
+
+use std::fs;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex};
+
+use bloom::config::{load_config, LogdConfig};
+use bloom::journal::{boot_id, LogFrame, LOGD_QUERY_SOCKET_PATH, LOGD_SOCKET_PATH};
+
+use crate::journal::{parse_boot_id, Journal};
+use crate::server::serve_queries;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Binary journal shared by init, verdantd and every captured service.
+const JOURNAL_PATH: &str = "/var/log/verdant/journal";
+
+/// Largest frame accepted; longer datagrams are cut off by the kernel.
+const MAX_FRAME: usize = 256 * 1024;
+
+fn main() {
+    println!("[logd] verdant-logd v{} starting", VERSION);
+
+    let config = match load_config() {
+        Ok(config) => config.logd,
+        Err(e) => {
+            eprintln!("[logd] Cannot load config, using defaults: {}", e);
+            LogdConfig::default()
+        }
+    };
+
+    let max_size = config.max_size_mb.saturating_mul(1024 * 1024);
+    let journal = match Journal::open(JOURNAL_PATH, max_size, config.max_files) {
+        Ok(journal) => Arc::new(Mutex::new(journal)),
+        Err(e) => {
+            eprintln!("[logd] Cannot open {}: {}", JOURNAL_PATH, e);
+            process::exit(1);
+        }
+    };
+
+    let Some(boot) = parse_boot_id(&boot_id()) else {
+        eprintln!("[logd] Cannot determine the boot id");
+        process::exit(1);
+    };
+
+    if let Some(parent) = Path::new(LOGD_SOCKET_PATH).parent()
+        && let Err(e) = fs::create_dir_all(parent)
+    {
+        eprintln!("[logd] Cannot create {}: {}", parent.display(), e);
+        process::exit(1);
+    }
+
+    let _ = fs::remove_file(LOGD_SOCKET_PATH);
+    let socket = match UnixDatagram::bind(LOGD_SOCKET_PATH) {
+        Ok(socket) => socket,
+        Err(e) => {
+            eprintln!("[logd] Cannot bind {}: {}", LOGD_SOCKET_PATH, e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = serve_queries(LOGD_QUERY_SOCKET_PATH, Arc::clone(&journal)) {
+        eprintln!("[logd] Cannot serve queries on {}: {}", LOGD_QUERY_SOCKET_PATH, e);
+        process::exit(1);
+    }
+
+    let mut buf = vec![0; MAX_FRAME];
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(e) => {
+                eprintln!("[logd] Receive failed: {}", e);
+                continue;
+            }
+        };
+
+        let frame: LogFrame = match serde_json::from_slice(&buf[..len]) {
+            Ok(frame) => frame,
+            Err(_) => continue, // not ours, or cut off
+        };
+
+        if let Err(e) = journal.lock().unwrap().append(boot, &frame) {
+            eprintln!("[logd] Cannot write to journal: {}", e);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use bloom::journal::JournalQuery;
+
+use crate::journal::Journal;
+
+/// Answer journal queries on `path`: one JSON `JournalQuery` line in, one
+/// JSON array of entries out, per connection.
+pub fn serve_queries(path: &str, journal: Arc<Mutex<Journal>>) -> std::io::Result<()> {
+    let _ = fs::remove_file(path);
+    let listener = UnixListener::bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let journal = Arc::clone(&journal);
+            thread::spawn(move || handle_query(stream, &journal));
+        }
+    });
+
+    Ok(())
+}
+
+fn handle_query(mut stream: UnixStream, journal: &Mutex<Journal>) {
+    let mut line = Vec::new();
+    if BufReader::new(&stream).read_until(b'\n', &mut line).is_err() {
+        return;
+    }
+
+    let query: JournalQuery = match serde_json::from_slice(&line) {
+        Ok(query) => query,
+        Err(e) => {
+            eprintln!("[logd] Ignoring malformed query: {}", e);
+            return;
+        }
+    };
+
+    let entries = match journal.lock().unwrap().query(&query) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("[logd] Query failed: {}", e);
+            Vec::new()
+        }
+    };
+
+    if let Ok(mut response) = serde_json::to_vec(&entries) {
+        response.push(b'\n');
+        let _ = stream.write_all(&response);
+    }
+}
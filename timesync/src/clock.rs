@@ -0,0 +1,39 @@
+use std::io;
+
+pub const NANOS_PER_SEC: i128 = 1_000_000_000;
+
+/// Set the realtime clock forward or back by `offset` nanoseconds at once.
+pub fn step(offset: i128) -> io::Result<()> {
+    let mut now = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    if unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut now) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let target = now.tv_sec as i128 * NANOS_PER_SEC + now.tv_nsec as i128 + offset;
+    let target = libc::timespec {
+        tv_sec: target.div_euclid(NANOS_PER_SEC) as libc::time_t,
+        tv_nsec: target.rem_euclid(NANOS_PER_SEC) as libc::c_long,
+    };
+
+    if unsafe { libc::clock_settime(libc::CLOCK_REALTIME, &target) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Have the kernel gradually speed up or slow down the clock until it has
+/// made up `offset` nanoseconds, replacing any correction still under way.
+/// Time never goes backwards, but this only corrects about half a
+/// millisecond per second.
+pub fn slew(offset: i128) -> io::Result<()> {
+    let micros = offset / 1000;
+    let delta = libc::timeval {
+        tv_sec: micros.div_euclid(1_000_000) as libc::time_t,
+        tv_usec: micros.rem_euclid(1_000_000) as libc::suseconds_t,
+    };
+
+    if unsafe { libc::adjtime(&delta, std::ptr::null_mut()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+mod clock;
+mod sntp;
+
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bloom::config::{load_config, ClockAdjust, TimesyncConfig};
+
+use crate::sntp::{QueryError, Sample};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Until the first answer, as the network may still be coming up, servers
+/// are asked again after this long, doubling up to the poll interval.
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+
+/// Longest a server asking us to slow down is left alone, the longest poll
+/// interval NTP allows.
+const MAX_RATE_BACKOFF: Duration = Duration::from_secs(1 << 17);
+
+/// A configured server and whether it may be asked right now.
+struct Server {
+    name: String,
+    /// Not asked before this; pushed back whenever it answers RATE.
+    next_query: Instant,
+    /// How long the last RATE held it off, doubling with each one.
+    rate_backoff: Duration,
+    /// It answered DENY or RSTR and is never asked again.
+    denied: bool,
+}
+
+fn main() {
+    println!("[timesyncd] verdant-timesyncd v{} starting", VERSION);
+
+    let config = match load_config() {
+        Ok(config) => config.timesync,
+        Err(e) => {
+            eprintln!("[timesyncd] Cannot load config, using defaults: {}", e);
+            TimesyncConfig::default()
+        }
+    };
+
+    if config.servers.is_empty() {
+        eprintln!("[timesyncd] No NTP servers configured");
+        process::exit(1);
+    }
+
+    let poll_interval = Duration::from_secs(config.poll_interval.max(16));
+    let mut retry = Some(FIRST_RETRY);
+    let mut servers: Vec<Server> = config
+        .servers
+        .iter()
+        .map(|name| Server {
+            name: name.clone(),
+            next_query: Instant::now(),
+            rate_backoff: Duration::ZERO,
+            denied: false,
+        })
+        .collect();
+
+    loop {
+        if servers.iter().all(|server| server.denied) {
+            eprintln!("[timesyncd] Every NTP server refused service");
+            process::exit(1);
+        }
+
+        match best_sample(&mut servers, poll_interval) {
+            Some(sample) => {
+                adjust_clock(&config, &sample);
+                retry = None;
+            }
+            None => eprintln!("[timesyncd] No NTP server answered"),
+        }
+
+        match retry {
+            Some(wait) => {
+                thread::sleep(wait);
+                retry = Some((wait * 2).min(poll_interval));
+            }
+            None => thread::sleep(poll_interval),
+        }
+    }
+}
+
+/// Ask every server that may be asked and keep the answer with the shortest
+/// round trip, whose offset is least skewed by network delay.
+fn best_sample(servers: &mut [Server], poll_interval: Duration) -> Option<Sample> {
+    let now = Instant::now();
+    servers
+        .iter_mut()
+        .filter(|server| !server.denied && server.next_query <= now)
+        .filter_map(|server| match sntp::query(&server.name) {
+            Ok(sample) => Some(sample),
+            Err(QueryError::KissOfDeath { code, .. }) if code == "RATE" => {
+                server.rate_backoff = (server.rate_backoff * 2).max(poll_interval).min(MAX_RATE_BACKOFF);
+                server.next_query = now + server.rate_backoff;
+                eprintln!(
+                    "[timesyncd] {} asked to be polled less often, next in {}s",
+                    server.name,
+                    server.rate_backoff.as_secs()
+                );
+                None
+            }
+            Err(QueryError::KissOfDeath { code, .. }) if code == "DENY" || code == "RSTR" => {
+                server.denied = true;
+                eprintln!("[timesyncd] {} refused service ({}), no longer asking it", server.name, code);
+                None
+            }
+            Err(e) => {
+                eprintln!("[timesyncd] {}: {}", server.name, e);
+                None
+            }
+        })
+        .min_by_key(|sample| sample.delay)
+}
+
+fn adjust_clock(config: &TimesyncConfig, sample: &Sample) {
+    let threshold = config.step_threshold_ms as i128 * 1_000_000;
+    let step = match config.adjust {
+        ClockAdjust::Auto => sample.offset.abs() > threshold,
+        ClockAdjust::Slew => false,
+        ClockAdjust::Step => true,
+    };
+
+    let (result, action) = if step {
+        (clock::step(sample.offset), "Stepped")
+    } else {
+        (clock::slew(sample.offset), "Slewing")
+    };
+
+    let offset_ms = sample.offset as f64 / 1e6;
+    match result {
+        Ok(()) => println!(
+            "[timesyncd] {} clock by {:+.3}ms (server {}, stratum {}, delay {:.3}ms)",
+            action,
+            offset_ms,
+            sample.server,
+            sample.stratum,
+            sample.delay as f64 / 1e6
+        ),
+        Err(e) => eprintln!("[timesyncd] Cannot adjust clock by {:+.3}ms: {}", offset_ms, e),
+    }
+}
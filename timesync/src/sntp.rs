@@ -0,0 +1,204 @@
+use std::fmt;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::clock::NANOS_PER_SEC;
+
+pub const NTP_PORT: u16 = 123;
+
+const PACKET_LEN: usize = 48;
+
+/// Seconds from the NTP epoch, 1900, to the Unix one.
+const NTP_UNIX_OFFSET: i128 = 2_208_988_800;
+
+/// How long a server has to answer.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Leap indicator 0, version 4, mode 3 (client).
+const CLIENT_HEADER: u8 = 0x23;
+const MODE_SERVER: u8 = 4;
+/// Leap indicator meaning the server's clock is not synchronized.
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// One answer from a server: how far off the local clock is, and the round
+/// trip it took, in nanoseconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub server: SocketAddr,
+    pub offset: i128,
+    pub delay: i128,
+    pub stratum: u8,
+}
+
+/// Why a server gave no time.
+#[derive(Debug)]
+pub enum QueryError {
+    Io(io::Error),
+    /// The server answered with a kiss-o'-death packet carrying this code,
+    /// such as RATE (ask less often), DENY or RSTR (stop asking).
+    KissOfDeath { server: SocketAddr, code: String },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Io(e) => write!(f, "{}", e),
+            QueryError::KissOfDeath { server, code } => write!(f, "{}: kiss-o'-death {}", server, code),
+        }
+    }
+}
+
+impl From<io::Error> for QueryError {
+    fn from(e: io::Error) -> Self {
+        QueryError::Io(e)
+    }
+}
+
+/// Ask `server`, a name or address with an optional port, for the time,
+/// trying each address it resolves to until one answers. A kiss-o'-death
+/// speaks for the whole server and is returned straight away.
+pub fn query(server: &str) -> Result<Sample, QueryError> {
+    let addrs: Vec<SocketAddr> = match server.to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(_) => (server, NTP_PORT).to_socket_addrs()?.collect(),
+    };
+
+    let mut last_err = QueryError::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", server)));
+    for addr in addrs {
+        match query_addr(addr) {
+            Ok(sample) => return Ok(sample),
+            Err(e @ QueryError::KissOfDeath { .. }) => return Err(e),
+            Err(e) => last_err = e,
+        }
+    }
+    Err(last_err)
+}
+
+fn query_addr(addr: SocketAddr) -> Result<Sample, QueryError> {
+    let bind: SocketAddr = if addr.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(addr)?;
+    socket.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+
+    let mut request = [0u8; PACKET_LEN];
+    request[0] = CLIENT_HEADER;
+    // Echoed back as the originate timestamp, tying the answer to this request
+    let t1 = realtime_nanos();
+    let sent = to_ntp(t1);
+    request[40..48].copy_from_slice(&sent.to_be_bytes());
+    socket.send(&request)?;
+
+    let mut response = [0u8; PACKET_LEN + 20];
+    let (len, t4) = loop {
+        let len = socket.recv(&mut response)?;
+        let t4 = realtime_nanos();
+        // Stray datagrams, such as a late answer to an earlier request, are skipped
+        if len >= PACKET_LEN && read_timestamp(&response, 24) == sent {
+            break (len, t4);
+        }
+    };
+    let response = &response[..len];
+
+    let leap = response[0] >> 6;
+    let mode = response[0] & 0x07;
+    let stratum = response[1];
+    if mode != MODE_SERVER {
+        return Err(invalid(addr, "not a server reply"));
+    }
+    if stratum == 0 {
+        let code = String::from_utf8_lossy(&response[12..16]).trim_end_matches('\0').to_string();
+        return Err(QueryError::KissOfDeath { server: addr, code });
+    }
+    if leap == LEAP_UNSYNCHRONIZED || stratum > 15 {
+        return Err(invalid(addr, "server is not synchronized"));
+    }
+
+    if read_timestamp(response, 40) == 0 {
+        return Err(invalid(addr, "no transmit timestamp"));
+    }
+    let t2 = from_ntp(read_timestamp(response, 32));
+    let t3 = from_ntp(read_timestamp(response, 40));
+
+    // The server's own timestamps disagree with the round trip measured here
+    let delay = (t4 - t1) - (t3 - t2);
+    if delay < 0 {
+        return Err(invalid(addr, "negative round-trip delay"));
+    }
+
+    Ok(Sample {
+        server: addr,
+        offset: ((t2 - t1) + (t3 - t4)) / 2,
+        delay,
+        stratum,
+    })
+}
+
+fn invalid(addr: SocketAddr, reason: &str) -> QueryError {
+    QueryError::Io(io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", addr, reason)))
+}
+
+fn read_timestamp(packet: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(packet[at..at + 8].try_into().unwrap())
+}
+
+/// The realtime clock in nanoseconds since the Unix epoch; negative when
+/// the clock is set before it.
+fn realtime_nanos() -> i128 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_nanos() as i128,
+        Err(e) => -(e.duration().as_nanos() as i128),
+    }
+}
+
+/// Nanoseconds since the Unix epoch as a 32.32 fixed-point NTP timestamp.
+fn to_ntp(nanos: i128) -> u64 {
+    let secs = nanos.div_euclid(NANOS_PER_SEC) + NTP_UNIX_OFFSET;
+    let frac = (nanos.rem_euclid(NANOS_PER_SEC) << 32) / NANOS_PER_SEC;
+    ((secs as u64) << 32) | frac as u64
+}
+
+/// An NTP timestamp as nanoseconds since the Unix epoch. Seconds below 2^31
+/// are taken to be in the era starting in 2036, after the counter wraps.
+fn from_ntp(timestamp: u64) -> i128 {
+    let mut secs = (timestamp >> 32) as i128;
+    if secs < 1 << 31 {
+        secs += 1 << 32;
+    }
+    let frac = (timestamp & 0xffff_ffff) as i128;
+    (secs - NTP_UNIX_OFFSET) * NANOS_PER_SEC + ((frac * NANOS_PER_SEC) >> 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unix_epoch_in_ntp() {
+        assert_eq!(to_ntp(0), (NTP_UNIX_OFFSET as u64) << 32);
+        assert_eq!(from_ntp((NTP_UNIX_OFFSET as u64) << 32), 0);
+    }
+
+    #[test]
+    fn fraction_round_trip() {
+        let nanos = 1_700_000_000 * NANOS_PER_SEC + NANOS_PER_SEC / 2;
+        let timestamp = to_ntp(nanos);
+
+        assert_eq!(timestamp & 0xffff_ffff, 0x8000_0000);
+        assert_eq!(from_ntp(timestamp), nanos);
+    }
+
+    #[test]
+    fn before_unix_epoch() {
+        let nanos = -NANOS_PER_SEC / 4;
+        assert_eq!(to_ntp(nanos), ((NTP_UNIX_OFFSET as u64 - 1) << 32) | 0xc000_0000);
+    }
+
+    #[test]
+    fn seconds_wrap_into_next_era() {
+        // 2036-02-07, where the 32-bit seconds counter wraps
+        let era_start = ((1i128 << 32) - NTP_UNIX_OFFSET) * NANOS_PER_SEC;
+        assert_eq!(from_ntp(0), era_start);
+        assert_eq!(from_ntp(10 << 32), era_start + 10 * NANOS_PER_SEC);
+    }
+}
@@ -0,0 +1,154 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use verdantd::{dropin_files, parse_with_dropins};
+
+use crate::verify::{describe, installed_files, parse};
+
+/// Drop-in written by `vctl edit`, loaded after the service file and any
+/// drop-ins that sort before it.
+const OVERRIDE_NAME: &str = "override.conf";
+
+/// Open the override drop-in of service `name` in the user's editor and save
+/// it once it parses. Returns whether anything on disk changed, in which
+/// case verdantd needs a reload.
+pub fn edit(name: &str) -> Result<bool, String> {
+    let file = find_service_file(name).ok_or_else(|| format!("no service file defines '{}'", name))?;
+    let file_str = file.to_str().unwrap_or_default();
+
+    let dir = PathBuf::from(format!("{}.d", file_str));
+    let created_dir = !dir.is_dir();
+    fs::create_dir_all(&dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+
+    let target = dir.join(OVERRIDE_NAME);
+    // Edited under a name the parser ignores, so a bad edit never goes live
+    let scratch = dir.join(format!("{}.edit", OVERRIDE_NAME));
+
+    let original = fs::read_to_string(&target).ok();
+    let seed = original.clone().unwrap_or_else(|| {
+        format!(
+            "# Overrides for '{}'; settings here replace those in {}.\n\
+             # A cmd: or instances: list replaces the whole list.\n\n",
+            name,
+            file.display()
+        )
+    });
+    fs::write(&scratch, &seed).map_err(|e| format!("cannot write {}: {}", scratch.display(), e))?;
+
+    let edited = match edit_until_valid(file_str, &target, &scratch) {
+        Ok(Some(edited)) => edited,
+        other => {
+            let _ = fs::remove_file(&scratch);
+            if created_dir {
+                let _ = fs::remove_dir(&dir);
+            }
+            return other.map(|_| false);
+        }
+    };
+
+    if original.as_deref() == Some(edited.as_str()) || (original.is_none() && edited == seed) {
+        let _ = fs::remove_file(&scratch);
+        if created_dir {
+            let _ = fs::remove_dir(&dir);
+        }
+        println!("No changes made");
+        return Ok(false);
+    }
+
+    // Nothing but comments left: drop the override altogether
+    if !has_settings(&edited) {
+        let _ = fs::remove_file(&scratch);
+        if original.is_none() {
+            if created_dir {
+                let _ = fs::remove_dir(&dir);
+            }
+            return Ok(false);
+        }
+        fs::remove_file(&target).map_err(|e| format!("cannot remove {}: {}", target.display(), e))?;
+        println!("Removed {}", target.display());
+        return Ok(true);
+    }
+
+    fs::rename(&scratch, &target).map_err(|e| format!("cannot save {}: {}", target.display(), e))?;
+    println!("Saved {}", target.display());
+    Ok(true)
+}
+
+/// The installed file defining `name`, by name, alias or template.
+fn find_service_file(name: &str) -> Option<PathBuf> {
+    installed_files().into_iter().find(|path| {
+        parse(path).is_ok_and(|services| {
+            services.iter().any(|s| {
+                s.name == name || s.aliases.iter().any(|a| a == name) || s.template_instance(name).is_some()
+            })
+        })
+    })
+}
+
+/// Run the editor on `scratch` until it parses together with the service
+/// file and its other drop-ins. Returns the accepted contents, or None if
+/// the user gave up on an invalid edit.
+fn edit_until_valid(file: &str, target: &Path, scratch: &Path) -> Result<Option<String>, String> {
+    loop {
+        run_editor(scratch)?;
+
+        let edited = fs::read_to_string(scratch).map_err(|e| format!("cannot read {}: {}", scratch.display(), e))?;
+
+        let dropins: Vec<PathBuf> = dropin_files(file)
+            .into_iter()
+            .map(|p| if p == target { scratch.to_path_buf() } else { p })
+            .chain((!target.exists()).then(|| scratch.to_path_buf()))
+            .collect();
+
+        match parse_with_dropins(file, &dropins) {
+            Ok(_) => return Ok(Some(edited)),
+            Err(e) => {
+                eprintln!("{}", describe(e));
+                if !ask("Edit again? [Y/n] ") {
+                    eprintln!("Discarded changes to {}", target.display());
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// $VISUAL, then $EDITOR, then vi. Run through the shell so the variable
+/// may carry arguments.
+fn run_editor(path: &Path) -> Result<(), String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+
+    let status = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .map_err(|e| format!("cannot run {}: {}", editor, e))?;
+
+    if !status.success() {
+        return Err(format!("{} exited with {}", editor, status));
+    }
+    Ok(())
+}
+
+fn ask(prompt: &str) -> bool {
+    print!("{}", prompt);
+    let _ = io::stdout().flush();
+
+    let mut answer = String::new();
+    if !matches!(io::stdin().read_line(&mut answer), Ok(n) if n > 0) {
+        return false;
+    }
+    !matches!(answer.trim().to_lowercase().as_str(), "n" | "no")
+}
+
+fn has_settings(contents: &str) -> bool {
+    contents.lines().map(str::trim).any(|line| !line.is_empty() && !line.starts_with('#'))
+}
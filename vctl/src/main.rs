@@ -1,39 +1,420 @@
-use clap::{Parser, Subcommand};
-use bloom::ipc::{IpcRequest, IpcTarget, IpcCommand, send_ipc_request, VERDANTD_SOCKET_PATH};
+
+mod edit;
+mod verify;
+mod watch;
+
+use chrono::{Local, TimeZone};
+use clap::{Parser, Subcommand, ValueEnum};
+use bloom::journal::{self, JournalQuery};
+use bloom::status::LogLevel;
+use bloom::time::{BootTime, BootTimes, BOOT_TIMES_PATH};
+use bloom::ipc::{IpcRequest, IpcResponse, IpcTarget, IpcCommand, IpcInternal, KillWho, PowerAction, send_ipc_request, send_ipc_stream_request, INIT_SOCKET_PATH, VERDANTD_SOCKET_PATH};
 
 #[derive(Parser)]
 #[command(name = "vctl")]
 #[command(about = "Verdant Control CLI", long_about = None)]
 struct Cli {
+    /// Which daemon to talk to
+    #[arg(long, value_enum, global = true, default_value_t = Target::Verdantd)]
+    target: Target,
+
+    /// How to print results: tables for people, JSON for scripts
+    #[arg(long, value_enum, global = true, default_value_t = Output::Table)]
+    output: Output,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Target {
+    Init,
+    Verdantd,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Who {
+    Main,
+    All,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    Table,
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
-    Shutdown,
-    Reboot,
+    /// Power off, now or at a set time (`vctl shutdown +10 "maintenance"`)
+    #[command(alias = "poweroff")]
+    Shutdown {
+        /// `now`, `+MINUTES` or `HH:MM`
+        time: Option<String>,
+        /// Broadcast to logged-in users while counting down
+        message: Option<String>,
+        /// Call off a scheduled shutdown or reboot
+        #[arg(short, long, conflicts_with_all = ["time", "message"])]
+        cancel: bool,
+    },
+    /// Reboot, now or at a set time
+    Reboot {
+        /// `now`, `+MINUTES` or `HH:MM`
+        time: Option<String>,
+        /// Broadcast to logged-in users while counting down
+        message: Option<String>,
+        /// Boot into the EFI firmware setup screen
+        #[arg(long)]
+        firmware_setup: bool,
+    },
+    /// Halt without powering off, now or at a set time
+    Halt {
+        /// `now`, `+MINUTES` or `HH:MM`
+        time: Option<String>,
+        /// Broadcast to logged-in users while counting down
+        message: Option<String>,
+    },
+    /// Stop services and boot straight into a new kernel, skipping the firmware
+    Kexec {
+        /// Kernel image to boot; defaults to the one loaded with `kexec -l`
+        #[arg(long)]
+        kernel: Option<String>,
+        /// Initramfs for the new kernel
+        #[arg(long, requires = "kernel")]
+        initrd: Option<String>,
+        /// Kernel command line; defaults to the running kernel's
+        #[arg(long, requires = "kernel")]
+        append: Option<String>,
+    },
+    /// Suspend to RAM, running the sleep hooks first
+    Suspend,
+    /// Suspend to disk, running the sleep hooks first
+    Hibernate,
+    /// Re-execute verdantd (e.g. after an upgrade) without stopping services
+    DaemonReexec {
+        /// Re-execute init (PID 1) instead
+        #[arg(long)]
+        init: bool,
+    },
+    /// Pick up new and changed service files without restarting anything
+    DaemonReload,
+    /// Start a service
+    Start { name: String },
+    /// Stop a service (and anything part_of or bound to it)
+    Stop { name: String },
+    /// Restart a service (and anything part_of or bound to it)
+    Restart { name: String },
+    /// Ask a running service to reload its configuration
+    Reload { name: String },
+    /// Send a signal to a running service
+    Kill {
+        name: String,
+        /// Signal name or number
+        #[arg(short, long, default_value = "SIGTERM")]
+        signal: String,
+        /// Signal only the main process, or every process of the service
+        #[arg(long, value_enum, default_value_t = Who::All)]
+        who: Who,
+    },
+    /// Start a service at boot (the default)
+    Enable { name: String },
+    /// Skip a service at boot; it can still be started manually
+    Disable { name: String },
+    /// Prevent a service from being started by anything until unmasked
+    Mask { name: String },
+    /// Allow a masked service to be started again
+    Unmask { name: String },
+    /// Allow a service that hit its start limit to be started again
+    ResetFailed {
+        /// Service to reset; all of them if omitted
+        name: Option<String>,
+    },
+    /// Stop everything outside a boot target and start everything in it
+    Isolate { name: String },
+    /// Show daemon status, or the status of a single service
+    Status {
+        /// Service to inspect (verdantd only)
+        name: Option<String>,
+    },
+    /// Print every effective setting of a service as key=value lines
+    Show { name: String },
+    /// Show what a service depends on as a tree
+    ListDependencies {
+        name: String,
+        /// Show what depends on the service instead
+        #[arg(long)]
+        reverse: bool,
+    },
+    /// Show the captured output of a service
+    Logs {
+        name: String,
+        /// Number of lines to show
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+        /// Keep printing new lines as they are written
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Query the journal kept by verdant-logd
+    Journal {
+        /// Only entries from this service, or `init` / `verdantd`
+        #[arg(short, long)]
+        source: Option<String>,
+        /// Boot id to show, or `all`; defaults to the current boot
+        #[arg(short, long)]
+        boot: Option<String>,
+        /// Minimum level: info, warn, fail or ok
+        #[arg(short, long)]
+        priority: Option<String>,
+        /// Number of entries to show
+        #[arg(short = 'n', long, default_value_t = 100)]
+        lines: usize,
+    },
+    /// Continuously show the state of every service as it changes
+    Watch {
+        /// Column to sort by
+        #[arg(long, value_enum, default_value_t = watch::SortKey::Name)]
+        sort: watch::SortKey,
+    },
+    /// Show the boot steps and services that took longest during this boot
+    Blame,
+    /// Check service files for errors before installing them, without verdantd
+    Verify {
+        /// Service files to check
+        #[arg(required_unless_present = "all")]
+        paths: Vec<String>,
+        /// Check every file in the service directory
+        #[arg(long)]
+        all: bool,
+    },
+    /// Edit a service's override drop-in, then reload verdantd
+    Edit { name: String },
+    /// Show timer-activated services with their next and last run
+    ListTimers,
+    /// Print the effective merged configuration (base, drop-ins, environment)
+    ShowConfig,
+    /// Show the hostname, or set it through init
+    Hostname {
+        name: Option<String>,
+        /// Only apply it while no static hostname is configured, as for a
+        /// name from DHCP, and do not write /etc/hostname
+        #[arg(long)]
+        transient: bool,
+    },
+    /// Load, unload or list kernel modules through init
+    Module {
+        #[command(subcommand)]
+        action: ModuleAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ModuleAction {
+    /// Load a kernel module (blacklisted modules are refused)
+    Load { name: String },
+    /// Unload a kernel module
+    Unload { name: String },
+    /// List currently loaded kernel modules
+    List,
 }
 
 fn main() {
     let cli = Cli::parse();
+    let output = cli.output;
+
+    // The journal is served by verdant-logd rather than over IPC
+    if let Commands::Journal { source, boot, priority, lines } = cli.command {
+        show_journal(source, boot, priority, lines, output);
+        return;
+    }
+
+    if let Commands::Verify { paths, all } = &cli.command {
+        if !verify::verify(paths, *all) {
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let ipc_command = match cli.command {
-        Commands::Shutdown => IpcCommand::Shutdown,
-        Commands::Reboot => IpcCommand::Reboot,
+    // A saved override is picked up by the daemon-reload below
+    if let Commands::Edit { name } = &cli.command {
+        match edit::edit(name) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                eprintln!("Cannot edit '{}': {}", name, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Commands::Watch { sort } = cli.command {
+        if !watch::watch(sort, output) {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Commands::Hostname { name: None, .. } = cli.command {
+        match std::fs::read_to_string("/proc/sys/kernel/hostname") {
+            Ok(name) => println!("{}", name.trim()),
+            Err(e) => {
+                eprintln!("Cannot read hostname: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // Boot timings are read straight from the file init and verdantd write
+    if let Commands::Blame = cli.command {
+        show_blame(output);
+        return;
+    }
+
+    let selected = match cli.target {
+        Target::Init => IpcTarget::Init,
+        Target::Verdantd => IpcTarget::Verdantd,
+    };
+
+    let (target, ipc_command) = match cli.command {
+        Commands::Shutdown { cancel: true, .. } => (IpcTarget::Verdantd, IpcCommand::CancelShutdown),
+        Commands::Shutdown { time, message, .. } => power_command(PowerAction::PowerOff, time, message, selected),
+        Commands::Reboot { time, message, firmware_setup } => {
+            power_command(PowerAction::Reboot { firmware_setup }, time, message, selected)
+        }
+        Commands::Halt { time, message } => power_command(PowerAction::Halt, time, message, selected),
+        Commands::Kexec { kernel, initrd, append } => (
+            IpcTarget::Verdantd,
+            IpcCommand::Kexec {
+                kernel: kernel.map(absolute),
+                initrd: initrd.map(absolute),
+                cmdline: append,
+            },
+        ),
+        Commands::Suspend => (IpcTarget::Verdantd, IpcCommand::Suspend),
+        Commands::Hibernate => (IpcTarget::Verdantd, IpcCommand::Hibernate),
+        Commands::DaemonReexec { init: true } => (IpcTarget::Init, IpcCommand::Reexec),
+        Commands::DaemonReexec { init: false } => (IpcTarget::Verdantd, IpcCommand::Reexec),
+        Commands::DaemonReload | Commands::Edit { .. } => (IpcTarget::Verdantd, IpcCommand::Internal(IpcInternal::ReloadConfig)),
+        Commands::Start { name } => (IpcTarget::Verdantd, IpcCommand::StartService(name)),
+        Commands::Stop { name } => (IpcTarget::Verdantd, IpcCommand::StopService(name)),
+        Commands::Restart { name } => (IpcTarget::Verdantd, IpcCommand::RestartService(name)),
+        Commands::Reload { name } => (IpcTarget::Verdantd, IpcCommand::ReloadService(name)),
+        Commands::Kill { name, signal, who } => {
+            let who = match who {
+                Who::Main => KillWho::Main,
+                Who::All => KillWho::All,
+            };
+            (IpcTarget::Verdantd, IpcCommand::KillService { name, signal, who })
+        }
+        Commands::Enable { name } => (IpcTarget::Verdantd, IpcCommand::EnableService(name)),
+        Commands::Disable { name } => (IpcTarget::Verdantd, IpcCommand::DisableService(name)),
+        Commands::Mask { name } => (IpcTarget::Verdantd, IpcCommand::MaskService(name)),
+        Commands::Unmask { name } => (IpcTarget::Verdantd, IpcCommand::UnmaskService(name)),
+        Commands::ResetFailed { name } => (IpcTarget::Verdantd, IpcCommand::ResetFailed(name)),
+        Commands::Isolate { name } => (IpcTarget::Verdantd, IpcCommand::Isolate(name)),
+        Commands::Status { name: None } => (selected, IpcCommand::GetStatus),
+        Commands::Status { name: Some(name) } => (IpcTarget::Verdantd, IpcCommand::GetServiceStatus(name)),
+        Commands::Logs { name, lines, follow } => (
+            IpcTarget::Verdantd,
+            IpcCommand::ServiceLogs {
+                name,
+                lines,
+                cursors: None,
+                follow,
+            },
+        ),
+        Commands::Show { name } => (IpcTarget::Verdantd, IpcCommand::ShowService(name)),
+        Commands::ListDependencies { name, reverse } => {
+            (IpcTarget::Verdantd, IpcCommand::ListDependencies { name, reverse })
+        }
+        Commands::Hostname { name: Some(name), transient } => (IpcTarget::Init, IpcCommand::SetHostname { name, transient }),
+        Commands::Journal { .. }
+        | Commands::Blame
+        | Commands::Verify { .. }
+        | Commands::Watch { .. }
+        | Commands::Hostname { name: None, .. } => {
+            unreachable!("handled above")
+        }
+        Commands::ListTimers => (IpcTarget::Verdantd, IpcCommand::ListTimers),
+        Commands::ShowConfig => (selected, IpcCommand::GetConfig),
+        Commands::Module { action } => (
+            IpcTarget::Init,
+            match action {
+                ModuleAction::Load { name } => IpcCommand::ModuleLoad(name),
+                ModuleAction::Unload { name } => IpcCommand::ModuleUnload(name),
+                ModuleAction::List => IpcCommand::ModuleList,
+            },
+        ),
     };
 
+    let socket_path = match target {
+        IpcTarget::Init => INIT_SOCKET_PATH,
+        IpcTarget::Verdantd => VERDANTD_SOCKET_PATH,
+    };
+
+    let is_module_list = matches!(ipc_command, IpcCommand::ModuleList);
+    let is_init_status = target == IpcTarget::Init && matches!(ipc_command, IpcCommand::GetStatus);
+    let is_show_config = matches!(ipc_command, IpcCommand::GetConfig);
+    let is_list_timers = matches!(ipc_command, IpcCommand::ListTimers);
+    let is_logs = matches!(ipc_command, IpcCommand::ServiceLogs { .. });
+    let is_show = matches!(ipc_command, IpcCommand::ShowService(_));
+    let is_dependencies = matches!(ipc_command, IpcCommand::ListDependencies { .. });
+    let is_follow = matches!(ipc_command, IpcCommand::ServiceLogs { follow: true, .. });
+    let is_daemon_reload = matches!(ipc_command, IpcCommand::Internal(IpcInternal::ReloadConfig));
+    let is_service_status = target == IpcTarget::Verdantd
+        && matches!(ipc_command, IpcCommand::GetStatus | IpcCommand::GetServiceStatus(_));
+
     let request = IpcRequest {
-        target: IpcTarget::Verdantd,
+        id: 0,
+        target,
         command: ipc_command,
     };
 
-    match send_ipc_request(VERDANTD_SOCKET_PATH, &request) {
+    // Followed logs arrive as a stream of chunks until interrupted
+    let result = if is_follow {
+        send_ipc_stream_request(socket_path, &request, |chunk| match output {
+            Output::Table => print_logs(&chunk),
+            Output::Json => println!("{}", chunk),
+        })
+    } else {
+        send_ipc_request(socket_path, &request)
+    };
+
+    match result {
+        Ok(response) if output == Output::Json => {
+            // While following, every chunk has already been printed as a JSON line
+            if !(is_follow && response.success) {
+                print_json(&response);
+            }
+        }
         Ok(response) => {
-            if response.success {
-                println!("Command succeeded: {}", response.message);
-            } else {
+            if !response.success {
                 eprintln!("Command failed: {}", response.message);
+            } else if is_module_list {
+                print_module_list(&response);
+            } else if is_init_status {
+                print_init_status(&response);
+            } else if is_show_config {
+                print_config(&response);
+            } else if is_list_timers {
+                print_timers(&response);
+            } else if is_logs {
+                if let Some(data) = response.data.as_ref() {
+                    print_logs(data);
+                }
+            } else if is_show {
+                print_properties(&response);
+            } else if is_dependencies {
+                if let Some(tree) = response.data.as_ref() {
+                    print_dependency_tree(tree, "", "");
+                }
+            } else if is_daemon_reload {
+                print_reload_summary(&response);
+            } else if is_service_status {
+                print_service_status(&response);
+            } else {
+                println!("Command succeeded: {}", response.message);
             }
         }
         Err(e) => {
@@ -42,3 +423,483 @@ fn main() {
     }
 }
 
+/// `path` made absolute, since verdantd and init resolve it from `/`.
+fn absolute(path: String) -> String {
+    std::path::absolute(&path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or(path)
+}
+
+/// A shutdown, reboot or halt at `time`. Delayed ones are counted down by
+/// verdantd whichever target was selected.
+fn power_command(
+    action: PowerAction,
+    time: Option<String>,
+    message: Option<String>,
+    selected: IpcTarget,
+) -> (IpcTarget, IpcCommand) {
+    let delay_secs = match time.as_deref().map(parse_delay).unwrap_or(Ok(0)) {
+        Ok(delay) => delay,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let target = if delay_secs > 0 { IpcTarget::Verdantd } else { selected };
+    (target, action.command(delay_secs, message))
+}
+
+/// Seconds until `now`, `+MINUTES` or the next `HH:MM`, as shutdown(8) takes them.
+fn parse_delay(time: &str) -> Result<u64, String> {
+    if time == "now" {
+        return Ok(0);
+    }
+
+    if let Some(minutes) = time.strip_prefix('+') {
+        return minutes
+            .parse::<u64>()
+            .ok()
+            .and_then(|m| m.checked_mul(60))
+            .ok_or_else(|| format!("Invalid delay '{}': expected +MINUTES", time));
+    }
+
+    let clock = chrono::NaiveTime::parse_from_str(time, "%H:%M")
+        .map_err(|_| format!("Invalid time '{}': expected now, +MINUTES or HH:MM", time))?;
+
+    let now = Local::now();
+    let mut at = now.date_naive().and_time(clock);
+    if at <= now.naive_local() {
+        at += chrono::TimeDelta::days(1);
+    }
+    Ok((at - now.naive_local()).num_seconds().max(0) as u64)
+}
+
+fn print_module_list(response: &IpcResponse) {
+    let modules = response
+        .data
+        .as_ref()
+        .and_then(|d| d.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    println!("{:<28} {:>10} {:>5}  USED BY", "MODULE", "SIZE", "REFS");
+    for module in modules {
+        let name = module.get("name").and_then(|v| v.as_str()).unwrap_or("?");
+        let size = module.get("size").and_then(|v| v.as_u64()).unwrap_or(0);
+        let refs = module.get("refcount").and_then(|v| v.as_u64()).unwrap_or(0);
+        let used_by = module
+            .get("used_by")
+            .and_then(|v| v.as_array())
+            .map(|list| {
+                list.iter()
+                    .filter_map(|v| v.as_str())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default();
+
+        println!("{:<28} {:>10} {:>5}  {}", name, size, refs, used_by);
+    }
+}
+
+fn print_config(response: &IpcResponse) {
+    let Some(data) = response.data.as_ref() else {
+        println!("{}", response.message);
+        return;
+    };
+
+    // Render as TOML so the output can be compared with, or pasted into, config files
+    match toml::to_string_pretty(data) {
+        Ok(text) => print!("{}", text),
+        Err(_) => println!("{}", serde_json::to_string_pretty(data).unwrap_or_default()),
+    }
+}
+
+fn print_init_status(response: &IpcResponse) {
+    let Some(data) = response.data.as_ref() else {
+        println!("{}", response.message);
+        return;
+    };
+
+    let text = |v: Option<&serde_json::Value>| {
+        v.and_then(|v| v.as_str()).unwrap_or("-").to_string()
+    };
+
+    println!("Phase:          {}", text(data.get("phase")));
+    println!(
+        "Uptime:         {}",
+        data.get("uptime_secs")
+            .and_then(|v| v.as_u64())
+            .map(format_duration_secs)
+            .unwrap_or_else(|| "-".into())
+    );
+    println!(
+        "Boot took:      {}",
+        data.get("boot_duration_ms")
+            .and_then(|v| v.as_u64())
+            .map(|ms| format!("{}.{:03}s", ms / 1000, ms % 1000))
+            .unwrap_or_else(|| "-".into())
+    );
+    println!(
+        "Pending action: {}",
+        data.get("pending_action").and_then(|v| v.as_str()).unwrap_or("none")
+    );
+
+    if let Some(verdantd) = data.get("verdantd") {
+        let pid = verdantd
+            .get("pid")
+            .and_then(|v| v.as_u64())
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "-".into());
+        let running = verdantd.get("running").and_then(|v| v.as_bool()).unwrap_or(false);
+        let booted = verdantd.get("boot_complete").and_then(|v| v.as_bool()).unwrap_or(false);
+
+        println!(
+            "Verdantd:       {} (pid {}, boot {})",
+            if running { "running" } else { "NOT RUNNING" },
+            pid,
+            if booted { "complete" } else { "incomplete" }
+        );
+    }
+
+    if let Some(mounts) = data.get("mounts") {
+        let total = mounts.get("total").and_then(|v| v.as_u64()).unwrap_or(0);
+        println!("Mounts:         {} total", total);
+
+        if let Some(devices) = mounts.get("devices").and_then(|v| v.as_array()) {
+            for dev in devices {
+                let ro = dev.get("read_only").and_then(|v| v.as_bool()).unwrap_or(false);
+                println!(
+                    "  {:<24} {:<20} {:<8} {}",
+                    text(dev.get("target")),
+                    text(dev.get("source")),
+                    text(dev.get("fstype")),
+                    if ro { "ro" } else { "rw" }
+                );
+            }
+        }
+    }
+}
+
+fn print_service_status(response: &IpcResponse) {
+    // A single service comes back as an object, the full listing as an array
+    let services = match response.data.as_ref() {
+        Some(serde_json::Value::Array(list)) => list.clone(),
+        Some(obj @ serde_json::Value::Object(_)) => vec![obj.clone()],
+        _ => {
+            println!("{}", response.message);
+            return;
+        }
+    };
+
+    println!(
+        "{:<24} {:<9} {:<8} {:<10} {:<8} {:>7} {:>14} {:>8}",
+        "SERVICE", "STARTUP", "ENABLED", "STATE", "HEALTH", "PID", "UPTIME", "RESTARTS"
+    );
+
+    let mut any_stale = false;
+    let mut any_limited = false;
+
+    for svc in services {
+        let text = |key: &str| svc.get(key).and_then(|v| v.as_str()).unwrap_or("-").to_string();
+        let mut state = text("state");
+        if svc.get("needs_restart").and_then(|v| v.as_bool()).unwrap_or(false) {
+            state.push('*');
+            any_stale = true;
+        }
+        if svc.get("start_limited").and_then(|v| v.as_bool()).unwrap_or(false) {
+            state.push('!');
+            any_limited = true;
+        }
+        let pid = svc
+            .get("pid")
+            .and_then(|v| v.as_u64())
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| "-".into());
+        let uptime = svc
+            .get("uptime_secs")
+            .and_then(|v| v.as_u64())
+            .map(format_duration_secs)
+            .unwrap_or_else(|| "-".into());
+        let restarts = svc.get("restarts").and_then(|v| v.as_u64()).unwrap_or(0);
+        let enabled = svc.get("enabled").and_then(|v| v.as_bool()).unwrap_or(true);
+        let masked = svc.get("masked").and_then(|v| v.as_bool()).unwrap_or(false);
+        let health = match svc.get("healthy").and_then(|v| v.as_bool()) {
+            Some(true) => "ok",
+            Some(false) => "failing",
+            None => "-",
+        };
+
+        println!(
+            "{:<24} {:<9} {:<8} {:<10} {:<8} {:>7} {:>14} {:>8}",
+            text("name"),
+            text("startup"),
+            if masked { "masked" } else if enabled { "yes" } else { "no" },
+            state,
+            health,
+            pid,
+            uptime,
+            restarts
+        );
+    }
+
+    if any_stale {
+        println!("\n* service file changed; restart to apply");
+    }
+    if any_limited {
+        println!("\n! start limit hit; run vctl reset-failed to allow starts");
+    }
+
+    // Resource usage is only worth the space when looking at one service
+    if let Some(usage) = response.data.as_ref().and_then(|d| d.get("usage")).filter(|u| u.is_object()) {
+        let field = |key: &str| usage.get(key).and_then(|v| v.as_u64());
+
+        println!();
+        if let Some(bytes) = field("memory_bytes") {
+            println!("  Memory: {}", format_bytes(bytes));
+        }
+        if let Some(tasks) = field("tasks") {
+            println!("  Tasks:  {}", tasks);
+        }
+        if let Some(usec) = field("cpu_usec") {
+            println!("  CPU:    {}.{:03}s", usec / 1_000_000, (usec % 1_000_000) / 1000);
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.1}{}", value, UNITS[unit])
+    }
+}
+
+fn print_reload_summary(response: &IpcResponse) {
+    println!("{}", response.message);
+
+    let Some(summary) = response.data.as_ref() else {
+        return;
+    };
+
+    let sections = [
+        ("added", "Added"),
+        ("changed", "Updated"),
+        ("needs_restart", "Restart to apply"),
+        ("removed", "No longer on disk"),
+        ("errors", "Errors"),
+    ];
+
+    for (key, label) in sections {
+        let items: Vec<&str> = summary
+            .get(key)
+            .and_then(|v| v.as_array())
+            .map(|list| list.iter().filter_map(|v| v.as_str()).collect())
+            .unwrap_or_default();
+
+        if !items.is_empty() {
+            println!("  {}:", label);
+            for item in items {
+                println!("    {}", item);
+            }
+        }
+    }
+}
+
+/// Print the lines of a log chunk.
+fn print_logs(data: &serde_json::Value) {
+    for line in data.get("lines").and_then(|v| v.as_array()).into_iter().flatten() {
+        println!("{}", line.as_str().unwrap_or_default());
+    }
+}
+
+/// The whole response, so scripts see `success` and `message` as well as `data`.
+fn print_json(response: &IpcResponse) {
+    match serde_json::to_string_pretty(response) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("Cannot encode response: {}", e),
+    }
+}
+
+fn show_journal(source: Option<String>, boot: Option<String>, priority: Option<String>, lines: usize, output: Output) {
+    let min_level = match priority.as_deref().map(str::to_lowercase).as_deref() {
+        None => None,
+        Some("info") => Some(LogLevel::Info),
+        Some("warn") => Some(LogLevel::Warn),
+        Some("fail") => Some(LogLevel::Fail),
+        Some("ok") => Some(LogLevel::Ok),
+        Some(other) => {
+            eprintln!("Unknown priority: {}", other);
+            return;
+        }
+    };
+
+    let boot_id = match boot.as_deref() {
+        Some("all") => None,
+        Some(id) => Some(id.replace('-', "")),
+        None => Some(journal::boot_id()),
+    };
+
+    let query = JournalQuery {
+        source,
+        boot_id,
+        min_level,
+        limit: lines,
+    };
+
+    let entries = match journal::query(&query) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to query verdant-logd: {}", e);
+            return;
+        }
+    };
+
+    if output == Output::Json {
+        match serde_json::to_string_pretty(&entries) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Cannot encode entries: {}", e),
+        }
+        return;
+    }
+
+    for entry in entries {
+        let time = Local
+            .timestamp_micros(entry.timestamp_us as i64)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "-".into());
+
+        println!("{} {} [{}] {}", time, entry.source, entry.level.as_str(), entry.message);
+    }
+}
+
+fn print_properties(response: &IpcResponse) {
+    let properties = response.data.as_ref().and_then(|d| d.as_array()).cloned().unwrap_or_default();
+
+    for property in properties {
+        let key = property.get(0).and_then(|v| v.as_str()).unwrap_or("?");
+        let value = property.get(1).and_then(|v| v.as_str()).unwrap_or_default();
+        println!("{}={}", key, value);
+    }
+}
+
+/// Print one node of a dependency tree and everything below it. `lead`
+/// goes before this node's own line, `indent` before its children's.
+fn print_dependency_tree(node: &serde_json::Value, lead: &str, indent: &str) {
+    let name = node.get("name").and_then(|v| v.as_str()).unwrap_or("?");
+    let state = node.get("state").and_then(|v| v.as_str()).unwrap_or("not loaded");
+    let relation = node.get("relation").and_then(|v| v.as_str()).unwrap_or_default();
+    let cycle = node.get("cycle").and_then(|v| v.as_bool()).unwrap_or(false);
+
+    let mut line = format!("{}{} ({})", lead, name, state);
+    if !relation.is_empty() {
+        line.push_str(&format!(" [{}]", relation));
+    }
+    if cycle {
+        line.push_str(" (cycle)");
+    }
+    println!("{}", line);
+
+    let children = node.get("children").and_then(|v| v.as_array()).cloned().unwrap_or_default();
+    for (i, child) in children.iter().enumerate() {
+        let last = i + 1 == children.len();
+        let (branch, next) = if last { ("└── ", "    ") } else { ("├── ", "│   ") };
+        print_dependency_tree(child, &format!("{}{}", indent, branch), &format!("{}{}", indent, next));
+    }
+}
+
+fn show_blame(output: Output) {
+    let times = BootTimes::load();
+
+    if output == Output::Json {
+        match serde_json::to_string_pretty(&times) {
+            Ok(json) => println!("{}", json),
+            Err(e) => eprintln!("Cannot encode boot times: {}", e),
+        }
+        return;
+    }
+
+    if times.init.is_empty() && times.services.is_empty() {
+        eprintln!("No boot times recorded in {}", BOOT_TIMES_PATH);
+        return;
+    }
+
+    let mut entries: Vec<(&BootTime, &str)> = times
+        .init
+        .iter()
+        .map(|t| (t, "init"))
+        .chain(times.services.iter().map(|t| (t, "service")))
+        .collect();
+    entries.sort_by_key(|(time, _)| std::cmp::Reverse(time.duration_ms));
+
+    for (time, kind) in entries {
+        println!(
+            "{:>7}.{:03}s  {:<32} {}",
+            time.duration_ms / 1000,
+            time.duration_ms % 1000,
+            time.name,
+            kind
+        );
+    }
+
+    let init_ms: u64 = times.init.iter().map(|t| t.duration_ms).sum();
+    println!();
+    println!(
+        "init steps took {}.{:03}s; {} services were activated",
+        init_ms / 1000,
+        init_ms % 1000,
+        times.services.len()
+    );
+}
+
+fn print_timers(response: &IpcResponse) {
+    let timers = response
+        .data
+        .as_ref()
+        .and_then(|d| d.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    println!("{:<24} {:>16} {:>16}", "SERVICE", "NEXT", "LAST");
+    for timer in timers {
+        let name = timer.get("service").and_then(|v| v.as_str()).unwrap_or("?");
+        let next = timer
+            .get("next_in_secs")
+            .and_then(|v| v.as_u64())
+            .map(|s| format!("in {}", format_duration_secs(s)))
+            .unwrap_or_else(|| "-".into());
+        let last = timer
+            .get("last_ago_secs")
+            .and_then(|v| v.as_u64())
+            .map(|s| format!("{} ago", format_duration_secs(s)))
+            .unwrap_or_else(|| "never".into());
+
+        println!("{:<24} {:>16} {:>16}", name, next, last);
+    }
+}
+
+fn format_duration_secs(secs: u64) -> String {
+    let days = secs / 86_400;
+    let hours = (secs % 86_400) / 3600;
+    let mins = (secs % 3600) / 60;
+    let secs = secs % 60;
+
+    if days > 0 {
+        format!("{}d {:02}h {:02}m {:02}s", days, hours, mins, secs)
+    } else if hours > 0 {
+        format!("{}h {:02}m {:02}s", hours, mins, secs)
+    } else {
+        format!("{}m {:02}s", mins, secs)
+    }
+}
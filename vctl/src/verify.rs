@@ -0,0 +1,221 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use bloom::errors::BloomError;
+use nix::unistd::{Group, User};
+use verdantd::{check_names, parse_service_file, resolve_order, Service, SERVICE_DIR};
+
+/// PATH services are started with, as set up by init.
+const SERVICE_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+#[derive(Default)]
+struct Report {
+    errors: usize,
+    warnings: usize,
+}
+
+impl Report {
+    fn error(&mut self, path: &Path, msg: impl Display) {
+        eprintln!("{}: error: {}", path.display(), msg);
+        self.errors += 1;
+    }
+
+    fn warn(&mut self, path: &Path, msg: impl Display) {
+        eprintln!("{}: warning: {}", path.display(), msg);
+        self.warnings += 1;
+    }
+}
+
+/// Check service files the way verdantd would load them, without talking to
+/// it: `paths`, or with `all` everything in the service directory. Files are
+/// checked against the installed services for name clashes, dependencies and
+/// cycles. Returns false if anything would keep a service from loading or
+/// starting.
+pub fn verify(paths: &[String], all: bool) -> bool {
+    let installed = installed_files();
+    let targets: Vec<PathBuf> = if all {
+        installed.clone()
+    } else {
+        paths.iter().map(PathBuf::from).collect()
+    };
+
+    if targets.is_empty() {
+        eprintln!("No service files to verify");
+        return false;
+    }
+
+    let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+    let replaced: HashSet<PathBuf> = targets.iter().map(|p| canonical(p)).collect();
+
+    let mut report = Report::default();
+    let mut services: Vec<Service> = Vec::new();
+
+    // Installed services being verified are replaced by their new version
+    for path in installed.iter().filter(|p| !replaced.contains(&canonical(p))) {
+        if let Ok(parsed) = parse(path).and_then(|parsed| check_names(&services, parsed)) {
+            services.extend(parsed);
+        }
+    }
+
+    // Where each verified service came from, for reporting
+    let mut origins: HashMap<String, &Path> = HashMap::new();
+
+    for path in &targets {
+        match parse(path).and_then(|parsed| check_names(&services, parsed)) {
+            Ok(parsed) => {
+                for service in &parsed {
+                    check_service(&mut report, path, service);
+                    origins.insert(service.name.clone(), path);
+                }
+                services.extend(parsed);
+            }
+            Err(e) => report.error(path, describe(e)),
+        }
+    }
+
+    check_graph(&mut report, &services, &origins);
+
+    println!(
+        "{} file(s) checked: {} error(s), {} warning(s)",
+        targets.len(),
+        report.errors,
+        report.warnings
+    );
+    report.errors == 0
+}
+
+pub(crate) fn installed_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(SERVICE_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("vs"))
+        .collect();
+
+    files.sort();
+    files
+}
+
+pub(crate) fn parse(path: &Path) -> Result<Vec<Service>, BloomError> {
+    parse_service_file(path.to_str().unwrap_or_default())
+}
+
+/// The message of a parse error without the "Parse error:" prefix.
+pub(crate) fn describe(e: BloomError) -> String {
+    match e {
+        BloomError::Parse(msg) => msg,
+        other => other.to_string(),
+    }
+}
+
+/// Checks that only need the service itself: its commands and its account.
+fn check_service(report: &mut Report, path: &Path, service: &Service) {
+    let root = Path::new(service.root_dir.as_deref().unwrap_or("/"));
+
+    let commands = std::iter::once(("cmd", service.cmd.as_str()))
+        .chain(service.exec_steps.iter().filter_map(|step| Some(("cmd step", step.first()?.as_str()))))
+        .chain(
+            [
+                ("reload_cmd", &service.reload_cmd),
+                ("stop_cmd", &service.stop_cmd),
+                ("cleanup_cmd", &service.cleanup_cmd),
+                ("health_cmd", &service.health_cmd),
+            ]
+            .into_iter()
+            .filter_map(|(key, cmd)| Some((key, cmd.as_ref()?.first()?.as_str()))),
+        );
+
+    for (key, program) in commands {
+        // Filled in per instance of a template
+        if program.contains("{}") || program.contains("%i") {
+            continue;
+        }
+
+        if !find_program(root, program) {
+            report.error(
+                path,
+                format!("'{}': {} '{}' is not an executable file", service.name, key, program),
+            );
+        }
+    }
+
+    if let Some(name) = &service.user {
+        match User::from_name(name) {
+            Ok(Some(_)) => {}
+            Ok(None) => report.error(path, format!("'{}': user '{}' does not exist", service.name, name)),
+            Err(e) => report.warn(path, format!("'{}': cannot look up user '{}': {}", service.name, name, e)),
+        }
+    }
+
+    if let Some(name) = &service.group {
+        match Group::from_name(name) {
+            Ok(Some(_)) => {}
+            Ok(None) => report.error(path, format!("'{}': group '{}' does not exist", service.name, name)),
+            Err(e) => report.warn(path, format!("'{}': cannot look up group '{}': {}", service.name, name, e)),
+        }
+    }
+
+    if let Some(dir) = &service.working_dir
+        && !within(root, dir).is_dir()
+    {
+        report.warn(path, format!("'{}': working_dir '{}' does not exist", service.name, dir));
+    }
+}
+
+/// Checks across services: references to services that do not exist and
+/// dependency cycles, reported only for the services being verified.
+fn check_graph(report: &mut Report, services: &[Service], origins: &HashMap<String, &Path>) {
+    let exists = |name: &str| {
+        services.iter().any(|s| {
+            s.name == name || s.aliases.iter().any(|a| a == name) || s.template_instance(name).is_some()
+        })
+    };
+
+    for service in services {
+        let Some(path) = origins.get(&service.name) else {
+            continue;
+        };
+
+        for dep in service.dependencies.iter().filter(|d| !exists(d)) {
+            report.error(path, format!("'{}' requires '{}', which does not exist", service.name, dep));
+        }
+
+        let optional = [("wants", &service.wants), ("part_of", &service.part_of), ("binds_to", &service.binds_to)];
+        for (key, names) in optional {
+            for name in names.iter().filter(|n| !exists(n)) {
+                report.warn(path, format!("'{}': {} '{}', which does not exist", service.name, key, name));
+            }
+        }
+    }
+
+    let order = resolve_order(&services.iter().collect::<Vec<_>>());
+    for name in &order.cyclic {
+        if let Some(path) = origins.get(name) {
+            report.error(path, format!("'{}' is part of, or depends on, a dependency cycle", name));
+        }
+    }
+}
+
+/// Whether `program` resolves to an executable file, looked up on the
+/// service PATH when it has no slash, inside `root` for chrooted services.
+fn find_program(root: &Path, program: &str) -> bool {
+    let candidates: Vec<PathBuf> = if program.contains('/') {
+        vec![PathBuf::from(program)]
+    } else {
+        SERVICE_PATH.split(':').map(|dir| Path::new(dir).join(program)).collect()
+    };
+
+    candidates.iter().any(|candidate| {
+        fs::metadata(within(root, candidate))
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    })
+}
+
+fn within(root: &Path, path: impl AsRef<Path>) -> PathBuf {
+    let path = path.as_ref();
+    root.join(path.strip_prefix("/").unwrap_or(path))
+}
@@ -0,0 +1,145 @@
+use std::cmp::Reverse;
+use std::io::{self, IsTerminal, Write};
+
+use bloom::ipc::{send_ipc_stream_request, IpcCommand, IpcRequest, IpcTarget, VERDANTD_SOCKET_PATH};
+use chrono::Local;
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::{format_duration_secs, Output};
+
+/// Column `vctl watch` sorts by.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SortKey {
+    Name,
+    /// Failed and degraded services first
+    State,
+    /// Most recently (re)started first
+    Uptime,
+    /// Most restarts first
+    Restarts,
+}
+
+/// Keep redrawing the status of every service as verdantd reports changes,
+/// until interrupted or verdantd goes away.
+pub fn watch(sort: SortKey, output: Output) -> bool {
+    let request = IpcRequest {
+        id: 0,
+        target: IpcTarget::Verdantd,
+        command: IpcCommand::WatchServices,
+    };
+
+    let color = io::stdout().is_terminal();
+    let result = send_ipc_stream_request(VERDANTD_SOCKET_PATH, &request, |chunk| match output {
+        Output::Table => render(chunk, sort, color),
+        Output::Json => println!("{}", chunk),
+    });
+
+    match result {
+        Ok(response) if !response.success => {
+            eprintln!("Command failed: {}", response.message);
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("Failed to send IPC request: {}", e);
+            false
+        }
+    }
+}
+
+fn render(chunk: Value, sort: SortKey, color: bool) {
+    let mut services = match chunk {
+        Value::Array(list) => list,
+        _ => return,
+    };
+
+    let text = |svc: &Value, key: &str| svc.get(key).and_then(|v| v.as_str()).unwrap_or("-").to_string();
+    let number = |svc: &Value, key: &str| svc.get(key).and_then(|v| v.as_u64());
+
+    match sort {
+        SortKey::Name => services.sort_by_key(|s| text(s, "name")),
+        SortKey::State => services.sort_by_key(|s| (severity(&text(s, "state")), text(s, "name"))),
+        SortKey::Uptime => {
+            services.sort_by_key(|s| (number(s, "uptime_secs").unwrap_or(u64::MAX), text(s, "name")))
+        }
+        SortKey::Restarts => {
+            services.sort_by_key(|s| (Reverse(number(s, "restarts").unwrap_or(0)), text(s, "name")))
+        }
+    }
+
+    let count = |state: &str| services.iter().filter(|s| text(s, "state") == state).count();
+
+    let mut screen = String::new();
+    screen.push_str(&format!(
+        "{} services: {} running, {} failed, {} degraded    {}    Ctrl-C to quit\n\n",
+        services.len(),
+        count("running"),
+        count("failed"),
+        count("degraded"),
+        Local::now().format("%H:%M:%S")
+    ));
+    screen.push_str(&format!(
+        "{:<24} {:<10} {:<8} {:>7} {:>14} {:>8}\n",
+        "SERVICE", "STATE", "HEALTH", "PID", "UPTIME", "RESTARTS"
+    ));
+
+    for svc in &services {
+        let state = text(svc, "state");
+        let health = match svc.get("healthy").and_then(|v| v.as_bool()) {
+            Some(true) => "ok",
+            Some(false) => "failing",
+            None => "-",
+        };
+        let pid = number(svc, "pid").map(|p| p.to_string()).unwrap_or_else(|| "-".into());
+        let uptime = number(svc, "uptime_secs").map(format_duration_secs).unwrap_or_else(|| "-".into());
+
+        // Padded before colouring so the escape codes do not count as width
+        let padded = format!("{:<10}", state);
+        let state_column = match state_color(&state).filter(|_| color) {
+            Some(code) => format!("\x1b[{}m{}\x1b[0m", code, padded),
+            None => padded,
+        };
+
+        screen.push_str(&format!(
+            "{:<24} {} {:<8} {:>7} {:>14} {:>8}\n",
+            text(svc, "name"),
+            state_column,
+            health,
+            pid,
+            uptime,
+            number(svc, "restarts").unwrap_or(0)
+        ));
+    }
+
+    // Overwrite in place rather than clearing first, which flickers
+    let screen = format!("\x1b[H{}\x1b[J", screen.replace('\n', "\x1b[K\n"));
+
+    let mut stdout = io::stdout().lock();
+    let _ = stdout.write_all(screen.as_bytes());
+    let _ = stdout.flush();
+}
+
+/// Sort order for `--sort state`: what needs attention comes first.
+fn severity(state: &str) -> u8 {
+    match state {
+        "failed" => 0,
+        "degraded" => 1,
+        "starting" | "stopping" | "reloading" => 2,
+        "running" => 3,
+        "skipped" => 4,
+        _ => 5,
+    }
+}
+
+/// ANSI colour for a state, if it gets one.
+fn state_color(state: &str) -> Option<&'static str> {
+    match state {
+        "running" => Some("32"),
+        "failed" => Some("31"),
+        "degraded" => Some("35"),
+        "starting" | "stopping" | "reloading" => Some("33"),
+        "stopped" | "skipped" => Some("2"),
+        _ => None,
+    }
+}
@@ -0,0 +1,223 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::process::Command;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use nix::fcntl::OFlag;
+use nix::unistd::{getpgrp, pipe2, setpgid, Pid};
+
+use bloom::config::MountConfig;
+use bloom::errors::BloomError;
+
+const MOUNT_PATH: &str = "/bin/mount";
+
+/// The autofs protocol spoken: v5 has direct mounts.
+const AUTOFS_PROTO_VERSION: u32 = 5;
+
+/// ioctls on the autofs mount, from <linux/auto_fs.h>.
+const AUTOFS_IOC_READY: u32 = 0x9360;
+const AUTOFS_IOC_FAIL: u32 = 0x9361;
+const AUTOFS_IOC_SETTIMEOUT: u32 = 0xc000_9364 | ((size_of::<libc::c_ulong>() as u32) << 16);
+const AUTOFS_IOC_EXPIRE_MULTI: u32 = 0x4004_9366;
+
+/// Packet types sent for a direct mount.
+const AUTOFS_PTYPE_MISSING_DIRECT: i32 = 5;
+const AUTOFS_PTYPE_EXPIRE_DIRECT: i32 = 6;
+
+/// `struct autofs_v5_packet`, padded to 8 bytes; the kernel writes one per read.
+const PACKET_SIZE: usize = 304;
+
+/// One `[[mount]]` table with `automount = true`, once its autofs trap is set.
+struct Automount {
+    config: MountConfig,
+    /// The autofs mount itself, opened before anything covers it.
+    ioctl: File,
+}
+
+/// Put an autofs trap on each automount's target, then mount the real
+/// filesystem the first time something looks inside and unmount it again
+/// once it has sat unused for its `idle_timeout`. Returns how many were
+/// set up; a failing one is logged and skipped.
+///
+/// The kernel lets verdantd's process group through the trap so it can mount
+/// over it; services are kept out of that group, so they trigger it.
+pub fn start_automounts(mounts: &[MountConfig]) -> usize {
+    let automounts: Vec<&MountConfig> = mounts.iter().filter(|m| m.automount).collect();
+    if automounts.is_empty() {
+        return 0;
+    }
+
+    // Under init, verdantd starts out in init's group
+    if let Err(e) = setpgid(Pid::from_raw(0), Pid::from_raw(0)) {
+        eprintln!("[verdantd] Cannot start a process group for automounts: {}", e);
+        return 0;
+    }
+
+    let mut started = 0;
+    for config in automounts {
+        match set_trap(config) {
+            Ok((pipe, ioctl)) => {
+                let automount = Arc::new(Automount {
+                    config: config.clone(),
+                    ioctl,
+                });
+                watch(pipe, Arc::clone(&automount));
+                expire(automount);
+                started += 1;
+            }
+            Err(e) => eprintln!("[verdantd] Cannot set up automount at {}: {}", config.target, e),
+        }
+    }
+
+    started
+}
+
+/// Mount autofs at the target, returning the pipe the kernel writes
+/// requests into and the opened mount.
+fn set_trap(config: &MountConfig) -> Result<(File, File), BloomError> {
+    fs::create_dir_all(&config.target).map_err(BloomError::Io)?;
+
+    let (read_end, write_end) = pipe2(OFlag::O_CLOEXEC).map_err(BloomError::Nix)?;
+    let options = format!(
+        "fd={},pgrp={},minproto={v},maxproto={v},direct",
+        write_end.as_raw_fd(),
+        getpgrp(),
+        v = AUTOFS_PROTO_VERSION
+    );
+
+    let target = CString::new(config.target.as_str()).map_err(|e| BloomError::Custom(e.to_string()))?;
+    let options = CString::new(options).map_err(|e| BloomError::Custom(e.to_string()))?;
+
+    // The kernel keeps its own reference to the write end
+    let result = unsafe {
+        libc::mount(
+            c"automount".as_ptr(),
+            target.as_ptr(),
+            c"autofs".as_ptr(),
+            0,
+            options.as_ptr().cast(),
+        )
+    };
+    drop(write_end);
+    if result != 0 {
+        return Err(BloomError::Io(io::Error::last_os_error()));
+    }
+
+    let ioctl = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECTORY)
+        .open(&config.target)
+        .map_err(BloomError::Io)?;
+
+    if config.idle_timeout > 0 {
+        let mut timeout = config.idle_timeout as libc::c_ulong;
+        if unsafe { libc::ioctl(ioctl.as_raw_fd(), AUTOFS_IOC_SETTIMEOUT as _, &mut timeout) } != 0 {
+            return Err(BloomError::Io(io::Error::last_os_error()));
+        }
+    }
+
+    Ok((File::from(read_end), ioctl))
+}
+
+/// Answer the kernel's mount and expire requests for one automount.
+fn watch(mut pipe: File, automount: Arc<Automount>) {
+    thread::spawn(move || {
+        let target = &automount.config.target;
+        let mut packet = [0u8; PACKET_SIZE];
+
+        loop {
+            match pipe.read(&mut packet) {
+                Ok(0) => break,
+                Ok(n) if n >= 12 => {}
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    eprintln!("[verdantd] Automount {} stopped: {}", target, e);
+                    break;
+                }
+            }
+
+            let kind = i32::from_ne_bytes(packet[4..8].try_into().unwrap());
+            let token = u32::from_ne_bytes(packet[8..12].try_into().unwrap());
+
+            let result = match kind {
+                AUTOFS_PTYPE_MISSING_DIRECT => mount(&automount.config),
+                AUTOFS_PTYPE_EXPIRE_DIRECT => unmount(target),
+                _ => continue,
+            };
+
+            let reply = match &result {
+                Ok(()) => AUTOFS_IOC_READY,
+                Err(e) => {
+                    eprintln!("[verdantd] Automount {} failed: {}", target, e);
+                    AUTOFS_IOC_FAIL
+                }
+            };
+
+            // Wakes whoever is waiting on the mount point
+            unsafe {
+                libc::ioctl(automount.ioctl.as_raw_fd(), reply as _, token as libc::c_ulong);
+            }
+        }
+    });
+}
+
+/// Ask the kernel to expire the mount while it is idle. The ioctl only
+/// returns once `watch` has handled the expire request, so it cannot run
+/// on the same thread.
+fn expire(automount: Arc<Automount>) {
+    let timeout = automount.config.idle_timeout;
+    if timeout == 0 {
+        return;
+    }
+
+    thread::spawn(move || {
+        let interval = Duration::from_secs((timeout / 4).max(1));
+        let how: libc::c_int = 0;
+
+        loop {
+            thread::sleep(interval);
+            unsafe {
+                libc::ioctl(automount.ioctl.as_raw_fd(), AUTOFS_IOC_EXPIRE_MULTI as _, &how);
+            }
+        }
+    });
+}
+
+/// Mount the real filesystem over the trap through mount(8), which also
+/// resolves `UUID=` and `LABEL=` sources and runs network helpers.
+fn mount(config: &MountConfig) -> Result<(), BloomError> {
+    let mut command = Command::new(MOUNT_PATH);
+    if let Some(fstype) = &config.fstype {
+        command.args(["-t", fstype]);
+    }
+
+    let output = command
+        .args(["-o", &config.options, &config.source, &config.target])
+        .output()
+        .map_err(BloomError::Io)?;
+
+    if output.status.success() {
+        eprintln!("[verdantd] Automounted {} at {}", config.source, config.target);
+        Ok(())
+    } else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        Err(BloomError::Custom(format!("mount failed: {}", stderr.trim())))
+    }
+}
+
+fn unmount(target: &str) -> Result<(), BloomError> {
+    let path = CString::new(target).map_err(|e| BloomError::Custom(e.to_string()))?;
+    if unsafe { libc::umount2(path.as_ptr(), 0) } != 0 {
+        return Err(BloomError::Io(io::Error::last_os_error()));
+    }
+
+    eprintln!("[verdantd] Unmounted idle automount {}", target);
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+
+use bloom::config::ButtonAction;
+use bloom::ipc::IpcCommand;
+
+use crate::manager::Manager;
+use crate::sleep;
+
+/// Event devices, each with its capabilities under `<name>/device/capabilities`.
+const INPUT_CLASS_DIR: &str = "/sys/class/input";
+
+const EV_KEY: u16 = 0x01;
+const EV_SW: u16 = 0x05;
+const KEY_POWER: u16 = 116;
+const SW_LID: u16 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Button {
+    Power,
+    Lid,
+}
+
+impl Button {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Button::Power => "Power button pressed",
+            Button::Lid => "Lid closed",
+        }
+    }
+}
+
+/// Listen on every input device with a power button or lid switch and carry
+/// out the configured action when one is pressed or closed. Power-off goes
+/// to the main loop through `shutdown_tx` like any other shutdown request.
+/// Returns how many devices are being listened on.
+pub fn watch_buttons(
+    power_key: ButtonAction,
+    lid_switch: ButtonAction,
+    manager: Arc<Manager>,
+    shutdown_tx: Sender<IpcCommand>,
+) -> usize {
+    let mut devices = 0;
+
+    for (path, power, lid) in input_devices() {
+        let power = power && power_key != ButtonAction::Ignore;
+        let lid = lid && lid_switch != ButtonAction::Ignore;
+        if !power && !lid {
+            continue;
+        }
+
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("[verdantd] Cannot open {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        let manager = Arc::clone(&manager);
+        let shutdown_tx = shutdown_tx.clone();
+        devices += 1;
+
+        thread::spawn(move || {
+            let mut buf = vec![0u8; mem::size_of::<libc::input_event>()];
+
+            while file.read_exact(&mut buf).is_ok() {
+                let button = match parse_event(&buf) {
+                    (EV_KEY, KEY_POWER, 1) if power => Button::Power,
+                    (EV_SW, SW_LID, 1) if lid => Button::Lid,
+                    _ => continue,
+                };
+                let action = if button == Button::Power { power_key } else { lid_switch };
+
+                eprintln!("[verdantd] {}", button.as_str());
+                perform(action, button, &manager, &shutdown_tx);
+            }
+
+            eprintln!("[verdantd] Stopped listening on {}", path.display());
+        });
+    }
+
+    devices
+}
+
+fn perform(action: ButtonAction, button: Button, manager: &Manager, shutdown_tx: &Sender<IpcCommand>) {
+    match action {
+        ButtonAction::Poweroff => {
+            let _ = shutdown_tx.send(IpcCommand::Shutdown {
+                delay_secs: 0,
+                message: Some(button.as_str().into()),
+            });
+        }
+        // Returns once awake again; presses while asleep are refused by sleep()
+        ButtonAction::Suspend => {
+            if let Err(e) = sleep::sleep(manager, false) {
+                eprintln!("[verdantd] Cannot suspend: {}", e);
+            }
+        }
+        ButtonAction::Ignore => {}
+    }
+}
+
+/// Type, code and value of a raw `struct input_event`. They are its last
+/// eight bytes, after a timestamp whose size depends on the architecture.
+fn parse_event(buf: &[u8]) -> (u16, u16, i32) {
+    let tail = &buf[buf.len() - 8..];
+
+    (
+        u16::from_ne_bytes([tail[0], tail[1]]),
+        u16::from_ne_bytes([tail[2], tail[3]]),
+        i32::from_ne_bytes([tail[4], tail[5], tail[6], tail[7]]),
+    )
+}
+
+/// `/dev/input/event*` devices with whether each has a power key and a lid
+/// switch.
+fn input_devices() -> Vec<(PathBuf, bool, bool)> {
+    let mut devices: Vec<_> = fs::read_dir(INPUT_CLASS_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            if !name.starts_with("event") {
+                return None;
+            }
+
+            let capabilities = entry.path().join("device/capabilities");
+            let power = has_capability(&capabilities.join("key"), KEY_POWER);
+            let lid = has_capability(&capabilities.join("sw"), SW_LID);
+
+            Some((Path::new("/dev/input").join(name), power, lid))
+        })
+        .collect();
+
+    devices.sort();
+    devices
+}
+
+/// Whether `bit` is set in a capability bitmap from sysfs: hex words of the
+/// kernel's `long` size, most significant first.
+fn has_capability(path: &Path, bit: u16) -> bool {
+    let Ok(bitmap) = fs::read_to_string(path) else {
+        return false;
+    };
+
+    let word_bits = usize::BITS as usize;
+    let (word, offset) = (bit as usize / word_bits, bit as usize % word_bits);
+
+    bitmap
+        .split_whitespace()
+        .rev()
+        .nth(word)
+        .and_then(|w| usize::from_str_radix(w, 16).ok())
+        .is_some_and(|w| w & (1 << offset) != 0)
+}
@@ -0,0 +1,176 @@
+use std::fs;
+use std::io;
+
+use bloom::errors::BloomError;
+
+/// Capability names, indexed by capability number.
+const CAPABILITY_NAMES: [&str; 41] = [
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+/// Highest capability number the running kernel supports.
+const CAP_LAST_CAP_PATH: &str = "/proc/sys/kernel/cap_last_cap";
+
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+/// Capabilities a service keeps, as a bitmask over capability numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilitySet(u64);
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+impl CapabilitySet {
+    /// Parse names like `CAP_NET_BIND_SERVICE` or `net_bind_service`.
+    /// An empty list drops every capability.
+    pub fn parse(names: &[String]) -> Result<Self, BloomError> {
+        let mut mask = 0u64;
+
+        for name in names {
+            let lower = name.to_lowercase();
+            let bare = lower.strip_prefix("cap_").unwrap_or(&lower);
+            let number = CAPABILITY_NAMES
+                .iter()
+                .position(|n| *n == bare)
+                .ok_or_else(|| BloomError::Parse(format!("Unknown capability: {name}")))?;
+            mask |= 1 << number;
+        }
+
+        Ok(Self(mask))
+    }
+
+    /// Names of the capabilities kept, in capability number order.
+    pub fn names(&self) -> Vec<&'static str> {
+        CAPABILITY_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(number, _)| self.contains(*number as u32))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    fn contains(&self, cap: u32) -> bool {
+        cap < 64 && self.0 & (1 << cap) != 0
+    }
+
+    /// Read in the parent; the child must not touch the filesystem.
+    pub fn last_cap() -> u32 {
+        fs::read_to_string(CAP_LAST_CAP_PATH)
+            .ok()
+            .and_then(|s| s.trim().parse().ok())
+            .unwrap_or(CAPABILITY_NAMES.len() as u32 - 1)
+    }
+
+    /// Remove everything else from the bounding set and keep permitted
+    /// capabilities across the coming uid change. Runs in the child, as root.
+    pub fn restrict_bounding_set(&self, last_cap: u32) -> io::Result<()> {
+        unsafe {
+            for cap in (0..=last_cap).filter(|c| !self.contains(*c)) {
+                if libc::prctl(libc::PR_CAPBSET_DROP, cap as libc::c_ulong, 0, 0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make exactly this set effective and raise it into the ambient set so
+    /// it survives exec for a non-root user. Runs in the child after setuid.
+    pub fn apply(&self, last_cap: u32) -> io::Result<()> {
+        let low = self.0 as u32;
+        let high = (self.0 >> 32) as u32;
+        let header = CapHeader {
+            version: LINUX_CAPABILITY_VERSION_3,
+            pid: 0,
+        };
+        let data = [
+            CapData { effective: low, permitted: low, inheritable: low },
+            CapData { effective: high, permitted: high, inheritable: high },
+        ];
+
+        unsafe {
+            if libc::syscall(libc::SYS_capset, &header, data.as_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            for cap in (0..=last_cap).filter(|c| self.contains(*c)) {
+                let raise = libc::prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_RAISE as libc::c_ulong,
+                    cap as libc::c_ulong,
+                    0,
+                    0,
+                );
+                if raise != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Forbid the service and its children from gaining privileges on exec.
+pub fn set_no_new_privs() -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
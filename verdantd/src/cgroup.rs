@@ -0,0 +1,234 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde::Serialize;
+
+use bloom::errors::BloomError;
+
+/// Parent cgroup for every service verdantd launches.
+const CGROUP_ROOT: &str = "/sys/fs/cgroup/verdant";
+
+/// Where controllers are enabled for the cgroups below it.
+const CGROUP_MOUNT: &str = "/sys/fs/cgroup";
+
+/// A cgroup limit: a number, or `max` for none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupLimit {
+    Max,
+    Value(u64),
+}
+
+impl CgroupLimit {
+    /// `max`, a plain number, or for byte limits a number with a K/M/G/T suffix.
+    pub fn parse(key: &str, s: &str, bytes: bool) -> Result<Self, BloomError> {
+        let invalid = || BloomError::Parse(format!("Invalid {key}: {s}"));
+        let s = s.trim();
+
+        if s.eq_ignore_ascii_case("max") || s.eq_ignore_ascii_case("infinity") {
+            return Ok(CgroupLimit::Max);
+        }
+
+        let (number, multiplier) = match s.char_indices().last() {
+            Some((i, unit)) if bytes && unit.is_ascii_alphabetic() => {
+                let shift = match unit.to_ascii_uppercase() {
+                    'K' => 10,
+                    'M' => 20,
+                    'G' => 30,
+                    'T' => 40,
+                    _ => return Err(invalid()),
+                };
+                (&s[..i], 1u64 << shift)
+            }
+            _ => (s, 1),
+        };
+
+        number
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(multiplier))
+            .map(CgroupLimit::Value)
+            .ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for CgroupLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CgroupLimit::Max => write!(f, "max"),
+            CgroupLimit::Value(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+/// Controller settings written into a service's cgroup before it starts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CgroupQuota {
+    /// Hard memory limit; the OOM killer acts beyond it.
+    pub memory_max: Option<CgroupLimit>,
+    /// Soft memory limit; the service is throttled and reclaimed above it.
+    pub memory_high: Option<CgroupLimit>,
+    /// Relative CPU share, 1 to 10000 (100 is the kernel default).
+    pub cpu_weight: Option<u32>,
+    /// Maximum number of processes and threads.
+    pub tasks_max: Option<CgroupLimit>,
+}
+
+impl CgroupQuota {
+    pub fn is_empty(&self) -> bool {
+        *self == CgroupQuota::default()
+    }
+
+    /// Controllers that must be enabled for these settings.
+    fn controllers(&self) -> Vec<&'static str> {
+        let mut controllers = Vec::new();
+        if self.memory_max.is_some() || self.memory_high.is_some() {
+            controllers.push("+memory");
+        }
+        if self.cpu_weight.is_some() {
+            controllers.push("+cpu");
+        }
+        if self.tasks_max.is_some() {
+            controllers.push("+pids");
+        }
+        controllers
+    }
+}
+
+/// Resource usage of a service's cgroup, as shown by `vctl status <service>`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CgroupUsage {
+    pub memory_bytes: Option<u64>,
+    pub tasks: Option<u64>,
+    pub cpu_usec: Option<u64>,
+}
+
+/// A cgroup v2 directory holding one service's whole process tree.
+#[derive(Debug)]
+pub struct ServiceCgroup {
+    path: PathBuf,
+}
+
+impl ServiceCgroup {
+    /// Create (or reuse) the cgroup for `service_name`.
+    /// Fails if cgroup2 is not mounted at /sys/fs/cgroup.
+    pub fn create(service_name: &str) -> io::Result<Self> {
+        if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "cgroup2 is not mounted"));
+        }
+
+        let path = Path::new(CGROUP_ROOT).join(service_name.replace('/', "_"));
+        fs::create_dir_all(&path)?;
+
+        Ok(Self { path })
+    }
+
+    /// Enable the needed controllers down to this cgroup and write the quota.
+    pub fn apply_quota(&self, quota: &CgroupQuota) -> io::Result<()> {
+        if quota.is_empty() {
+            return Ok(());
+        }
+
+        let controllers = quota.controllers().join(" ");
+        for parent in [CGROUP_MOUNT, CGROUP_ROOT] {
+            fs::write(Path::new(parent).join("cgroup.subtree_control"), &controllers)?;
+        }
+
+        let settings = [
+            ("memory.max", quota.memory_max.map(|l| l.to_string())),
+            ("memory.high", quota.memory_high.map(|l| l.to_string())),
+            ("cpu.weight", quota.cpu_weight.map(|w| w.to_string())),
+            ("pids.max", quota.tasks_max.map(|l| l.to_string())),
+        ];
+
+        for (file, value) in settings {
+            if let Some(value) = value {
+                fs::write(self.path.join(file), value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Current memory, task count and CPU time. Fields are None when the
+    /// corresponding controller is not enabled.
+    pub fn usage(&self) -> CgroupUsage {
+        let read_u64 = |file: &str| {
+            fs::read_to_string(self.path.join(file))
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+        };
+
+        let cpu_usec = fs::read_to_string(self.path.join("cpu.stat")).ok().and_then(|stat| {
+            stat.lines()
+                .find_map(|l| l.strip_prefix("usage_usec "))
+                .and_then(|v| v.trim().parse().ok())
+        });
+
+        CgroupUsage {
+            memory_bytes: read_u64("memory.current"),
+            tasks: read_u64("pids.current"),
+            cpu_usec,
+        }
+    }
+
+    /// Open `cgroup.procs` for writing. Writing "0" to it from the child
+    /// (between fork and exec) moves the child in before it can fork again.
+    pub fn procs_file(&self) -> io::Result<File> {
+        OpenOptions::new().write(true).open(self.path.join("cgroup.procs"))
+    }
+
+    /// PIDs currently in the cgroup.
+    pub fn pids(&self) -> Vec<i32> {
+        fs::read_to_string(self.path.join("cgroup.procs"))
+            .map(|s| s.lines().filter_map(|l| l.trim().parse().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    /// True once no process is left in the cgroup.
+    pub fn is_empty(&self) -> bool {
+        match fs::read_to_string(self.path.join("cgroup.events")) {
+            Ok(events) => events.lines().any(|l| l.trim() == "populated 0"),
+            Err(_) => self.pids().is_empty(),
+        }
+    }
+
+    /// Send `signal` to every process in the cgroup.
+    pub fn signal(&self, signal: Signal) {
+        for pid in self.pids() {
+            let _ = kill(Pid::from_raw(pid), signal);
+        }
+    }
+
+    /// SIGKILL the whole tree. Uses `cgroup.kill` (Linux 5.14+) and falls
+    /// back to signalling each member on older kernels.
+    pub fn kill_all(&self) {
+        if fs::write(self.path.join("cgroup.kill"), b"1").is_err() {
+            self.signal(Signal::SIGKILL);
+        }
+    }
+
+    /// Kill anything left behind, wait for the cgroup to drain, and remove it.
+    pub fn release(&self, timeout: Duration) -> io::Result<()> {
+        let start = Instant::now();
+
+        if !self.is_empty() {
+            self.kill_all();
+
+            while !self.is_empty() && start.elapsed() < timeout {
+                sleep(Duration::from_millis(20));
+            }
+        }
+
+        match fs::remove_dir(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
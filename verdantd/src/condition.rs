@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::Path;
+
+use bloom::cmdline::{get_param, read_cmdline};
+use bloom::errors::BloomError;
+
+/// A precondition checked right before a service starts. An unmet
+/// condition skips the service instead of failing it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    PathExists { path: String, negate: bool },
+    /// `vm`, `container`, `none`, or a specific technology such as `kvm`.
+    Virtualization { kind: String, negate: bool },
+    /// `param` must be present, or `param=value` must match exactly.
+    KernelCmdline { param: String, negate: bool },
+}
+
+impl Condition {
+    /// Parse a `condition_*` key. A leading `!` in the value negates it.
+    pub fn parse(key: &str, value: &str) -> Result<Self, BloomError> {
+        let (negate, value) = match value.strip_prefix('!') {
+            Some(rest) => (true, rest.trim().to_string()),
+            None => (false, value.to_string()),
+        };
+
+        if value.is_empty() {
+            return Err(BloomError::Parse(format!("Empty value for {key}")));
+        }
+
+        match key {
+            "condition_path_exists" => Ok(Condition::PathExists { path: value, negate }),
+            "condition_virtualization" => Ok(Condition::Virtualization { kind: value.to_lowercase(), negate }),
+            "condition_kernel_cmdline" => Ok(Condition::KernelCmdline { param: value, negate }),
+            _ => Err(BloomError::Parse(format!("Unknown key: {key}"))),
+        }
+    }
+
+    fn holds(&self) -> bool {
+        match self {
+            Condition::PathExists { path, negate } => Path::new(path).exists() != *negate,
+            Condition::Virtualization { kind, negate } => virtualization_matches(kind) != *negate,
+            Condition::KernelCmdline { param, negate } => cmdline_matches(param) != *negate,
+        }
+    }
+
+    /// The `condition_*` key and value this condition was written as.
+    pub fn as_property(&self) -> (&'static str, String) {
+        let not = |negate: &bool| if *negate { "!" } else { "" };
+
+        match self {
+            Condition::PathExists { path, negate } => ("condition_path_exists", format!("{}{}", not(negate), path)),
+            Condition::Virtualization { kind, negate } => {
+                ("condition_virtualization", format!("{}{}", not(negate), kind))
+            }
+            Condition::KernelCmdline { param, negate } => {
+                ("condition_kernel_cmdline", format!("{}{}", not(negate), param))
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        let (key, value) = self.as_property();
+        format!("{}={}", key, value)
+    }
+}
+
+/// Description of the first condition that does not hold, if any.
+pub fn first_unmet(conditions: &[Condition]) -> Option<String> {
+    conditions.iter().find(|c| !c.holds()).map(|c| c.describe())
+}
+
+fn cmdline_matches(param: &str) -> bool {
+    let params = read_cmdline();
+
+    match param.split_once('=') {
+        Some((key, value)) => get_param(&params, key).is_some_and(|v| v == value),
+        None => get_param(&params, param).is_some(),
+    }
+}
+
+fn virtualization_matches(kind: &str) -> bool {
+    let container = detect_container();
+    let vm = detect_vm();
+
+    match kind {
+        "none" | "no" | "false" => container.is_none() && vm.is_none(),
+        "yes" | "true" => container.is_some() || vm.is_some(),
+        "container" => container.is_some(),
+        "vm" => vm.is_some(),
+        specific => container.as_deref() == Some(specific) || vm.as_deref() == Some(specific),
+    }
+}
+
+/// Container technology we are running under, if any.
+fn detect_container() -> Option<String> {
+    if Path::new("/.dockerenv").exists() {
+        return Some("docker".into());
+    }
+    if Path::new("/run/.containerenv").exists() {
+        return Some("podman".into());
+    }
+
+    // Container managers export `container=<name>` to PID 1
+    let environ = fs::read("/proc/1/environ").ok()?;
+    environ
+        .split(|b| *b == 0)
+        .filter_map(|var| std::str::from_utf8(var).ok())
+        .find_map(|var| var.strip_prefix("container="))
+        .map(|name| name.to_lowercase())
+}
+
+/// Hypervisor we are running under, if any.
+fn detect_vm() -> Option<String> {
+    if let Ok(kind) = fs::read_to_string("/sys/hypervisor/type") {
+        return Some(kind.trim().to_lowercase());
+    }
+
+    let dmi = ["sys_vendor", "product_name", "board_vendor", "bios_vendor"]
+        .iter()
+        .filter_map(|f| fs::read_to_string(format!("/sys/class/dmi/id/{}", f)).ok())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    const DMI_VENDORS: &[(&str, &str)] = &[
+        ("kvm", "kvm"),
+        ("qemu", "qemu"),
+        ("vmware", "vmware"),
+        ("virtualbox", "oracle"),
+        ("innotek", "oracle"),
+        ("microsoft corporation", "microsoft"),
+        ("xen", "xen"),
+        ("bochs", "bochs"),
+        ("parallels", "parallels"),
+        ("amazon ec2", "amazon"),
+    ];
+
+    if let Some((_, kind)) = DMI_VENDORS.iter().find(|(needle, _)| dmi.contains(needle)) {
+        return Some(kind.to_string());
+    }
+
+    // Any hypervisor sets this CPU flag even when DMI is uninformative
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo
+        .lines()
+        .filter(|l| l.starts_with("flags"))
+        .any(|l| l.split_whitespace().any(|f| f == "hypervisor"))
+        .then(|| "vm-other".to_string())
+}
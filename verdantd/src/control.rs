@@ -1,21 +1,67 @@
-use std::fs::OpenOptions;
-use std::process::{Command, Child};
+use std::fs;
+use std::process::Child;
 use std::io;
 use std::time::{Duration, Instant};
 use std::thread::sleep;
 
-use crate::service::{RestartPolicy, Service};
+use crate::cgroup::ServiceCgroup;
+use crate::notify::NotifySocket;
+use crate::process;
+use crate::service::{KillMode, RestartPolicy, Service, ServiceType};
 use bloom::errors::BloomError;
+use bloom::ipc::KillWho;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
 
 pub struct ServiceHandle {
-    pub child: Child,
+    /// The process we spawned; None once adopted across a re-exec.
+    pub child: Option<Child>,
     pub start_time: Instant,
     pub exit_status: Option<i32>, // Track exit code
+    pub cgroup: Option<ServiceCgroup>,
+    pub notify: Option<NotifySocket>,
+    /// Daemon PID adopted from the pid file of a `forking` service.
+    /// Once set, this is the process being supervised instead of `child`.
+    pub main_pid: Option<u32>,
+    /// Set once `cleanup_cmd` has run for this run of the service.
+    cleaned_up: bool,
 }
 
 impl ServiceHandle {
+    /// Take over a process started by a previous verdantd instance, which
+    /// is still our child since re-exec keeps the PID.
+    pub fn adopt(pid: u32, uptime: Duration, cgroup: Option<ServiceCgroup>) -> Self {
+        let now = Instant::now();
+
+        Self {
+            child: None,
+            start_time: now.checked_sub(uptime).unwrap_or(now),
+            exit_status: None,
+            cgroup,
+            notify: None,
+            main_pid: Some(pid),
+            cleaned_up: false,
+        }
+    }
+
+    /// PID of the process being supervised.
+    pub fn pid(&self) -> u32 {
+        self.main_pid
+            .or_else(|| self.child.as_ref().map(|c| c.id()))
+            .unwrap_or_default()
+    }
+
     pub fn is_running(&mut self) -> bool {
-        match self.child.try_wait() {
+        if let Some(pid) = self.main_pid {
+            return self.main_pid_running(pid);
+        }
+
+        let Some(child) = self.child.as_mut() else {
+            return false;
+        };
+
+        match child.try_wait() {
             Ok(Some(status)) => {
                 self.exit_status = status.code(); // Record exit code
                 false
@@ -25,91 +71,298 @@ impl ServiceHandle {
         }
     }
 
-    pub fn wait_with_timeout(&mut self, timeout: Duration) -> io::Result<Option<i32>> {
+    /// Reap `pid` if it is our child: a process adopted across a re-exec, or
+    /// a daemon that forked away from its launcher and was reparented to us
+    /// as subreaper. Otherwise, e.g. without subreaper support, just probe it.
+    fn main_pid_running(&mut self, pid: u32) -> bool {
+        match waitpid(Pid::from_raw(pid as i32), Some(WaitPidFlag::WNOHANG)) {
+            Ok(WaitStatus::StillAlive) => true,
+            Ok(WaitStatus::Exited(_, code)) => {
+                self.exit_status = Some(code);
+                false
+            }
+            Ok(WaitStatus::Signaled(..)) => false,
+            Ok(_) => true,
+            Err(_) => pid_alive(pid),
+        }
+    }
+
+    /// Wait for the supervised process to exit. Returns false on timeout.
+    pub fn wait_with_timeout(&mut self, timeout: Duration) -> io::Result<bool> {
         let start = Instant::now();
 
         while start.elapsed() < timeout {
-            match self.child.try_wait()? {
-                Some(status) => {
+            if let Some(pid) = self.main_pid {
+                if !self.main_pid_running(pid) {
+                    return Ok(true);
+                }
+            } else if let Some(child) = self.child.as_mut() {
+                if let Some(status) = child.try_wait()? {
                     self.exit_status = status.code(); // Record on wait too
-                    return Ok(status.code());
+                    return Ok(true);
                 }
-                None => sleep(Duration::from_millis(50)),
+            } else {
+                return Ok(true);
             }
+
+            sleep(Duration::from_millis(50));
         }
 
-        Ok(None) // timed out
+        Ok(false) // timed out
     }
 
+    /// SIGKILL the service, including every process in its cgroup.
     pub fn kill(&mut self) -> io::Result<()> {
-        self.child.kill()
+        if let Some(cgroup) = &self.cgroup {
+            cgroup.kill_all();
+        }
+
+        if let Some(pid) = self.main_pid {
+            return signal_pid(pid, Signal::SIGKILL).map_err(io::Error::from);
+        }
+
+        match self.child.as_mut() {
+            Some(child) => child.kill(),
+            None => Ok(()),
+        }
+    }
+
+    /// Send `signal` to the main process or, with `KillWho::All`, to every
+    /// process in the cgroup. Returns how many processes were signalled.
+    pub fn signal(&self, signal: Signal, who: KillWho) -> Result<usize, BloomError> {
+        if who == KillWho::All
+            && let Some(cgroup) = &self.cgroup
+        {
+            let pids = cgroup.pids();
+            cgroup.signal(signal);
+            return Ok(pids.len());
+        }
+
+        signal_pid(self.pid(), signal)?;
+        Ok(1)
+    }
+
+    /// Reap whatever the service left behind in its cgroup and remove it.
+    /// Call once the main process is gone.
+    pub fn release(&mut self) {
+        if let Some(cgroup) = self.cgroup.take()
+            && let Err(e) = cgroup.release(Duration::from_secs(5))
+        {
+            eprintln!("[verdantd] Failed to remove cgroup: {}", e);
+        }
     }
 }
 
-/// Start a service, spawning its process.
-/// Returns a `ServiceHandle` on success.
-pub fn start_service(service: &Service) -> Result<ServiceHandle, BloomError> {
-    let mut cmd = Command::new(&service.cmd);
-    if !service.args.is_empty() {
-        cmd.args(&service.args);
+fn signal_pid(pid: u32, signal: Signal) -> nix::Result<()> {
+    kill(Pid::from_raw(pid as i32), signal)
+}
+
+/// True if `pid` still exists (signal 0 probes without delivering anything).
+fn pid_alive(pid: u32) -> bool {
+    kill(Pid::from_raw(pid as i32), None).is_ok()
+}
+
+/// A signal given as `SIGHUP`, `HUP` (in any case) or a number.
+pub fn parse_signal(s: &str) -> Result<Signal, BloomError> {
+    let s = s.trim();
+    let parsed = match s.parse::<i32>() {
+        Ok(number) => Signal::try_from(number).ok(),
+        Err(_) => {
+            let upper = s.to_ascii_uppercase();
+            let name = if upper.starts_with("SIG") { upper } else { format!("SIG{}", upper) };
+            name.parse::<Signal>().ok()
+        }
+    };
+
+    parsed.ok_or_else(|| BloomError::Custom(format!("Unknown signal: {}", s)))
+}
+
+/// Read a daemon PID from a pid file, if it has been written yet.
+pub fn read_pid_file(path: &str) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok().filter(|pid| *pid > 1)
+}
+
+/// How long a `reload_cmd` may run before it is killed and the reload fails.
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Ask a running service to reload its configuration, either by running
+/// its `reload_cmd` (with MAINPID set to `pid`) or by sending SIGHUP.
+pub fn reload_service(service: &Service, pid: u32) -> Result<(), BloomError> {
+    let Some(argv) = &service.reload_cmd else {
+        return signal_pid(pid, Signal::SIGHUP).map_err(BloomError::from);
+    };
+
+    let child = process::helper_command(service, argv)?
+        .env("MAINPID", pid.to_string())
+        .spawn()
+        .map_err(BloomError::Io)?;
+
+    wait_helper(child, RELOAD_TIMEOUT, "reload_cmd")
+}
+
+/// Wait for a helper command, killing it once `timeout` passes.
+pub(crate) fn wait_helper(mut child: Child, timeout: Duration, what: &str) -> Result<(), BloomError> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return if status.success() {
+                Ok(())
+            } else {
+                Err(BloomError::Custom(format!("{} exited with {}", what, status)))
+            };
+        }
+
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(BloomError::Custom(format!("{} timed out", what)));
+        }
+
+        sleep(Duration::from_millis(50));
+    }
+}
+
+/// How long a `cleanup_cmd` may run before it is killed.
+const CLEANUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wrap up a run whose main process is gone, whether it stopped, crashed or
+/// was killed: sweep its cgroup and run `cleanup_cmd` with EXIT_STATUS set.
+/// Later calls for the same run only sweep.
+pub fn cleanup_service(service: &Service, handle: &mut ServiceHandle) {
+    handle.release();
+
+    if handle.cleaned_up {
+        return;
     }
+    handle.cleaned_up = true;
 
-    // Apply stdout redirection if explicitly set
-    if let Some(ref path) = service.stdout {
-        let stdout_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .map_err(BloomError::Io)?;
-        cmd.stdout(stdout_file);
+    if let Some(argv) = &service.cleanup_cmd
+        && let Err(e) = run_cleanup_cmd(service, handle, argv)
+    {
+        eprintln!("[verdantd] cleanup_cmd for '{}' failed: {}", service.name, e);
     }
+}
 
-    // Apply stderr redirection if explicitly set
-    if let Some(ref path) = service.stderr {
-        let stderr_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .map_err(BloomError::Io)?;
-        cmd.stderr(stderr_file);
+fn run_cleanup_cmd(service: &Service, handle: &ServiceHandle, argv: &[String]) -> Result<(), BloomError> {
+    let mut cmd = process::helper_command(service, argv)?;
+    cmd.env("MAINPID", handle.pid().to_string());
+    if let Some(code) = handle.exit_status {
+        cmd.env("EXIT_STATUS", code.to_string());
     }
 
     let child = cmd.spawn().map_err(BloomError::Io)?;
+    wait_helper(child, CLEANUP_TIMEOUT, "cleanup_cmd")
+}
+
+/// Start a service, spawning its process.
+/// Returns a `ServiceHandle` on success.
+pub fn start_service(service: &Service) -> Result<ServiceHandle, BloomError> {
+    run_exec_steps(service)?;
+
+    let notify = match service.service_type {
+        ServiceType::Notify => {
+            let (uid, gid) = process::service_owner(service)?;
+            Some(NotifySocket::bind(&service.name, uid, gid).map_err(BloomError::Io)?)
+        }
+        ServiceType::Simple | ServiceType::Oneshot | ServiceType::Forking => None,
+    };
+
+    let (child, cgroup) = process::spawn(service, notify.as_ref().map(|n| n.path()))?;
 
     Ok(ServiceHandle {
-        child,
+        child: Some(child),
         start_time: Instant::now(),
         exit_status: None,
+        cgroup,
+        notify,
+        main_pid: None,
+        cleaned_up: false,
     })
 }
 
+/// Run the setup commands of a `cmd` list in order, all within
+/// `timeout_start`. The first one to fail aborts the start.
+fn run_exec_steps(service: &Service) -> Result<(), BloomError> {
+    let start = Instant::now();
+
+    for (i, argv) in service.exec_steps.iter().enumerate() {
+        let child = process::helper_command(service, argv)?.spawn().map_err(BloomError::Io)?;
+        let what = format!("cmd step {} ({})", i + 1, argv[0]);
+
+        wait_helper(child, service.timeout_start.saturating_sub(start.elapsed()), &what)
+            .map_err(|e| BloomError::Custom(format!("'{}': {}", service.name, e)))?;
+    }
+
+    Ok(())
+}
+
 /// Stop a running service cleanly.
+/// `stop_cmd`, if set, runs first as the service's user; after it, SIGTERM
+/// goes to the whole cgroup so forked helpers get a chance to exit too.
+/// Anything still alive once `timeout` has passed is killed along with the cgroup.
 /// Returns Ok(true) if stopped gracefully, Ok(false) if killed forcibly.
-pub fn stop_service(handle: &mut ServiceHandle, timeout: Duration) -> Result<bool, BloomError> {
-    #[cfg(unix)]
+pub fn stop_service(service: &Service, handle: &mut ServiceHandle, timeout: Duration) -> Result<bool, BloomError> {
+    let start = Instant::now();
+
+    if let Some(argv) = &service.stop_cmd
+        && handle.is_running()
+        && let Err(e) = run_stop_cmd(service, handle, argv, timeout)
     {
-        use nix::sys::signal::{kill, Signal};
-        use nix::unistd::Pid;
+        eprintln!("[verdantd] stop_cmd for '{}' failed: {}", service.name, e);
+    }
 
-        let pid = Pid::from_raw(handle.child.id() as i32);
+    let result = signal_and_wait(handle, service.kill_mode, timeout.saturating_sub(start.elapsed()));
 
+    // These modes leave the rest of the cgroup alone, so stop tracking it
+    if matches!(service.kill_mode, KillMode::MainProcess | KillMode::None) {
+        handle.cgroup = None;
+    }
+
+    cleanup_service(service, handle);
+    result
+}
+
+fn run_stop_cmd(service: &Service, handle: &ServiceHandle, argv: &[String], timeout: Duration) -> Result<(), BloomError> {
+    let child = process::helper_command(service, argv)?
+        .env("MAINPID", handle.pid().to_string())
+        .spawn()
+        .map_err(BloomError::Io)?;
+
+    wait_helper(child, timeout, "stop_cmd")
+}
+
+fn signal_and_wait(handle: &mut ServiceHandle, mode: KillMode, timeout: Duration) -> Result<bool, BloomError> {
+    #[cfg(unix)]
+    {
         // Check if it's already exited before signaling
-        if let Ok(Some(_)) = handle.child.try_wait() {
+        if !handle.is_running() {
             // Already exited
             return Ok(true);
         }
 
-        kill(pid, Signal::SIGTERM).map_err(BloomError::from)?;
+        match (mode, &handle.cgroup) {
+            (KillMode::None, _) => {}
+            (KillMode::ControlGroup, Some(cgroup)) => cgroup.signal(Signal::SIGTERM),
+            _ => signal_pid(handle.pid(), Signal::SIGTERM).map_err(BloomError::from)?,
+        }
 
-        match handle.wait_with_timeout(timeout)? {
-            Some(_) => Ok(true),
-            None => {
-                kill(pid, Signal::SIGKILL).map_err(BloomError::from)?;
-                match handle.wait_with_timeout(Duration::from_secs(5))? {
-                    Some(_) => Ok(false),
-                    None => Err(BloomError::Custom("Failed to kill service process".into())),
-                }
+        if handle.wait_with_timeout(timeout)? {
+            return Ok(true);
+        }
+
+        match mode {
+            KillMode::None => {
+                eprintln!("[verdantd] PID {} still running after stop (kill_mode: none)", handle.pid());
+                return Ok(false);
             }
+            KillMode::MainProcess => signal_pid(handle.pid(), Signal::SIGKILL).map_err(BloomError::from)?,
+            KillMode::ControlGroup | KillMode::Mixed => handle.kill().map_err(BloomError::Io)?,
+        }
+
+        if handle.wait_with_timeout(Duration::from_secs(5))? {
+            Ok(false)
+        } else {
+            Err(BloomError::Custom("Failed to kill service process".into()))
         }
     }
     #[cfg(not(unix))]
@@ -128,13 +381,13 @@ pub fn restart_service(
     match service.restart {
         RestartPolicy::Never => {
             if let Some(mut handle) = current_handle {
-                stop_service(&mut handle, Duration::from_secs(5))?;
+                stop_service(service, &mut handle, Duration::from_secs(5))?;
             }
             Ok(None)
         }
         RestartPolicy::Always => {
             if let Some(mut handle) = current_handle {
-                let _ = stop_service(&mut handle, Duration::from_secs(5));
+                let _ = stop_service(service, &mut handle, Duration::from_secs(5));
             }
             let new_handle = start_service(service)?;
             Ok(Some(new_handle))
@@ -145,6 +398,9 @@ pub fn restart_service(
                     return Ok(Some(handle)); // still running
                 }
 
+                // Sweep leftovers before a new instance reuses the cgroup
+                cleanup_service(service, &mut handle);
+
                 // Check if last exit status was a failure (non-zero)
                 match handle.exit_status {
                     Some(code) if code != 0 => {
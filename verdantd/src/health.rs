@@ -0,0 +1,99 @@
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Periodic health probe for a running service. The probe command runs in
+/// the background and is only polled, so a slow check never stalls the
+/// supervisor.
+#[derive(Debug)]
+pub struct HealthCheck {
+    cmd: String,
+    args: Vec<String>,
+    interval: Duration,
+    next_at: Instant,
+    in_flight: Option<(Child, Instant)>,
+    /// Failed probes in a row; reset by a passing probe.
+    pub consecutive_failures: u32,
+    /// Result of the most recent completed probe.
+    pub last_passed: Option<bool>,
+}
+
+impl HealthCheck {
+    pub fn new(cmd: String, args: Vec<String>, interval: Duration) -> Self {
+        Self {
+            cmd,
+            args,
+            interval,
+            next_at: Instant::now() + interval,
+            in_flight: None,
+            consecutive_failures: 0,
+            last_passed: None,
+        }
+    }
+
+    /// Start a new probe if one is due and collect a finished one.
+    /// Returns `Some(passed)` whenever a probe completes.
+    pub fn poll(&mut self) -> Option<bool> {
+        let now = Instant::now();
+
+        if let Some((child, started)) = &mut self.in_flight {
+            let passed = match child.try_wait() {
+                Ok(Some(status)) => status.success(),
+                Ok(None) if now.duration_since(*started) < self.interval => return None,
+                // Hung probe or wait error: count as a failure
+                _ => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    false
+                }
+            };
+
+            self.in_flight = None;
+            self.record(passed);
+            return Some(passed);
+        }
+
+        if now >= self.next_at {
+            self.next_at = now + self.interval;
+
+            match Command::new(&self.cmd)
+                .args(&self.args)
+                .stdin(Stdio::null())
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .process_group(0)
+                .spawn()
+            {
+                Ok(child) => self.in_flight = Some((child, now)),
+                Err(_) => {
+                    self.record(false);
+                    return Some(false);
+                }
+            }
+        }
+
+        None
+    }
+
+    fn record(&mut self, passed: bool) {
+        self.last_passed = Some(passed);
+        if passed {
+            self.consecutive_failures = 0;
+        } else {
+            self.consecutive_failures += 1;
+        }
+    }
+
+    /// Forget previous results and wait a full interval before probing again.
+    /// Called whenever the service (re)starts or stops.
+    pub fn reset(&mut self) {
+        if let Some((mut child, _)) = self.in_flight.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        self.next_at = Instant::now() + self.interval;
+        self.consecutive_failures = 0;
+        self.last_passed = None;
+    }
+}
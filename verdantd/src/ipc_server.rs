@@ -1,13 +1,126 @@
 use std::fs;
 use std::path::Path;
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use bloom::ipc::{IpcCommand, IpcRequest, IpcResponse, serve_ipc_socket, VERDANTD_SOCKET_PATH};
+use bloom::config::VerdantConfig;
+use bloom::efi;
+use bloom::errors::BloomError;
+use bloom::ipc::{
+    send_ipc_request, serve_ipc_socket, IpcCommand, IpcInternal, IpcRequest, IpcResponse, IpcStream, IpcTarget,
+    PowerAction, INIT_SOCKET_PATH, VERDANTD_SOCKET_PATH,
+};
 
-/// Spawns the IPC server for verdantd. Handles shutdown and reboot commands.
+use crate::manager::{LogChunk, Manager};
+use crate::schedule::PowerSchedule;
+use crate::sleep;
+
+/// How often a followed log is checked for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often watched services are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Longest a watcher goes without a fresh snapshot, so uptimes keep moving.
+const WATCH_REFRESH: Duration = Duration::from_secs(1);
+
+fn service_action_response(name: &str, verb: &str, result: Result<(), BloomError>) -> IpcResponse {
+    match result {
+        Ok(()) => IpcResponse {
+            success: true,
+            message: format!("{} '{}'", verb, name),
+            data: None,
+        },
+        Err(BloomError::NotFound) => IpcResponse {
+            success: false,
+            message: format!("No such service: {}", name),
+            data: None,
+        },
+        Err(e) => IpcResponse {
+            success: false,
+            message: format!("Failed on '{}': {}", name, e),
+            data: None,
+        },
+    }
+}
+
+/// Stream `chunk` and then everything the service writes after it, until
+/// the client hangs up or the logs can no longer be read.
+fn follow_logs(manager: &Manager, name: &str, chunk: LogChunk, stream: &mut IpcStream) -> IpcResponse {
+    let mut cursors = chunk.cursors.clone();
+    let mut pending = Some(chunk);
+
+    loop {
+        if let Some(chunk) = pending.take()
+            && stream.send(serde_json::to_value(chunk).unwrap_or_default()).is_err()
+        {
+            return IpcResponse {
+                success: true,
+                message: "Client went away".into(),
+                data: None,
+            };
+        }
+
+        thread::sleep(FOLLOW_INTERVAL);
+
+        match manager.service_logs(name, 0, Some(cursors.clone())) {
+            Ok(chunk) => {
+                cursors = chunk.cursors.clone();
+                if !chunk.lines.is_empty() {
+                    pending = Some(chunk);
+                }
+            }
+            Err(e) => {
+                return IpcResponse {
+                    success: false,
+                    message: format!("Stopped following '{}': {}", name, e),
+                    data: None,
+                };
+            }
+        }
+    }
+}
+
+/// Stream the status of every service, at once and then on every state,
+/// PID, restart or health change, until the client hangs up.
+fn watch_services(manager: &Manager, stream: &mut IpcStream) -> IpcResponse {
+    let mut last_key = Vec::new();
+    let mut last_sent: Option<Instant> = None;
+
+    loop {
+        let services = manager.status();
+        let key: Vec<_> = services
+            .iter()
+            .map(|s| (s.name.clone(), s.state, s.pid, s.restarts, s.healthy))
+            .collect();
+
+        if key != last_key || last_sent.is_none_or(|at| at.elapsed() >= WATCH_REFRESH) {
+            if stream.send(serde_json::to_value(&services).unwrap_or_default()).is_err() {
+                return IpcResponse {
+                    success: true,
+                    message: "Client went away".into(),
+                    data: None,
+                };
+            }
+            last_key = key;
+            last_sent = Some(Instant::now());
+        }
+
+        thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Spawns the IPC server for verdantd. Handles shutdown, reboot, halt and status commands.
 ///
-/// Sends a `Shutdown` or `Reboot` command to the main manager thread via the provided channel.
-pub fn run_ipc_server(shutdown_tx: Sender<IpcCommand>) -> std::io::Result<()> {
+/// Sends a `Shutdown`, `Reboot` or `Halt` command to the main manager thread via the provided channel,
+/// straight away or once a requested delay has been counted down.
+pub fn run_ipc_server(
+    shutdown_tx: Sender<IpcCommand>,
+    config: Arc<VerdantConfig>,
+    manager: Arc<Manager>,
+) -> std::io::Result<()> {
     let socket_path = Path::new(VERDANTD_SOCKET_PATH);
 
     // Ensure parent directory exists
@@ -20,8 +133,10 @@ pub fn run_ipc_server(shutdown_tx: Sender<IpcCommand>) -> std::io::Result<()> {
         fs::remove_file(socket_path)?;
     }
 
+    let schedule = Arc::new(PowerSchedule::default());
+
     // Now serve IPC
-    serve_ipc_socket(VERDANTD_SOCKET_PATH, move |request: IpcRequest| {
+    serve_ipc_socket(VERDANTD_SOCKET_PATH, move |request: IpcRequest, stream: &mut IpcStream| {
         if request.target != bloom::ipc::IpcTarget::Verdantd {
             return IpcResponse {
                 success: false,
@@ -31,11 +146,94 @@ pub fn run_ipc_server(shutdown_tx: Sender<IpcCommand>) -> std::io::Result<()> {
         }
 
         match request.command {
-            IpcCommand::Shutdown | IpcCommand::Reboot => {
+            // Refused up front rather than after every service is down
+            IpcCommand::Reboot { firmware_setup: true, .. } if !efi::firmware_setup_supported() => IpcResponse {
+                success: false,
+                message: "The firmware does not support booting into its setup screen".into(),
+                data: None,
+            },
+
+            IpcCommand::Shutdown { delay_secs, .. }
+            | IpcCommand::Reboot { delay_secs, .. }
+            | IpcCommand::Halt { delay_secs, .. }
+                if delay_secs > 0 =>
+            {
+                let (action, delay, message) = PowerAction::from_command(&request.command).unwrap();
+                match schedule.schedule(action, Duration::from_secs(delay), message, shutdown_tx.clone()) {
+                    Some(at) => IpcResponse {
+                        success: true,
+                        message: format!(
+                            "Scheduled {} for {}; cancel with `vctl shutdown --cancel`",
+                            action.as_str(),
+                            at.format("%Y-%m-%d %H:%M:%S")
+                        ),
+                        data: None,
+                    },
+                    None => IpcResponse {
+                        success: false,
+                        message: format!("A delay of {}s is too long", delay),
+                        data: None,
+                    },
+                }
+            }
+
+            IpcCommand::Kexec { ref kernel, ref initrd, ref cmdline } => {
+                // Loaded by init before any service is stopped, while /boot is
+                // mounted and backing out is still possible
+                let load = IpcRequest {
+                    id: 0,
+                    target: IpcTarget::Init,
+                    command: IpcCommand::KexecLoad {
+                        kernel: kernel.clone(),
+                        initrd: initrd.clone(),
+                        cmdline: cmdline.clone(),
+                    },
+                };
+
+                match send_ipc_request(INIT_SOCKET_PATH, &load) {
+                    Err(e) => IpcResponse {
+                        success: false,
+                        message: format!("Cannot reach init: {}", e),
+                        data: None,
+                    },
+                    Ok(resp) if !resp.success => resp,
+                    Ok(_) => match shutdown_tx.send(PowerAction::Kexec.command(0, None)) {
+                        Ok(_) => IpcResponse {
+                            success: true,
+                            message: "Proceeding with kexec".into(),
+                            data: None,
+                        },
+                        Err(e) => IpcResponse {
+                            success: false,
+                            message: format!("Failed to trigger kexec: {}", e),
+                            data: None,
+                        },
+                    }
+                }
+            }
+
+            IpcCommand::CancelShutdown => {
+                if schedule.cancel() {
+                    IpcResponse {
+                        success: true,
+                        message: "Scheduled shutdown cancelled".into(),
+                        data: None,
+                    }
+                } else {
+                    IpcResponse {
+                        success: false,
+                        message: "No shutdown is scheduled".into(),
+                        data: None,
+                    }
+                }
+            }
+
+            IpcCommand::Shutdown { .. } | IpcCommand::Reboot { .. } | IpcCommand::Halt { .. } => {
+                let action = PowerAction::from_command(&request.command).map_or("power off", |(a, ..)| a.as_str());
                 match shutdown_tx.send(request.command.clone()) {
                     Ok(_) => IpcResponse {
                         success: true,
-                        message: format!("Proceeding with {:?}", request.command),
+                        message: format!("Proceeding with {}", action),
                         data: None,
                     },
                     Err(e) => IpcResponse {
@@ -46,6 +244,221 @@ pub fn run_ipc_server(shutdown_tx: Sender<IpcCommand>) -> std::io::Result<()> {
                 }
             }
 
+            IpcCommand::Suspend | IpcCommand::Hibernate => {
+                let hibernate = matches!(request.command, IpcCommand::Hibernate);
+                match sleep::sleep(&manager, hibernate) {
+                    Ok(()) => IpcResponse {
+                        success: true,
+                        message: format!("Resumed from {}", if hibernate { "hibernation" } else { "suspend" }),
+                        data: None,
+                    },
+                    Err(e) => IpcResponse {
+                        success: false,
+                        message: format!("Failed to {}: {}", if hibernate { "hibernate" } else { "suspend" }, e),
+                        data: None,
+                    },
+                }
+            }
+
+            IpcCommand::Reexec => match shutdown_tx.send(IpcCommand::Reexec) {
+                Ok(_) => IpcResponse {
+                    success: true,
+                    message: "Re-executing verdantd".into(),
+                    data: None,
+                },
+                Err(e) => IpcResponse {
+                    success: false,
+                    message: format!("Failed to trigger re-exec: {}", e),
+                    data: None,
+                },
+            },
+
+            IpcCommand::Internal(IpcInternal::ReloadConfig) => match manager.reload_services() {
+                Ok(summary) => IpcResponse {
+                    success: true,
+                    message: format!(
+                        "Reloaded: {} added, {} changed, {} need restart",
+                        summary.added.len(),
+                        summary.changed.len(),
+                        summary.needs_restart.len()
+                    ),
+                    data: serde_json::to_value(summary).ok(),
+                },
+                Err(e) => IpcResponse {
+                    success: false,
+                    message: format!("Failed to reload services: {}", e),
+                    data: None,
+                },
+            },
+
+            IpcCommand::StartService(ref name) => {
+                service_action_response(name, "Started", manager.start_service(name))
+            }
+
+            IpcCommand::StopService(ref name) => {
+                service_action_response(name, "Stopped", manager.stop_service(name))
+            }
+
+            IpcCommand::RestartService(ref name) => {
+                service_action_response(name, "Restarted", manager.restart_service(name))
+            }
+
+            IpcCommand::ReloadService(ref name) => {
+                service_action_response(name, "Reloaded", manager.reload_service(name))
+            }
+
+            IpcCommand::KillService { ref name, ref signal, who } => match manager.kill_service(name, signal, who) {
+                Ok(count) => IpcResponse {
+                    success: true,
+                    message: format!("Sent {} to {} process(es) of '{}'", signal, count, name),
+                    data: None,
+                },
+                Err(e) => service_action_response(name, "Signalled", Err(e)),
+            },
+
+            IpcCommand::EnableService(ref name) => {
+                service_action_response(name, "Enabled", manager.set_enabled(name, true))
+            }
+
+            IpcCommand::DisableService(ref name) => {
+                service_action_response(name, "Disabled", manager.set_enabled(name, false))
+            }
+
+            IpcCommand::MaskService(ref name) => {
+                service_action_response(name, "Masked", manager.set_masked(name, true))
+            }
+
+            IpcCommand::UnmaskService(ref name) => {
+                service_action_response(name, "Unmasked", manager.set_masked(name, false))
+            }
+
+            IpcCommand::ResetFailed(ref name) => match manager.reset_failed(name.as_deref()) {
+                Ok(reset) => IpcResponse {
+                    success: true,
+                    message: match reset.len() {
+                        0 => "Nothing to reset".into(),
+                        _ => format!("Reset {}", reset.join(", ")),
+                    },
+                    data: None,
+                },
+                Err(BloomError::NotFound) => IpcResponse {
+                    success: false,
+                    message: format!("No such service: {}", name.as_deref().unwrap_or_default()),
+                    data: None,
+                },
+                Err(e) => IpcResponse {
+                    success: false,
+                    message: format!("Failed to reset: {}", e),
+                    data: None,
+                },
+            },
+
+            IpcCommand::Isolate(ref target) => match config.verdantd.target(target) {
+                Some(packages) => match manager.isolate(packages) {
+                    Ok(()) => IpcResponse {
+                        success: true,
+                        message: format!("Isolated target '{}'", target),
+                        data: None,
+                    },
+                    Err(e) => IpcResponse {
+                        success: false,
+                        message: format!("Failed to isolate '{}': {}", target, e),
+                        data: None,
+                    },
+                },
+                None => IpcResponse {
+                    success: false,
+                    message: format!("No such target: {}", target),
+                    data: None,
+                },
+            },
+
+            IpcCommand::GetStatus => {
+                let services = manager.status();
+                IpcResponse {
+                    success: true,
+                    message: format!("{} services", services.len()),
+                    data: serde_json::to_value(services).ok(),
+                }
+            }
+
+            IpcCommand::WatchServices => watch_services(&manager, stream),
+
+            IpcCommand::GetServiceStatus(ref name) => match manager.service_status(name) {
+                Some(status) => IpcResponse {
+                    success: true,
+                    message: format!("Status of '{}'", name),
+                    data: serde_json::to_value(status).ok(),
+                },
+                None => IpcResponse {
+                    success: false,
+                    message: format!("No such service: {}", name),
+                    data: None,
+                },
+            },
+
+            IpcCommand::ShowService(ref name) => match manager.service_properties(name) {
+                Some(properties) => IpcResponse {
+                    success: true,
+                    message: format!("Properties of '{}'", name),
+                    data: serde_json::to_value(properties).ok(),
+                },
+                None => IpcResponse {
+                    success: false,
+                    message: format!("No such service: {}", name),
+                    data: None,
+                },
+            },
+
+            IpcCommand::ListDependencies { ref name, reverse } => match manager.dependency_tree(name, reverse) {
+                Ok(tree) => IpcResponse {
+                    success: true,
+                    message: format!("Dependencies of '{}'", name),
+                    data: serde_json::to_value(tree).ok(),
+                },
+                Err(_) => IpcResponse {
+                    success: false,
+                    message: format!("No such service: {}", name),
+                    data: None,
+                },
+            },
+
+            IpcCommand::ServiceLogs { ref name, lines, ref cursors, follow } => {
+                match manager.service_logs(name, lines, cursors.clone()) {
+                    Ok(chunk) if follow => follow_logs(&manager, name, chunk, stream),
+                    Ok(chunk) => IpcResponse {
+                        success: true,
+                        message: format!("{} lines", chunk.lines.len()),
+                        data: serde_json::to_value(chunk).ok(),
+                    },
+                    Err(BloomError::NotFound) => IpcResponse {
+                        success: false,
+                        message: format!("No such service: {}", name),
+                        data: None,
+                    },
+                    Err(e) => IpcResponse {
+                        success: false,
+                        message: format!("Cannot read logs of '{}': {}", name, e),
+                        data: None,
+                    },
+                }
+            }
+
+            IpcCommand::ListTimers => {
+                let timers = manager.list_timers();
+                IpcResponse {
+                    success: true,
+                    message: format!("{} timers", timers.len()),
+                    data: serde_json::to_value(timers).ok(),
+                }
+            }
+
+            IpcCommand::GetConfig => IpcResponse {
+                success: true,
+                message: "Effective verdantd configuration".into(),
+                data: serde_json::to_value(&*config).ok(),
+            },
+
             _ => IpcResponse {
                 success: false,
                 message: format!("Unhandled command: {:?}", request.command),
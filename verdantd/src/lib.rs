@@ -0,0 +1,24 @@
+//! Service file parsing, shared with `vctl verify` and `vctl edit` so they
+//! check files exactly the way verdantd loads them.
+
+// Shared with the verdantd binary, which uses the rest of them
+#[allow(dead_code)]
+mod capability;
+#[allow(dead_code)]
+mod cgroup;
+#[allow(dead_code)]
+mod condition;
+#[allow(dead_code)]
+mod loader;
+mod ordering;
+mod parser;
+#[allow(dead_code)]
+mod persist;
+mod service;
+#[allow(dead_code)]
+mod timer;
+
+pub use loader::{check_names, SERVICE_DIR};
+pub use ordering::resolve_order;
+pub use parser::{dropin_files, parse_service_file, parse_with_dropins};
+pub use service::Service;
@@ -1,50 +1,34 @@
+use std::collections::HashMap;
 use std::fs;
 
 use crate::parser::parse_service_file;
+use crate::persist;
 use crate::service::Service;
+use bloom::errors::BloomError;
 use bloom::log::FileLogger;
 use bloom::status;
 
-const SERVICE_DIR: &str = "/etc/verdant/services";
+pub const SERVICE_DIR: &str = "/etc/verdant/services";
 
 pub fn load_services(logger: &mut dyn FileLogger) -> (Vec<Service>, usize, usize) {
-    let mut services = Vec::new();
-    let mut loaded_count = 0;
-    let mut failed_count = 0;
-
-    let entries = match fs::read_dir(SERVICE_DIR) {
-        Ok(entries) => entries,
+    let (services, errors) = match scan_services() {
+        Ok(scan) => scan,
         Err(e) => {
             logger.log(
                 status::LogLevel::Fail,
                 &format!("Failed to read service directory: {}", e),
             );
-            return (services, 0, 0);
+            return (Vec::new(), 0, 0);
         }
     };
 
-    for entry in entries {
-        if let Ok(entry) = entry {
-            let path = entry.path();
-
-            if path.extension().and_then(|e| e.to_str()) == Some("vs") {
-                match parse_service_file(path.to_str().unwrap_or_default()) {
-                    Ok(mut parsed_services) => {
-                        loaded_count += parsed_services.len();
-                        services.append(&mut parsed_services);
-                    }
-                    Err(err) => {
-                        failed_count += 1;
-                        logger.log(
-                            status::LogLevel::Fail,
-                            &format!("Failed to load {}: {}", path.display(), err),
-                        );
-                    }
-                }
-            }
-        }
+    for err in &errors {
+        logger.log(status::LogLevel::Fail, err);
     }
 
+    let loaded_count = services.len();
+    let failed_count = errors.len();
+
     logger.log(
         status::LogLevel::Info,
         &format!(
@@ -56,3 +40,55 @@ pub fn load_services(logger: &mut dyn FileLogger) -> (Vec<Service>, usize, usize
     (services, loaded_count, failed_count)
 }
 
+/// Parse every `.vs` file in the service directory. Returns the services
+/// that loaded and one message per file that did not.
+pub fn scan_services() -> std::io::Result<(Vec<Service>, Vec<String>)> {
+    let mut services = Vec::new();
+    let mut errors = Vec::new();
+
+    for entry in fs::read_dir(SERVICE_DIR)?.flatten() {
+        let path = entry.path();
+
+        if path.extension().and_then(|e| e.to_str()) != Some("vs") {
+            continue;
+        }
+
+        let parsed = parse_service_file(path.to_str().unwrap_or_default())
+            .and_then(|parsed| check_names(&services, parsed));
+
+        match parsed {
+            Ok(mut parsed_services) => {
+                for service in &mut parsed_services {
+                    service.enabled = persist::is_enabled(&service.name);
+                    service.masked = persist::is_masked(&service.name);
+                }
+                services.append(&mut parsed_services);
+            }
+            Err(err) => errors.push(format!("Failed to load {}: {}", path.display(), err)),
+        }
+    }
+
+    Ok((services, errors))
+}
+
+/// Reject a file if any name or alias in it is already taken, by a
+/// service in `loaded` or by another service from the same file.
+pub fn check_names(loaded: &[Service], new: Vec<Service>) -> Result<Vec<Service>, BloomError> {
+    let mut taken: HashMap<&str, &str> = HashMap::new();
+    for service in loaded {
+        taken.insert(&service.name, &service.name);
+        for alias in &service.aliases {
+            taken.insert(alias, &service.name);
+        }
+    }
+
+    for service in &new {
+        for name in std::iter::once(&service.name).chain(&service.aliases) {
+            if let Some(owner) = taken.insert(name, &service.name) {
+                return Err(BloomError::Parse(format!("'{}' is already used by '{}'", name, owner)));
+            }
+        }
+    }
+
+    Ok(new)
+}
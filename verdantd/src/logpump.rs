@@ -0,0 +1,280 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, PipeReader, Read, Seek, SeekFrom, Write};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use bloom::journal;
+use bloom::status::LogLevel;
+use chrono::Local;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+use crate::service::Service;
+
+/// Longer lines are split so a process that never writes a newline cannot
+/// make the pump buffer without bound.
+const MAX_LINE: u64 = 16 * 1024;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+
+/// Which output stream of the service a line came from.
+#[derive(Debug, Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+
+    fn level(&self) -> LogLevel {
+        match self {
+            Stream::Stdout => LogLevel::Info,
+            Stream::Stderr => LogLevel::Warn,
+        }
+    }
+}
+
+/// When a log file is rotated and how many compressed archives are kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rotation {
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub max_files: u32,
+}
+
+impl Rotation {
+    pub fn for_service(service: &Service) -> Self {
+        Self {
+            max_size: service.log_max_size,
+            max_age: service.log_max_age,
+            max_files: service.log_max_files,
+        }
+    }
+}
+
+/// Log files currently written to, so every pump writing to a path shares
+/// one writer and sees its rotations.
+static OPEN_LOGS: Mutex<Vec<(String, Weak<Mutex<LogFile>>)>> = Mutex::new(Vec::new());
+
+/// A service log file, shared by every pump that writes into it.
+pub struct LogFile {
+    path: String,
+    file: File,
+    size: u64,
+    /// When the current file was started, for `max_age`.
+    since: SystemTime,
+    rotation: Rotation,
+}
+
+impl LogFile {
+    /// Open `path` for appending, or join the writer already open for it.
+    /// The most recent `rotation` wins.
+    pub fn open(path: &str, rotation: Rotation) -> io::Result<Arc<Mutex<Self>>> {
+        let mut open = OPEN_LOGS.lock().unwrap();
+        open.retain(|(_, log)| log.strong_count() > 0);
+
+        if let Some(log) = open.iter().find(|(p, _)| p == path).and_then(|(_, log)| log.upgrade()) {
+            log.lock().unwrap().rotation = rotation;
+            return Ok(log);
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+
+        let log = Arc::new(Mutex::new(Self {
+            path: path.to_string(),
+            file,
+            size: metadata.len(),
+            since: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            rotation,
+        }));
+
+        open.push((path.to_string(), Arc::downgrade(&log)));
+        Ok(log)
+    }
+
+    /// Append one line as `<timestamp> <stream>: <line>` in a single write,
+    /// so lines from stdout and stderr never interleave.
+    fn write_line(&mut self, stream: Stream, line: &[u8]) -> io::Result<()> {
+        let mut buf = format!("{} {}: ", Local::now().format(TIMESTAMP_FORMAT), stream.as_str()).into_bytes();
+        buf.extend_from_slice(line);
+        buf.push(b'\n');
+
+        self.file.write_all(&buf)?;
+        self.size += buf.len() as u64;
+
+        if self.rotation_due()
+            && let Err(e) = self.rotate()
+        {
+            eprintln!("[verdantd] Cannot rotate {}: {}", self.path, e);
+            // Try again after another full period rather than on every line
+            self.size = 0;
+            self.since = SystemTime::now();
+        }
+
+        Ok(())
+    }
+
+    fn rotation_due(&self) -> bool {
+        let too_big = self.rotation.max_size.is_some_and(|max| self.size >= max);
+        let too_old = self
+            .rotation
+            .max_age
+            .is_some_and(|max| self.since.elapsed().is_ok_and(|age| age >= max));
+
+        too_big || too_old
+    }
+
+    /// Shift `<path>.N.gz` up by one, dropping the oldest, compress the
+    /// current contents into `<path>.1.gz` and start the file over.
+    fn rotate(&mut self) -> io::Result<()> {
+        let archive = |n: u32| format!("{}.{}.gz", self.path, n);
+        let keep = self.rotation.max_files;
+
+        if keep > 0 {
+            ignore_missing(fs::remove_file(archive(keep)))?;
+            for n in (1..keep).rev() {
+                ignore_missing(fs::rename(archive(n), archive(n + 1)))?;
+            }
+            compress(&self.path, &archive(1))?;
+        }
+
+        // Appends continue at the new end of the file
+        self.file.set_len(0)?;
+        self.size = 0;
+        self.since = SystemTime::now();
+        Ok(())
+    }
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+/// gzip `src` into `dest`, which only appears once complete.
+fn compress(src: &str, dest: &str) -> io::Result<()> {
+    let partial = format!("{}.tmp", dest);
+
+    let mut encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    io::copy(&mut File::open(src)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+
+    fs::rename(partial, dest)
+}
+
+/// Create a pipe for one output stream of `service` and a thread that
+/// copies every line written to it into `log`. The thread ends once all
+/// copies of the write end are closed, which is when the process and
+/// anything it forked have exited.
+pub fn pipe(service: &str, stream: Stream, log: Arc<Mutex<LogFile>>) -> io::Result<Stdio> {
+    let (reader, writer) = io::pipe()?;
+
+    let name = service.to_string();
+    thread::Builder::new()
+        .name(format!("log-{}", service))
+        .spawn(move || pump(&name, reader, stream, &log))?;
+
+    Ok(Stdio::from(writer))
+}
+
+fn pump(service: &str, reader: PipeReader, stream: Stream, log: &Mutex<LogFile>) {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    let mut reported = false;
+
+    loop {
+        line.clear();
+
+        match (&mut reader).take(MAX_LINE).read_until(b'\n', &mut line) {
+            Ok(0) => return,
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        }
+
+        if line.ends_with(b"\n") {
+            line.pop();
+        }
+
+        journal::send(service, stream.level(), &String::from_utf8_lossy(&line));
+
+        // Keep draining on errors so the service never blocks on a full pipe
+        if let Err(e) = log.lock().unwrap().write_line(stream, &line)
+            && !reported
+        {
+            eprintln!("[verdantd] Cannot write {} of '{}': {}", stream.as_str(), service, e);
+            reported = true;
+        }
+    }
+}
+
+/// How far back `tail` reads at a time while looking for line starts.
+const TAIL_CHUNK: u64 = 64 * 1024;
+
+/// Upper bound on what a single `read_since` returns.
+const MAX_READ: u64 = 1024 * 1024;
+
+/// The last `count` lines of a log file and the offset just past them.
+pub fn tail(path: &str, count: usize) -> io::Result<(Vec<String>, u64)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    // Read backwards until enough line breaks have been seen
+    let mut start = len;
+    let mut buf = Vec::new();
+    while start > 0 && buf.iter().filter(|b| **b == b'\n').count() <= count {
+        let chunk = TAIL_CHUNK.min(start);
+        start -= chunk;
+
+        let mut block = vec![0; chunk as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut block)?;
+        block.extend_from_slice(&buf);
+        buf = block;
+    }
+
+    let mut lines = split_lines(&buf);
+    // The first line may have been cut in half
+    if start > 0 && !lines.is_empty() {
+        lines.remove(0);
+    }
+    let skip = lines.len().saturating_sub(count);
+
+    Ok((lines.split_off(skip), len))
+}
+
+/// Complete lines written to a log file since `offset`, and the offset to
+/// continue from. A file that shrank was rotated and is read from the start.
+pub fn read_since(path: &str, offset: u64) -> io::Result<(Vec<String>, u64)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let offset = if len < offset { 0 } else { offset };
+
+    let mut buf = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(MAX_READ).read_to_end(&mut buf)?;
+
+    // A partial last line is left for the next call
+    let complete = buf.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+    buf.truncate(complete);
+
+    Ok((split_lines(&buf), offset + complete as u64))
+}
+
+fn split_lines(buf: &[u8]) -> Vec<String> {
+    buf.split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| String::from_utf8_lossy(line).into_owned())
+        .collect()
+}
@@ -1,21 +1,43 @@
+mod automount;
+mod buttons;
+mod capability;
+mod cgroup;
+mod condition;
 mod control;
+mod health;
 mod ipc_server;
 mod loader;
+mod logpump;
 mod manager;
+mod netmount;
+mod notify;
+mod ordering;
 mod parser;
+mod persist;
+mod process;
+mod reaper;
+mod reexec;
+mod schedule;
 mod service;
 mod shutdown;
+mod sleep;
 mod supervisor;
+mod timer;
 mod tty;
 
+use std::sync::Arc;
 use std::sync::mpsc::channel;
 use std::thread;
 use std::time::Duration;
 
+use bloom::config::{load_config, VerdantConfig, RESCUE_TARGET};
+use bloom::fstab::{has_network_mounts, is_network_mount};
 use bloom::ipc::{IpcCommand, IpcRequest, IpcTarget, send_ipc_request, INIT_SOCKET_PATH, VERDANTD_SOCKET_PATH};
-use bloom::log::{ConsoleLogger, ConsoleLoggerImpl, FileLogger, FileLoggerImpl};
+use bloom::log::{ConsoleLogger, ConsoleLoggerImpl, FileLogger, FileLoggerImpl, BOOT_LOG_PATH};
 use bloom::status::LogLevel;
 
+use crate::automount::start_automounts;
+use crate::buttons::watch_buttons;
 use crate::manager::Manager;
 use crate::loader::load_services;
 use crate::ipc_server::run_ipc_server;
@@ -23,9 +45,12 @@ use crate::ipc_server::run_ipc_server;
 // Get the Cargo package version set at compile time
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Pause between acknowledging `vctl daemon-reexec` and performing it.
+const REEXEC_GRACE: Duration = Duration::from_millis(200);
+
 fn main() {
-    let mut console_logger = ConsoleLoggerImpl::new(LogLevel::Info);
-    let mut file_logger = FileLoggerImpl::new(LogLevel::Info, "/var/log/verdant/verdantd.log");
+    let mut console_logger = ConsoleLoggerImpl::new(LogLevel::Info).with_boot_log(BOOT_LOG_PATH, false);
+    let mut file_logger = FileLoggerImpl::new(LogLevel::Info, "/var/log/verdant/verdantd.log").with_journal("verdantd");
 
     console_logger.banner(&format!(
         "Verdantd Service Manager v{} - Cultivating System Harmony",
@@ -36,6 +61,20 @@ fn main() {
         .initialize(&mut console_logger)
         .expect("Failed to init file logger");
 
+    if let Err(e) = console_logger.open_boot_log() {
+        file_logger.log(LogLevel::Warn, &format!("Cannot write {}: {}", BOOT_LOG_PATH, e));
+    }
+
+    let config = Arc::new(match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            let msg = format!("Failed to load configuration, using defaults: {}", e);
+            console_logger.message(LogLevel::Warn, &msg, Duration::ZERO);
+            file_logger.log(LogLevel::Warn, &msg);
+            VerdantConfig::default()
+        }
+    });
+
     let (_services, loaded_count, failed_count) = load_services(&mut file_logger);
 
     console_logger.message(
@@ -44,19 +83,87 @@ fn main() {
         Duration::ZERO,
     );
 
-    let manager = Manager::new(&mut file_logger);
-    manager.start_startup_services(&["base", "network", "system"], &mut file_logger, &mut console_logger);
+    // Before any service starts, so every daemon it forks off is ours
+    if let Err(e) = reaper::become_subreaper() {
+        file_logger.log(LogLevel::Warn, &format!("Cannot become a child subreaper: {}", e));
+    }
+
+    let manager = Arc::new(Manager::new(&mut file_logger));
+    manager.start_event_loop();
+    reaper::start_reaper(Arc::clone(&manager));
 
-    
-thread::spawn(|| {
-    if let Err(e) = tty::spawn_tty("tty1") {
-        eprintln!("Failed to launch getty on tty1: {}", e);
+    // After `vctl daemon-reexec` the services are already up; just adopt them
+    let restored = manager.restore_state();
+
+    if let Some(adopted) = restored {
+        let msg = format!("Re-executed; adopted {} running services", adopted);
+        console_logger.message(LogLevel::Ok, &msg, Duration::ZERO);
+        file_logger.log(LogLevel::Ok, &msg);
+    } else {
+        let target = config.verdantd.boot_target();
+        let packages = match config.verdantd.target(&target) {
+            Some(packages) => packages,
+            None => {
+                let fallback = &config.verdantd.default_target;
+                let msg = format!("Unknown boot target '{}', falling back to '{}'", target, fallback);
+                console_logger.message(LogLevel::Warn, &msg, Duration::ZERO);
+                file_logger.log(LogLevel::Warn, &msg);
+                config.verdantd.target(fallback).unwrap_or_default()
+            }
+        };
+        let packages: Vec<&str> = packages.iter().map(String::as_str).collect();
+
+        // Before any service can look inside the mount points
+        let automounts = start_automounts(&config.mounts);
+        if automounts > 0 {
+            file_logger.log(LogLevel::Info, &format!("Watching {} automount point(s)", automounts));
+        }
+
+        manager.start_startup_services(&packages, &mut file_logger, &mut console_logger);
+
+        // Held back by init until the services above bring the network up
+        let network_mounts = config
+            .mounts
+            .iter()
+            .any(|m| !m.automount && is_network_mount(m.fstype.as_deref().unwrap_or_default(), &m.options));
+        if has_network_mounts() || network_mounts {
+            netmount::mount_when_online();
+        }
+    }
+
+    manager.start_timers();
+
+    if config.verdantd.watch_services
+        && let Err(e) = manager.watch_services()
+    {
+        let msg = format!("Cannot watch service directory, use `vctl daemon-reload`: {}", e);
+        console_logger.message(LogLevel::Warn, &msg, Duration::ZERO);
+        file_logger.log(LogLevel::Warn, &msg);
+    }
+
+    // The getty from before a re-exec is still running
+    if restored.is_none() && config.verdantd.boot_target() == RESCUE_TARGET {
+        // The console is not left without any way to log in
+        if let Err(e) = tty::spawn_rescue_shell() {
+            let tty = config.init.tty_sessions.first().map_or("tty1", String::as_str);
+            eprintln!("Failed to launch rescue shell, starting a getty on {} instead: {}", tty, e);
+            if let Err(e) = tty::spawn_tty(tty) {
+                eprintln!("Failed to launch getty on {}: {}", tty, e);
+            }
+        }
+    } else if restored.is_none() {
+        for tty in &config.init.tty_sessions {
+            if let Err(e) = tty::spawn_tty(tty) {
+                eprintln!("Failed to launch getty on {}: {}", tty, e);
+            }
+        }
     }
-});
 
     let (shutdown_tx, shutdown_rx) = channel::<IpcCommand>();
 
     let ipc_shutdown_tx = shutdown_tx.clone();
+    let ipc_config = Arc::clone(&config);
+    let ipc_manager = Arc::clone(&manager);
 
 
 console_logger.message(
@@ -70,16 +177,31 @@ file_logger.log(
 );
 
 thread::spawn(move || {
-    if let Err(e) = run_ipc_server(ipc_shutdown_tx) {
+    if let Err(e) = run_ipc_server(ipc_shutdown_tx, ipc_config, ipc_manager) {
         eprintln!("IPC server failed: {}", e);
     }
 });
 
+    let listening = watch_buttons(
+        config.verdantd.power_key,
+        config.verdantd.lid_switch,
+        Arc::clone(&manager),
+        shutdown_tx.clone(),
+    );
+    if listening > 0 {
+        file_logger.log(
+            LogLevel::Info,
+            &format!("Listening for power button and lid events on {} device(s)", listening),
+        );
+    }
 
     loop {
         if let Ok(command) = shutdown_rx.recv() {
             match command {
-                IpcCommand::Shutdown | IpcCommand::Reboot => {
+                IpcCommand::Shutdown { .. }
+                | IpcCommand::Reboot { .. }
+                | IpcCommand::Halt { .. }
+                | IpcCommand::Kexec { .. } => {
                     let msg = "Shutting down all services...";
                     console_logger.message(LogLevel::Info, msg, Duration::ZERO);
                     file_logger.log(LogLevel::Info, msg);
@@ -98,6 +220,7 @@ thread::spawn(move || {
                     }
 
                     let notify = IpcRequest {
+                        id: 0,
                         target: IpcTarget::Init,
                         command,
                     };
@@ -110,6 +233,20 @@ thread::spawn(move || {
 
                     std::process::exit(0);
                 }
+                IpcCommand::Reexec => {
+                    let msg = "Re-executing verdantd...";
+                    console_logger.message(LogLevel::Info, msg, Duration::ZERO);
+                    file_logger.log(LogLevel::Info, msg);
+
+                    // Let the IPC server answer vctl before the socket goes away
+                    thread::sleep(REEXEC_GRACE);
+
+                    if let Err(e) = manager.reexec() {
+                        let msg = format!("Re-exec failed, continuing as before: {e}");
+                        console_logger.message(LogLevel::Fail, &msg, Duration::ZERO);
+                        file_logger.log(LogLevel::Fail, &msg);
+                    }
+                }
                 _ => {
                     // Ignore other commands
                 }
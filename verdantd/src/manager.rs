@@ -1,60 +1,262 @@
+use std::collections::{HashMap, HashSet};
 use std::thread;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use serde::Serialize;
+
+use bloom::device::wait_for_device;
 use bloom::errors::BloomError;
+use bloom::ipc::KillWho;
 use bloom::log::{FileLogger, ConsoleLogger};
+use bloom::status::{LogLevel, ServiceState};
+use bloom::time::{BootTime, BootTimes, ProcessTimer};
 
-use crate::loader::load_services;
-use crate::supervisor::Supervisor;
+use crate::cgroup::CgroupUsage;
+use crate::control::{parse_signal, reload_service};
+use crate::loader::{load_services, scan_services, SERVICE_DIR};
+use crate::logpump;
+use crate::ordering;
+use crate::parser;
+use crate::service::Service;
+use crate::persist;
+use crate::reexec::{self, SavedService};
+use crate::supervisor::{ServiceEvent, Supervisor};
 use crate::shutdown;
+use crate::timer::{Timer, TimerStatus};
+
+/// Resolution of the timer scheduler.
+const TIMER_TICK: Duration = Duration::from_secs(1);
+
+/// Quiet period after the last change in the service directory before reloading.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How often the service directory watcher checks for events.
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often boot ordering re-checks a dependency that has not reported ready.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Point-in-time view of one supervised service, returned over IPC.
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub desc: String,
+    pub startup: &'static str,
+    pub state: ServiceState,
+    pub pid: Option<u32>,
+    pub uptime_secs: Option<u64>,
+    pub restarts: u32,
+    pub last_exit: Option<i32>,
+    /// Result of the last health probe, if the service has one.
+    pub healthy: Option<bool>,
+    /// Whether the supervisor currently wants the service running.
+    pub wanted: bool,
+    pub enabled: bool,
+    pub masked: bool,
+    /// A daemon-reload changed the definition; it applies on restart.
+    pub needs_restart: bool,
+    /// Hit its start limit; `vctl reset-failed` allows starting it again.
+    pub start_limited: bool,
+    /// cgroup resource usage, while the service runs in its own cgroup.
+    pub usage: Option<CgroupUsage>,
+}
+
+impl ServiceStatus {
+    fn from_supervisor(sup: &Supervisor) -> Self {
+        Self {
+            name: sup.service.name.clone(),
+            desc: sup.service.desc.clone(),
+            startup: sup.service.startup.as_str(),
+            state: sup.service.state,
+            pid: sup.handle.as_ref().map(|h| h.pid()),
+            uptime_secs: sup.handle.as_ref().map(|h| h.start_time.elapsed().as_secs()),
+            restarts: sup.restart_count,
+            last_exit: sup.last_exit,
+            healthy: sup.health.as_ref().and_then(|h| h.last_passed),
+            wanted: sup.should_run,
+            enabled: sup.service.enabled,
+            masked: sup.service.masked,
+            needs_restart: sup.needs_restart(),
+            start_limited: sup.start_limited,
+            usage: sup.handle.as_ref().and_then(|h| h.cgroup.as_ref()).map(|cg| cg.usage()),
+        }
+    }
+}
+
+/// Outcome of a daemon-reload, returned over IPC.
+#[derive(Debug, Default, Serialize)]
+pub struct ReloadSummary {
+    pub added: Vec<String>,
+    /// Changed while stopped; the new definition is already in effect.
+    pub changed: Vec<String>,
+    /// Changed while running; the new definition applies on restart.
+    pub needs_restart: Vec<String>,
+    /// Loaded services whose file is gone. They are left as they are.
+    pub removed: Vec<String>,
+    /// Files that failed to parse.
+    pub errors: Vec<String>,
+}
+
+/// A slice of a service's captured output, returned over IPC.
+#[derive(Debug, Serialize)]
+pub struct LogChunk {
+    /// Lines in timestamp order, stdout and stderr merged.
+    pub lines: Vec<String>,
+    /// Where to continue reading each log file from on the next call.
+    pub cursors: Vec<u64>,
+}
+
+/// One service in a dependency tree, returned over IPC.
+#[derive(Debug, Serialize)]
+pub struct DependencyNode {
+    pub name: String,
+    /// How this service and its parent are related: `requires`, `wants`,
+    /// `binds-to` or `part-of`. Empty for the root.
+    pub relation: &'static str,
+    /// `None` when no service of that name is loaded.
+    pub state: Option<ServiceState>,
+    /// Already shown further up this branch, so not expanded again.
+    pub cycle: bool,
+    pub children: Vec<DependencyNode>,
+}
+
+/// Name, state and outgoing relations of a service, for building trees
+/// without holding any supervisor lock.
+struct GraphEntry {
+    name: String,
+    state: ServiceState,
+    relations: Vec<(&'static str, String)>,
+}
+
+impl GraphEntry {
+    fn new(service: &Service) -> Self {
+        let relations = [
+            ("requires", &service.dependencies),
+            ("wants", &service.wants),
+            ("binds-to", &service.binds_to),
+            ("part-of", &service.part_of),
+        ]
+        .into_iter()
+        .flat_map(|(relation, names)| names.iter().map(move |name| (relation, name.clone())))
+        .collect();
+
+        Self {
+            name: service.name.clone(),
+            state: service.state,
+            relations,
+        }
+    }
+}
+
+/// Boot progress shared by the per-service start threads. A service is
+/// pending until its thread finishes, successfully or not.
+struct BootBarrier {
+    state: Mutex<BootProgress>,
+    changed: Condvar,
+}
+
+struct BootProgress {
+    pending: HashSet<String>,
+    failed: HashSet<String>,
+}
+
+impl BootBarrier {
+    fn new(names: &[String]) -> Self {
+        Self {
+            state: Mutex::new(BootProgress {
+                pending: names.iter().cloned().collect(),
+                failed: HashSet::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn finish(&self, name: &str, up: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.pending.remove(name);
+        if !up {
+            state.failed.insert(name.to_string());
+        }
+        self.changed.notify_all();
+    }
+
+    /// Wait for `name` to settle if it is part of this boot. Returns false
+    /// only if it was booted and did not come up.
+    fn wait_for(&self, name: &str) -> bool {
+        let state = self.state.lock().unwrap();
+        let state = self.changed.wait_while(state, |s| s.pending.contains(name)).unwrap();
+        !state.failed.contains(name)
+    }
+}
 
 pub struct Manager {
-    supervisors: Vec<Arc<Mutex<Supervisor>>>,
+    supervisors: RwLock<Vec<Arc<Mutex<Supervisor>>>>,
+    /// `foo@.vs` templates without static instances, instantiated on demand.
+    templates: RwLock<Vec<Service>>,
+    /// Alias -> name of the service it stands for.
+    aliases: RwLock<HashMap<String, String>>,
     running: Arc<AtomicBool>,
+    /// Supervisors leave their services alone while set.
+    paused: Arc<AtomicBool>,
+    events_tx: Sender<ServiceEvent>,
+    events_rx: Mutex<Option<Receiver<ServiceEvent>>>,
+    timers: Mutex<Vec<Timer>>,
 }
 
 impl Manager {
     /// Takes both file logger and console logger.
     pub fn new(logger: &mut dyn FileLogger) -> Self {
         let (services, _loaded_count, _failed_count) = load_services(logger);
+        let (events_tx, events_rx): (Sender<ServiceEvent>, _) = channel();
+        let (templates, services): (Vec<_>, Vec<_>) = services.into_iter().partition(|s| s.is_template());
+
+        let timers = services
+            .iter()
+            .filter_map(|s| s.timer.clone().map(|spec| Timer::new(s.name.clone(), spec)))
+            .collect();
 
         let supervisors = services
             .into_iter()
-            .map(|service| Arc::new(Mutex::new(Supervisor::new(service))))
+            .map(|service| Arc::new(Mutex::new(Supervisor::new(service).with_events(events_tx.clone()))))
             .collect();
 
-        Self {
-            supervisors,
+        let manager = Self {
+            supervisors: RwLock::new(supervisors),
+            templates: RwLock::new(templates),
+            aliases: RwLock::new(HashMap::new()),
             running: Arc::new(AtomicBool::new(true)),
-        }
+            paused: Arc::new(AtomicBool::new(false)),
+            events_tx,
+            events_rx: Mutex::new(Some(events_rx)),
+            timers: Mutex::new(timers),
+        };
+        manager.rebuild_aliases();
+        manager
     }
 
     /// Starts supervising all services concurrently.
     pub fn start_all(&self) {
-        let running = self.running.clone();
-
-        for supervisor in &self.supervisors {
-            let sup = supervisor.clone();
-            let running = running.clone();
-
-            thread::spawn(move || {
-                let mut sup = sup.lock().unwrap();
-
-                // Run the supervise loop until manager is stopped
-                while running.load(Ordering::Relaxed) {
-                    if let Err(e) = sup.supervise_loop(running.clone()) {
-                        eprintln!("Supervisor error for {}: {:?}", sup.service.name, e);
-                    }
-                }
-
-                // On exit, ensure service is stopped cleanly
-                let _ = sup.stop();
-            });
+        for supervisor in &self.supervisors() {
+            self.spawn_supervisor(supervisor.clone());
         }
     }
 
     /// Starts only services whose startup package matches one in `allowed_startups`.
+    /// Independent services start in parallel; each one waits until everything
+    /// it is ordered after has come up (or failed) before starting. A service
+    /// is only started once everything it `requires` is running; if a
+    /// dependency fails, is missing or is part of a cycle, the dependent is
+    /// held back and the reason is logged.
+    /// `wants` only affect ordering: a missing or failed one is noted and the
+    /// dependent starts anyway.
+    /// Services outside those packages are still supervised so they can be
+    /// started on demand, but they stay stopped until asked for.
     /// Logs to both file and console loggers.
     pub fn start_startup_services(
         &self,
@@ -62,62 +264,950 @@ impl Manager {
         file_logger: &mut dyn FileLogger,
         console_logger: &mut dyn ConsoleLogger,
     ) {
-        let running = self.running.clone();
+        let mut log = |level: LogLevel, msg: &str| {
+            file_logger.log(level, msg);
+            console_logger.message(level, msg, Duration::from_secs(0));
+        };
 
         let mut matched_count = 0;
+        let mut boot_set = Vec::new();
+
+        for supervisor in &self.supervisors() {
+            let mut guard = supervisor.lock().unwrap();
+
+            // Held back until its dependencies are up
+            guard.should_run = false;
+
+            if !allowed_startups.contains(&guard.service.startup.as_str()) {
+                continue;
+            }
+
+            matched_count += 1;
+
+            if guard.service.masked {
+                log(LogLevel::Info, &format!("Skipping masked service '{}'", guard.service.name));
+            } else if guard.service.timer.is_some() {
+                log(LogLevel::Info, &format!("'{}' is started by its timer", guard.service.name));
+            } else if guard.service.enabled {
+                boot_set.push(guard.service.clone());
+            } else {
+                log(LogLevel::Info, &format!("Skipping disabled service '{}'", guard.service.name));
+            }
+        }
+
+        if matched_count == 0 {
+            for startup in allowed_startups {
+                log(LogLevel::Warn, &format!("No services found for startup package '{}'", startup));
+            }
+        }
+
+        let plan = ordering::resolve_order(&boot_set.iter().collect::<Vec<_>>());
+        let barrier = BootBarrier::new(&plan.order);
+
+        for name in &plan.cyclic {
+            log(LogLevel::Fail, &format!("Not starting '{}': dependency cycle", name));
+            barrier.finish(name, false);
+        }
+
+        // One thread per service; each blocks only on its own dependencies.
+        // Log lines are funnelled back here since the loggers are not shared.
+        let (log_tx, log_rx) = channel::<(LogLevel, String)>();
+        let activations = Mutex::new(Vec::new());
+
+        thread::scope(|scope| {
+            for service in plan.order.iter().filter_map(|name| boot_set.iter().find(|s| &s.name == name)) {
+                let log_tx = log_tx.clone();
+                let barrier = &barrier;
+                let activations = &activations;
+
+                scope.spawn(move || {
+                    let up = self.boot_service(service, barrier, &log_tx, activations);
+                    barrier.finish(&service.name, up);
+                });
+            }
+
+            drop(log_tx);
+            for (level, msg) in log_rx {
+                log(level, &msg);
+            }
+        });
+
+        // Added to what init recorded, for `vctl blame`
+        let mut boot_times = BootTimes::load();
+        boot_times.services = activations.into_inner().unwrap();
+        if let Err(e) = boot_times.save() {
+            log(LogLevel::Warn, &format!("Cannot record boot times: {}", e));
+        }
+
+        for supervisor in &self.supervisors() {
+            self.spawn_supervisor(supervisor.clone());
+        }
+    }
+
+    /// Boot a single service once everything it is ordered after has settled,
+    /// noting in `activations` how long it took to become ready.
+    /// Returns whether it came up, which is what its dependents wait on.
+    fn boot_service(
+        &self,
+        service: &Service,
+        barrier: &BootBarrier,
+        log_tx: &Sender<(LogLevel, String)>,
+        activations: &Mutex<Vec<BootTime>>,
+    ) -> bool {
+        let log = |level: LogLevel, msg: String| {
+            let _ = log_tx.send((level, msg));
+        };
+
+        let failed: HashSet<String> = service
+            .dependencies
+            .iter()
+            .chain(&service.wants)
+            .filter(|dep| !barrier.wait_for(dep))
+            .cloned()
+            .collect();
+
+        // Dependencies outside this boot may still be coming up on their own
+        for dep in service.dependencies.iter().chain(&service.wants) {
+            let Some(supervisor) = self.find(dep) else {
+                continue;
+            };
+
+            if supervisor.lock().unwrap().service.state == ServiceState::Starting {
+                log(LogLevel::Info, format!("'{}' waiting for '{}' to become ready", service.name, dep));
+                Self::wait_until_ready(&supervisor);
+            }
+        }
+
+        if let Some(reason) = self.unmet_dependency(service, &failed) {
+            log(LogLevel::Warn, format!("Not starting '{}': {}", service.name, reason));
+            return false;
+        }
+
+        for dep in &service.wants {
+            if let Some(reason) = self.dependency_down(dep, &failed) {
+                log(LogLevel::Info, format!("'{}' starting without wanted '{}': {}", service.name, dep, reason));
+            }
+        }
+
+        if !service.required_devices.is_empty() {
+            log(LogLevel::Info, format!("'{}' waiting for {}", service.name, service.required_devices.join(", ")));
+            if let Err(e) = wait_for_devices(service) {
+                log(LogLevel::Fail, format!("Not starting '{}': {}", service.name, e));
+                return false;
+            }
+        }
+
+        let mut msg = format!("Starting service '{}' in startup package '{}'", service.name, service.startup.as_str());
+        if !service.dependencies.is_empty() {
+            msg.push_str(&format!(" (after {})", service.dependencies.join(", ")));
+        }
+        log(LogLevel::Info, msg);
+
+        let Some(supervisor) = self.find(&service.name) else {
+            return false;
+        };
+
+        let timer = ProcessTimer::start();
+        {
+            let mut sup = supervisor.lock().unwrap();
+            sup.should_run = true;
+
+            if let Err(e) = sup.start() {
+                sup.should_run = false;
+                log(LogLevel::Fail, format!("Failed to start '{}': {}", service.name, e));
+                return false;
+            }
 
-        for supervisor in &self.supervisors {
-            let sup = supervisor.clone();
-            let startup_str = sup.lock().unwrap().service.startup.as_str();
+            if let Some(reason) = &sup.skip_reason {
+                log(LogLevel::Info, format!("Skipped '{}': {} not met", service.name, reason));
+                return true;
+            }
+        }
 
-            if allowed_startups.contains(&startup_str) {
-                matched_count += 1;
+        Self::wait_until_ready(&supervisor);
+        activations.lock().unwrap().push(BootTime::new(&service.name, timer.elapsed()));
 
-                // Log the matched service startup package to both loggers
-                let msg = format!("Starting service '{}' in startup package '{}'", sup.lock().unwrap().service.name, startup_str);
-                file_logger.log(bloom::status::LogLevel::Info, &msg);
-                console_logger.message(bloom::status::LogLevel::Info, &msg, std::time::Duration::from_secs(0));
+        let state = supervisor.lock().unwrap().service.state;
+        let up = matches!(state, ServiceState::Running | ServiceState::Degraded);
+        if !up {
+            log(LogLevel::Fail, format!("'{}' did not come up ({:?})", service.name, state));
+        }
+        up
+    }
 
-                let running = running.clone();
-                thread::spawn(move || {
-                    let mut sup = sup.lock().unwrap();
+    /// Block until a Starting service either reports ready or times out.
+    fn wait_until_ready(supervisor: &Arc<Mutex<Supervisor>>) {
+        loop {
+            {
+                let mut sup = supervisor.lock().unwrap();
+                if let Err(e) = sup.check_ready() {
+                    eprintln!("[verdantd] Readiness check failed for '{}': {}", sup.service.name, e);
+                }
+                if !sup.is_settling() {
+                    return;
+                }
+            }
 
-                    while running.load(Ordering::Relaxed) {
-                        if let Err(e) = sup.supervise_loop(running.clone()) {
-                            eprintln!("Supervisor error for {}: {:?}", sup.service.name, e);
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+
+    /// Why `service` cannot start yet, if any service it requires is not running.
+    fn unmet_dependency(&self, service: &Service, blocked: &HashSet<String>) -> Option<String> {
+        service
+            .dependencies
+            .iter()
+            .find_map(|dep| self.dependency_down(dep, blocked).map(|reason| format!("dependency {}", reason)))
+    }
+
+    /// Why `dep` cannot serve as a dependency right now, if it is not up.
+    fn dependency_down(&self, dep: &str, blocked: &HashSet<String>) -> Option<String> {
+        if blocked.contains(dep) {
+            return Some(format!("'{}' did not start", dep));
+        }
+
+        let Some(supervisor) = self.find(dep) else {
+            return Some(format!("'{}' does not exist", dep));
+        };
+
+        // A skipped dependency does not apply on this system, so it cannot block
+        let state = supervisor.lock().unwrap().service.state;
+        if !matches!(state, ServiceState::Running | ServiceState::Degraded | ServiceState::Skipped) {
+            return Some(format!("'{}' is {:?}, not running", dep, state));
+        }
+
+        None
+    }
+
+    fn spawn_supervisor(&self, supervisor: Arc<Mutex<Supervisor>>) {
+        let running = self.running.clone();
+        let paused = self.paused.clone();
+        thread::spawn(move || Supervisor::supervise_loop(supervisor, running, paused));
+    }
+
+    /// Stop or resume checking on services, restarting them and probing
+    /// their health, e.g. while the machine sleeps.
+    pub fn pause_supervision(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// Spawns the thread that reacts to supervisor lifecycle events,
+    /// propagating deaths along `binds_to` relationships and taking down
+    /// dependents once a service they require is gone for good.
+    pub fn start_event_loop(self: &Arc<Self>) {
+        let Some(events_rx) = self.events_rx.lock().unwrap().take() else {
+            return;
+        };
+
+        let manager = Arc::clone(self);
+        thread::spawn(move || {
+            for event in events_rx {
+                if !manager.running.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                match event {
+                    ServiceEvent::Exited { name, code } => {
+                        for dependent in manager.bound_to(&name) {
+                            println!(
+                                "[verdantd] Stopping '{}': bound service '{}' exited (code {:?})",
+                                dependent, name, code
+                            );
+                            if let Err(e) = manager.stop_service(&dependent) {
+                                eprintln!("[verdantd] Failed to stop '{}': {}", dependent, e);
+                            }
                         }
                     }
+                    ServiceEvent::Inactive { name } => {
+                        for dependent in manager.requiring(&name) {
+                            println!("[verdantd] Stopping '{}': required service '{}' is down", dependent, name);
+                            if let Err(e) = manager.stop_service(&dependent) {
+                                eprintln!("[verdantd] Failed to stop '{}': {}", dependent, e);
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
 
-                    let _ = sup.stop();
-                });
+    /// Spawns the scheduler thread that launches timer-activated services.
+    /// Runs even without timers, since a daemon-reload may add some.
+    pub fn start_timers(self: &Arc<Self>) {
+        let manager = Arc::clone(self);
+        thread::spawn(move || {
+            while manager.running.load(Ordering::Relaxed) {
+                let now = SystemTime::now();
+                let due: Vec<String> = manager
+                    .timers
+                    .lock()
+                    .unwrap()
+                    .iter_mut()
+                    .filter_map(|t| t.fire_if_due(now).then(|| t.service.clone()))
+                    .collect();
+
+                for name in due {
+                    println!("[verdantd] Timer elapsed, starting '{}'", name);
+                    if let Err(e) = manager.start_service(&name) {
+                        eprintln!("[verdantd] Timer failed to start '{}': {}", name, e);
+                    }
+                }
+
+                thread::sleep(TIMER_TICK);
             }
+        });
+    }
+
+    /// Watch the service directory and daemon-reload once `.vs` files stop
+    /// changing, so bursts from a package manager cause a single reload.
+    pub fn watch_services(self: &Arc<Self>) -> Result<(), BloomError> {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?;
+        inotify.add_watch(
+            SERVICE_DIR,
+            AddWatchFlags::IN_CREATE
+                | AddWatchFlags::IN_CLOSE_WRITE
+                | AddWatchFlags::IN_DELETE
+                | AddWatchFlags::IN_MOVED_TO
+                | AddWatchFlags::IN_MOVED_FROM,
+        )?;
+
+        let manager = Arc::clone(self);
+        thread::spawn(move || {
+            let mut last_change: Option<Instant> = None;
+
+            while manager.running.load(Ordering::Relaxed) {
+                match inotify.read_events() {
+                    Ok(events) => {
+                        let touches_service = events.iter().any(|e| {
+                            e.name
+                                .as_ref()
+                                .is_some_and(|n| Path::new(n).extension().is_some_and(|ext| ext == "vs"))
+                        });
+                        if touches_service {
+                            last_change = Some(Instant::now());
+                        }
+                    }
+                    Err(Errno::EAGAIN) => {}
+                    Err(e) => {
+                        eprintln!("[verdantd] Stopped watching {}: {}", SERVICE_DIR, e);
+                        return;
+                    }
+                }
+
+                if last_change.is_some_and(|t| t.elapsed() >= WATCH_DEBOUNCE) {
+                    last_change = None;
+
+                    match manager.reload_services() {
+                        Ok(summary) => println!(
+                            "[verdantd] Service files changed: {} added, {} changed, {} need restart, {} failed",
+                            summary.added.len(),
+                            summary.changed.len(),
+                            summary.needs_restart.len(),
+                            summary.errors.len()
+                        ),
+                        Err(e) => eprintln!("[verdantd] Automatic reload failed: {}", e),
+                    }
+                }
+
+                thread::sleep(WATCH_POLL_INTERVAL);
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Replace the timer for `service` if its schedule changed.
+    fn update_timer(&self, service: &Service) {
+        let mut timers = self.timers.lock().unwrap();
+
+        let current = timers.iter().find(|t| t.service == service.name).map(|t| &t.spec);
+        if current == service.timer.as_ref() {
+            return;
         }
 
-        if matched_count == 0 {
-            for startup in allowed_startups {
-                let msg = format!("No services found for startup package '{}'", startup);
-                file_logger.log(bloom::status::LogLevel::Warn, &msg);
-                console_logger.message(bloom::status::LogLevel::Warn, &msg, std::time::Duration::from_secs(0));
+        timers.retain(|t| t.service != service.name);
+        if let Some(spec) = &service.timer {
+            timers.push(Timer::new(service.name.clone(), spec.clone()));
+        }
+    }
+
+    /// Rescan the service directory: register new services (stopped) and
+    /// hand changed definitions to their supervisors. Running processes are
+    /// never touched.
+    pub fn reload_services(&self) -> Result<ReloadSummary, BloomError> {
+        let (services, errors) = scan_services()?;
+        let (templates, services): (Vec<_>, Vec<_>) = services.into_iter().partition(|s| s.is_template());
+
+        let mut summary = ReloadSummary {
+            errors,
+            ..Default::default()
+        };
+
+        *self.templates.write().unwrap() = templates;
+
+        for service in &services {
+            self.update_timer(service);
+
+            let Some(supervisor) = self.find(&service.name) else {
+                let mut sup = Supervisor::new(service.clone()).with_events(self.events_tx.clone());
+                sup.should_run = false;
+
+                let supervisor = Arc::new(Mutex::new(sup));
+                self.supervisors.write().unwrap().push(supervisor.clone());
+                self.spawn_supervisor(supervisor);
+
+                summary.added.push(service.name.clone());
+                continue;
+            };
+
+            let mut sup = supervisor.lock().unwrap();
+            if sup.update_definition(service.clone()) {
+                if sup.needs_restart() {
+                    summary.needs_restart.push(service.name.clone());
+                } else {
+                    summary.changed.push(service.name.clone());
+                }
+            }
+        }
+
+        self.rebuild_aliases();
+
+        let templates = self.templates.read().unwrap();
+        summary.removed = self.dependents_where(|s| {
+            let name = &s.service.name;
+            !services.iter().any(|svc| &svc.name == name)
+                && !templates.iter().any(|t| t.template_instance(name).is_some())
+        });
+
+        Ok(summary)
+    }
+
+    /// Next and last run of every timer.
+    pub fn list_timers(&self) -> Vec<TimerStatus> {
+        let now = SystemTime::now();
+        self.timers.lock().unwrap().iter().map(|t| t.status(now)).collect()
+    }
+
+    /// PIDs of supervised processes, launchers of `forking` services
+    /// included. Their supervisors reap them and record how they exited.
+    pub fn supervised_pids(&self) -> HashSet<u32> {
+        let mut pids = HashSet::new();
+
+        for supervisor in &self.supervisors() {
+            let Ok(sup) = supervisor.lock() else {
+                continue;
+            };
+            if let Some(handle) = &sup.handle {
+                pids.extend(handle.main_pid);
+                pids.extend(handle.child.as_ref().map(|c| c.id()));
+            }
+        }
+
+        pids
+    }
+
+    /// Snapshot of the supervisor list; instances may be added at runtime.
+    fn supervisors(&self) -> Vec<Arc<Mutex<Supervisor>>> {
+        self.supervisors.read().unwrap().clone()
+    }
+
+    /// Find a supervisor by name, instantiating a template for names like
+    /// `foo@bar` when no such service exists yet.
+    fn find_or_instantiate(&self, name: &str) -> Option<Arc<Mutex<Supervisor>>> {
+        if let Some(supervisor) = self.find(name) {
+            return Some(supervisor);
+        }
+
+        let mut service = self
+            .templates
+            .read()
+            .unwrap()
+            .iter()
+            .find_map(|t| t.template_instance(name).map(|inst| parser::instantiate(t, inst)))?;
+
+        service.enabled = persist::is_enabled(&service.name);
+        service.masked = persist::is_masked(&service.name);
+
+        let supervisor = {
+            let mut supervisors = self.supervisors.write().unwrap();
+
+            // Another request may have created it while we were unlocked
+            if let Some(existing) = supervisors
+                .iter()
+                .find(|sup| sup.lock().map(|s| s.service.name == name).unwrap_or(false))
+            {
+                return Some(existing.clone());
             }
+
+            let mut sup = Supervisor::new(service).with_events(self.events_tx.clone());
+            sup.should_run = false;
+
+            let supervisor = Arc::new(Mutex::new(sup));
+            supervisors.push(supervisor.clone());
+            supervisor
+        };
+
+        self.rebuild_aliases();
+        self.spawn_supervisor(supervisor.clone());
+        Some(supervisor)
+    }
+
+    /// Index the aliases of every supervised service. Names are unique
+    /// across services and aliases, which loading enforces.
+    fn rebuild_aliases(&self) {
+        let mut index = HashMap::new();
+
+        for supervisor in &self.supervisors() {
+            let sup = supervisor.lock().unwrap();
+            for alias in &sup.service.aliases {
+                index.entry(alias.clone()).or_insert_with(|| sup.service.name.clone());
+            }
+        }
+
+        *self.aliases.write().unwrap() = index;
+    }
+
+    /// The service name `name` stands for, which is itself unless it is an alias.
+    fn resolve(&self, name: &str) -> String {
+        self.aliases
+            .read()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn find(&self, name: &str) -> Option<Arc<Mutex<Supervisor>>> {
+        let name = self.resolve(name);
+
+        self.supervisors()
+            .iter()
+            .find(|sup| sup.lock().map(|s| s.service.name == name).unwrap_or(false))
+            .cloned()
+    }
+
+    /// Services that declare `binds_to` on `name`.
+    fn bound_to(&self, name: &str) -> Vec<String> {
+        self.dependents_where(|s| s.service.binds_to.iter().any(|b| b == name))
+    }
+
+    /// Services that `require` `name` and are currently wanted.
+    fn requiring(&self, name: &str) -> Vec<String> {
+        self.dependents_where(|s| s.should_run && s.service.dependencies.iter().any(|d| d == name))
+    }
+
+    /// Services that declare `part_of`, `binds_to` or `requires` on `name`;
+    /// these follow `name` when it is stopped or restarted.
+    fn propagation_targets(&self, name: &str) -> Vec<String> {
+        self.dependents_where(|s| {
+            s.service.part_of.iter().any(|p| p == name)
+                || s.service.binds_to.iter().any(|b| b == name)
+                || s.service.dependencies.iter().any(|d| d == name)
+        })
+    }
+
+    fn dependents_where(&self, predicate: impl Fn(&Supervisor) -> bool) -> Vec<String> {
+        self.supervisors()
+            .iter()
+            .filter_map(|sup| {
+                let sup = sup.lock().ok()?;
+                predicate(&sup).then(|| sup.service.name.clone())
+            })
+            .collect()
+    }
+
+    /// Start a single service on demand; `foo@bar` instantiates the
+    /// `foo@` template if needed. Refuses while a required service is down.
+    pub fn start_service(&self, name: &str) -> Result<(), BloomError> {
+        let name = &self.resolve(name);
+        let supervisor = self.find_or_instantiate(name).ok_or(BloomError::NotFound)?;
+
+        let service = supervisor.lock().unwrap().service.clone();
+        if let Some(reason) = self.unmet_dependency(&service, &HashSet::new()) {
+            return Err(BloomError::Custom(format!("Not starting '{}': {}", name, reason)));
+        }
+        wait_for_devices(&service).map_err(|e| BloomError::Custom(format!("Not starting '{}': {}", name, e)))?;
+
+        let mut sup = supervisor.lock().unwrap();
+
+        sup.should_run = true;
+        sup.reset_backoff();
+        sup.start()
+    }
+
+    /// Stop a service and everything that is `part_of`, `binds_to` or `requires` it.
+    pub fn stop_service(&self, name: &str) -> Result<(), BloomError> {
+        let name = &self.resolve(name);
+        let supervisor = self.find(name).ok_or(BloomError::NotFound)?;
+        let mut visited = HashSet::new();
+        visited.insert(name.to_string());
+
+        self.stop_propagated(name, &mut visited);
+
+        let mut sup = supervisor.lock().unwrap();
+        sup.stop()
+    }
+
+    /// Restart a service; `part_of`, `binds_to` and `requires` dependents are
+    /// restarted with it.
+    pub fn restart_service(&self, name: &str) -> Result<(), BloomError> {
+        let name = &self.resolve(name);
+        let supervisor = self.find_or_instantiate(name).ok_or(BloomError::NotFound)?;
+        let mut visited = HashSet::new();
+        visited.insert(name.to_string());
+
+        // Dependents go down first and come back up after their parent
+        let stopped = self.stop_propagated(name, &mut visited);
+
+        {
+            let mut sup = supervisor.lock().unwrap();
+            sup.stop()?;
+            sup.should_run = true;
+            sup.reset_backoff();
+            sup.start()?;
+        }
+
+        // Dependents that require it refuse to start until it is ready
+        if !stopped.is_empty() {
+            Self::wait_until_ready(&supervisor);
+        }
+
+        for dependent in stopped.iter().rev() {
+            if let Err(e) = self.start_service(dependent) {
+                eprintln!("[verdantd] Failed to restart '{}' after '{}': {}", dependent, name, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Switch to the target made of `packages`: stop every service outside
+    /// it, then start its enabled services in dependency order.
+    pub fn isolate(&self, packages: &[String]) -> Result<(), BloomError> {
+        let in_target = |sup: &Supervisor| packages.iter().any(|p| p == sup.service.startup.as_str());
+
+        for name in self.dependents_where(|s| !in_target(s) && (s.should_run || s.handle.is_some())) {
+            if let Err(e) = self.stop_service(&name) {
+                eprintln!("[verdantd] Failed to stop '{}' while isolating: {}", name, e);
+            }
+        }
+
+        let wanted: Vec<Service> = self
+            .supervisors()
+            .iter()
+            .filter_map(|sup| {
+                let sup = sup.lock().ok()?;
+                let wanted = in_target(&sup) && sup.service.enabled && sup.service.timer.is_none();
+                wanted.then(|| sup.service.clone())
+            })
+            .collect();
+
+        let plan = ordering::resolve_order(&wanted.iter().collect::<Vec<_>>());
+        let mut failed = plan.cyclic;
+
+        for name in &plan.order {
+            if let Err(e) = self.start_service(name) {
+                eprintln!("[verdantd] Failed to start '{}' while isolating: {}", name, e);
+                failed.push(name.clone());
+            } else if let Some(supervisor) = self.find(name) {
+                Self::wait_until_ready(&supervisor);
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(BloomError::Custom(format!("could not start {}", failed.join(", "))))
         }
     }
 
+    /// Reload a running service's configuration without restarting it.
+    pub fn reload_service(&self, name: &str) -> Result<(), BloomError> {
+        let supervisor = self.find(name).ok_or(BloomError::NotFound)?;
+        let (service, pid, previous) = supervisor.lock().unwrap().begin_reload()?;
+
+        // A reload_cmd may take a while; status and stop must not wait on it
+        let result = reload_service(&service, pid);
+
+        supervisor.lock().unwrap().finish_reload(previous, result.is_ok());
+        result
+    }
+
+    /// Send `signal` to `name`; returns how many processes received it.
+    pub fn kill_service(&self, name: &str, signal: &str, who: KillWho) -> Result<usize, BloomError> {
+        let signal = parse_signal(signal)?;
+        let supervisor = self.find(name).ok_or(BloomError::NotFound)?;
+        let mut sup = supervisor.lock().unwrap();
+        sup.kill(signal, who)
+    }
+
+    /// Recursively stop the propagation targets of `name`, deepest first.
+    /// Returns the names stopped, in the order they were stopped.
+    fn stop_propagated(&self, name: &str, visited: &mut HashSet<String>) -> Vec<String> {
+        let mut stopped = Vec::new();
+
+        for dependent in self.propagation_targets(name) {
+            if !visited.insert(dependent.clone()) {
+                continue;
+            }
+
+            stopped.extend(self.stop_propagated(&dependent, visited));
+
+            if let Some(sup) = self.find(&dependent) {
+                if let Err(e) = sup.lock().unwrap().stop() {
+                    eprintln!("[verdantd] Failed to stop '{}' (follows '{}'): {}", dependent, name, e);
+                }
+                stopped.push(dependent);
+            }
+        }
+
+        stopped
+    }
+
+    /// Clear the Failed state and start limit of `name`, or of every service.
+    /// Returns the services that were reset.
+    pub fn reset_failed(&self, name: Option<&str>) -> Result<Vec<String>, BloomError> {
+        let supervisors = match name {
+            Some(name) => vec![self.find(name).ok_or(BloomError::NotFound)?],
+            None => self.supervisors(),
+        };
+
+        Ok(supervisors
+            .iter()
+            .filter_map(|sup| {
+                let mut sup = sup.lock().unwrap();
+                sup.reset_failed().then(|| sup.service.name.clone())
+            })
+            .collect())
+    }
+
+    /// Persistently enable or disable a service for future boots.
+    /// Does not start or stop it now.
+    pub fn set_enabled(&self, name: &str, enabled: bool) -> Result<(), BloomError> {
+        let name = &self.resolve(name);
+        let supervisor = self.find(name).ok_or(BloomError::NotFound)?;
+
+        persist::set_enabled(name, enabled)?;
+        supervisor.lock().unwrap().service.enabled = enabled;
+
+        Ok(())
+    }
+
+    /// Persistently mask or unmask a service. Masking does not stop it, but
+    /// nothing can start it again (boot, dependents, timers or `vctl start`)
+    /// until it is unmasked.
+    pub fn set_masked(&self, name: &str, masked: bool) -> Result<(), BloomError> {
+        let name = &self.resolve(name);
+        let supervisor = self.find(name).ok_or(BloomError::NotFound)?;
+
+        persist::set_masked(name, masked)?;
+        supervisor.lock().unwrap().service.masked = masked;
+
+        Ok(())
+    }
+
+    /// Status of every supervised service, in load order.
+    pub fn status(&self) -> Vec<ServiceStatus> {
+        self.supervisors()
+            .iter()
+            .filter_map(|sup| sup.lock().ok().map(|s| ServiceStatus::from_supervisor(&s)))
+            .collect()
+    }
+
+    /// Status of a single service by name.
+    pub fn service_status(&self, name: &str) -> Option<ServiceStatus> {
+        let supervisor = self.find(name)?;
+        let sup = supervisor.lock().ok()?;
+        Some(ServiceStatus::from_supervisor(&sup))
+    }
+
+    /// Every effective setting of a service, as `vctl show` prints them.
+    pub fn service_properties(&self, name: &str) -> Option<Vec<(&'static str, String)>> {
+        let supervisor = self.find(name)?;
+        let sup = supervisor.lock().ok()?;
+        Some(sup.service.properties())
+    }
+
+    /// What `name` depends on, recursively, or with `reverse` everything
+    /// that depends on it.
+    pub fn dependency_tree(&self, name: &str, reverse: bool) -> Result<DependencyNode, BloomError> {
+        let name = self.find(name).ok_or(BloomError::NotFound)?.lock().unwrap().service.name.clone();
+
+        let graph: Vec<GraphEntry> = self
+            .supervisors()
+            .iter()
+            .map(|sup| GraphEntry::new(&sup.lock().unwrap().service))
+            .collect();
+
+        let mut path = Vec::new();
+        Ok(Self::dependency_node(&graph, &name, "", reverse, &mut path))
+    }
+
+    fn dependency_node(
+        graph: &[GraphEntry],
+        name: &str,
+        relation: &'static str,
+        reverse: bool,
+        path: &mut Vec<String>,
+    ) -> DependencyNode {
+        let entry = graph.iter().find(|e| e.name == name);
+        let cycle = path.iter().any(|n| n == name);
+
+        let mut node = DependencyNode {
+            name: name.to_string(),
+            relation,
+            state: entry.map(|e| e.state),
+            cycle,
+            children: Vec::new(),
+        };
+
+        if cycle {
+            return node;
+        }
+
+        let children: Vec<(&'static str, String)> = if reverse {
+            graph
+                .iter()
+                .flat_map(|e| e.relations.iter().filter(|(_, n)| n == name).map(|(r, _)| (*r, e.name.clone())))
+                .collect()
+        } else {
+            entry.map(|e| e.relations.clone()).unwrap_or_default()
+        };
+
+        path.push(name.to_string());
+        node.children = children
+            .into_iter()
+            .map(|(relation, child)| Self::dependency_node(graph, &child, relation, reverse, path))
+            .collect();
+        path.pop();
+
+        node
+    }
+
+    /// Captured output of a service: the last `lines` lines, or, with the
+    /// `cursors` of a previous call, whatever was written since.
+    pub fn service_logs(&self, name: &str, lines: usize, cursors: Option<Vec<u64>>) -> Result<LogChunk, BloomError> {
+        let supervisor = self.find(name).ok_or(BloomError::NotFound)?;
+        let service = supervisor.lock().unwrap().service.clone();
+
+        let mut paths: Vec<&String> = Vec::new();
+        for path in service.stdout.iter().chain(&service.stderr) {
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+
+        if paths.is_empty() {
+            return Err(BloomError::Custom(format!("'{}' does not capture its output", service.name)));
+        }
+
+        let mut chunk = LogChunk {
+            lines: Vec::new(),
+            cursors: Vec::new(),
+        };
+
+        for (i, path) in paths.iter().enumerate() {
+            let read = match cursors.as_ref().and_then(|c| c.get(i)) {
+                Some(offset) => logpump::read_since(path, *offset),
+                None => logpump::tail(path, lines),
+            };
+
+            // Nothing has been logged yet
+            let (mut file_lines, cursor) = match read {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Vec::new(), 0),
+                other => other?,
+            };
+
+            chunk.lines.append(&mut file_lines);
+            chunk.cursors.push(cursor);
+        }
+
+        // Lines start with their timestamp, so this interleaves the files
+        chunk.lines.sort();
+        if cursors.is_none() {
+            let skip = chunk.lines.len().saturating_sub(lines);
+            chunk.lines.drain(..skip);
+        }
+
+        Ok(chunk)
+    }
+
     /// Stops all supervisors and services cleanly.
     pub fn stop_all(&self) {
         self.running.store(false, Ordering::Relaxed);
 
-        for supervisor in &self.supervisors {
+        for supervisor in &self.supervisors() {
             if let Ok(mut sup) = supervisor.lock() {
                 let _ = sup.stop();
             }
         }
     }
 
+    /// Save supervisor state and replace this process with the verdantd
+    /// binary on disk. Services keep running and are adopted by the new
+    /// instance. Only returns if the exec failed.
+    pub fn reexec(&self) -> Result<(), BloomError> {
+        let supervisors = self.supervisors();
+
+        // Held until the exec so no supervisor acts on state already saved
+        let guards: Vec<_> = supervisors.iter().map(|sup| sup.lock().unwrap()).collect();
+        let saved: Vec<SavedService> = guards.iter().map(|sup| SavedService::from_supervisor(sup)).collect();
+
+        reexec::save_state(&saved)?;
+        let err = reexec::exec_self();
+
+        let _ = std::fs::remove_file(reexec::STATE_PATH);
+        Err(BloomError::Io(err))
+    }
+
+    /// Pick up state left by a previous instance that re-executed into us.
+    /// Returns the number of adopted processes, or None on a normal boot.
+    pub fn restore_state(&self) -> Option<usize> {
+        let saved = reexec::take_state()?;
+        let loaded = self.supervisors();
+
+        // Anything not in the saved state was not running before either
+        for supervisor in &loaded {
+            supervisor.lock().unwrap().should_run = false;
+        }
+
+        let mut adopted = 0;
+        for entry in saved {
+            let Some(supervisor) = self.find_or_instantiate(&entry.name) else {
+                eprintln!(
+                    "[verdantd] '{}' no longer exists; leaving PID {:?} unsupervised",
+                    entry.name, entry.pid
+                );
+                continue;
+            };
+
+            if entry.restore(&mut supervisor.lock().unwrap()) {
+                adopted += 1;
+            }
+        }
+
+        // Instances created above already have their supervisor thread
+        for supervisor in loaded {
+            self.spawn_supervisor(supervisor);
+        }
+
+        Some(adopted)
+    }
+
     /// Clean shutdown, waits for supervisors to stop and returns errors if any.
     pub fn shutdown_all_services(&self) -> Result<(), BloomError> {
         self.running.store(false, Ordering::Relaxed);
 
-        shutdown::shutdown_all(&self.supervisors)
+        shutdown::shutdown_all(&self.supervisors())
     }
 }
 
+/// Block until every `requires_device` of `service` exists, all within its
+/// `timeout_start`.
+fn wait_for_devices(service: &Service) -> Result<(), BloomError> {
+    let deadline = Instant::now() + service.timeout_start;
+    for spec in &service.required_devices {
+        wait_for_device(spec, deadline.saturating_duration_since(Instant::now()))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bloom::ipc::{send_ipc_request, IpcCommand, IpcRequest, IpcTarget, INIT_SOCKET_PATH};
+
+/// How often to check whether the network has come online.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before warning that network filesystems are still held back.
+const ONLINE_WARN_AFTER: Duration = Duration::from_secs(90);
+
+/// Wait in the background until the network is online, then have init mount
+/// the fstab entries it skipped at boot for lack of one.
+pub fn mount_when_online() {
+    thread::spawn(|| {
+        let started = Instant::now();
+        let mut warned = false;
+
+        while !network_online() {
+            if !warned && started.elapsed() >= ONLINE_WARN_AFTER {
+                warned = true;
+                eprintln!("[verdantd] Network still offline, network filesystems are not mounted yet");
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+
+        let request = IpcRequest {
+            id: 0,
+            target: IpcTarget::Init,
+            command: IpcCommand::MountNetworkFilesystems,
+        };
+
+        match send_ipc_request(INIT_SOCKET_PATH, &request) {
+            Ok(response) if response.success => {}
+            Ok(response) => eprintln!("[verdantd] {}", response.message),
+            Err(e) => eprintln!("[verdantd] Cannot ask init to mount network filesystems: {}", e),
+        }
+    });
+}
+
+/// The network-online milestone: a default route, IPv4 or IPv6, exists.
+pub fn network_online() -> bool {
+    // Iface Destination Gateway Flags ...; destination 0.0.0.0 and RTF_UP
+    let ipv4 = fs::read_to_string("/proc/net/route").is_ok_and(|routes| {
+        routes.lines().skip(1).any(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields.len() > 3
+                && fields[1] == "00000000"
+                && u32::from_str_radix(fields[3], 16).is_ok_and(|flags| flags & libc::RTF_UP as u32 != 0)
+        })
+    });
+
+    // Destination, prefix length, ..., device last; ::/0 off loopback
+    let ipv6 = fs::read_to_string("/proc/net/ipv6_route").is_ok_and(|routes| {
+        routes.lines().any(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields.len() == 10 && fields[0].bytes().all(|b| b == b'0') && fields[1] == "00" && fields[9] != "lo"
+        })
+    });
+
+    ipv4 || ipv6
+}
@@ -0,0 +1,71 @@
+use std::fs;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{chown, Gid, Uid};
+
+/// Directory holding one notification socket per `notify` service.
+const NOTIFY_DIR: &str = "/run/verdant/notify";
+
+/// Datagram socket a `notify` service reports its state on, following the
+/// sd_notify protocol: newline-separated `KEY=VALUE` pairs, READY=1 when up.
+#[derive(Debug)]
+pub struct NotifySocket {
+    socket: UnixDatagram,
+    path: PathBuf,
+    ready: bool,
+}
+
+impl NotifySocket {
+    /// Bind the socket for `service_name`, owned by `uid` and `gid` where set
+    /// so a service that drops privileges can still send to it.
+    pub fn bind(service_name: &str, uid: Option<Uid>, gid: Option<Gid>) -> io::Result<Self> {
+        fs::create_dir_all(NOTIFY_DIR)?;
+
+        let path = Path::new(NOTIFY_DIR).join(format!("{}.sock", service_name.replace('/', "_")));
+
+        // Clean up stale socket from a previous run
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+
+        let socket = UnixDatagram::bind(&path)?;
+        socket.set_nonblocking(true)?;
+        if uid.is_some() || gid.is_some() {
+            chown(&path, uid, gid)?;
+        }
+
+        Ok(Self {
+            socket,
+            path,
+            ready: false,
+        })
+    }
+
+    /// Value for the child's NOTIFY_SOCKET environment variable.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Drain pending messages and report whether READY=1 has been seen.
+    pub fn poll_ready(&mut self) -> bool {
+        let mut buf = [0u8; 4096];
+
+        while let Ok(len) = self.socket.recv(&mut buf) {
+            for line in String::from_utf8_lossy(&buf[..len]).lines() {
+                if line.trim() == "READY=1" {
+                    self.ready = true;
+                }
+            }
+        }
+
+        self.ready
+    }
+}
+
+impl Drop for NotifySocket {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
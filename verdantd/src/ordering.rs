@@ -0,0 +1,60 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::service::Service;
+
+/// Result of ordering a set of services by their `dependencies` and `wants`.
+#[derive(Debug, Default)]
+pub struct StartupOrder {
+    /// Service names, each after everything it depends on.
+    pub order: Vec<String>,
+    /// Services caught in (or behind) a dependency cycle; these cannot be started.
+    pub cyclic: Vec<String>,
+}
+
+/// Topologically sort `services` so dependencies come first.
+/// Ties keep the input order. Dependencies on services outside the set are
+/// ignored here and left for the caller to report.
+pub fn resolve_order(services: &[&Service]) -> StartupOrder {
+    let index: HashMap<&str, usize> = services
+        .iter()
+        .enumerate()
+        .map(|(i, s)| (s.name.as_str(), i))
+        .collect();
+
+    let mut in_degree = vec![0usize; services.len()];
+    let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); services.len()];
+
+    for (i, service) in services.iter().enumerate() {
+        for dep in service.dependencies.iter().chain(&service.wants) {
+            if let Some(&d) = index.get(dep.as_str()) {
+                in_degree[i] += 1;
+                dependents[d].push(i);
+            }
+        }
+    }
+
+    let mut queue: VecDeque<usize> = (0..services.len()).filter(|&i| in_degree[i] == 0).collect();
+    let mut result = StartupOrder::default();
+    let mut placed = vec![false; services.len()];
+
+    while let Some(i) = queue.pop_front() {
+        placed[i] = true;
+        result.order.push(services[i].name.clone());
+
+        for &dependent in &dependents[i] {
+            in_degree[dependent] -= 1;
+            if in_degree[dependent] == 0 {
+                queue.push_back(dependent);
+            }
+        }
+    }
+
+    result.cyclic = services
+        .iter()
+        .zip(placed)
+        .filter(|(_, placed)| !placed)
+        .map(|(s, _)| s.name.clone())
+        .collect();
+
+    result
+}
@@ -1,7 +1,17 @@
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::service::{Service, StartupPackage, RestartPolicy};
+use crate::service::{
+    DEFAULT_HEALTH_INTERVAL, DEFAULT_LOG_MAX_FILES, DEFAULT_RESTART_DELAY,
+    DEFAULT_START_LIMIT_INTERVAL, DEFAULT_TIMEOUT_START, IoClass, KillMode, ResourceLimit, SchedPolicy, Service, ServiceType, StartupPackage, RestartPolicy,
+};
+use nix::sys::resource::RLIM_INFINITY;
+use crate::capability::CapabilitySet;
+use crate::cgroup::{CgroupLimit, CgroupQuota};
+use crate::condition::Condition;
+use crate::timer::{parse_duration, Calendar, TimerSpec};
 use bloom::status::ServiceState;
 use bloom::errors::BloomError;
 
@@ -55,44 +65,235 @@ fn parse_quoted_args(s: &str) -> Vec<String> {
     args
 }
 
+/// Split a comma-separated value into trimmed, non-empty items.
+fn parse_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Comma-separated CPU numbers and ranges, e.g. `0-3, 6`.
+fn parse_cpu_list(s: &str) -> Result<Vec<usize>, BloomError> {
+    let invalid = || BloomError::Parse(format!("Invalid cpu_affinity: {s}"));
+    let mut cpus = Vec::new();
+
+    for item in parse_list(s) {
+        let (first, last) = item.split_once('-').unwrap_or((&item, &item));
+        let first: usize = first.trim().parse().map_err(|_| invalid())?;
+        let last: usize = last.trim().parse().map_err(|_| invalid())?;
+
+        if first > last || last >= libc::CPU_SETSIZE as usize {
+            return Err(invalid());
+        }
+        cpus.extend(first..=last);
+    }
+
+    Ok(cpus)
+}
+
+/// An integer key that must fall within `range`.
+fn parse_ranged<T>(key: &str, s: &str, range: std::ops::RangeInclusive<T>) -> Result<T, BloomError>
+where
+    T: std::str::FromStr + PartialOrd,
+{
+    s.parse::<T>()
+        .ok()
+        .filter(|v| range.contains(v))
+        .ok_or_else(|| BloomError::Parse(format!("Invalid {key}: {s}")))
+}
+
+/// `N`, `soft:hard` or `infinity`; a single value sets both soft and hard.
+fn parse_limit(key: &str, s: &str) -> Result<ResourceLimit, BloomError> {
+    let resource = key
+        .strip_prefix("limit_")
+        .and_then(ResourceLimit::resource_from_str)
+        .ok_or_else(|| BloomError::Parse(format!("Unknown key: {key}")))?;
+
+    let value = |v: &str| match v.trim() {
+        "infinity" | "unlimited" => Ok(RLIM_INFINITY),
+        v => v
+            .parse::<u64>()
+            .map_err(|_| BloomError::Parse(format!("Invalid value for {key}: {s}"))),
+    };
+
+    let (soft, hard) = match s.split_once(':') {
+        Some((soft, hard)) => (value(soft)?, value(hard)?),
+        None => (value(s)?, value(s)?),
+    };
+
+    if soft > hard {
+        return Err(BloomError::Parse(format!("{key}: soft limit exceeds hard limit")));
+    }
+
+    Ok(ResourceLimit { resource, soft, hard })
+}
+
+fn parse_bool(key: &str, s: &str) -> Result<bool, BloomError> {
+    match s.to_lowercase().as_str() {
+        "yes" | "true" | "on" | "1" => Ok(true),
+        "no" | "false" | "off" | "0" => Ok(false),
+        _ => Err(BloomError::Parse(format!("Invalid boolean for {key}: {s}"))),
+    }
+}
+
+/// A key whose values follow as `- item` lines.
+#[derive(Clone, Copy, PartialEq)]
+enum ListBlock {
+    Instances,
+    Cmd,
+}
+
+/// Parse a service file together with its drop-ins.
 pub fn parse_service_file(path: &str) -> Result<Vec<Service>, BloomError> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    parse_with_dropins(path, &dropin_files(path))
+}
+
+/// Drop-in files for the service file at `path`: every `*.conf` in
+/// `<path>.d/`, in name order.
+pub fn dropin_files(path: &str) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(format!("{}.d", path))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|p| p.extension().and_then(|e| e.to_str()) == Some("conf"))
+        .collect();
 
+    files.sort();
+    files
+}
+
+/// Parse `path` followed by `dropins`. A setting in a drop-in replaces the
+/// one from the file, lists included.
+pub fn parse_with_dropins(path: &str, dropins: &[PathBuf]) -> Result<Vec<Service>, BloomError> {
+    // Each line with the drop-in it came from, if any, and its line number
+    let mut lines: Vec<(Option<&Path>, usize, String)> = Vec::new();
+    for (source, file) in std::iter::once((None, Path::new(path))).chain(dropins.iter().map(|d| (Some(d.as_path()), d.as_path()))) {
+        for (index, line) in BufReader::new(File::open(file)?).lines().enumerate() {
+            lines.push((source, index + 1, line?));
+        }
+    }
+
+    // Errors name the line so `vctl verify` can point at it
+    let mut at = None;
+    parse_lines(&lines, &mut at).map_err(|e| match (e, at) {
+        (BloomError::Parse(msg), Some((None, number))) => BloomError::Parse(format!("line {}: {}", number, msg)),
+        (BloomError::Parse(msg), Some((Some(dropin), number))) => {
+            BloomError::Parse(format!("{} line {}: {}", dropin.display(), number, msg))
+        }
+        (other, _) => other,
+    })
+}
+
+/// Parse the lines of a service file and its drop-ins, leaving in `at` the
+/// file and line being parsed when an error occurs there.
+fn parse_lines<'a>(
+    lines: &[(Option<&'a Path>, usize, String)],
+    at: &mut Option<(Option<&'a Path>, usize)>,
+) -> Result<Vec<Service>, BloomError> {
     let mut name = None;
+    let mut aliases = Vec::new();
     let mut desc = None;
     let mut cmd = None;
     let mut args = Vec::new();
+    let mut cmd_list = Vec::new();
     let mut startup = None;
     let mut restart = None;
+    let mut restart_delay = None;
+    let mut start_limit_burst = None;
+    let mut start_limit_interval = None;
+    let mut service_type = None;
+    let mut timeout_start = None;
+    let mut remain_after_exit = false;
+    let mut capabilities = None;
+    let mut no_new_privs = false;
+    let mut quota = CgroupQuota::default();
+    let mut nice = None;
+    let mut cpu_affinity = Vec::new();
+    let mut sched_policy = None;
+    let mut sched_priority = None;
+    let mut io_class = None;
+    let mut io_priority = None;
+    let mut root_dir = None;
+    let mut root_bind = Vec::new();
+    let mut private_tmp = false;
+    let mut protect_system = false;
+    let mut pid_file = None;
+    let mut reload_cmd = None;
+    let mut stop_cmd = None;
+    let mut cleanup_cmd = None;
+    let mut kill_mode = None;
+    let mut health_cmd = None;
+    let mut health_interval = None;
+    let mut health_restart = false;
+    let mut timer = TimerSpec::default();
     let mut tags = Vec::new();
     let mut instances = Vec::new();
     let mut stdout: Option<String> = None;
     let mut stderr: Option<String> = None;
-    let mut in_instance_block = false;
+    let mut log_max_size = None;
+    let mut log_max_age = None;
+    let mut log_max_files = None;
+    let mut user = None;
+    let mut group = None;
+    let mut umask = None;
+    let mut working_dir = None;
+    let mut limits = Vec::new();
+    let mut conditions = Vec::new();
+    let mut dependencies = Vec::new();
+    let mut required_devices = Vec::new();
+    let mut wants = Vec::new();
+    let mut part_of = Vec::new();
+    let mut binds_to = Vec::new();
+    let mut list_block = None;
+    let mut current_source = None;
 
-    for line in reader.lines() {
-        let line = line?;
+    for (source, number, line) in lines {
+        *at = Some((*source, *number));
         let line = line.trim();
 
+        // Lists never continue into the next file
+        if *source != current_source {
+            current_source = *source;
+            list_block = None;
+        }
+
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
 
         if line.starts_with("instances:") {
-            in_instance_block = true;
+            if source.is_some() {
+                instances.clear();
+            }
+            list_block = Some(ListBlock::Instances);
             continue;
         }
 
-        if in_instance_block {
+        // A bare `cmd:` introduces a list of commands run in sequence
+        if line == "cmd:" {
+            if source.is_some() {
+                cmd = None;
+                args.clear();
+                cmd_list.clear();
+            }
+            list_block = Some(ListBlock::Cmd);
+            continue;
+        }
+
+        if let Some(block) = list_block {
             if line.starts_with('-') {
-                let value = line.trim_start_matches('-').trim().to_string();
+                let value = line.trim_start_matches('-').trim();
                 if !value.is_empty() {
-                    instances.push(value);
+                    match block {
+                        ListBlock::Instances => instances.push(value.to_string()),
+                        ListBlock::Cmd => cmd_list.push(parse_quoted_args(value)),
+                    }
                 }
                 continue;
             } else {
-                in_instance_block = false;
+                list_block = None;
             }
         }
 
@@ -100,58 +301,276 @@ pub fn parse_service_file(path: &str) -> Result<Vec<Service>, BloomError> {
             let key = key.trim();
             let val = val.trim();
 
+            // A plain cmd in a drop-in replaces a cmd list from the file
+            if source.is_some() && (key == "cmd" || key == "args") {
+                cmd_list.clear();
+            }
+
             match key {
                 "name" => name = Some(val.to_string()),
+                "aliases" => aliases = parse_list(val),
                 "desc" => desc = Some(val.to_string()),
                 "cmd" => cmd = Some(val.to_string()),
                 "args" => args = parse_quoted_args(val),
                 "startup" => startup = StartupPackage::from_str(val),
                 "restart" => restart = RestartPolicy::from_str(val),
-                "tags" => tags = val.split(',').map(|s| s.trim().to_string()).collect(),
+                "restart_delay" => restart_delay = Some(parse_duration(val)?),
+                "start_limit_burst" | "max_restarts" => start_limit_burst = Some(parse_ranged(key, val, 0..=u32::MAX)?),
+                "start_limit_interval" | "restart_window" => start_limit_interval = Some(parse_duration(val)?),
+                "type" => service_type = ServiceType::from_str(val),
+                "reload_cmd" => reload_cmd = Some(parse_quoted_args(val)).filter(|a| !a.is_empty()),
+                "cleanup_cmd" | "on_stop_cmd" => {
+                    cleanup_cmd = Some(parse_quoted_args(val)).filter(|a| !a.is_empty())
+                }
+                "kill_mode" => {
+                    kill_mode = Some(
+                        KillMode::from_str(val)
+                            .ok_or_else(|| BloomError::Parse(format!("Invalid kill_mode: {val}")))?,
+                    );
+                }
+                "stop_cmd" => stop_cmd = Some(parse_quoted_args(val)).filter(|a| !a.is_empty()),
+                "health_cmd" => health_cmd = Some(parse_quoted_args(val)).filter(|a| !a.is_empty()),
+                "health_interval" => health_interval = Some(parse_duration(val)?),
+                "health_restart" => health_restart = parse_bool(key, val)?,
+                "pid_file" => pid_file = Some(val.to_string()),
+                "remain_after_exit" => remain_after_exit = parse_bool(key, val)?,
+                "capabilities" => capabilities = Some(CapabilitySet::parse(&parse_list(val))?),
+                "no_new_privs" => no_new_privs = parse_bool(key, val)?,
+                "memory_max" => quota.memory_max = Some(CgroupLimit::parse(key, val, true)?),
+                "memory_high" => quota.memory_high = Some(CgroupLimit::parse(key, val, true)?),
+                "cpu_weight" => quota.cpu_weight = Some(parse_ranged(key, val, 1..=10_000)?),
+                "tasks_max" => quota.tasks_max = Some(CgroupLimit::parse(key, val, false)?),
+                "nice" => nice = Some(parse_ranged(key, val, -20..=19)?),
+                "cpu_affinity" => cpu_affinity = parse_cpu_list(val)?,
+                "sched_policy" => {
+                    sched_policy = Some(
+                        SchedPolicy::from_str(val)
+                            .ok_or_else(|| BloomError::Parse(format!("Invalid sched_policy: {val}")))?,
+                    );
+                }
+                "sched_priority" => sched_priority = Some(parse_ranged(key, val, 0..=99)?),
+                "io_class" => {
+                    io_class = Some(
+                        IoClass::from_str(val)
+                            .ok_or_else(|| BloomError::Parse(format!("Invalid io_class: {val}")))?,
+                    );
+                }
+                "io_priority" => io_priority = Some(parse_ranged(key, val, 0..=7)?),
+                "root_dir" => root_dir = Some(val.to_string()),
+                "root_bind" => root_bind = parse_list(val),
+                "private_tmp" => private_tmp = parse_bool(key, val)?,
+                "protect_system" => protect_system = parse_bool(key, val)?,
+                "timeout_start" => {
+                    let secs = val
+                        .parse::<u64>()
+                        .map_err(|_| BloomError::Parse(format!("Invalid timeout_start: {val}")))?;
+                    timeout_start = Some(Duration::from_secs(secs));
+                }
+                "tags" => tags = parse_list(val),
                 "stdout" => stdout = Some(val.to_string()),
                 "stderr" => stderr = Some(val.to_string()),
+                "log_max_size" => {
+                    log_max_size = match CgroupLimit::parse(key, val, true)? {
+                        CgroupLimit::Value(0) => return Err(BloomError::Parse(format!("Invalid {key}: {val}"))),
+                        CgroupLimit::Value(bytes) => Some(bytes),
+                        CgroupLimit::Max => None,
+                    };
+                }
+                "log_max_age" => log_max_age = Some(parse_duration(val)?),
+                "log_max_files" => log_max_files = Some(parse_ranged(key, val, 0..=1000)?),
+                "user" => user = Some(val.to_string()),
+                "group" => group = Some(val.to_string()),
+                "working_dir" => working_dir = Some(val.to_string()),
+                "umask" => {
+                    umask = Some(
+                        u32::from_str_radix(val, 8)
+                            .ok()
+                            .filter(|m| *m <= 0o777)
+                            .ok_or_else(|| BloomError::Parse(format!("Invalid umask: {val}")))?,
+                    );
+                }
+                "on_boot_sec" => timer.on_boot_sec = Some(parse_duration(val)?),
+                "on_unit_active_sec" => timer.on_unit_active_sec = Some(parse_duration(val)?),
+                "on_calendar" => timer.on_calendar = Some(Calendar::parse(val)?),
+                k if k.starts_with("limit_") => limits.push(parse_limit(k, val)?),
+                k if k.starts_with("condition_") => conditions.push(Condition::parse(k, val)?),
+                "requires" | "dependencies" => dependencies = parse_list(val),
+                "requires_device" => required_devices = parse_list(val),
+                "wants" => wants = parse_list(val),
+                "part_of" => part_of = parse_list(val),
+                "binds_to" => binds_to = parse_list(val),
 
                 _ => return Err(BloomError::Parse(format!("Unknown key: {key}"))),
             }
         }
     }
+    *at = None;
 
     let name = name.ok_or_else(|| BloomError::Parse("Missing name".into()))?;
+
+    // In list form the last command is the supervised one
+    let mut exec_steps = Vec::new();
+    if cmd_list.iter().any(Vec::is_empty) {
+        return Err(BloomError::Parse("Empty command in cmd list".into()));
+    }
+    if let Some(mut last) = cmd_list.pop() {
+        if cmd.is_some() || !args.is_empty() {
+            return Err(BloomError::Parse("A cmd list cannot be combined with cmd or args".into()));
+        }
+
+        args = last.split_off(1);
+        cmd = last.pop();
+        exec_steps = cmd_list;
+    }
+
     let cmd = cmd.ok_or_else(|| BloomError::Parse("Missing cmd".into()))?;
 
+    if service_type == Some(ServiceType::Forking) && pid_file.is_none() {
+        return Err(BloomError::Parse("type: forking requires pid_file".into()));
+    }
+
+    // Real-time policies need a priority of at least 1, the others exactly 0
+    if let Some(prio) = sched_priority
+        && sched_policy.is_some_and(|p| p.is_realtime()) != (prio > 0)
+    {
+        return Err(BloomError::Parse(format!("sched_priority {prio} does not fit the sched_policy")));
+    }
+
+    if !root_bind.is_empty() && root_dir.is_none() {
+        return Err(BloomError::Parse("root_bind requires root_dir".into()));
+    }
+
+    if aliases.contains(&name) {
+        return Err(BloomError::Parse(format!("'{name}' lists itself in aliases")));
+    }
+
     let base = Service {
         name,
+        aliases,
         desc: desc.unwrap_or_default(),
         cmd,
         args,
+        exec_steps,
         startup: startup.unwrap_or(StartupPackage::Custom),
         restart: restart.unwrap_or(RestartPolicy::Never),
+        restart_delay: restart_delay.unwrap_or(DEFAULT_RESTART_DELAY),
+        start_limit_burst: start_limit_burst.unwrap_or(0),
+        start_limit_interval: start_limit_interval.unwrap_or(DEFAULT_START_LIMIT_INTERVAL),
+        service_type: service_type.unwrap_or(ServiceType::Simple),
+        timeout_start: timeout_start.unwrap_or(DEFAULT_TIMEOUT_START),
+        reload_cmd,
+        stop_cmd,
+        cleanup_cmd,
+        kill_mode: kill_mode.unwrap_or(KillMode::ControlGroup),
+        health_cmd,
+        health_interval: health_interval.unwrap_or(DEFAULT_HEALTH_INTERVAL),
+        health_restart,
+        pid_file,
+        remain_after_exit,
+        capabilities,
+        no_new_privs,
+        quota,
+        nice,
+        cpu_affinity,
+        sched_policy,
+        sched_priority,
+        io_class,
+        io_priority,
+        root_dir,
+        root_bind,
+        private_tmp,
+        protect_system,
         tags,
         instances: vec![],
         state: ServiceState::Stopped,
         stdout,
         stderr,
+        log_max_size,
+        log_max_age,
+        log_max_files: log_max_files.unwrap_or(DEFAULT_LOG_MAX_FILES),
+        user,
+        group,
+        umask,
+        working_dir,
+        limits,
+        dependencies,
+        required_devices,
+        wants,
+        part_of,
+        binds_to,
+        conditions,
+        timer: (!timer.is_empty()).then_some(timer),
+        enabled: true,
+        masked: false,
     };
 
-    // If instances were defined, create one service per instance with `{}` replaced
+    // If instances were defined, create one service per instance
     if !instances.is_empty() {
-        let mut expanded = Vec::new();
-        for inst in instances {
-            let svc = Service {
-                name: base.name.replace("{}", &inst),
-                desc: base.desc.replace("{}", &inst),
-                cmd: base.cmd.replace("{}", &inst),
-                args: base.args.iter().map(|a| a.replace("{}", &inst)).collect(),
-                stdout: base.stdout.as_ref().map(|s| s.replace("{}", &inst)),
-                stderr: base.stderr.as_ref().map(|s| s.replace("{}", &inst)),
-                instances: vec![inst.clone()],
-                ..base.clone()
-            };
-            expanded.push(svc);
-        }
-        Ok(expanded)
+        Ok(instances.iter().map(|inst| instantiate(&base, inst)).collect())
     } else {
         Ok(vec![base])
     }
 }
 
+/// Expand a template for one instance, replacing `{}` and `%i` in every
+/// field that may refer to it.
+pub fn instantiate(template: &Service, inst: &str) -> Service {
+    let sub = |s: &str| s.replace("{}", inst).replace("%i", inst);
+    let sub_all = |list: &[String]| list.iter().map(|s| sub(s)).collect::<Vec<_>>();
+
+    Service {
+        name: sub(&template.name),
+        aliases: sub_all(&template.aliases),
+        desc: sub(&template.desc),
+        cmd: sub(&template.cmd),
+        args: sub_all(&template.args),
+        exec_steps: template.exec_steps.iter().map(|step| sub_all(step)).collect(),
+        stdout: template.stdout.as_deref().map(sub),
+        stderr: template.stderr.as_deref().map(sub),
+        working_dir: template.working_dir.as_deref().map(sub),
+        root_dir: template.root_dir.as_deref().map(sub),
+        reload_cmd: template.reload_cmd.as_deref().map(sub_all),
+        stop_cmd: template.stop_cmd.as_deref().map(sub_all),
+        cleanup_cmd: template.cleanup_cmd.as_deref().map(sub_all),
+        health_cmd: template.health_cmd.as_deref().map(sub_all),
+        pid_file: template.pid_file.as_deref().map(sub),
+        dependencies: sub_all(&template.dependencies),
+        required_devices: sub_all(&template.required_devices),
+        wants: sub_all(&template.wants),
+        part_of: sub_all(&template.part_of),
+        binds_to: sub_all(&template.binds_to),
+        conditions: template
+            .conditions
+            .iter()
+            .map(|c| match c {
+                Condition::PathExists { path, negate } => Condition::PathExists { path: sub(path), negate: *negate },
+                other => other.clone(),
+            })
+            .collect(),
+        instances: vec![inst.to_string()],
+        ..template.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(parse_quoted_args("  -i  eth0 -q "), ["-i", "eth0", "-q"]);
+        assert!(parse_quoted_args("").is_empty());
+    }
+
+    #[test]
+    fn keeps_quoted_arguments_whole() {
+        assert_eq!(parse_quoted_args(r#"-c "echo hello world""#), ["-c", "echo hello world"]);
+        assert_eq!(parse_quoted_args("--name 'my service'"), ["--name", "my service"]);
+        assert_eq!(parse_quoted_args(r#"'say "hi"' "it's""#), [r#"say "hi""#, "it's"]);
+    }
+
+    #[test]
+    fn empty_quotes_are_an_argument() {
+        assert_eq!(parse_quoted_args(r#"--prefix "" -v"#), ["--prefix", "", "-v"]);
+    }
+}
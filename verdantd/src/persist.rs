@@ -0,0 +1,49 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A service is disabled when a marker file with its name exists here.
+/// Absence means enabled, so new services start at boot without extra setup.
+const DISABLED_DIR: &str = "/etc/verdant/services-disabled";
+
+/// A masked service has a marker here and cannot be started by anything.
+const MASKED_DIR: &str = "/etc/verdant/services-masked";
+
+fn marker_path(dir: &str, name: &str) -> PathBuf {
+    // Instance names like `tty@tty1` are safe file names; slashes are not
+    Path::new(dir).join(name.replace('/', "_"))
+}
+
+/// True unless the service has been disabled with `vctl disable`.
+pub fn is_enabled(name: &str) -> bool {
+    !marker_path(DISABLED_DIR, name).exists()
+}
+
+/// Persistently mark a service as enabled or disabled.
+pub fn set_enabled(name: &str, enabled: bool) -> io::Result<()> {
+    set_marker(DISABLED_DIR, name, !enabled)
+}
+
+/// True if the service has been masked with `vctl mask`.
+pub fn is_masked(name: &str) -> bool {
+    marker_path(MASKED_DIR, name).exists()
+}
+
+/// Persistently mask or unmask a service.
+pub fn set_masked(name: &str, masked: bool) -> io::Result<()> {
+    set_marker(MASKED_DIR, name, masked)
+}
+
+fn set_marker(dir: &str, name: &str, present: bool) -> io::Result<()> {
+    let marker = marker_path(dir, name);
+
+    if present {
+        fs::create_dir_all(dir)?;
+        fs::write(&marker, b"")
+    } else {
+        match fs::remove_file(&marker) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
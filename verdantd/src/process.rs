@@ -0,0 +1,525 @@
+use std::ffi::CString;
+use std::fs;
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command};
+
+use bloom::errors::BloomError;
+use bloom::locale::{read_locale_conf, LOCALE_VARS};
+use nix::sys::resource::setrlimit;
+use nix::unistd::{getgrouplist, Gid, Group, Uid, User};
+
+use crate::capability::{self, CapabilitySet};
+use crate::cgroup::ServiceCgroup;
+use crate::logpump::{self, LogFile, Rotation, Stream};
+use crate::service::{IoClass, Service};
+
+/// Build the command line and stdio redirections for a service.
+fn build_command(service: &Service, program: &str, args: &[String]) -> Result<Command, BloomError> {
+    let mut cmd = Command::new(program);
+    if !args.is_empty() {
+        cmd.args(args);
+    }
+
+    // The locale is read afresh so a changed locale.conf applies on restart;
+    // without one the locale verdantd inherited from init stays
+    if let Ok(locale) = read_locale_conf() {
+        for name in LOCALE_VARS {
+            cmd.env_remove(name);
+        }
+        cmd.envs(locale);
+    }
+
+    // Out of verdantd's process group, which autofs lets past automount traps
+    cmd.process_group(0);
+
+    // Output goes through pipes so each line can be timestamped
+    let rotation = Rotation::for_service(service);
+
+    if let Some(ref path) = service.stdout {
+        let log = LogFile::open(path, rotation)?;
+        cmd.stdout(logpump::pipe(&service.name, Stream::Stdout, log)?);
+    }
+
+    if let Some(ref path) = service.stderr {
+        let log = LogFile::open(path, rotation)?;
+        cmd.stderr(logpump::pipe(&service.name, Stream::Stderr, log)?);
+    }
+
+    // Inside a root_dir the sandbox changes directory after the chroot
+    if let Some(ref dir) = service.working_dir
+        && service.root_dir.is_none()
+    {
+        cmd.current_dir(dir);
+    }
+
+    Ok(cmd)
+}
+
+/// Identity to switch to in the child, resolved in the parent so the
+/// child only has to make plain syscalls between fork and exec.
+struct Credentials {
+    uid: Option<Uid>,
+    gid: Option<Gid>,
+    /// Raw ids so the child does not need to allocate.
+    groups: Vec<libc::gid_t>,
+}
+
+fn resolve_credentials(service: &Service, cmd: &mut Command) -> Result<Option<Credentials>, BloomError> {
+    if service.user.is_none() && service.group.is_none() {
+        return Ok(None);
+    }
+
+    let (user, gid) = lookup_identity(service)?;
+    let mut groups = Vec::new();
+
+    if let Some(user) = &user {
+        let name = CString::new(user.name.as_str())
+            .map_err(|_| BloomError::Custom(format!("Invalid user name '{}'", user.name)))?;
+        groups = getgrouplist(&name, gid.unwrap_or(user.gid))?
+            .into_iter()
+            .map(|g| g.as_raw())
+            .collect();
+
+        cmd.env("USER", &user.name)
+            .env("LOGNAME", &user.name)
+            .env("HOME", &user.dir);
+    }
+
+    Ok(Some(Credentials {
+        uid: user.map(|u| u.uid),
+        gid,
+        groups,
+    }))
+}
+
+/// The user and group a service runs as, if it sets either.
+fn lookup_identity(service: &Service) -> Result<(Option<User>, Option<Gid>), BloomError> {
+    let user = match &service.user {
+        Some(name) => Some(
+            User::from_name(name)?
+                .ok_or_else(|| BloomError::Custom(format!("User '{}' does not exist", name)))?,
+        ),
+        None => None,
+    };
+
+    let gid = match &service.group {
+        Some(name) => Some(
+            Group::from_name(name)?
+                .ok_or_else(|| BloomError::Custom(format!("Group '{}' does not exist", name)))?
+                .gid,
+        ),
+        None => user.as_ref().map(|u| u.gid),
+    };
+
+    Ok((user, gid))
+}
+
+/// Owner for what a service has to write to outside its own process, such
+/// as its notify socket: the service's uid and gid, or None where it does
+/// not change them.
+pub fn service_owner(service: &Service) -> Result<(Option<Uid>, Option<Gid>), BloomError> {
+    let (user, gid) = lookup_identity(service)?;
+    Ok((user.map(|u| u.uid), gid))
+}
+
+/// Everything the child applies while dropping privileges, prepared in the parent.
+struct Privileges {
+    creds: Option<Credentials>,
+    umask: Option<u32>,
+    /// Capabilities to keep, with the highest capability the kernel knows.
+    capabilities: Option<(CapabilitySet, u32)>,
+    no_new_privs: bool,
+}
+
+/// Apply umask, restrict capabilities and drop privileges in the child.
+fn drop_privileges(privileges: &Privileges) -> std::io::Result<()> {
+    let check = |ret: libc::c_int| {
+        if ret != 0 {
+            Err(std::io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    };
+
+    // Still root here, so the bounding set can be narrowed
+    if let Some((caps, last_cap)) = &privileges.capabilities {
+        caps.restrict_bounding_set(*last_cap)?;
+    }
+
+    unsafe {
+        if let Some(mask) = privileges.umask {
+            libc::umask(mask as libc::mode_t);
+        }
+
+        if let Some(creds) = &privileges.creds {
+            // Groups first: once the uid is dropped we no longer may change them
+            if let Some(gid) = creds.gid {
+                check(libc::setgroups(creds.groups.len(), creds.groups.as_ptr()))?;
+                check(libc::setgid(gid.as_raw()))?;
+            }
+
+            if let Some(uid) = creds.uid {
+                check(libc::setuid(uid.as_raw()))?;
+            }
+        }
+    }
+
+    if let Some((caps, last_cap)) = &privileges.capabilities {
+        caps.apply(*last_cap)?;
+    }
+
+    if privileges.no_new_privs {
+        capability::set_no_new_privs()?;
+    }
+
+    Ok(())
+}
+
+/// `ioprio_set` target: a single process, by PID.
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/// CPU and I/O scheduling for the child, prepared in the parent.
+struct Scheduling {
+    nice: Option<i32>,
+    affinity: Option<libc::cpu_set_t>,
+    /// Policy and static priority.
+    policy: Option<(libc::c_int, libc::c_int)>,
+    ioprio: Option<libc::c_int>,
+}
+
+impl Scheduling {
+    fn for_service(service: &Service) -> Option<Self> {
+        let affinity = (!service.cpu_affinity.is_empty()).then(|| unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            for cpu in &service.cpu_affinity {
+                libc::CPU_SET(*cpu, &mut set);
+            }
+            set
+        });
+
+        let policy = service.sched_policy.map(|p| {
+            // Real-time policies default to the lowest real-time priority
+            let default = if p.is_realtime() { 1 } else { 0 };
+            (p.as_raw(), service.sched_priority.unwrap_or(default))
+        });
+
+        let ioprio = service.io_class.map(|class| {
+            let level = match class {
+                IoClass::Idle => 0,
+                _ => service.io_priority.unwrap_or(4) as libc::c_int,
+            };
+            (class.as_raw() << IOPRIO_CLASS_SHIFT) | level
+        });
+
+        let scheduling = Self {
+            nice: service.nice,
+            affinity,
+            policy,
+            ioprio,
+        };
+
+        let any = scheduling.nice.is_some()
+            || scheduling.affinity.is_some()
+            || scheduling.policy.is_some()
+            || scheduling.ioprio.is_some();
+        any.then_some(scheduling)
+    }
+
+    fn apply(&self) -> std::io::Result<()> {
+        let check = |ret: libc::c_int| {
+            if ret != 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+
+        unsafe {
+            if let Some(set) = &self.affinity {
+                check(libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), set))?;
+            }
+
+            if let Some((policy, priority)) = self.policy {
+                let param = libc::sched_param { sched_priority: priority };
+                check(libc::sched_setscheduler(0, policy, &param))?;
+            }
+
+            if let Some(nice) = self.nice {
+                check(libc::setpriority(libc::PRIO_PROCESS, 0, nice))?;
+            }
+
+            if let Some(ioprio) = self.ioprio
+                && libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) != 0
+            {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Directories made read-only by `protect_system`.
+const PROTECTED_DIRS: [&std::ffi::CStr; 2] = [c"/usr", c"/etc"];
+
+/// Mount namespace setup for a service, prepared in the parent so the child
+/// only makes plain syscalls.
+struct Sandbox {
+    private_tmp: bool,
+    protect_system: bool,
+    root_dir: Option<CString>,
+    /// Host paths and where they are bind-mounted inside `root_dir`.
+    binds: Vec<(CString, CString)>,
+    /// Directory to enter after the chroot.
+    cwd: CString,
+}
+
+impl Sandbox {
+    fn for_service(service: &Service) -> Result<Option<Self>, BloomError> {
+        if !service.private_tmp && !service.protect_system && service.root_dir.is_none() {
+            return Ok(None);
+        }
+
+        let cstring = |s: &str| {
+            CString::new(s).map_err(|_| BloomError::Custom(format!("Invalid path '{}'", s)))
+        };
+
+        let mut binds = Vec::new();
+
+        if let Some(root) = &service.root_dir {
+            for path in &service.root_bind {
+                let target = Path::new(root).join(path.trim_start_matches('/'));
+
+                // The mount point has to exist inside the new root
+                fs::create_dir_all(&target)?;
+                binds.push((cstring(path)?, cstring(&target.to_string_lossy())?));
+            }
+        }
+
+        Ok(Some(Self {
+            private_tmp: service.private_tmp,
+            protect_system: service.protect_system,
+            root_dir: service.root_dir.as_deref().map(cstring).transpose()?,
+            binds,
+            cwd: cstring(service.working_dir.as_deref().unwrap_or("/"))?,
+        }))
+    }
+
+    /// Move the child into a private mount namespace, bind the requested
+    /// paths into `root_dir` and chroot there, then set up `private_tmp` and
+    /// `protect_system` inside it. Nothing mounted here leaks back to the host.
+    fn enter(&self) -> std::io::Result<()> {
+        let check = |ret: libc::c_int| {
+            if ret != 0 {
+                Err(std::io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+
+        unsafe {
+            check(libc::unshare(libc::CLONE_NEWNS))?;
+
+            // Stop mount events propagating back to the host namespace
+            check(libc::mount(
+                std::ptr::null(),
+                c"/".as_ptr(),
+                std::ptr::null(),
+                libc::MS_REC | libc::MS_PRIVATE,
+                std::ptr::null(),
+            ))?;
+
+            if let Some(root) = &self.root_dir {
+                for (source, target) in &self.binds {
+                    check(libc::mount(
+                        source.as_ptr(),
+                        target.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        std::ptr::null(),
+                    ))?;
+                }
+
+                check(libc::chroot(root.as_ptr()))?;
+                check(libc::chdir(c"/".as_ptr()))?;
+            }
+
+            // From here on paths resolve inside root_dir, if there is one
+            if self.private_tmp {
+                check(libc::mount(
+                    c"tmpfs".as_ptr(),
+                    c"/tmp".as_ptr(),
+                    c"tmpfs".as_ptr(),
+                    libc::MS_NOSUID | libc::MS_NODEV,
+                    c"mode=1777".as_ptr().cast(),
+                ))?;
+            }
+
+            if self.protect_system {
+                for dir in PROTECTED_DIRS {
+                    // A bind mount onto itself can then be remounted read-only
+                    check(libc::mount(
+                        dir.as_ptr(),
+                        dir.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REC,
+                        std::ptr::null(),
+                    ))?;
+                    check(libc::mount(
+                        std::ptr::null(),
+                        dir.as_ptr(),
+                        std::ptr::null(),
+                        libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                        std::ptr::null(),
+                    ))?;
+                }
+            }
+
+            if self.root_dir.is_some() {
+                check(libc::chdir(self.cwd.as_ptr()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn register_privileges(service: &Service, cmd: &mut Command, creds: Option<Credentials>) {
+    let privileges = Privileges {
+        creds,
+        umask: service.umask,
+        capabilities: service.capabilities.map(|caps| (caps, CapabilitySet::last_cap())),
+        no_new_privs: service.no_new_privs,
+    };
+
+    if privileges.creds.is_some()
+        || privileges.umask.is_some()
+        || privileges.capabilities.is_some()
+        || privileges.no_new_privs
+    {
+        unsafe {
+            cmd.pre_exec(move || drop_privileges(&privileges));
+        }
+    }
+}
+
+/// Command for a helper such as `stop_cmd`, run the way the main process
+/// is: same user, environment, root and working directory, same log files.
+/// It stays outside the service's cgroup so stopping the service does not
+/// take the helper down with it.
+pub fn helper_command(service: &Service, argv: &[String]) -> Result<Command, BloomError> {
+    let (program, args) = argv
+        .split_first()
+        .ok_or_else(|| BloomError::Custom(format!("Empty helper command for '{}'", service.name)))?;
+
+    let mut cmd = build_command(service, program, args)?;
+    let creds = resolve_credentials(service, &mut cmd)?;
+
+    if let Some(sandbox) = Sandbox::for_service(service)? {
+        unsafe {
+            cmd.pre_exec(move || sandbox.enter());
+        }
+    }
+
+    register_privileges(service, &mut cmd, creds);
+
+    Ok(cmd)
+}
+
+/// Spawn the service's main process inside its own cgroup.
+/// Without cgroup2 the service still starts, but only the main PID can be
+/// tracked and signalled. `notify_socket` is exported as NOTIFY_SOCKET.
+pub fn spawn(
+    service: &Service,
+    notify_socket: Option<&Path>,
+) -> Result<(Child, Option<ServiceCgroup>), BloomError> {
+    let mut cmd = build_command(service, &service.cmd, &service.args)?;
+
+    if let Some(path) = notify_socket {
+        cmd.env("NOTIFY_SOCKET", path);
+    }
+
+    let creds = resolve_credentials(service, &mut cmd)?;
+
+    let cgroup = match ServiceCgroup::create(&service.name) {
+        Ok(cgroup) => Some(cgroup),
+        Err(e) => {
+            eprintln!("[verdantd] No cgroup for '{}', tracking main PID only: {}", service.name, e);
+            None
+        }
+    };
+
+    // A quota that cannot be enforced is a configuration error, not a hint
+    if let Some(cgroup) = &cgroup {
+        cgroup.apply_quota(&service.quota).map_err(|e| {
+            BloomError::Custom(format!("Cannot apply cgroup limits for '{}': {}", service.name, e))
+        })?;
+    } else if !service.quota.is_empty() {
+        return Err(BloomError::Custom(format!(
+            "'{}' sets cgroup limits but cgroup2 is not available",
+            service.name
+        )));
+    }
+
+    // Opened before fork so the child only needs an async-signal-safe write()
+    let procs = match cgroup.as_ref().map(|cg| cg.procs_file()).transpose() {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("[verdantd] Cannot join cgroup for '{}': {}", service.name, e);
+            None
+        }
+    };
+
+    if let Some(ref procs) = procs {
+        let fd = procs.as_raw_fd();
+        unsafe {
+            cmd.pre_exec(move || {
+                // "0" means the writing process itself
+                if libc::write(fd, b"0".as_ptr().cast(), 1) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Before dropping privileges, so hard limits can still be raised
+    if !service.limits.is_empty() {
+        let limits = service.limits.clone();
+        unsafe {
+            cmd.pre_exec(move || {
+                for limit in &limits {
+                    setrlimit(limit.resource, limit.soft, limit.hard)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Raising priority needs root as well
+    if let Some(scheduling) = Scheduling::for_service(service) {
+        unsafe {
+            cmd.pre_exec(move || scheduling.apply());
+        }
+    }
+
+    // Mounting needs root, so this too runs before credentials are dropped
+    if let Some(sandbox) = Sandbox::for_service(service)? {
+        unsafe {
+            cmd.pre_exec(move || sandbox.enter());
+        }
+    }
+
+    // Registered after the cgroup hook: joining needs root
+    register_privileges(service, &mut cmd, creds);
+
+    let child = cmd.spawn().map_err(BloomError::Io)?;
+
+    // Only track the cgroup if the child was actually placed in it
+    let cgroup = if procs.is_some() { cgroup } else { None };
+
+    Ok((child, cgroup))
+}
@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::manager::Manager;
+
+/// How often to look for exited processes nobody is waiting for.
+const REAP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a zombie is left for whoever spawned it to collect. Supervisors
+/// and health checks poll every couple of seconds; past this it is an orphan.
+const REAP_GRACE: Duration = Duration::from_secs(10);
+
+/// Have processes orphaned below verdantd, such as the daemon a `forking`
+/// service double-forks, reparented to verdantd instead of init. Its
+/// supervisor can then reap it and learn how it exited, rather than only
+/// noticing that the PID has gone.
+pub fn become_subreaper() -> io::Result<()> {
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reap adopted processes that no supervisor tracks, which would otherwise
+/// stay zombies now that init no longer collects them. Supervised PIDs are
+/// left for their supervisors, so the exit status reaches the right one.
+pub fn start_reaper(manager: Arc<Manager>) {
+    thread::spawn(move || {
+        let mut seen: HashMap<u32, Instant> = HashMap::new();
+
+        loop {
+            thread::sleep(REAP_INTERVAL);
+
+            let zombies = zombie_children();
+            seen.retain(|pid, _| zombies.contains(pid));
+            if zombies.is_empty() {
+                continue;
+            }
+
+            let supervised = manager.supervised_pids();
+            let now = Instant::now();
+
+            for pid in zombies {
+                if supervised.contains(&pid) {
+                    continue;
+                }
+
+                let first_seen = *seen.entry(pid).or_insert(now);
+                if now.duration_since(first_seen) < REAP_GRACE {
+                    continue;
+                }
+
+                seen.remove(&pid);
+                match waitpid(Pid::from_raw(pid as i32), Some(WaitPidFlag::WNOHANG)) {
+                    Ok(WaitStatus::Exited(_, code)) => {
+                        println!("[verdantd] Reaped orphaned process {} (exit code {})", pid, code)
+                    }
+                    Ok(WaitStatus::Signaled(_, signal, _)) => {
+                        println!("[verdantd] Reaped orphaned process {} (killed by {:?})", pid, signal)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    });
+}
+
+/// Children of verdantd that have exited but not been waited for.
+fn zombie_children() -> Vec<u32> {
+    let own = std::process::id();
+
+    fs::read_dir("/proc")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let pid: u32 = entry.file_name().to_str()?.parse().ok()?;
+
+            // The name is in parentheses and may itself contain spaces
+            let stat = fs::read_to_string(entry.path().join("stat")).ok()?;
+            let mut fields = stat.rsplit_once(')')?.1.split_whitespace();
+            let state = fields.next()?;
+            let ppid: u32 = fields.next()?.parse().ok()?;
+
+            (state == "Z" && ppid == own).then_some(pid)
+        })
+        .collect()
+}
@@ -0,0 +1,114 @@
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use bloom::status::ServiceState;
+
+use crate::cgroup::ServiceCgroup;
+use crate::control::ServiceHandle;
+use crate::supervisor::Supervisor;
+
+/// Supervisor state handed from the old verdantd to the new one.
+pub const STATE_PATH: &str = "/run/verdant/state.json";
+
+/// What a re-executed verdantd needs to carry on supervising a service.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SavedService {
+    pub name: String,
+    pub state: ServiceState,
+    pub should_run: bool,
+    pub restart_count: u32,
+    pub last_exit: Option<i32>,
+    /// Supervised PID, if the service was running.
+    pub pid: Option<u32>,
+    pub uptime_ms: u64,
+    /// Whether the service runs in its own cgroup.
+    pub cgroup: bool,
+    #[serde(default)]
+    pub start_limited: bool,
+}
+
+impl SavedService {
+    pub fn from_supervisor(sup: &Supervisor) -> Self {
+        Self {
+            name: sup.service.name.clone(),
+            state: sup.service.state,
+            should_run: sup.should_run,
+            restart_count: sup.restart_count,
+            last_exit: sup.last_exit,
+            pid: sup.handle.as_ref().map(|h| h.pid()),
+            uptime_ms: sup
+                .handle
+                .as_ref()
+                .map(|h| h.start_time.elapsed().as_millis() as u64)
+                .unwrap_or(0),
+            cgroup: sup.handle.as_ref().is_some_and(|h| h.cgroup.is_some()),
+            start_limited: sup.start_limited,
+        }
+    }
+
+    /// Apply the saved state to a freshly loaded supervisor, adopting the
+    /// running process if there is one. Returns whether a process was adopted.
+    pub fn restore(self, sup: &mut Supervisor) -> bool {
+        sup.should_run = self.should_run;
+        sup.restart_count = self.restart_count;
+        sup.last_exit = self.last_exit;
+        sup.start_limited = self.start_limited;
+
+        // Readiness tracking does not survive the exec; treat it as settled
+        sup.service.state = match self.state {
+            ServiceState::Starting | ServiceState::Reloading => ServiceState::Running,
+            state => state,
+        };
+
+        let Some(pid) = self.pid else {
+            return false;
+        };
+
+        let cgroup = if self.cgroup {
+            ServiceCgroup::create(&sup.service.name).ok()
+        } else {
+            None
+        };
+
+        sup.handle = Some(ServiceHandle::adopt(pid, Duration::from_millis(self.uptime_ms), cgroup));
+        true
+    }
+}
+
+pub fn save_state(services: &[SavedService]) -> io::Result<()> {
+    if let Some(parent) = Path::new(STATE_PATH).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let json = serde_json::to_vec(services).map_err(io::Error::other)?;
+    fs::write(STATE_PATH, json)
+}
+
+/// Read and remove the saved state, if this process was started by a re-exec.
+pub fn take_state() -> Option<Vec<SavedService>> {
+    let contents = fs::read(STATE_PATH).ok()?;
+    let _ = fs::remove_file(STATE_PATH);
+
+    match serde_json::from_slice(&contents) {
+        Ok(services) => Some(services),
+        Err(e) => {
+            eprintln!("[verdantd] Ignoring unreadable {}: {}", STATE_PATH, e);
+            None
+        }
+    }
+}
+
+/// Replace this process with the verdantd binary on disk, keeping the PID
+/// and therefore every child. Only returns on failure.
+pub fn exec_self() -> io::Error {
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_else(|| "/usr/sbin/verdantd".into());
+
+    Command::new(program).args(args).exec()
+}
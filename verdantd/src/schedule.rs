@@ -0,0 +1,181 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bloom::ipc::{IpcCommand, PowerAction};
+use chrono::{DateTime, Local, TimeDelta};
+
+/// Present while a shutdown is close; login refuses non-root users then.
+const NOLOGIN_PATH: &str = "/run/nologin";
+
+/// How close a shutdown has to be before logins are refused.
+const NOLOGIN_WITHIN: Duration = Duration::from_secs(5 * 60);
+
+/// Seconds left at which users are reminded, besides when it is scheduled.
+const ANNOUNCE_AT: &[u64] = &[3600, 1800, 900, 600, 300, 180, 120, 60, 30, 10];
+
+struct Pending {
+    id: u64,
+    action: PowerAction,
+    deadline: Instant,
+    message: Option<String>,
+}
+
+impl Pending {
+    fn action(&self) -> &'static str {
+        self.action.as_str()
+    }
+
+    fn announcement(&self, remaining: Duration) -> String {
+        let mut text = format!("The system will {} in {}.", self.action(), describe(remaining));
+        if let Some(message) = &self.message {
+            text.push('\n');
+            text.push_str(message);
+        }
+        text
+    }
+
+    /// The immediate command sent once the countdown runs out.
+    fn command(self) -> IpcCommand {
+        self.action.command(0, self.message)
+    }
+}
+
+/// A shutdown, reboot or halt requested with a delay. At most one is pending;
+/// scheduling another replaces it.
+#[derive(Default)]
+pub struct PowerSchedule {
+    pending: Mutex<Option<Pending>>,
+    next_id: AtomicU64,
+}
+
+impl PowerSchedule {
+    /// Count down `delay`, warning logged-in users along the way, then send
+    /// the immediate command for `action` to `tx`. Returns when that will be,
+    /// or None without scheduling anything if `delay` is too far out to count.
+    pub fn schedule(
+        self: &Arc<Self>,
+        action: PowerAction,
+        delay: Duration,
+        message: Option<String>,
+        tx: Sender<IpcCommand>,
+    ) -> Option<DateTime<Local>> {
+        let deadline = Instant::now().checked_add(delay)?;
+        let at = Local::now().checked_add_signed(TimeDelta::from_std(delay).ok()?)?;
+
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        *self.pending.lock().unwrap() = Some(Pending {
+            id,
+            action,
+            deadline,
+            message,
+        });
+
+        let schedule = Arc::clone(self);
+        thread::spawn(move || schedule.count_down(id, tx));
+
+        Some(at)
+    }
+
+    /// Call off the pending shutdown, if there is one.
+    pub fn cancel(&self) -> bool {
+        let Some(pending) = self.pending.lock().unwrap().take() else {
+            return false;
+        };
+
+        let _ = fs::remove_file(NOLOGIN_PATH);
+        wall(&format!("The scheduled {} has been cancelled.", pending.action()));
+        true
+    }
+
+    fn count_down(&self, id: u64, tx: Sender<IpcCommand>) {
+        let mut next_announcement = u64::MAX;
+        let mut nologin = false;
+
+        loop {
+            let mut pending = self.pending.lock().unwrap();
+            // Cancelled, or replaced by a newer schedule with its own thread
+            let Some(current) = pending.as_ref().filter(|p| p.id == id) else {
+                return;
+            };
+
+            let remaining = current.deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                let _ = tx.send(pending.take().unwrap().command());
+                return;
+            }
+
+            let announcement = (remaining.as_secs() < next_announcement).then(|| current.announcement(remaining));
+            if announcement.is_some() {
+                next_announcement = ANNOUNCE_AT.iter().copied().find(|t| *t < remaining.as_secs()).unwrap_or(0);
+            }
+
+            if !nologin && remaining <= NOLOGIN_WITHIN {
+                nologin = true;
+                let _ = fs::write(NOLOGIN_PATH, format!("{}\n", current.announcement(remaining)));
+            }
+            drop(pending);
+
+            if let Some(text) = announcement {
+                wall(&text);
+            }
+            thread::sleep(remaining.min(Duration::from_secs(1)));
+        }
+    }
+}
+
+/// Rounded up to whole minutes, or seconds under a minute.
+fn describe(remaining: Duration) -> String {
+    let secs = remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0);
+    let (count, unit) = if secs >= 60 { (secs.div_ceil(60), "minute") } else { (secs, "second") };
+
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+/// Write `text` to every virtual console and pseudo-terminal, like wall(1).
+/// Terminals that would block are skipped.
+fn wall(text: &str) {
+    let banner = format!(
+        "\r\nBroadcast message from verdantd ({}):\r\n\r\n{}\r\n\r\n",
+        Local::now().format("%H:%M"),
+        text.replace('\n', "\r\n")
+    );
+
+    let virtual_consoles = fs::read_dir("/dev")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix("tty"))
+                .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+        });
+
+    let pseudo_terminals = fs::read_dir("/dev/pts")
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit()))
+        });
+
+    for tty in virtual_consoles.chain(pseudo_terminals) {
+        if let Ok(mut file) = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+            .open(&tty)
+        {
+            let _ = file.write_all(banner.as_bytes());
+        }
+    }
+}
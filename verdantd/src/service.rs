@@ -1,18 +1,175 @@
+use std::time::Duration;
+
 use bloom::status::ServiceState;
+use nix::sys::resource::{Resource, RLIM_INFINITY};
+
+use crate::capability::CapabilitySet;
+use crate::cgroup::{CgroupLimit, CgroupQuota};
+use crate::condition::Condition;
+use crate::timer::TimerSpec;
+
+/// How long a `notify` service may take to report READY=1 when the
+/// service file does not set `timeout_start`.
+pub const DEFAULT_TIMEOUT_START: Duration = Duration::from_secs(90);
+
+/// Wait before the first restart when `restart_delay` is not set.
+pub const DEFAULT_RESTART_DELAY: Duration = Duration::from_secs(1);
 
-#[derive(Debug, Clone)]
+/// Window for `start_limit_burst` when `start_limit_interval` is not set.
+pub const DEFAULT_START_LIMIT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Compressed archives kept by log rotation when `log_max_files` is not set.
+pub const DEFAULT_LOG_MAX_FILES: u32 = 5;
+
+/// How often `health_cmd` runs when `health_interval` is not set.
+pub const DEFAULT_HEALTH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Service {
     pub name: String,
+    /// Other names the service answers to, e.g. `sshd` for `openssh`.
+    pub aliases: Vec<String>,
     pub desc: String,
     pub cmd: String,
     pub args: Vec<String>,
+    /// Setup commands run in order before `cmd`; each must succeed.
+    pub exec_steps: Vec<Vec<String>>,
     pub startup: StartupPackage,
     pub restart: RestartPolicy,
+    /// Base delay before a restart; doubles with each consecutive failure.
+    pub restart_delay: Duration,
+    /// Starts allowed within `start_limit_interval`, restarts and manual ones
+    /// included, before the service is Failed until `vctl reset-failed`
+    /// (0 = unlimited). Also read as `max_restarts` and `restart_window`.
+    pub start_limit_burst: u32,
+    pub start_limit_interval: Duration,
+    pub service_type: ServiceType,
+    /// Deadline for a `notify` service to signal readiness, or a `oneshot` to finish.
+    pub timeout_start: Duration,
+    /// Command that makes the service re-read its configuration;
+    /// without it, reload sends SIGHUP to the main process.
+    pub reload_cmd: Option<Vec<String>>,
+    /// Run to stop the service, as its user and in its environment, before
+    /// any remaining processes are signalled.
+    pub stop_cmd: Option<Vec<String>>,
+    /// Run once the main process is gone, however it went down.
+    pub cleanup_cmd: Option<Vec<String>>,
+    /// Which processes get signalled on stop.
+    pub kill_mode: KillMode,
+    /// Probe command; a non-zero exit marks the service Degraded, then Failed.
+    pub health_cmd: Option<Vec<String>>,
+    pub health_interval: Duration,
+    /// Restart the service once the health check keeps failing.
+    pub health_restart: bool,
+    /// Where a `forking` service writes its daemon PID.
+    pub pid_file: Option<String>,
+    /// A `oneshot` that exited successfully stays Running until stopped.
+    pub remain_after_exit: bool,
     pub tags: Vec<String>,
     pub instances: Vec<String>,
     pub state: ServiceState,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    /// Rotate the log files once they grow past this many bytes...
+    pub log_max_size: Option<u64>,
+    /// ...or once they have been written to for this long.
+    pub log_max_age: Option<Duration>,
+    /// Compressed archives kept by rotation.
+    pub log_max_files: u32,
+    /// Account to run as; its primary group and supplementary groups apply
+    /// unless `group` overrides the primary group.
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub umask: Option<u32>,
+    pub working_dir: Option<String>,
+    /// Capabilities the service keeps; every other one is dropped, even for root.
+    pub capabilities: Option<CapabilitySet>,
+    /// Forbid gaining privileges through setuid binaries or file capabilities.
+    pub no_new_privs: bool,
+    /// Directory the service is chrooted into before exec.
+    pub root_dir: Option<String>,
+    /// Host paths such as /dev or /proc bind-mounted into `root_dir`.
+    pub root_bind: Vec<String>,
+    /// Give the service its own empty /tmp, invisible to everything else.
+    pub private_tmp: bool,
+    /// Mount /usr and /etc read-only for the service.
+    pub protect_system: bool,
+    /// Niceness, -20 (most favourable) to 19.
+    pub nice: Option<i32>,
+    /// CPUs the service may run on; empty means all of them.
+    pub cpu_affinity: Vec<usize>,
+    pub sched_policy: Option<SchedPolicy>,
+    /// Static priority for `fifo` and `rr`, 1 to 99.
+    pub sched_priority: Option<i32>,
+    pub io_class: Option<IoClass>,
+    /// Priority within `realtime` or `best-effort`, 0 (highest) to 7.
+    pub io_priority: Option<u8>,
+    /// cgroup controller limits for the whole service (`memory_max` and friends).
+    pub quota: CgroupQuota,
+    /// Resource limits applied to the main process (`limit_*` keys).
+    pub limits: Vec<ResourceLimit>,
+    /// Services that must be running before this one starts (`requires`, or
+    /// the older `dependencies` key). When one goes down for good, this
+    /// service is stopped too.
+    pub dependencies: Vec<String>,
+    /// Block devices, as paths or `UUID=`/`LABEL=` specs, waited for before
+    /// the service starts, for up to `timeout_start` (`requires_device`).
+    pub required_devices: Vec<String>,
+    /// Services started before this one if present, without being required.
+    pub wants: Vec<String>,
+    /// Stopping or restarting any of these services also stops or restarts this one.
+    pub part_of: Vec<String>,
+    /// This service is stopped whenever any of these services stops or dies.
+    pub binds_to: Vec<String>,
+    /// Checked before every start; any unmet condition skips the service.
+    pub conditions: Vec<Condition>,
+    /// Timer-activated services are launched on schedule instead of at boot.
+    pub timer: Option<TimerSpec>,
+    /// Disabled services are not started at boot but can still be started manually.
+    pub enabled: bool,
+    /// Masked services cannot be started at all, not even manually.
+    pub masked: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimit {
+    pub resource: Resource,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl ResourceLimit {
+    /// Map a `limit_<name>` key suffix to its resource.
+    pub fn resource_from_str(name: &str) -> Option<Resource> {
+        match name {
+            "nofile" => Some(Resource::RLIMIT_NOFILE),
+            "nproc" => Some(Resource::RLIMIT_NPROC),
+            "core" => Some(Resource::RLIMIT_CORE),
+            "as" => Some(Resource::RLIMIT_AS),
+            "memlock" => Some(Resource::RLIMIT_MEMLOCK),
+            "stack" => Some(Resource::RLIMIT_STACK),
+            "cpu" => Some(Resource::RLIMIT_CPU),
+            "fsize" => Some(Resource::RLIMIT_FSIZE),
+            "data" => Some(Resource::RLIMIT_DATA),
+            _ => None,
+        }
+    }
+
+    /// The `limit_<name>` key this limit is set with.
+    pub fn key(&self) -> &'static str {
+        match self.resource {
+            Resource::RLIMIT_NOFILE => "limit_nofile",
+            Resource::RLIMIT_NPROC => "limit_nproc",
+            Resource::RLIMIT_CORE => "limit_core",
+            Resource::RLIMIT_AS => "limit_as",
+            Resource::RLIMIT_MEMLOCK => "limit_memlock",
+            Resource::RLIMIT_STACK => "limit_stack",
+            Resource::RLIMIT_CPU => "limit_cpu",
+            Resource::RLIMIT_FSIZE => "limit_fsize",
+            Resource::RLIMIT_DATA => "limit_data",
+            _ => "limit_unknown",
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -31,6 +188,191 @@ pub enum RestartPolicy {
     OnFailure,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceType {
+    /// Running as soon as the process is spawned.
+    Simple,
+    /// Running once the process sends READY=1 on $NOTIFY_SOCKET.
+    Notify,
+    /// Runs to completion; dependents wait for it to exit successfully.
+    Oneshot,
+    /// The command daemonizes; the real PID is read from `pid_file`
+    /// after the launcher exits.
+    Forking,
+}
+
+/// What stopping a service signals (`kill_mode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillMode {
+    /// SIGTERM, then SIGKILL, to every process in the cgroup.
+    ControlGroup,
+    /// SIGTERM to the main process only, then SIGKILL to the whole cgroup.
+    Mixed,
+    /// Only the main process is signalled; the rest is left running.
+    MainProcess,
+    /// Nothing is signalled; `stop_cmd` is expected to do the work.
+    None,
+}
+
+/// CPU scheduling policy (`sched_policy`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    Other,
+    Batch,
+    Idle,
+    /// Real-time, first in first out.
+    Fifo,
+    /// Real-time, round robin.
+    Rr,
+}
+
+/// I/O scheduling class (`io_class`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+impl Service {
+    /// True if both come from the same definition, ignoring runtime state.
+    pub fn same_definition(&self, other: &Service) -> bool {
+        let mut other = other.clone();
+        other.state = self.state;
+        other.enabled = self.enabled;
+        other.masked = self.masked;
+        *self == other
+    }
+
+    /// Every effective setting as `key=value` pairs, named after the service
+    /// file keys, with defaults filled in. Unset options have empty values;
+    /// keys that can be given more than once repeat.
+    pub fn properties(&self) -> Vec<(&'static str, String)> {
+        let opt = |v: Option<String>| v.unwrap_or_default();
+        let secs = |d: Duration| format!("{}s", d.as_secs());
+        let bytes = |v: Option<&CgroupLimit>| opt(v.map(|l| l.to_string()));
+        let limit = |v: u64| if v == RLIM_INFINITY { "infinity".to_string() } else { v.to_string() };
+
+        let mut props = vec![
+            ("name", self.name.clone()),
+            ("aliases", self.aliases.join(", ")),
+            ("desc", self.desc.clone()),
+        ];
+
+        for step in &self.exec_steps {
+            props.push(("cmd_step", join_args(step)));
+        }
+
+        props.extend([
+            ("cmd", self.cmd.clone()),
+            ("args", join_args(&self.args)),
+            ("startup", self.startup.as_str().to_string()),
+            ("type", self.service_type.as_str().to_string()),
+            ("restart", self.restart.as_str().to_string()),
+            ("restart_delay", secs(self.restart_delay)),
+            ("start_limit_burst", self.start_limit_burst.to_string()),
+            ("start_limit_interval", secs(self.start_limit_interval)),
+            ("timeout_start", secs(self.timeout_start)),
+            ("reload_cmd", opt(self.reload_cmd.as_deref().map(join_args))),
+            ("stop_cmd", opt(self.stop_cmd.as_deref().map(join_args))),
+            ("cleanup_cmd", opt(self.cleanup_cmd.as_deref().map(join_args))),
+            ("kill_mode", self.kill_mode.as_str().to_string()),
+            ("health_cmd", opt(self.health_cmd.as_deref().map(join_args))),
+            ("health_interval", secs(self.health_interval)),
+            ("health_restart", self.health_restart.to_string()),
+            ("pid_file", opt(self.pid_file.clone())),
+            ("remain_after_exit", self.remain_after_exit.to_string()),
+            ("tags", self.tags.join(", ")),
+            ("instances", self.instances.join(", ")),
+            ("stdout", opt(self.stdout.clone())),
+            ("stderr", opt(self.stderr.clone())),
+            ("log_max_size", opt(self.log_max_size.map(|v| v.to_string()))),
+            ("log_max_age", opt(self.log_max_age.map(secs))),
+            ("log_max_files", self.log_max_files.to_string()),
+            ("user", opt(self.user.clone())),
+            ("group", opt(self.group.clone())),
+            ("umask", opt(self.umask.map(|m| format!("{:04o}", m)))),
+            ("working_dir", opt(self.working_dir.clone())),
+            ("capabilities", opt(self.capabilities.map(|c| c.names().join(", ")))),
+            ("no_new_privs", self.no_new_privs.to_string()),
+            ("root_dir", opt(self.root_dir.clone())),
+            ("root_bind", self.root_bind.join(", ")),
+            ("private_tmp", self.private_tmp.to_string()),
+            ("protect_system", self.protect_system.to_string()),
+            ("nice", opt(self.nice.map(|n| n.to_string()))),
+            ("cpu_affinity", self.cpu_affinity.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", ")),
+            ("sched_policy", opt(self.sched_policy.map(|p| p.as_str().to_string()))),
+            ("sched_priority", opt(self.sched_priority.map(|p| p.to_string()))),
+            ("io_class", opt(self.io_class.map(|c| c.as_str().to_string()))),
+            ("io_priority", opt(self.io_priority.map(|p| p.to_string()))),
+            ("memory_max", bytes(self.quota.memory_max.as_ref())),
+            ("memory_high", bytes(self.quota.memory_high.as_ref())),
+            ("cpu_weight", opt(self.quota.cpu_weight.map(|w| w.to_string()))),
+            ("tasks_max", bytes(self.quota.tasks_max.as_ref())),
+        ]);
+
+        for l in &self.limits {
+            props.push((l.key(), format!("{}:{}", limit(l.soft), limit(l.hard))));
+        }
+
+        for condition in &self.conditions {
+            props.push(condition.as_property());
+        }
+
+        if let Some(timer) = &self.timer {
+            props.extend([
+                ("on_boot_sec", opt(timer.on_boot_sec.map(secs))),
+                ("on_unit_active_sec", opt(timer.on_unit_active_sec.map(secs))),
+                ("on_calendar", opt(timer.on_calendar.map(|c| c.to_string()))),
+            ]);
+        }
+
+        props.extend([
+            ("requires", self.dependencies.join(", ")),
+            ("requires_device", self.required_devices.join(", ")),
+            ("wants", self.wants.join(", ")),
+            ("part_of", self.part_of.join(", ")),
+            ("binds_to", self.binds_to.join(", ")),
+            ("enabled", self.enabled.to_string()),
+            ("masked", self.masked.to_string()),
+            ("state", format!("{:?}", self.state).to_lowercase()),
+        ]);
+
+        props
+    }
+
+    /// A template (`foo@.vs` with `name: foo@{}`) that has no static
+    /// `instances:` list and is only instantiated on demand.
+    pub fn is_template(&self) -> bool {
+        self.name.contains("{}") || self.name.contains("%i")
+    }
+
+    /// If `name` is an instance of this template, the instance part.
+    pub fn template_instance<'a>(&self, name: &'a str) -> Option<&'a str> {
+        let placeholder = if self.name.contains("{}") { "{}" } else { "%i" };
+        let (prefix, suffix) = self.name.split_once(placeholder)?;
+
+        name.strip_prefix(prefix)?
+            .strip_suffix(suffix)
+            .filter(|inst| !inst.is_empty())
+    }
+}
+
+/// Arguments as they would be written in a service file, quoting any
+/// that would otherwise split or vanish.
+fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(|a| {
+            if a.is_empty() || a.contains(char::is_whitespace) {
+                format!("\"{}\"", a)
+            } else {
+                a.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 impl StartupPackage {
     pub fn from_str(s: &str) -> Option<Self> {
         match s.to_lowercase().as_str() {
@@ -63,5 +405,129 @@ impl RestartPolicy {
             _ => None,
         }
     }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RestartPolicy::Never => "never",
+            RestartPolicy::Always => "always",
+            RestartPolicy::OnFailure => "on-failure",
+        }
+    }
+
+    /// Whether a process that exited with `exit_code` gets restarted.
+    pub fn restarts_after(&self, exit_code: Option<i32>) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::Always => true,
+            RestartPolicy::OnFailure => matches!(exit_code, Some(code) if code != 0),
+        }
+    }
 }
 
+
+impl ServiceType {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "simple" => Some(Self::Simple),
+            "notify" => Some(Self::Notify),
+            "oneshot" => Some(Self::Oneshot),
+            "forking" => Some(Self::Forking),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServiceType::Simple => "simple",
+            ServiceType::Notify => "notify",
+            ServiceType::Oneshot => "oneshot",
+            ServiceType::Forking => "forking",
+        }
+    }
+}
+
+impl KillMode {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "control-group" => Some(Self::ControlGroup),
+            "mixed" => Some(Self::Mixed),
+            "main-process" => Some(Self::MainProcess),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KillMode::ControlGroup => "control-group",
+            KillMode::Mixed => "mixed",
+            KillMode::MainProcess => "main-process",
+            KillMode::None => "none",
+        }
+    }
+}
+
+impl SchedPolicy {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "other" => Some(Self::Other),
+            "batch" => Some(Self::Batch),
+            "idle" => Some(Self::Idle),
+            "fifo" => Some(Self::Fifo),
+            "rr" => Some(Self::Rr),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchedPolicy::Other => "other",
+            SchedPolicy::Batch => "batch",
+            SchedPolicy::Idle => "idle",
+            SchedPolicy::Fifo => "fifo",
+            SchedPolicy::Rr => "rr",
+        }
+    }
+
+    pub fn is_realtime(&self) -> bool {
+        matches!(self, SchedPolicy::Fifo | SchedPolicy::Rr)
+    }
+
+    pub fn as_raw(&self) -> libc::c_int {
+        match self {
+            SchedPolicy::Other => libc::SCHED_OTHER,
+            SchedPolicy::Batch => libc::SCHED_BATCH,
+            SchedPolicy::Idle => libc::SCHED_IDLE,
+            SchedPolicy::Fifo => libc::SCHED_FIFO,
+            SchedPolicy::Rr => libc::SCHED_RR,
+        }
+    }
+}
+
+impl IoClass {
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "realtime" => Some(Self::Realtime),
+            "best-effort" => Some(Self::BestEffort),
+            "idle" => Some(Self::Idle),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IoClass::Realtime => "realtime",
+            IoClass::BestEffort => "best-effort",
+            IoClass::Idle => "idle",
+        }
+    }
+
+    /// Class number as used by ioprio_set.
+    pub fn as_raw(&self) -> libc::c_int {
+        match self {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        }
+    }
+}
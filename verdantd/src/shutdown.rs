@@ -2,6 +2,7 @@ use bloom::errors::BloomError;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use crate::control::cleanup_service;
 use crate::supervisor::Supervisor;
 
 /// Timeout per service shutdown
@@ -19,10 +20,10 @@ pub fn shutdown_all(supervisors: &[Arc<Mutex<Supervisor>>]) -> Result<(), BloomE
         if let Some(handle) = sup.handle.as_mut() {
             // First try clean stop
             match handle.wait_with_timeout(Duration::from_secs(SHUTDOWN_TIMEOUT_SECS)) {
-                Ok(Some(_exit_code)) => {
+                Ok(true) => {
                     // Stopped cleanly
                 }
-                Ok(None) => {
+                Ok(false) => {
                     // Timeout: force kill
                     if let Err(e) = handle.kill() {
                         failures.push(format!("Failed to kill {}: {}", sup.service.name, e));
@@ -38,6 +39,12 @@ pub fn shutdown_all(supervisors: &[Arc<Mutex<Supervisor>>]) -> Result<(), BloomE
                 }
             }
         }
+
+        // Take down anything the service forked that is still around
+        let sup = &mut *sup;
+        if let Some(handle) = sup.handle.as_mut() {
+            cleanup_service(&sup.service, handle);
+        }
     }
 
     if failures.is_empty() {
@@ -0,0 +1,83 @@
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bloom::errors::BloomError;
+use bloom::ipc::{send_ipc_request, IpcCommand, IpcRequest, IpcTarget, INIT_SOCKET_PATH};
+
+use crate::control::wait_helper;
+use crate::manager::Manager;
+
+/// Executables run with `pre <suspend|hibernate>` before sleeping and
+/// `post <suspend|hibernate>` after waking, in name order.
+pub const SLEEP_HOOK_DIR: &str = "/etc/verdant/sleep.d";
+
+/// How long a single hook may run before it is killed.
+const HOOK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Held while the machine goes to sleep, so a second request cannot follow
+/// the first one down.
+static SLEEPING: Mutex<()> = Mutex::new(());
+
+/// Suspend, or with `hibernate` suspend to disk, through init. Returns once
+/// the machine is awake again and the post-sleep hooks have run.
+/// Supervision is paused throughout so nothing is restarted half-asleep.
+pub fn sleep(manager: &Manager, hibernate: bool) -> Result<(), BloomError> {
+    let Ok(_guard) = SLEEPING.try_lock() else {
+        return Err(BloomError::Custom("Already going to sleep".into()));
+    };
+
+    let (kind, command) = if hibernate {
+        ("hibernate", IpcCommand::Hibernate)
+    } else {
+        ("suspend", IpcCommand::Suspend)
+    };
+
+    run_hooks("pre", kind);
+    manager.pause_supervision(true);
+
+    let request = IpcRequest {
+        id: 0,
+        target: IpcTarget::Init,
+        command,
+    };
+    let result = send_ipc_request(INIT_SOCKET_PATH, &request);
+
+    manager.pause_supervision(false);
+    run_hooks("post", kind);
+
+    match result {
+        Ok(response) if response.success => Ok(()),
+        Ok(response) => Err(BloomError::Custom(response.message)),
+        Err(e) => Err(BloomError::Io(e)),
+    }
+}
+
+/// A failing hook is logged but never keeps the machine awake.
+fn run_hooks(stage: &str, kind: &str) {
+    let mut hooks: Vec<PathBuf> = fs::read_dir(SLEEP_HOOK_DIR)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .collect();
+    hooks.sort();
+
+    for hook in hooks {
+        let what = hook.display().to_string();
+        let result = Command::new(&hook)
+            .args([stage, kind])
+            .process_group(0)
+            .spawn()
+            .map_err(BloomError::Io)
+            .and_then(|child| wait_helper(child, HOOK_TIMEOUT, &what));
+
+        if let Err(e) = result {
+            eprintln!("[verdantd] Sleep hook {} failed: {}", what, e);
+        }
+    }
+}
@@ -1,27 +1,142 @@
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use bloom::status::ServiceState;
 use bloom::errors::BloomError;
+use bloom::ipc::KillWho;
+use nix::sys::signal::Signal;
 
-use crate::service::Service;
-use crate::control::{ServiceHandle, start_service, stop_service, restart_service};
+use crate::condition;
+use crate::health::HealthCheck;
+use crate::service::{Service, ServiceType};
+use crate::control::{
+    ServiceHandle, cleanup_service, read_pid_file, start_service, stop_service, restart_service,
+};
+
+/// How often each supervisor checks on its service.
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Backoff stops doubling after this many consecutive failures.
+const MAX_BACKOFF_DOUBLINGS: u32 = 6;
+
+/// Failed health probes in a row before a Degraded service counts as Failed.
+const HEALTH_FAILURE_LIMIT: u32 = 3;
+
+/// Upper bound for a single restart delay.
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+/// Lifecycle notifications sent from supervisors to the Manager.
+#[derive(Debug, Clone)]
+pub enum ServiceEvent {
+    /// The main process exited on its own (crash or clean exit).
+    Exited { name: String, code: Option<i32> },
+    /// The service went down and will not be restarted.
+    Inactive { name: String },
+}
 
 pub struct Supervisor {
     pub service: Service,
     pub handle: Option<ServiceHandle>,
     pub should_run: bool, // NEW: track if this service should continue running
+    pub restart_count: u32,
+    /// Exit code of the most recent run, once it has finished.
+    pub last_exit: Option<i32>,
+    /// Starts within the current `start_limit_interval`.
+    recent_starts: VecDeque<Instant>,
+    /// Hit `start_limit_burst`; nothing starts it until `reset_failed`.
+    pub start_limited: bool,
+    /// Failures since the service last stayed up; drives the backoff.
+    consecutive_failures: u32,
+    /// When the pending restart is due.
+    restart_at: Option<Instant>,
+    pub health: Option<HealthCheck>,
+    /// Definition loaded by a daemon-reload, applied on the next start.
+    pending: Option<Service>,
+    /// Why the last start was skipped, if a condition did not hold.
+    pub skip_reason: Option<String>,
+    /// Set while a `notify` service is Starting and has not sent READY=1.
+    ready_deadline: Option<Instant>,
+    events: Option<Sender<ServiceEvent>>,
 }
 
 impl Supervisor {
     pub fn new(service: Service) -> Self {
         Self {
+            health: Self::health_check(&service),
+            pending: None,
+            skip_reason: None,
             service,
             handle: None,
             should_run: true,
+            restart_count: 0,
+            last_exit: None,
+            recent_starts: VecDeque::new(),
+            start_limited: false,
+            consecutive_failures: 0,
+            restart_at: None,
+            ready_deadline: None,
+            events: None,
+        }
+    }
+
+    fn health_check(service: &Service) -> Option<HealthCheck> {
+        let (cmd, args) = service.health_cmd.as_ref()?.split_first()?;
+        Some(HealthCheck::new(cmd.clone(), args.to_vec(), service.health_interval))
+    }
+
+    /// Take a definition from a daemon-reload. A stopped service switches
+    /// over at once; a running one keeps its current definition until it is
+    /// next started. Returns false if nothing changed.
+    pub fn update_definition(&mut self, service: Service) -> bool {
+        if self.service.same_definition(&service) {
+            self.pending = None;
+            return false;
+        }
+
+        self.pending = Some(service);
+        if self.handle.is_none() {
+            self.apply_pending();
         }
+        true
+    }
+
+    /// Whether a reloaded definition is waiting for a restart.
+    pub fn needs_restart(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn apply_pending(&mut self) {
+        let Some(mut service) = self.pending.take() else {
+            return;
+        };
+
+        service.state = self.service.state;
+        service.enabled = self.service.enabled;
+        service.masked = self.service.masked;
+        self.health = Self::health_check(&service);
+        self.service = service;
+    }
+
+    /// Attach the channel used to report lifecycle events to the Manager.
+    pub fn with_events(mut self, events: Sender<ServiceEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    fn emit(&self, event: ServiceEvent) {
+        if let Some(tx) = &self.events {
+            let _ = tx.send(event);
+        }
+    }
+
+    fn emit_inactive(&self) {
+        self.emit(ServiceEvent::Inactive {
+            name: self.service.name.clone(),
+        });
     }
 
     /// Start the service if not already running.
@@ -31,22 +146,59 @@ impl Supervisor {
             return Ok(());
         }
 
+        self.apply_pending();
+
+        if self.service.masked {
+            self.should_run = false;
+            return Err(BloomError::Custom(format!("'{}' is masked", self.service.name)));
+        }
+
+        if self.start_limited || self.start_limit_hit() {
+            self.enter_start_limited();
+            return Err(BloomError::Custom(format!(
+                "'{}' hit its start limit; run vctl reset-failed to allow starting it again",
+                self.service.name
+            )));
+        }
+
+        self.skip_reason = condition::first_unmet(&self.service.conditions);
+        if self.skip_reason.is_some() {
+            // Not a failure: the service simply does not apply here
+            self.service.state = ServiceState::Skipped;
+            self.should_run = false;
+            return Ok(());
+        }
+
         self.service.state = ServiceState::Starting;
 
-        let handle = start_service(&self.service)?;
+        let handle = match start_service(&self.service) {
+            Ok(handle) => handle,
+            Err(e) => {
+                self.service.state = ServiceState::Failed;
+                return Err(e);
+            }
+        };
         self.handle = Some(handle);
-        self.service.state = ServiceState::Running;
+        self.mark_started();
 
         Ok(())
     }
 
     /// Stop the service if running.
     pub fn stop(&mut self) -> Result<(), BloomError> {
+        self.should_run = false; // Once stopped manually, don't restart
+        self.ready_deadline = None;
+        self.restart_at = None;
+
+        if let Some(health) = &mut self.health {
+            health.reset();
+        }
+
         if let Some(mut handle) = self.handle.take() {
             self.service.state = ServiceState::Stopping;
 
             // Timeout 5 seconds to stop cleanly
-            let stopped_cleanly = stop_service(&mut handle, Duration::from_secs(5))?;
+            let stopped_cleanly = stop_service(&self.service, &mut handle, Duration::from_secs(5))?;
 
             self.service.state = if stopped_cleanly {
                 ServiceState::Stopped
@@ -54,55 +206,481 @@ impl Supervisor {
                 ServiceState::Failed
             };
 
-            self.should_run = false; // Once stopped manually, don't restart
-
             Ok(())
         } else {
-            // Not running
+            // Not running; a finished remain_after_exit oneshot just goes inactive
+            if self.service.state == ServiceState::Running {
+                self.service.state = ServiceState::Stopped;
+            }
             Ok(())
         }
     }
 
+    /// Mark the running service as reloading. Returns the definition and
+    /// main PID the reload needs, and the state to go back to afterwards, so
+    /// the reload itself can run without this supervisor locked.
+    pub fn begin_reload(&mut self) -> Result<(Service, u32, ServiceState), BloomError> {
+        let Some(handle) = self.handle.as_ref() else {
+            return Err(BloomError::Custom(format!("'{}' is not running", self.service.name)));
+        };
+        if self.service.state == ServiceState::Reloading {
+            return Err(BloomError::Custom(format!("'{}' is already reloading", self.service.name)));
+        }
+
+        let previous = self.service.state;
+        self.service.state = ServiceState::Reloading;
+        Ok((self.service.clone(), handle.pid(), previous))
+    }
+
+    /// Record how a reload started by `begin_reload` went, unless the
+    /// service has since stopped or crashed.
+    pub fn finish_reload(&mut self, previous: ServiceState, succeeded: bool) {
+        if self.service.state == ServiceState::Reloading {
+            self.service.state = if succeeded { previous } else { ServiceState::Degraded };
+        }
+    }
+
+    /// Send `signal` to the running service. Whatever it does in response is
+    /// handled like any other exit or crash.
+    pub fn kill(&mut self, signal: Signal, who: KillWho) -> Result<usize, BloomError> {
+        let Some(handle) = self.handle.as_ref() else {
+            return Err(BloomError::Custom(format!("'{}' is not running", self.service.name)));
+        };
+
+        handle.signal(signal, who)
+    }
+
     /// Restart the service according to restart policy.
     pub fn restart(&mut self) -> Result<(), BloomError> {
-        let current_handle = self.handle.take();
+        let mut current_handle = self.handle.take();
+
+        // A masked service goes down instead of coming back
+        if self.service.masked {
+            if let Some(mut handle) = current_handle {
+                stop_service(&self.service, &mut handle, Duration::from_secs(5))?;
+            }
+            self.should_run = false;
+            self.service.state = ServiceState::Stopped;
+            self.emit_inactive();
+            return Ok(());
+        }
+
+        // Only a process that is really going away picks up a new definition
+        if current_handle.as_mut().is_none_or(|h| !h.is_running()) {
+            self.apply_pending();
+        }
+
         let new_handle_opt = restart_service(&self.service, current_handle)?;
 
         self.handle = new_handle_opt;
 
-        self.service.state = if self.handle.is_some() {
-            ServiceState::Running
+        if self.handle.is_some() {
+            self.mark_started();
         } else {
             // Service was not restarted (e.g. restart: never or clean exit)
             self.should_run = false;
-            ServiceState::Stopped
+            self.service.state = ServiceState::Stopped;
+            self.emit_inactive();
+        }
+
+        Ok(())
+    }
+
+    /// A freshly spawned `notify` service stays Starting until it reports
+    /// ready, a `oneshot` until it exits and a `forking` service until its
+    /// pid file appears; everything else is Running as soon as it is spawned.
+    fn mark_started(&mut self) {
+        self.recent_starts.push_back(Instant::now());
+
+        if let Some(health) = &mut self.health {
+            health.reset();
+        }
+
+        let awaits_ready = matches!(self.service.service_type, ServiceType::Oneshot | ServiceType::Forking)
+            || self.handle.as_ref().is_some_and(|h| h.notify.is_some());
+
+        if awaits_ready {
+            self.service.state = ServiceState::Starting;
+            self.ready_deadline = Some(Instant::now() + self.service.timeout_start);
+        } else {
+            self.service.state = ServiceState::Running;
+            self.ready_deadline = None;
+        }
+    }
+
+    /// Promote a Starting `notify` service to Running once READY=1 arrives,
+    /// settle a `oneshot` once it exits, or fail either when `timeout_start`
+    /// passes first.
+    pub fn check_ready(&mut self) -> Result<(), BloomError> {
+        let Some(deadline) = self.ready_deadline else {
+            return Ok(());
         };
 
+        if self.finish_oneshot() {
+            return Ok(());
+        }
+
+        let ready = match self.service.service_type {
+            ServiceType::Forking => self.adopt_forked_pid(),
+            _ => self.notify_ready(),
+        };
+
+        match ready {
+            Ok(true) => {
+                self.service.state = ServiceState::Running;
+                self.ready_deadline = None;
+            }
+            Ok(false) if Instant::now() >= deadline => {
+                let phase = self.startup_phase();
+                eprintln!(
+                    "[verdantd] '{}' timed out after {}s {}",
+                    self.service.name,
+                    self.service.timeout_start.as_secs(),
+                    phase
+                );
+                self.abort_start();
+            }
+            Ok(false) => {}
+            Err(reason) => {
+                // Leave the exited handle for `poll` to apply the restart policy
+                eprintln!("[verdantd] '{}' failed to start: {}", self.service.name, reason);
+                self.service.state = ServiceState::Failed;
+                self.ready_deadline = None;
+            }
+        }
+
         Ok(())
     }
 
-    /// Main supervise loop.
-    /// Checks the service status periodically and restarts if necessary.
-    /// Will exit cleanly when `running` is set to false.
-    pub fn supervise_loop(&mut self, running: Arc<AtomicBool>) -> Result<(), BloomError> {
-        while running.load(Ordering::Relaxed) {
-            if let Some(handle) = &mut self.handle {
-                if !handle.is_running() {
-                    // Process exited
-                    self.service.state = ServiceState::Failed;
+    /// What a Starting service is still waiting for, for timeout messages.
+    fn startup_phase(&mut self) -> &'static str {
+        match self.service.service_type {
+            ServiceType::Oneshot => "waiting for the command to exit",
+            ServiceType::Forking => {
+                let launcher_running = self
+                    .handle
+                    .as_mut()
+                    .and_then(|h| h.child.as_mut())
+                    .is_some_and(|c| matches!(c.try_wait(), Ok(None)));
+
+                if launcher_running {
+                    "waiting for the launcher to exit"
+                } else {
+                    "waiting for the pid file"
+                }
+            }
+            ServiceType::Notify | ServiceType::Simple => "waiting for READY=1",
+        }
+    }
+
+    /// Give up on a start that did not finish in time: kill everything it
+    /// spawned so far and leave it Failed, without restarting it.
+    fn abort_start(&mut self) {
+        self.ready_deadline = None;
+        self.restart_at = None;
+        self.should_run = false;
+
+        if let Some(mut handle) = self.handle.take() {
+            if let Err(e) = handle.kill() {
+                eprintln!("[verdantd] Failed to kill '{}': {}", self.service.name, e);
+            }
+            let _ = handle.wait_with_timeout(Duration::from_secs(5));
+            cleanup_service(&self.service, &mut handle);
+        }
 
-                    // Try to restart based on policy
+        if let Some(health) = &mut self.health {
+            health.reset();
+        }
+
+        self.service.state = ServiceState::Failed;
+        self.emit_inactive();
+    }
+
+    /// Restart a failed service after its backoff, unless that would go over
+    /// its start limit.
+    fn restart_after_failure(&mut self, uptime: Duration) {
+        if self.start_limit_hit() {
+            self.enter_start_limited();
+        } else {
+            self.schedule_restart(uptime);
+        }
+    }
+
+    /// Queue a restart with exponential backoff. How many restarts happen
+    /// is up to the start limit, checked before this is called.
+    fn schedule_restart(&mut self, uptime: Duration) {
+        let now = Instant::now();
+
+        // A run that outlasted the start limit interval was healthy; start the backoff over
+        if uptime >= self.service.start_limit_interval {
+            self.consecutive_failures = 0;
+        }
+
+        let backoff = 1u32 << self.consecutive_failures.min(MAX_BACKOFF_DOUBLINGS);
+        let delay = self.service.restart_delay.saturating_mul(backoff).min(MAX_RESTART_DELAY);
+
+        self.consecutive_failures += 1;
+        self.restart_at = Some(now + delay);
+    }
+
+    /// Whether another start would exceed `start_limit_burst`.
+    fn start_limit_hit(&mut self) -> bool {
+        let burst = self.service.start_limit_burst;
+        let interval = self.service.start_limit_interval;
+        let now = Instant::now();
+
+        self.recent_starts.retain(|t| now.duration_since(*t) < interval);
+        burst > 0 && self.recent_starts.len() as u32 >= burst
+    }
+
+    /// Stop trying for good: only `reset_failed` lets the service start again.
+    fn enter_start_limited(&mut self) {
+        if !self.start_limited {
+            eprintln!(
+                "[verdantd] '{}' started {} times within {}s, refusing further starts",
+                self.service.name,
+                self.service.start_limit_burst,
+                self.service.start_limit_interval.as_secs()
+            );
+        }
+
+        if let Some(mut handle) = self.handle.take() {
+            cleanup_service(&self.service, &mut handle);
+        }
+
+        self.start_limited = true;
+        self.should_run = false;
+        self.restart_at = None;
+        self.service.state = ServiceState::Failed;
+        self.emit_inactive();
+    }
+
+    /// Clear a start limit and any Failed state so the service can be
+    /// started again. Returns whether there was anything to reset.
+    pub fn reset_failed(&mut self) -> bool {
+        let was_failed = self.start_limited || self.service.state == ServiceState::Failed;
+
+        self.start_limited = false;
+        self.recent_starts.clear();
+        self.reset_backoff();
+
+        if self.service.state == ServiceState::Failed && self.handle.is_none() {
+            self.service.state = ServiceState::Stopped;
+        }
+
+        was_failed
+    }
+
+    /// Forget past failures so a manual start is not delayed.
+    pub fn reset_backoff(&mut self) {
+        self.consecutive_failures = 0;
+        self.restart_at = None;
+    }
+
+    /// True while a started service has not yet become ready.
+    pub fn is_settling(&self) -> bool {
+        self.ready_deadline.is_some()
+    }
+
+    fn notify_ready(&mut self) -> Result<bool, String> {
+        let Some(handle) = self.handle.as_mut() else {
+            return Ok(false);
+        };
+
+        if handle.notify.as_mut().is_some_and(|n| n.poll_ready()) {
+            Ok(true)
+        } else if !handle.is_running() {
+            Err("exited before reporting ready".into())
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// For a `forking` service, wait for the launcher to exit and then
+    /// adopt the daemon PID from `pid_file`.
+    fn adopt_forked_pid(&mut self) -> Result<bool, String> {
+        let Some(handle) = self.handle.as_mut() else {
+            return Ok(false);
+        };
+
+        if handle.main_pid.is_some() {
+            return Ok(true);
+        }
+
+        let Some(child) = handle.child.as_mut() else {
+            return Ok(true);
+        };
+
+        match child.try_wait() {
+            Ok(None) => Ok(false), // launcher still running
+            Ok(Some(status)) if !status.success() => {
+                handle.exit_status = status.code();
+                Err(format!("launcher exited with {}", status))
+            }
+            Ok(Some(_)) => match self.service.pid_file.as_deref().and_then(read_pid_file) {
+                Some(pid) => {
+                    handle.main_pid = Some(pid);
+                    Ok(true)
+                }
+                None => Ok(false), // pid file not written yet
+            },
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Record the result of a `oneshot` whose command has exited.
+    /// Returns false if this is not a finished oneshot.
+    fn finish_oneshot(&mut self) -> bool {
+        if self.service.service_type != ServiceType::Oneshot {
+            return false;
+        }
+
+        let Some(mut handle) = self.handle.take_if(|h| !h.is_running()) else {
+            return false;
+        };
+
+        cleanup_service(&self.service, &mut handle);
+
+        let code = handle.exit_status;
+        self.last_exit = code;
+        self.should_run = false;
+        self.ready_deadline = None;
+
+        self.service.state = match code {
+            Some(0) if self.service.remain_after_exit => ServiceState::Running,
+            Some(0) => ServiceState::Stopped,
+            _ => ServiceState::Failed,
+        };
+
+        if self.service.state != ServiceState::Running {
+            self.emit(ServiceEvent::Exited {
+                name: self.service.name.clone(),
+                code,
+            });
+            self.emit_inactive();
+        }
+
+        true
+    }
+
+    /// Single supervision step: notice exits, apply the restart policy,
+    /// and start the service if it is wanted but not running.
+    pub fn poll(&mut self) -> Result<(), BloomError> {
+        self.check_ready()?;
+
+        // A restart is pending; wait out the backoff
+        if let Some(at) = self.restart_at {
+            if Instant::now() >= at {
+                self.restart_at = None;
+                if self.handle.is_some() {
                     self.restart()?;
+                } else {
+                    // Taken down by its health check
+                    self.should_run = true;
+                    self.start()?;
+                }
+
+                if self.handle.is_some() {
+                    self.restart_count += 1;
                 }
-            } else if self.should_run {
-                // Only auto-start if restart policy allows it
-                self.start()?;
             }
+            return Ok(());
+        }
 
-            sleep(Duration::from_secs(2));
+        if let Some(handle) = &mut self.handle {
+            // While settling, exits are handled by `check_ready`
+            if self.ready_deadline.is_none() && !handle.is_running() {
+                // Process exited
+                let code = handle.exit_status;
+                let uptime = handle.start_time.elapsed();
+                cleanup_service(&self.service, handle);
+                self.last_exit = code;
+                self.service.state = ServiceState::Failed;
+                self.emit(ServiceEvent::Exited {
+                    name: self.service.name.clone(),
+                    code,
+                });
+
+                if self.service.restart.restarts_after(code) {
+                    self.restart_after_failure(uptime);
+                } else {
+                    // Let the policy settle the final state
+                    self.restart()?;
+                }
+            }
+        } else if self.should_run {
+            // Only auto-start if restart policy allows it
+            self.start()?;
         }
 
+        self.check_health()?;
+
         Ok(())
     }
-}
 
+    /// Run the health probe for a live service and update its state from
+    /// the result. A service failed only by its health check recovers once
+    /// the probe passes again.
+    fn check_health(&mut self) -> Result<(), BloomError> {
+        let live = matches!(
+            self.service.state,
+            ServiceState::Running | ServiceState::Degraded | ServiceState::Failed
+        );
+
+        if !live || self.handle.is_none() || self.restart_at.is_some() {
+            return Ok(());
+        }
+
+        let Some(health) = &mut self.health else {
+            return Ok(());
+        };
+
+        match health.poll() {
+            Some(true) => self.service.state = ServiceState::Running,
+            Some(false) if health.consecutive_failures >= HEALTH_FAILURE_LIMIT => {
+                eprintln!(
+                    "[verdantd] '{}' failed {} health checks in a row",
+                    self.service.name, health.consecutive_failures
+                );
+
+                if self.service.health_restart {
+                    // Restarted like a crashed service, within the same limits
+                    let uptime = self.handle.as_ref().map(|h| h.start_time.elapsed()).unwrap_or_default();
+                    self.stop()?;
+                    self.service.state = ServiceState::Failed;
+                    self.restart_after_failure(uptime);
+                } else {
+                    self.service.state = ServiceState::Failed;
+                }
+            }
+            Some(false) => self.service.state = ServiceState::Degraded,
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    /// Main supervise loop.
+    /// Checks the service status periodically and restarts if necessary.
+    /// The lock is only held for each step, so the Manager and IPC server can
+    /// inspect or control the service in between.
+    /// Nothing is checked while `paused` is set, e.g. around a suspend.
+    /// Will exit cleanly when `running` is set to false.
+    pub fn supervise_loop(supervisor: Arc<Mutex<Self>>, running: Arc<AtomicBool>, paused: Arc<AtomicBool>) {
+        while running.load(Ordering::Relaxed) {
+            if !paused.load(Ordering::Relaxed)
+                && let Ok(mut sup) = supervisor.lock()
+                && let Err(e) = sup.poll()
+            {
+                eprintln!("Supervisor error for {}: {:?}", sup.service.name, e);
+            }
+
+            sleep(SUPERVISE_INTERVAL);
+        }
+
+        // On exit, ensure service is stopped cleanly
+        if let Ok(mut sup) = supervisor.lock() {
+            let _ = sup.stop();
+        }
+    }
+}
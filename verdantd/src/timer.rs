@@ -0,0 +1,222 @@
+use std::fmt;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, TimeDelta, TimeZone, Timelike, Weekday};
+use serde::Serialize;
+
+use bloom::errors::BloomError;
+
+/// When a timer-activated service should be launched. Any combination of
+/// the three triggers may be set; the earliest pending one wins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimerSpec {
+    /// Launch once, this long after the system booted.
+    pub on_boot_sec: Option<Duration>,
+    /// Launch again this long after the previous launch.
+    pub on_unit_active_sec: Option<Duration>,
+    /// Launch at wall-clock times.
+    pub on_calendar: Option<Calendar>,
+}
+
+impl TimerSpec {
+    pub fn is_empty(&self) -> bool {
+        self.on_boot_sec.is_none() && self.on_unit_active_sec.is_none() && self.on_calendar.is_none()
+    }
+}
+
+/// Simple calendar expressions:
+/// `hourly`, `daily`, `weekly`, `*:MM`, `HH:MM`, `<weekday> HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Calendar {
+    Hourly { minute: u32 },
+    Daily { hour: u32, minute: u32 },
+    Weekly { weekday: Weekday, hour: u32, minute: u32 },
+}
+
+impl fmt::Display for Calendar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Calendar::Hourly { minute } => write!(f, "*:{:02}", minute),
+            Calendar::Daily { hour, minute } => write!(f, "{:02}:{:02}", hour, minute),
+            Calendar::Weekly { weekday, hour, minute } => write!(f, "{} {:02}:{:02}", weekday, hour, minute),
+        }
+    }
+}
+
+impl Calendar {
+    pub fn parse(s: &str) -> Result<Self, BloomError> {
+        let invalid = || BloomError::Parse(format!("Invalid calendar expression: {s}"));
+
+        match s.trim().to_lowercase().as_str() {
+            "hourly" => return Ok(Calendar::Hourly { minute: 0 }),
+            "daily" => return Ok(Calendar::Daily { hour: 0, minute: 0 }),
+            "weekly" => return Ok(Calendar::Weekly { weekday: Weekday::Mon, hour: 0, minute: 0 }),
+            _ => {}
+        }
+
+        let (weekday, time) = match s.trim().split_once(' ') {
+            Some((day, time)) => (Some(day.parse::<Weekday>().map_err(|_| invalid())?), time.trim()),
+            None => (None, s.trim()),
+        };
+
+        let (hour, minute) = time.split_once(':').ok_or_else(invalid)?;
+        let minute: u32 = minute.parse().ok().filter(|m| *m < 60).ok_or_else(invalid)?;
+
+        if hour == "*" {
+            return match weekday {
+                None => Ok(Calendar::Hourly { minute }),
+                Some(_) => Err(invalid()),
+            };
+        }
+
+        let hour: u32 = hour.parse().ok().filter(|h| *h < 24).ok_or_else(invalid)?;
+
+        Ok(match weekday {
+            Some(weekday) => Calendar::Weekly { weekday, hour, minute },
+            None => Calendar::Daily { hour, minute },
+        })
+    }
+
+    /// First matching local time strictly after `after`.
+    pub fn next_after(&self, after: DateTime<Local>) -> DateTime<Local> {
+        let now = after.naive_local();
+        let base = now.with_second(0).and_then(|t| t.with_nanosecond(0)).unwrap_or(now);
+
+        let (mut candidate, step) = match *self {
+            Calendar::Hourly { minute } => (base.with_minute(minute).unwrap_or(base), TimeDelta::hours(1)),
+            Calendar::Daily { hour, minute } => (at_time(base, hour, minute), TimeDelta::days(1)),
+            Calendar::Weekly { weekday, hour, minute } => {
+                let days_ahead = (7 + weekday.num_days_from_monday() as i64
+                    - base.weekday().num_days_from_monday() as i64)
+                    % 7;
+                (at_time(base, hour, minute) + TimeDelta::days(days_ahead), TimeDelta::weeks(1))
+            }
+        };
+
+        loop {
+            // Skip times that fall into a DST gap
+            if let Some(local) = Local.from_local_datetime(&candidate).earliest()
+                && local > after
+            {
+                return local;
+            }
+            candidate += step;
+        }
+    }
+}
+
+fn at_time(base: NaiveDateTime, hour: u32, minute: u32) -> NaiveDateTime {
+    base.date().and_hms_opt(hour, minute, 0).unwrap_or(base)
+}
+
+/// Accepts plain seconds or a number with an `s`, `min`/`m`, `h` or `d` suffix.
+pub fn parse_duration(s: &str) -> Result<Duration, BloomError> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+
+    let value: u64 = value
+        .parse()
+        .map_err(|_| BloomError::Parse(format!("Invalid duration: {s}")))?;
+
+    let multiplier = match unit.trim() {
+        "" | "s" | "sec" => 1,
+        "m" | "min" => 60,
+        "h" => 3600,
+        "d" => 86_400,
+        _ => return Err(BloomError::Parse(format!("Invalid duration unit: {s}"))),
+    };
+
+    Ok(Duration::from_secs(value * multiplier))
+}
+
+/// Scheduling state for one timer-activated service.
+#[derive(Debug, Clone)]
+pub struct Timer {
+    pub service: String,
+    pub spec: TimerSpec,
+    pub last_run: Option<SystemTime>,
+    pub next_run: Option<SystemTime>,
+    boot_fired: bool,
+}
+
+/// Row returned for `vctl list-timers`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TimerStatus {
+    pub service: String,
+    pub next_in_secs: Option<u64>,
+    pub last_ago_secs: Option<u64>,
+}
+
+impl Timer {
+    pub fn new(service: String, spec: TimerSpec) -> Self {
+        let mut timer = Self {
+            service,
+            spec,
+            last_run: None,
+            next_run: None,
+            boot_fired: false,
+        };
+        timer.schedule(SystemTime::now());
+        timer
+    }
+
+    /// True if the timer is due at `now`. Marks it as fired and schedules the next run.
+    pub fn fire_if_due(&mut self, now: SystemTime) -> bool {
+        match self.next_run {
+            Some(next) if next <= now => {
+                if let Some(delay) = self.spec.on_boot_sec
+                    && boot_time().is_none_or(|boot| boot + delay <= now)
+                {
+                    self.boot_fired = true;
+                }
+                self.last_run = Some(now);
+                self.schedule(now);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn schedule(&mut self, now: SystemTime) {
+        let mut candidates = Vec::new();
+
+        if let Some(delay) = self.spec.on_boot_sec
+            && !self.boot_fired
+        {
+            candidates.push(boot_time().unwrap_or(now) + delay);
+        }
+
+        if let Some(interval) = self.spec.on_unit_active_sec {
+            // With no activation yet, count from boot
+            let from = self.last_run.unwrap_or_else(|| boot_time().unwrap_or(now));
+            candidates.push(from + interval);
+        }
+
+        if let Some(calendar) = self.spec.on_calendar {
+            candidates.push(calendar.next_after(DateTime::<Local>::from(now)).into());
+        }
+
+        self.next_run = candidates.into_iter().min();
+    }
+
+    pub fn status(&self, now: SystemTime) -> TimerStatus {
+        TimerStatus {
+            service: self.service.clone(),
+            next_in_secs: self
+                .next_run
+                .map(|t| t.duration_since(now).unwrap_or_default().as_secs()),
+            last_ago_secs: self
+                .last_run
+                .map(|t| now.duration_since(t).unwrap_or_default().as_secs()),
+        }
+    }
+}
+
+/// Wall-clock time the kernel booted, derived from /proc/uptime.
+fn boot_time() -> Option<SystemTime> {
+    let contents = fs::read_to_string("/proc/uptime").ok()?;
+    let secs: f64 = contents.split_whitespace().next()?.parse().ok()?;
+    SystemTime::now().checked_sub(Duration::from_secs_f64(secs))
+}
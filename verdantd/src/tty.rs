@@ -70,3 +70,24 @@ pub fn spawn_tty(tty: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// Rescue boots get a root shell on the console instead of a getty, behind
+/// sulogin so the root password is still asked for. It comes back whenever
+/// it exits.
+pub fn spawn_rescue_shell() -> Result<(), String> {
+    bloom::shell::find_sulogin().ok_or("No sulogin binary found")?;
+
+    println!("[verdantd] Rescue boot: starting a root shell on the console");
+
+    thread::spawn(|| {
+        loop {
+            if let Err(e) = bloom::shell::run_sulogin() {
+                eprintln!("[verdantd] Failed to spawn rescue shell: {}", e);
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_secs(1));
+        }
+    });
+
+    Ok(())
+}